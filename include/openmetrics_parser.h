@@ -0,0 +1,118 @@
+#ifndef OPENMETRICS_PARSER_H
+#define OPENMETRICS_PARSER_H
+
+/* Generated with cbindgen from src/capi/mod.rs, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * A parsed exposition, flattened into its points
+ */
+typedef struct OmExposition OmExposition;
+
+/**
+ * A label of a point
+ */
+typedef struct OmLabel {
+  const char *name;
+  const char *value;
+} OmLabel;
+
+/**
+ * A single point of a parsed exposition
+ */
+typedef struct OmPoint {
+  /**
+   * The name of the family the point belongs to (e.g. `latency`)
+   */
+  const char *family;
+  /**
+   * The type of the family, as it's written in `# TYPE` lines (e.g. `histogram`)
+   */
+  const char *family_type;
+  /**
+   * The series name of the point (e.g. `latency_bucket`)
+   */
+  const char *name;
+  /**
+   * The labels of the point, sorted by name, not including the series name
+   */
+  const struct OmLabel *labels;
+  size_t label_count;
+  double value;
+  /**
+   * Milliseconds since the epoch, or NaN if the sample had no timestamp
+   */
+  double timestamp_ms;
+  /**
+   * Whether the value only ever goes up (until a reset), like counters and histogram buckets
+   */
+  bool monotonic;
+} OmPoint;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Parses an OpenMetrics exposition of `len` bytes (which needn't be NUL terminated). Returns
+ * null if it's invalid, setting `*error` to a message that must be freed with `om_string_free`
+ *
+ * # Safety
+ * `input` must point to at least `len` readable bytes, and `error` must be null or writable
+ */
+struct OmExposition *om_parse_openmetrics(const char *input, size_t len, char **error);
+
+/**
+ * Parses a Prometheus text exposition, in the same way as `om_parse_openmetrics`
+ *
+ * # Safety
+ * `input` must point to at least `len` readable bytes, and `error` must be null or writable
+ */
+struct OmExposition *om_parse_prometheus(const char *input, size_t len, char **error);
+
+/**
+ * The number of points in the exposition
+ *
+ * # Safety
+ * `exposition` must be null or returned by one of the parse functions, and not yet freed
+ */
+size_t om_exposition_point_count(const struct OmExposition *exposition);
+
+/**
+ * Iterates over the points of the exposition: `*cursor` starts at 0, and each call fills in
+ * `*point` with the next point and advances the cursor, until it returns false at the end
+ *
+ * # Safety
+ * `exposition` must be null or returned by one of the parse functions, and not yet freed.
+ * `cursor` and `point` must be writable
+ */
+bool om_exposition_next(const struct OmExposition *exposition,
+                        size_t *cursor,
+                        struct OmPoint *point);
+
+/**
+ * Frees an exposition, and every string handed out from it
+ *
+ * # Safety
+ * `exposition` must be null or returned by one of the parse functions, and not yet freed
+ */
+void om_exposition_free(struct OmExposition *exposition);
+
+/**
+ * Frees an error message
+ *
+ * # Safety
+ * `s` must be null or an error message from one of the parse functions, and not yet freed
+ */
+void om_string_free(char *s);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* OPENMETRICS_PARSER_H */
@@ -0,0 +1,45 @@
+// Loads the crate built for wasm32-unknown-unknown with the `wasm` feature, and wraps its
+// exports (see src/wasm/mod.rs) in functions that take a string and return plain JS objects:
+//
+//   const parser = await load(fetch("openmetrics_parser.wasm"));
+//   const { families } = parser.parsePrometheus(text);
+//
+// Parse errors are thrown as Errors. This is a hand written loader rather than wasm-bindgen
+// glue, which the crate doesn't generate.
+
+export async function load(source) {
+  const { instance } = await WebAssembly.instantiateStreaming(source, {});
+  return wrap(instance.exports);
+}
+
+export function wrap(exports) {
+  const encoder = new TextEncoder();
+  const decoder = new TextDecoder();
+
+  function call(parse, text) {
+    const input = encoder.encode(text);
+    const ptr = exports.om_json_alloc(input.length);
+    new Uint8Array(exports.memory.buffer, ptr, input.length).set(input);
+    const output = parse(ptr, input.length);
+    exports.om_json_dealloc(ptr, input.length);
+
+    const memory = new Uint8Array(exports.memory.buffer);
+    let end = output;
+    while (memory[end] !== 0) {
+      end++;
+    }
+
+    const result = JSON.parse(decoder.decode(memory.subarray(output, end)));
+    exports.om_json_free(output);
+    if (result.error !== undefined) {
+      throw new Error(result.error);
+    }
+
+    return result;
+  }
+
+  return {
+    parseOpenMetrics: (text) => call(exports.om_json_parse_openmetrics, text),
+    parsePrometheus: (text) => call(exports.om_json_parse_prometheus, text),
+  };
+}
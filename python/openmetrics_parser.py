@@ -0,0 +1,141 @@
+"""Python bindings for openmetrics-parser, over its C API.
+
+These are a partial stand-in for PyO3 bindings, which the crate doesn't provide: only parsing
+is exposed, through ctypes, and the results are plain dataclasses rather than the crate's
+exposition model.
+
+Build the shared library with the `capi` feature:
+
+    cargo rustc --release --features capi --crate-type cdylib
+
+and point OPENMETRICS_PARSER_LIB at it (target/release/libopenmetrics_parser.so, .dylib or
+.dll), or put it next to this file.
+
+    >>> from openmetrics_parser import parse_prometheus
+    >>> exposition = parse_prometheus('# TYPE up gauge\\nup{job="node"} 1\\n')
+    >>> exposition["up"].samples[0].labels
+    {'job': 'node'}
+
+Families are flattened into samples the same way as `MetricsExposition::for_each_point`, so a
+histogram has a sample per bucket (with an `le` label), plus its `_sum` and `_count`.
+"""
+
+import ctypes
+import math
+import os
+import sys
+from dataclasses import dataclass, field
+from typing import Dict, List, Optional
+
+__all__ = ["Exposition", "Family", "ParseError", "Sample", "parse_openmetrics", "parse_prometheus"]
+
+
+class ParseError(ValueError):
+    """The exposition isn't valid"""
+
+
+@dataclass
+class Sample:
+    name: str
+    labels: Dict[str, str]
+    value: float
+    # Milliseconds since the epoch, or None if the sample had no timestamp
+    timestamp: Optional[float]
+    # Whether the value only ever goes up (until a reset), like counters and histogram buckets
+    monotonic: bool
+
+
+@dataclass
+class Family:
+    name: str
+    type: str
+    samples: List[Sample] = field(default_factory=list)
+
+
+class Exposition(Dict[str, Family]):
+    """The families of an exposition, by name, in the order they were parsed"""
+
+
+class _Label(ctypes.Structure):
+    _fields_ = [("name", ctypes.c_char_p), ("value", ctypes.c_char_p)]
+
+
+class _Point(ctypes.Structure):
+    _fields_ = [
+        ("family", ctypes.c_char_p),
+        ("family_type", ctypes.c_char_p),
+        ("name", ctypes.c_char_p),
+        ("labels", ctypes.POINTER(_Label)),
+        ("label_count", ctypes.c_size_t),
+        ("value", ctypes.c_double),
+        ("timestamp_ms", ctypes.c_double),
+        ("monotonic", ctypes.c_bool),
+    ]
+
+
+def _library_path():
+    path = os.environ.get("OPENMETRICS_PARSER_LIB")
+    if path:
+        return path
+
+    name = {"darwin": "libopenmetrics_parser.dylib", "win32": "openmetrics_parser.dll"}
+    return os.path.join(
+        os.path.dirname(os.path.abspath(__file__)),
+        name.get(sys.platform, "libopenmetrics_parser.so"),
+    )
+
+
+_lib = ctypes.CDLL(_library_path())
+for _parse in (_lib.om_parse_openmetrics, _lib.om_parse_prometheus):
+    _parse.argtypes = [ctypes.c_char_p, ctypes.c_size_t, ctypes.POINTER(ctypes.c_void_p)]
+    _parse.restype = ctypes.c_void_p
+_lib.om_exposition_next.argtypes = [
+    ctypes.c_void_p,
+    ctypes.POINTER(ctypes.c_size_t),
+    ctypes.POINTER(_Point),
+]
+_lib.om_exposition_next.restype = ctypes.c_bool
+_lib.om_exposition_free.argtypes = [ctypes.c_void_p]
+_lib.om_string_free.argtypes = [ctypes.c_void_p]
+
+
+def _parse_with(parse, text):
+    data = text.encode("utf-8")
+    error = ctypes.c_void_p()
+    handle = parse(data, len(data), ctypes.byref(error))
+    if not handle:
+        message = ctypes.string_at(error.value).decode("utf-8")
+        _lib.om_string_free(error)
+        raise ParseError(message)
+
+    exposition = Exposition()
+    try:
+        cursor = ctypes.c_size_t(0)
+        point = _Point()
+        while _lib.om_exposition_next(handle, ctypes.byref(cursor), ctypes.byref(point)):
+            name = point.family.decode("utf-8")
+            if name not in exposition:
+                exposition[name] = Family(name, point.family_type.decode("utf-8"))
+
+            labels = {
+                point.labels[i].name.decode("utf-8"): point.labels[i].value.decode("utf-8")
+                for i in range(point.label_count)
+            }
+            timestamp = None if math.isnan(point.timestamp_ms) else point.timestamp_ms
+            exposition[name].samples.append(
+                Sample(point.name.decode("utf-8"), labels, point.value, timestamp, point.monotonic)
+            )
+    finally:
+        _lib.om_exposition_free(handle)
+
+    return exposition
+
+
+def parse_openmetrics(text: str) -> Exposition:
+    """Parses an OpenMetrics exposition, raising ParseError if it's invalid"""
+    return _parse_with(_lib.om_parse_openmetrics, text)
+
+
+def parse_prometheus(text: str) -> Exposition:
+    """Parses a Prometheus text exposition, raising ParseError if it's invalid"""
+    return _parse_with(_lib.om_parse_prometheus, text)
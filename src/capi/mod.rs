@@ -0,0 +1,307 @@
+//! A C ABI over the parsers, for embedding them in agents that aren't written in Rust. Build the
+//! crate as a static or dynamic library with the `capi` feature
+//! (e.g. `cargo rustc --release --features capi --crate-type staticlib`), and include
+//! `include/openmetrics_parser.h`, which is generated from this module with cbindgen
+//! (`cbindgen --config cbindgen.toml --output include/openmetrics_parser.h`).
+//! `python/openmetrics_parser.py` wraps this API for Python, through ctypes. It's a partial
+//! stand-in for PyO3 bindings, which this crate doesn't provide: only parsing is exposed, as
+//! plain Python objects rather than the exposition model.
+//!
+//! A parsed exposition is flattened into points, the same way `MetricsExposition::for_each_point`
+//! does: a histogram becomes its `_bucket`, `_sum` and `_count` points, with `le` as a label.
+//! Every string handed out stays valid until the exposition it came from is freed. A panic never
+//! unwinds into the caller: parsing fails with an error instead, and the other functions return
+//! as they would for a null exposition
+//! ```c
+//! char *error = NULL;
+//! OmExposition *exposition = om_parse_prometheus(text, strlen(text), &error);
+//! if (exposition == NULL) {
+//!     fprintf(stderr, "%s\n", error);
+//!     om_string_free(error);
+//!     return;
+//! }
+//!
+//! size_t cursor = 0;
+//! OmPoint point;
+//! while (om_exposition_next(exposition, &cursor, &point)) {
+//!     printf("%s %f\n", point.name, point.value);
+//! }
+//!
+//! om_exposition_free(exposition);
+//! ```
+
+use std::{
+    ffi::{c_char, CString},
+    fmt::Display,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+
+use crate::{
+    openmetrics::parse_openmetrics, prometheus::parse_prometheus, ExpositionTypeSet,
+    MetricsExposition, ParseError, RenderableMetricValue, SeriesValue, METRIC_NAME_LABEL,
+};
+
+/// A label of a point
+#[repr(C)]
+pub struct OmLabel {
+    pub name: *const c_char,
+    pub value: *const c_char,
+}
+
+/// A single point of a parsed exposition
+#[repr(C)]
+pub struct OmPoint {
+    /// The name of the family the point belongs to (e.g. `latency`)
+    pub family: *const c_char,
+
+    /// The type of the family, as it's written in `# TYPE` lines (e.g. `histogram`)
+    pub family_type: *const c_char,
+
+    /// The series name of the point (e.g. `latency_bucket`)
+    pub name: *const c_char,
+
+    /// The labels of the point, sorted by name, not including the series name
+    pub labels: *const OmLabel,
+    pub label_count: usize,
+    pub value: f64,
+
+    /// Milliseconds since the epoch, or NaN if the sample had no timestamp
+    pub timestamp_ms: f64,
+
+    /// Whether the value only ever goes up (until a reset), like counters and histogram buckets
+    pub monotonic: bool,
+}
+
+struct OwnedPoint {
+    family: usize,
+    name: CString,
+
+    // The strings `labels` points into
+    _label_strings: Vec<(CString, CString)>,
+    labels: Vec<OmLabel>,
+    value: f64,
+    timestamp_ms: f64,
+    monotonic: bool,
+}
+
+/// A parsed exposition, flattened into its points
+pub struct OmExposition {
+    /// The name and type of every family
+    families: Vec<(CString, CString)>,
+    points: Vec<OwnedPoint>,
+}
+
+/// Strings containing NUL can't be passed to C, so any NULs are dropped
+fn c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).unwrap()
+}
+
+fn flatten<TypeSet, ValueType>(exposition: &MetricsExposition<TypeSet, ValueType>) -> OmExposition
+where
+    TypeSet: Clone + ExpositionTypeSet + Display,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let mut flattened = OmExposition {
+        families: Vec::new(),
+        points: Vec::new(),
+    };
+
+    let mut current_family: Option<String> = None;
+    exposition.for_each_point(f64::NAN, |family, labels, timestamp, point| {
+        if current_family.as_deref() != Some(family.family_name.as_str()) {
+            current_family = Some(family.family_name.clone());
+            flattened.families.push((
+                c_string(&family.family_name),
+                c_string(&family.family_type.to_string()),
+            ));
+        }
+
+        let label_strings: Vec<(CString, CString)> = labels
+            .iter()
+            .filter(|(name, _)| name.as_str() != METRIC_NAME_LABEL)
+            .map(|(name, value)| (c_string(name), c_string(value)))
+            .collect();
+        let labels = label_strings
+            .iter()
+            .map(|(name, value)| OmLabel {
+                name: name.as_ptr(),
+                value: value.as_ptr(),
+            })
+            .collect();
+
+        flattened.points.push(OwnedPoint {
+            family: flattened.families.len() - 1,
+            name: c_string(&point.name),
+            _label_strings: label_strings,
+            labels,
+            value: point.value,
+            timestamp_ms: timestamp,
+            monotonic: point.monotonic,
+        });
+    });
+
+    flattened
+}
+
+/// Runs `f`, returning `fallback` if it panics, as unwinding across the FFI boundary is undefined
+fn guard<T, F: FnOnce() -> T>(fallback: T, f: F) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(fallback)
+}
+
+/// The message a panic was raised with
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_default();
+    format!("The parser panicked: {}", message)
+}
+
+/// Parses `len` bytes of `input` with `parse`, returning the flattened exposition, or null with
+/// `*error` set to a message (if `error` isn't null)
+unsafe fn parse_with<F>(
+    input: *const c_char,
+    len: usize,
+    error: *mut *mut c_char,
+    parse: F,
+) -> *mut OmExposition
+where
+    F: FnOnce(&str) -> Result<OmExposition, ParseError>,
+{
+    let result = if input.is_null() {
+        Err("Input is null".to_owned())
+    } else {
+        let bytes = std::slice::from_raw_parts(input as *const u8, len);
+        std::str::from_utf8(bytes)
+            .map_err(|e| format!("Input isn't valid UTF-8: {}", e))
+            .and_then(|text| {
+                catch_unwind(AssertUnwindSafe(|| parse(text)))
+                    .map_err(panic_message)?
+                    .map_err(|e| e.to_string())
+            })
+    };
+
+    match result {
+        Ok(exposition) => Box::into_raw(Box::new(exposition)),
+        Err(message) => {
+            if !error.is_null() {
+                *error = guard(ptr::null_mut(), || c_string(&message).into_raw());
+            }
+
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Parses an OpenMetrics exposition of `len` bytes (which needn't be NUL terminated). Returns
+/// null if it's invalid, setting `*error` to a message that must be freed with `om_string_free`
+///
+/// # Safety
+/// `input` must point to at least `len` readable bytes, and `error` must be null or writable
+#[no_mangle]
+pub unsafe extern "C" fn om_parse_openmetrics(
+    input: *const c_char,
+    len: usize,
+    error: *mut *mut c_char,
+) -> *mut OmExposition {
+    parse_with(input, len, error, |text| {
+        parse_openmetrics(text).map(|e| flatten(&e))
+    })
+}
+
+/// Parses a Prometheus text exposition, in the same way as `om_parse_openmetrics`
+///
+/// # Safety
+/// `input` must point to at least `len` readable bytes, and `error` must be null or writable
+#[no_mangle]
+pub unsafe extern "C" fn om_parse_prometheus(
+    input: *const c_char,
+    len: usize,
+    error: *mut *mut c_char,
+) -> *mut OmExposition {
+    parse_with(input, len, error, |text| {
+        parse_prometheus(text).map(|e| flatten(&e))
+    })
+}
+
+/// The number of points in the exposition
+///
+/// # Safety
+/// `exposition` must be null or returned by one of the parse functions, and not yet freed
+#[no_mangle]
+pub unsafe extern "C" fn om_exposition_point_count(exposition: *const OmExposition) -> usize {
+    guard(0, || exposition.as_ref().map_or(0, |e| e.points.len()))
+}
+
+/// Iterates over the points of the exposition: `*cursor` starts at 0, and each call fills in
+/// `*point` with the next point and advances the cursor, until it returns false at the end
+///
+/// # Safety
+/// `exposition` must be null or returned by one of the parse functions, and not yet freed.
+/// `cursor` and `point` must be writable
+#[no_mangle]
+pub unsafe extern "C" fn om_exposition_next(
+    exposition: *const OmExposition,
+    cursor: *mut usize,
+    point: *mut OmPoint,
+) -> bool {
+    guard(false, || next_point(exposition, cursor, point))
+}
+
+unsafe fn next_point(
+    exposition: *const OmExposition,
+    cursor: *mut usize,
+    point: *mut OmPoint,
+) -> bool {
+    let (exposition, cursor, out) = match (exposition.as_ref(), cursor.as_mut(), point.as_mut()) {
+        (Some(exposition), Some(cursor), Some(out)) => (exposition, cursor, out),
+        _ => return false,
+    };
+
+    let point = match exposition.points.get(*cursor) {
+        Some(point) => point,
+        None => return false,
+    };
+
+    let (family, family_type) = &exposition.families[point.family];
+    *out = OmPoint {
+        family: family.as_ptr(),
+        family_type: family_type.as_ptr(),
+        name: point.name.as_ptr(),
+        labels: point.labels.as_ptr(),
+        label_count: point.labels.len(),
+        value: point.value,
+        timestamp_ms: point.timestamp_ms,
+        monotonic: point.monotonic,
+    };
+
+    *cursor += 1;
+    true
+}
+
+/// Frees an exposition, and every string handed out from it
+///
+/// # Safety
+/// `exposition` must be null or returned by one of the parse functions, and not yet freed
+#[no_mangle]
+pub unsafe extern "C" fn om_exposition_free(exposition: *mut OmExposition) {
+    if !exposition.is_null() {
+        guard((), || drop(Box::from_raw(exposition)));
+    }
+}
+
+/// Frees an error message
+///
+/// # Safety
+/// `s` must be null or an error message from one of the parse functions, and not yet freed
+#[no_mangle]
+pub unsafe extern "C" fn om_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        guard((), || drop(CString::from_raw(s)));
+    }
+}
+
+#[cfg(test)]
+mod tests;
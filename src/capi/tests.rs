@@ -0,0 +1,204 @@
+use std::{
+    ffi::{c_char, CStr},
+    ptr,
+};
+
+use super::*;
+
+unsafe fn string(s: *const c_char) -> String {
+    CStr::from_ptr(s).to_str().unwrap().to_owned()
+}
+
+#[test]
+fn test_capi_points() {
+    let text = "# TYPE latency histogram\nlatency_bucket{path=\"/\",le=\"1\"} 2 1500\nlatency_bucket{path=\"/\",le=\"+Inf\"} 3 1500\nlatency_sum{path=\"/\"} 1.5 1500\nlatency_count{path=\"/\"} 3 1500\n# TYPE up gauge\nup 1\n";
+    unsafe {
+        let exposition =
+            om_parse_prometheus(text.as_ptr() as *const c_char, text.len(), ptr::null_mut());
+        assert!(!exposition.is_null());
+        assert_eq!(om_exposition_point_count(exposition), 5);
+
+        let mut cursor = 0;
+        let mut point: OmPoint = std::mem::zeroed();
+        let mut points = Vec::new();
+        while om_exposition_next(exposition, &mut cursor, &mut point) {
+            let labels: Vec<String> = (0..point.label_count)
+                .map(|i| {
+                    let label = &*point.labels.add(i);
+                    format!("{}={}", string(label.name), string(label.value))
+                })
+                .collect();
+            points.push((
+                string(point.family),
+                string(point.family_type),
+                string(point.name),
+                labels,
+                point.value,
+                point.timestamp_ms,
+            ));
+        }
+
+        assert_eq!(cursor, 5);
+        assert_eq!(
+            points[0],
+            (
+                "latency".to_owned(),
+                "histogram".to_owned(),
+                "latency_bucket".to_owned(),
+                vec!["le=1".to_owned(), "path=/".to_owned()],
+                2.,
+                1500.
+            )
+        );
+        assert_eq!(points[4].2, "up");
+        assert!(points[4].5.is_nan());
+
+        om_exposition_free(exposition);
+    }
+}
+
+#[test]
+fn test_capi_errors() {
+    let text = "up 1\n";
+    unsafe {
+        let mut error = ptr::null_mut();
+        let exposition =
+            om_parse_openmetrics(text.as_ptr() as *const c_char, text.len(), &mut error);
+        assert!(exposition.is_null());
+        assert!(!error.is_null());
+        assert!(!string(error).is_empty());
+        om_string_free(error);
+
+        let mut error = ptr::null_mut();
+        let invalid = [0xff_u8, b'\n'];
+        let exposition = om_parse_prometheus(invalid.as_ptr() as *const c_char, 2, &mut error);
+        assert!(exposition.is_null());
+        assert!(string(error).contains("UTF-8"));
+        om_string_free(error);
+
+        assert!(!om_exposition_next(
+            ptr::null(),
+            &mut 0,
+            &mut std::mem::zeroed()
+        ));
+    }
+}
+
+#[test]
+fn test_capi_panics() {
+    let text = "up 1\n";
+    unsafe {
+        let mut error = ptr::null_mut();
+        let exposition = parse_with(
+            text.as_ptr() as *const c_char,
+            text.len(),
+            &mut error,
+            |_| panic!("boom"),
+        );
+        assert!(exposition.is_null());
+        assert_eq!(string(error), "The parser panicked: boom");
+        om_string_free(error);
+    }
+}
+
+/// The C spelling of a Rust type in the C API, as cbindgen writes it
+fn c_type(rust: &str) -> String {
+    let rust = rust.trim();
+    if let Some(pointee) = rust.strip_prefix("*const ") {
+        return format!("const {}*", c_type(pointee));
+    }
+
+    if let Some(pointee) = rust.strip_prefix("*mut ") {
+        return format!("{}*", c_type(pointee));
+    }
+
+    match rust {
+        "" => "void ".to_owned(),
+        "c_char" => "char ".to_owned(),
+        "usize" => "size_t ".to_owned(),
+        "f64" => "double ".to_owned(),
+        "bool" => "bool ".to_owned(),
+        name if name.starts_with("Om") => format!("struct {} ", name),
+        other => panic!("No C type for {}", other),
+    }
+}
+
+/// Drops the comments from C source, and collapses whitespace (and the spaces around `*`), so
+/// that declarations compare equal however they're documented and wrapped
+fn normalize(source: &str) -> String {
+    let mut code = String::new();
+    let mut rest = source;
+    while let Some(start) = rest.find("/*") {
+        code.push_str(&rest[..start]);
+        rest = &rest[start + rest[start..].find("*/").unwrap() + 2..];
+    }
+    code.push_str(rest);
+
+    code.lines()
+        .map(|line| line.split("//").next().unwrap())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace(" *", "*")
+        .replace("* ", "*")
+        .replace("( ", "(")
+        .replace(" )", ")")
+}
+
+/// The header is generated with cbindgen, so nothing checks it against the Rust declarations
+/// other than this
+#[test]
+fn test_capi_header_matches() {
+    let source = include_str!("mod.rs");
+    let header = normalize(include_str!("../../include/openmetrics_parser.h"));
+
+    // Every exported function has a prototype, with the same argument and return types
+    let mut functions = 0;
+    for item in source.split("pub unsafe extern \"C\" fn ").skip(1) {
+        let signature = &item[..item.find('{').unwrap()];
+        let (name, rest) = signature.split_once('(').unwrap();
+        let (arguments, ret) = rest.rsplit_once(')').unwrap();
+        let arguments: Vec<String> = arguments
+            .split(',')
+            .filter(|a| !a.trim().is_empty())
+            .map(|argument| {
+                let (name, rust) = argument.split_once(':').unwrap();
+                format!("{}{}", c_type(rust), name.trim())
+            })
+            .collect();
+        let ret = c_type(ret.trim().trim_start_matches("->"));
+        let prototype = normalize(&format!("{}{}({});", ret, name, arguments.join(", ")));
+        assert!(
+            header.contains(&prototype),
+            "Header is missing `{}`",
+            prototype
+        );
+        functions += 1;
+    }
+
+    assert_eq!(header.matches(");").count(), functions);
+
+    // And every struct that's passed across has the same fields
+    for item in source.split("#[repr(C)]\npub struct ").skip(1) {
+        let (name, body) = item.split_once(" {").unwrap();
+        let fields: Vec<String> = body[..body.find("\n}").unwrap()]
+            .lines()
+            .filter_map(|line| line.trim().strip_prefix("pub "))
+            .map(|field| {
+                let (name, rust) = field.trim_end_matches(',').split_once(':').unwrap();
+                normalize(&format!("{}{}", c_type(rust), name.trim()))
+            })
+            .collect();
+
+        let start = format!("typedef struct {} {{", name);
+        let body = &header[header.find(&start).unwrap() + start.len()..];
+        let declared: Vec<String> = body[..body.find(&format!("}} {};", name)).unwrap()]
+            .split(';')
+            .map(|field| field.trim().to_owned())
+            .filter(|field| !field.is_empty())
+            .collect();
+        assert_eq!(declared, fields, "Fields of {} don't match", name);
+    }
+}
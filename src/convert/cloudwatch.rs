@@ -0,0 +1,136 @@
+//! Renders expositions in the CloudWatch Embedded Metric Format (EMF)
+//! https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    ExpositionTypeSet, MetricsExposition, RenderableMetricValue, SeriesValue, Timestamp,
+    METRIC_NAME_LABEL,
+};
+
+/// Controls which families are rendered, and how
+#[derive(Debug, Clone, Default)]
+pub struct EmfOptions {
+    /// The CloudWatch namespace the metrics are put in
+    pub namespace: String,
+
+    /// The families to render. All of them if None
+    pub families: Option<Vec<String>>,
+
+    /// The labels to turn into dimensions. All of them if None. Labels that aren't dimensions are dropped
+    pub dimensions: Option<Vec<String>>,
+}
+
+impl EmfOptions {
+    pub fn new(namespace: &str) -> Self {
+        Self {
+            namespace: namespace.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_families(mut self, families: &[&str]) -> Self {
+        self.families = Some(families.iter().map(|s| s.to_string()).collect());
+        self
+    }
+
+    pub fn with_dimensions(mut self, dimensions: &[&str]) -> Self {
+        self.dimensions = Some(dimensions.iter().map(|s| s.to_string()).collect());
+        self
+    }
+}
+
+/// Maps an OpenMetrics unit onto the CloudWatch one, if there is an equivalent
+fn cloudwatch_unit(unit: &str) -> Option<&'static str> {
+    Some(match unit {
+        "seconds" => "Seconds",
+        "milliseconds" => "Milliseconds",
+        "microseconds" => "Microseconds",
+        "bytes" => "Bytes",
+        "bits" => "Bits",
+        "percent" => "Percent",
+        _ => return None,
+    })
+}
+
+/// Renders every point of the selected families as its own EMF blob (one JSON object per line),
+/// ready to be written to a Lambda's stdout or the CloudWatch agent. The family unit is attached
+/// to the points measured in it (e.g. a histogram's `_sum`, but not its `_count` or `_bucket`s)
+/// ```
+/// use openmetrics_parser::prometheus::parse_prometheus;
+/// use openmetrics_parser::convert::cloudwatch::{render_emf, EmfOptions};
+///
+/// let exposition = parse_prometheus("temp{room=\"a\"} 21.5\n").unwrap();
+/// let emf = render_emf(&exposition, &EmfOptions::new("home"), 1000.);
+/// assert_eq!(
+///     emf,
+///     r#"{"_aws":{"CloudWatchMetrics":[{"Dimensions":[["room"]],"Metrics":[{"Name":"temp"}],"Namespace":"home"}],"Timestamp":1000},"room":"a","temp":21.5}"#.to_owned() + "\n"
+/// );
+/// ```
+pub fn render_emf<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    options: &EmfOptions,
+    default_timestamp: Timestamp,
+) -> String
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let mut emf = String::new();
+    exposition.for_each_point(default_timestamp, |family, labels, timestamp, point| {
+        if let Some(families) = &options.families {
+            if !families.contains(&family.family_name) {
+                return;
+            }
+        }
+
+        if !point.value.is_finite() {
+            // CloudWatch (and JSON) can't represent NaN or infinities
+            return;
+        }
+
+        let mut blob = Map::new();
+        let mut dimensions = Vec::new();
+        for (name, value) in labels.iter() {
+            let is_dimension = match &options.dimensions {
+                Some(dimensions) => dimensions.contains(name),
+                None => name != METRIC_NAME_LABEL,
+            };
+
+            if is_dimension {
+                dimensions.push(name.clone());
+                blob.insert(name.clone(), Value::from(value.as_str()));
+            }
+        }
+
+        let mut metric = json!({ "Name": point.name });
+        let measured_in_unit = point
+            .name
+            .strip_prefix(family.family_name.as_str())
+            .map(|suffix| ["", "_total", "_sum", "_gsum"].contains(&suffix))
+            .unwrap_or(false);
+        if measured_in_unit {
+            if let Some(unit) = cloudwatch_unit(&family.unit) {
+                metric["Unit"] = Value::from(unit);
+            }
+        }
+
+        blob.insert(
+            "_aws".to_owned(),
+            json!({
+                "Timestamp": timestamp as i64,
+                "CloudWatchMetrics": [{
+                    "Namespace": options.namespace,
+                    "Dimensions": [dimensions],
+                    "Metrics": [metric],
+                }],
+            }),
+        );
+        blob.insert(point.name.clone(), Value::from(point.value));
+
+        emf.push_str(&Value::Object(blob).to_string());
+        emf.push('\n');
+    });
+
+    emf
+}
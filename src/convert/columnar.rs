@@ -0,0 +1,128 @@
+//! Flattens expositions into columns, laid out the way Apache Arrow stores a record batch of
+//! `(name, <one column per label>, value, timestamp)`, for archiving scrapes in columnar formats
+//! (Arrow IPC, Parquet) for offline analysis
+//!
+//! Arrow support is partial: there's no `arrow` feature, and this crate doesn't build
+//! `RecordBatch`es or write Parquet itself, as it doesn't depend on `arrow`. What it does provide
+//! is the columns, each of which maps directly onto an Arrow array (`DictionaryColumn` onto a
+//! `DictionaryArray<UInt32Type>` of strings, `values` onto a `Float64Array` and `timestamps` onto
+//! a millisecond `TimestampMillisecondArray`), so a caller that does depend on `arrow` can build
+//! the batch (and write Parquet) without copying any strings more than once
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    ExpositionTypeSet, MetricsExposition, RenderableMetricValue, SeriesValue, Timestamp,
+    METRIC_NAME_LABEL,
+};
+
+/// A dictionary encoded string column: each row is an index into `values`, or None if the row
+/// doesn't have a value (e.g. a series without that label)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DictionaryColumn {
+    pub keys: Vec<Option<u32>>,
+    pub values: Vec<String>,
+    index: HashMap<String, u32>,
+}
+
+impl DictionaryColumn {
+    fn with_nulls(rows: usize) -> Self {
+        Self {
+            keys: vec![None; rows],
+            ..Default::default()
+        }
+    }
+
+    fn push(&mut self, value: Option<&str>) {
+        let key = value.map(|value| match self.index.get(value) {
+            Some(key) => *key,
+            None => {
+                let key = self.values.len() as u32;
+                self.values.push(value.to_owned());
+                self.index.insert(value.to_owned(), key);
+                key
+            }
+        });
+
+        self.keys.push(key);
+    }
+
+    /// The value of the given row, if it has one
+    pub fn get(&self, row: usize) -> Option<&str> {
+        self.keys
+            .get(row)
+            .copied()
+            .flatten()
+            .map(|key| self.values[key as usize].as_str())
+    }
+}
+
+/// An exposition flattened into one row per point (so a histogram gives a row per bucket, plus
+/// its `_sum` and `_count`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ColumnarBatch {
+    /// The series name of each row (e.g. `latency_bucket`)
+    pub names: DictionaryColumn,
+
+    /// A column per label name seen anywhere in the exposition, sorted by name
+    pub labels: BTreeMap<String, DictionaryColumn>,
+    pub values: Vec<f64>,
+
+    /// Milliseconds since the epoch
+    pub timestamps: Vec<i64>,
+}
+
+impl ColumnarBatch {
+    pub fn num_rows(&self) -> usize {
+        self.values.len()
+    }
+}
+
+/// Flattens the exposition into a `ColumnarBatch`. Points without a timestamp are given
+/// `default_timestamp` (in milliseconds, like the rest of the converters)
+/// ```
+/// use openmetrics_parser::prometheus::parse_prometheus;
+/// use openmetrics_parser::convert::columnar::to_columnar_batch;
+///
+/// let exposition = parse_prometheus("# TYPE up gauge\nup{job=\"a\"} 1\nup{job=\"b\"} 0\n").unwrap();
+/// let batch = to_columnar_batch(&exposition, 1000.);
+/// assert_eq!(batch.num_rows(), 2);
+/// assert_eq!(batch.names.values, vec!["up"]);
+/// assert_eq!(batch.labels["job"].get(1), Some("b"));
+/// assert_eq!(batch.timestamps, vec![1000, 1000]);
+/// ```
+pub fn to_columnar_batch<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    default_timestamp: Timestamp,
+) -> ColumnarBatch
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let mut batch = ColumnarBatch::default();
+    exposition.for_each_point(default_timestamp, |_, labels, timestamp, point| {
+        let row = batch.values.len();
+        batch.names.push(Some(&point.name));
+        for (name, value) in labels.iter() {
+            if name != METRIC_NAME_LABEL {
+                batch
+                    .labels
+                    .entry(name.clone())
+                    .or_insert_with(|| DictionaryColumn::with_nulls(row))
+                    .push(Some(value));
+            }
+        }
+
+        // Labels this point doesn't have are null
+        for column in batch.labels.values_mut() {
+            if column.keys.len() == row {
+                column.push(None);
+            }
+        }
+
+        batch.values.push(point.value);
+        batch.timestamps.push(timestamp as i64);
+    });
+
+    batch
+}
@@ -0,0 +1,194 @@
+//! Renders expositions as Datadog v2 series submission payloads (`POST /api/v2/series`)
+//! https://docs.datadoghq.com/api/latest/metrics/#submit-metrics
+
+use serde_json::{json, Value};
+
+use crate::{
+    ExpositionTypeSet, MetricsExposition, RenderableMetricValue, SeriesValue, Timestamp,
+    METRIC_NAME_LABEL,
+};
+
+/// The Datadog metric intake types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatadogMetricType {
+    Unspecified = 0,
+    Count = 1,
+    Rate = 2,
+    Gauge = 3,
+}
+
+/// How histograms are flattened into series
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DatadogHistograms {
+    /// A series per bucket (tagged with `le`), along with the `_sum` and `_count`
+    #[default]
+    Buckets,
+
+    /// Only the `_sum` and `_count`, for when the per bucket series cost too much
+    SumAndCount,
+}
+
+/// How summaries are flattened into series
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DatadogSummaries {
+    /// A series per quantile (tagged with `quantile`), along with the `_sum` and `_count`
+    #[default]
+    Quantiles,
+
+    /// Only the `_sum` and `_count`. Quantiles can't be aggregated across series, so they're
+    /// often of little use once in Datadog
+    SumAndCount,
+}
+
+#[derive(Debug, Clone)]
+pub struct DatadogOptions {
+    /// The type monotonic values are sent as (see `render_series_payload`)
+    pub monotonic_type: DatadogMetricType,
+    pub histograms: DatadogHistograms,
+    pub summaries: DatadogSummaries,
+}
+
+impl Default for DatadogOptions {
+    fn default() -> Self {
+        Self {
+            monotonic_type: DatadogMetricType::Gauge,
+            histograms: DatadogHistograms::default(),
+            summaries: DatadogSummaries::default(),
+        }
+    }
+}
+
+impl DatadogOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_monotonic_type(mut self, monotonic_type: DatadogMetricType) -> Self {
+        self.monotonic_type = monotonic_type;
+        self
+    }
+
+    pub fn with_histograms(mut self, histograms: DatadogHistograms) -> Self {
+        self.histograms = histograms;
+        self
+    }
+
+    pub fn with_summaries(mut self, summaries: DatadogSummaries) -> Self {
+        self.summaries = summaries;
+        self
+    }
+}
+
+/// Datadog only accepts units from a fixed list, which are singular where OpenMetrics' are plural
+fn datadog_unit(unit: &str) -> Option<&'static str> {
+    Some(match unit {
+        "seconds" => "second",
+        "milliseconds" => "millisecond",
+        "microseconds" => "microsecond",
+        "nanoseconds" => "nanosecond",
+        "bytes" => "byte",
+        "bits" => "bit",
+        "ratio" => "fraction",
+        "percent" => "percent",
+        "requests" => "request",
+        "errors" => "error",
+        "connections" => "connection",
+        _ => return None,
+    })
+}
+
+/// Builds a series payload with one series per point in the exposition. Labels become `name:value` tags,
+/// and known `# UNIT`s are mapped to their Datadog equivalent.
+///
+/// Non monotonic values (gauges, quantiles etc) are sent as gauges. Monotonic ones (counters, histogram buckets etc)
+/// are sent as `monotonic_type`: they're cumulative, whereas Datadog counts are per interval, so only use
+/// `DatadogMetricType::Count` if the exposition already holds deltas
+/// ```
+/// use openmetrics_parser::prometheus::parse_prometheus;
+/// use openmetrics_parser::convert::datadog::{render_series_payload, DatadogMetricType};
+///
+/// let exposition = parse_prometheus("# TYPE temp gauge\ntemp{room=\"a\"} 21.5\n").unwrap();
+/// let payload = render_series_payload(&exposition, 1636629071000., DatadogMetricType::Gauge);
+/// assert_eq!(
+///     payload,
+///     r#"{"series":[{"metric":"temp","points":[{"timestamp":1636629071,"value":21.5}],"tags":["room:a"],"type":3}]}"#
+/// );
+/// ```
+pub fn render_series_payload<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    default_timestamp: Timestamp,
+    monotonic_type: DatadogMetricType,
+) -> String
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let options = DatadogOptions::new().with_monotonic_type(monotonic_type);
+    render_series_payload_with_options(exposition, default_timestamp, &options)
+}
+
+/// Builds a series payload, flattening histograms and summaries as set by `options`
+/// ```
+/// use openmetrics_parser::prometheus::parse_prometheus;
+/// use openmetrics_parser::convert::datadog::{render_series_payload_with_options, DatadogHistograms, DatadogOptions};
+///
+/// let exposition = parse_prometheus("# TYPE latency histogram\nlatency_bucket{le=\"+Inf\"} 2\nlatency_sum 1.5\nlatency_count 2\n").unwrap();
+/// let options = DatadogOptions::new().with_histograms(DatadogHistograms::SumAndCount);
+/// let payload = render_series_payload_with_options(&exposition, 0., &options);
+/// assert!(!payload.contains("latency_bucket"));
+/// assert!(payload.contains("latency_sum"));
+/// ```
+pub fn render_series_payload_with_options<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    default_timestamp: Timestamp,
+    options: &DatadogOptions,
+) -> String
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let mut series = Vec::new();
+    exposition.for_each_point(default_timestamp, |family, labels, timestamp, point| {
+        if !point.value.is_finite() {
+            // Datadog (and JSON) can't represent NaN or infinities
+            return;
+        }
+
+        let skipped = point.extra_labels.iter().any(|(name, _)| match *name {
+            "le" => options.histograms == DatadogHistograms::SumAndCount,
+            "quantile" => options.summaries == DatadogSummaries::SumAndCount,
+            _ => false,
+        });
+
+        if skipped {
+            return;
+        }
+
+        let tags: Vec<String> = labels
+            .iter()
+            .filter(|(k, _)| k.as_str() != METRIC_NAME_LABEL)
+            .map(|(k, v)| format!("{}:{}", k, v))
+            .collect();
+
+        let metric_type = if point.monotonic {
+            options.monotonic_type
+        } else {
+            DatadogMetricType::Gauge
+        };
+
+        let mut s = json!({
+            "metric": point.name,
+            "type": metric_type as u8,
+            "points": [{"timestamp": (timestamp / 1000.) as i64, "value": point.value}],
+            "tags": tags,
+        });
+
+        if let Some(unit) = datadog_unit(&family.unit) {
+            s["unit"] = Value::from(unit);
+        }
+
+        series.push(s);
+    });
+
+    json!({ "series": series }).to_string()
+}
@@ -0,0 +1,207 @@
+//! Converts expositions between the Prometheus and OpenMetrics text formats, so that metrics
+//! from legacy exporters can be served as OpenMetrics (and the other way around)
+//!
+//! Prometheus timestamps are in milliseconds, OpenMetrics ones in seconds, so sample timestamps
+//! are scaled as they're converted (see `TimestampUnit`)
+
+use crate::{
+    CounterValue, ExpositionFormat, MetricFamily, MetricNumber, MetricsExposition, OpenMetricsType,
+    OpenMetricsValue, ParseError, PrometheusCounterValue, PrometheusType, PrometheusValue, Sample,
+};
+
+/// Converts a Prometheus exposition into an OpenMetrics one. Untyped families become unknown,
+/// and counter families lose their `_total` suffix (which OpenMetrics adds back when rendering).
+/// Fails if that leaves two families with the same name
+/// ```
+/// use openmetrics_parser::{convert::exposition::prometheus_to_openmetrics, prometheus::parse_prometheus};
+///
+/// let exposition = parse_prometheus("# TYPE requests_total counter\nrequests_total 5 1500\n# TYPE up untyped\nup 1\n").unwrap();
+/// let converted = prometheus_to_openmetrics(&exposition).unwrap();
+/// assert_eq!(
+///     converted.to_openmetrics_string(),
+///     "# TYPE requests counter\nrequests_total 5 1.5\nup 1\n# EOF\n"
+/// );
+/// ```
+pub fn prometheus_to_openmetrics(
+    exposition: &MetricsExposition<PrometheusType, PrometheusValue>,
+) -> Result<MetricsExposition<OpenMetricsType, OpenMetricsValue>, ParseError> {
+    let mut converted = MetricsExposition::new();
+    for family in exposition.families.values() {
+        let (name, family_type) = match family.family_type {
+            PrometheusType::Counter => (
+                family
+                    .family_name
+                    .strip_suffix("_total")
+                    .unwrap_or(&family.family_name),
+                OpenMetricsType::Counter,
+            ),
+            PrometheusType::Gauge => (family.family_name.as_str(), OpenMetricsType::Gauge),
+            PrometheusType::Histogram => (family.family_name.as_str(), OpenMetricsType::Histogram),
+            PrometheusType::Summary => (family.family_name.as_str(), OpenMetricsType::Summary),
+            PrometheusType::Unknown | PrometheusType::Untyped => {
+                (family.family_name.as_str(), OpenMetricsType::Unknown)
+            }
+        };
+
+        let samples = family.iter_samples().map(|sample| {
+            let value = match &sample.value {
+                PrometheusValue::Untyped(n) | PrometheusValue::Unknown(n) => {
+                    OpenMetricsValue::Unknown(*n)
+                }
+                PrometheusValue::Gauge(n) => OpenMetricsValue::Gauge(*n),
+                PrometheusValue::Counter(c) => OpenMetricsValue::Counter(CounterValue {
+                    value: c.value,
+                    created: None,
+                    exemplar: c.exemplar.clone(),
+                }),
+                PrometheusValue::Histogram(h) => OpenMetricsValue::Histogram(h.clone()),
+                PrometheusValue::Summary(s) => OpenMetricsValue::Summary(s.clone()),
+            };
+
+            Sample::new(
+                sample.get_label_values().to_vec(),
+                family.sample_timestamp(sample, ExpositionFormat::OpenMetrics.timestamp_unit()),
+                value,
+            )
+            .with_exemplar(sample.exemplar.clone())
+        });
+
+        insert_family(
+            &mut converted,
+            MetricFamily::new(
+                name.to_owned(),
+                family.get_label_names().to_vec(),
+                family_type,
+                family.help.clone(),
+                String::new(),
+            )
+            .with_samples(samples)?,
+        )?;
+    }
+
+    Ok(converted)
+}
+
+/// Converts an OpenMetrics exposition into a Prometheus one. Counter families gain a `_total`
+/// suffix, and the types Prometheus doesn't have are converted the way the client libraries
+/// expose them: info families become `_info` gauges, and statesets become gauges. Gauge
+/// histograms have no Prometheus equivalent, so fail the conversion, as do name clashes.
+/// Units and `_created` series are dropped
+pub fn openmetrics_to_prometheus(
+    exposition: &MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+) -> Result<MetricsExposition<PrometheusType, PrometheusValue>, ParseError> {
+    let mut converted = MetricsExposition::new();
+    for family in exposition.families.values() {
+        let (name, family_type) = match family.family_type {
+            OpenMetricsType::Counter => (
+                format!("{}_total", family.family_name),
+                PrometheusType::Counter,
+            ),
+            OpenMetricsType::Gauge | OpenMetricsType::StateSet => {
+                (family.family_name.clone(), PrometheusType::Gauge)
+            }
+            OpenMetricsType::Info => (
+                format!("{}_info", family.family_name),
+                PrometheusType::Gauge,
+            ),
+            OpenMetricsType::Histogram => (family.family_name.clone(), PrometheusType::Histogram),
+            OpenMetricsType::Summary => (family.family_name.clone(), PrometheusType::Summary),
+            OpenMetricsType::Unknown => (family.family_name.clone(), PrometheusType::Untyped),
+            OpenMetricsType::GaugeHistogram => {
+                return Err(ParseError::InvalidMetric(format!(
+                    "{} is a gauge histogram, which Prometheus can't represent",
+                    family.family_name
+                )))
+            }
+        };
+
+        let samples = family.iter_samples().map(|sample| {
+            let value = match &sample.value {
+                OpenMetricsValue::Unknown(n) | OpenMetricsValue::Untyped(n) => {
+                    PrometheusValue::Untyped(*n)
+                }
+                OpenMetricsValue::Gauge(n) | OpenMetricsValue::StateSet(n) => {
+                    PrometheusValue::Gauge(*n)
+                }
+                OpenMetricsValue::Info => PrometheusValue::Gauge(MetricNumber::Int(1)),
+                OpenMetricsValue::Counter(c) => PrometheusValue::Counter(PrometheusCounterValue {
+                    value: c.value,
+                    exemplar: c.exemplar.clone(),
+                }),
+                OpenMetricsValue::Histogram(h) | OpenMetricsValue::GaugeHistogram(h) => {
+                    let mut h = h.clone();
+                    h.created = None;
+                    PrometheusValue::Histogram(h)
+                }
+                OpenMetricsValue::Summary(s) => {
+                    let mut s = s.clone();
+                    s.created = None;
+                    PrometheusValue::Summary(s)
+                }
+            };
+
+            Sample::new(
+                sample.get_label_values().to_vec(),
+                family.sample_timestamp(sample, ExpositionFormat::Prometheus.timestamp_unit()),
+                value,
+            )
+            .with_exemplar(sample.exemplar.clone())
+        });
+
+        insert_family(
+            &mut converted,
+            MetricFamily::new(
+                name,
+                family.get_label_names().to_vec(),
+                family_type,
+                family.help.clone(),
+                String::new(),
+            )
+            .with_samples(samples)?,
+        )?;
+    }
+
+    Ok(converted)
+}
+
+fn insert_family<TypeSet, ValueType>(
+    exposition: &mut MetricsExposition<TypeSet, ValueType>,
+    family: MetricFamily<TypeSet, ValueType>,
+) -> Result<(), ParseError> {
+    if exposition.families.contains_key(&family.family_name) {
+        return Err(ParseError::InvalidMetric(format!(
+            "Converting gives two families called {}",
+            family.family_name
+        )));
+    }
+
+    exposition
+        .families
+        .insert(family.family_name.clone(), family);
+
+    Ok(())
+}
+
+impl TryFrom<&MetricsExposition<PrometheusType, PrometheusValue>>
+    for MetricsExposition<OpenMetricsType, OpenMetricsValue>
+{
+    type Error = ParseError;
+
+    fn try_from(
+        exposition: &MetricsExposition<PrometheusType, PrometheusValue>,
+    ) -> Result<Self, Self::Error> {
+        prometheus_to_openmetrics(exposition)
+    }
+}
+
+impl TryFrom<&MetricsExposition<OpenMetricsType, OpenMetricsValue>>
+    for MetricsExposition<PrometheusType, PrometheusValue>
+{
+    type Error = ParseError;
+
+    fn try_from(
+        exposition: &MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+    ) -> Result<Self, Self::Error> {
+        openmetrics_to_prometheus(exposition)
+    }
+}
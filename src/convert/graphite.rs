@@ -0,0 +1,109 @@
+//! Renders expositions in the Graphite plaintext protocol, for feeding Carbon
+//! https://graphite.readthedocs.io/en/latest/feeding-carbon.html
+//!
+//! Each point is a line of the form `<path> <value> <timestamp>`, with the timestamp in seconds
+
+use std::fmt::Write;
+
+use crate::{
+    ExpositionTypeSet, MetricsExposition, RenderableMetricValue, SeriesValue, Timestamp,
+    METRIC_NAME_LABEL,
+};
+
+/// How labels are folded into the metric path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GraphiteLabels {
+    /// Labels become path segments of the form `name=value`, sorted by name
+    /// (e.g. `http_requests_total.code=200.method=get`)
+    #[default]
+    Path,
+
+    /// Labels become Graphite tags (e.g. `http_requests_total;code=200;method=get`), which needs
+    /// Graphite 1.1 or later
+    Tags,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GraphiteOptions {
+    pub labels: GraphiteLabels,
+
+    /// A path prepended to every metric name (e.g. `servers.web-1`), without a trailing dot
+    pub prefix: Option<String>,
+}
+
+impl GraphiteOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_labels(mut self, labels: GraphiteLabels) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_owned());
+        self
+    }
+}
+
+/// Replaces the characters that would split a path segment or tag (or the line itself) with `_`
+pub(crate) fn sanitize(s: &str, out: &mut String) {
+    for c in s.chars() {
+        out.push(match c {
+            '.' | ';' | '~' | ' ' | '\t' | '\n' => '_',
+            c => c,
+        });
+    }
+}
+
+/// Renders every point in the exposition as a Graphite line. Points without a timestamp are given
+/// `default_timestamp` (in milliseconds, like the rest of the converters). Graphite can't store
+/// NaN or infinite values, so those points are skipped, as are empty label values
+/// ```
+/// use openmetrics_parser::prometheus::parse_prometheus;
+/// use openmetrics_parser::convert::graphite::{render_graphite, GraphiteOptions};
+///
+/// let exposition = parse_prometheus("# TYPE temp gauge\ntemp{room=\"a\",floor=\"1\"} 21.5\n").unwrap();
+/// let lines = render_graphite(&exposition, &GraphiteOptions::new(), 1000.);
+/// assert_eq!(lines, "temp.floor=1.room=a 21.5 1\n");
+/// ```
+pub fn render_graphite<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    options: &GraphiteOptions,
+    default_timestamp: Timestamp,
+) -> String
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let mut lines = String::new();
+    exposition.for_each_point(default_timestamp, |_, mut labels, timestamp, point| {
+        if !point.value.is_finite() {
+            return;
+        }
+
+        labels.remove(METRIC_NAME_LABEL);
+        if let Some(prefix) = &options.prefix {
+            lines.push_str(prefix);
+            lines.push('.');
+        }
+
+        sanitize(&point.name, &mut lines);
+        let separator = match options.labels {
+            GraphiteLabels::Path => '.',
+            GraphiteLabels::Tags => ';',
+        };
+
+        for (name, value) in labels.iter().filter(|(_, v)| !v.is_empty()) {
+            lines.push(separator);
+            sanitize(name, &mut lines);
+            lines.push('=');
+            sanitize(value, &mut lines);
+        }
+
+        let _ = writeln!(lines, " {} {}", point.value, (timestamp / 1000.) as i64);
+    });
+
+    lines
+}
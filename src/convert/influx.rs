@@ -0,0 +1,178 @@
+//! Renders expositions in the InfluxDB line protocol, for ingestion through Telegraf or the
+//! InfluxDB write API
+//! https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/
+//!
+//! Each line is of the form `<measurement>[,<tag>=<value>...] <field>=<value>[,...] <timestamp>`,
+//! with the timestamp in nanoseconds
+
+use std::{collections::BTreeMap, fmt::Write};
+
+use crate::{
+    ExpositionTypeSet, MetricsExposition, RenderableMetricValue, SeriesValue, Timestamp,
+    METRIC_NAME_LABEL,
+};
+
+/// What each line's measurement is
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum InfluxMeasurement {
+    /// The family name is the measurement, and fields are named after what they hold: `value`
+    /// for single values, `sum` and `count` for histograms and summaries
+    #[default]
+    Family,
+
+    /// Every line has the given measurement, and fields are named after their series (e.g.
+    /// `http_requests_total`, `latency_sum`)
+    Fixed(String),
+}
+
+/// How histogram buckets and summary quantiles are flattened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InfluxFlattening {
+    /// All the values of a sample go on one line, with bucket bounds and quantiles in the field
+    /// names (e.g. `0.5=3,+Inf=5,sum=2.5,count=5`)
+    #[default]
+    Fields,
+
+    /// Every bucket and quantile gets its own line, with an `le` or `quantile` tag
+    Tags,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InfluxOptions {
+    pub measurement: InfluxMeasurement,
+    pub flattening: InfluxFlattening,
+}
+
+impl InfluxOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_measurement(mut self, measurement: InfluxMeasurement) -> Self {
+        self.measurement = measurement;
+        self
+    }
+
+    pub fn with_flattening(mut self, flattening: InfluxFlattening) -> Self {
+        self.flattening = flattening;
+        self
+    }
+}
+
+/// Escapes the characters that are significant in measurements, tags and field keys
+fn escape(s: &str, out: &mut String) {
+    for c in s.chars() {
+        if matches!(c, ',' | '=' | ' ' | '\\') {
+            out.push('\\');
+        }
+
+        out.push(c);
+    }
+}
+
+/// Names the field of a point when the family is the measurement
+fn family_field_name(family_name: &str, point_name: &str) -> &'static str {
+    match point_name.strip_prefix(family_name) {
+        Some("_sum") | Some("_gsum") => "sum",
+        Some("_count") | Some("_gcount") => "count",
+        _ => "value",
+    }
+}
+
+/// Renders every point in the exposition as line protocol. Points without a timestamp are given
+/// `default_timestamp` (in milliseconds, like the rest of the converters). InfluxDB can't store
+/// NaN or infinite values, so those fields are skipped, as are empty label values
+/// ```
+/// use openmetrics_parser::prometheus::parse_prometheus;
+/// use openmetrics_parser::convert::influx::{to_influx_line_protocol, InfluxOptions};
+///
+/// let exposition = parse_prometheus("# TYPE rpc summary\nrpc{quantile=\"0.5\"} 0.2\nrpc_sum 3.5\nrpc_count 10\n").unwrap();
+/// let lines = to_influx_line_protocol(&exposition, &InfluxOptions::new(), 1000.);
+/// assert_eq!(lines, "rpc 0.5=0.2,sum=3.5,count=10 1000000000\n");
+/// ```
+pub fn to_influx_line_protocol<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    options: &InfluxOptions,
+    default_timestamp: Timestamp,
+) -> String
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let mut lines = String::new();
+
+    // The series key (measurement and tags), timestamp and fields of the line being built. When
+    // flattening into fields, the points of a sample share a line
+    let mut line: Option<(String, i64, String)> = None;
+    exposition.for_each_point(default_timestamp, |family, mut labels, timestamp, point| {
+        if !point.value.is_finite() {
+            return;
+        }
+
+        labels.remove(METRIC_NAME_LABEL);
+        let mut field = String::new();
+        let bound = point.extra_labels.first().map(|(_, bound)| bound);
+        match (&options.measurement, options.flattening, bound) {
+            (InfluxMeasurement::Family, InfluxFlattening::Fields, Some(bound)) => {
+                escape(bound, &mut field)
+            }
+            (InfluxMeasurement::Fixed(_), InfluxFlattening::Fields, Some(bound)) => {
+                escape(&format!("{}_{}", point.name, bound), &mut field)
+            }
+            (InfluxMeasurement::Family, _, _) => {
+                field.push_str(family_field_name(&family.family_name, &point.name))
+            }
+            (InfluxMeasurement::Fixed(_), _, _) => escape(&point.name, &mut field),
+        }
+
+        let _ = write!(field, "={}", point.value);
+        if options.flattening == InfluxFlattening::Fields {
+            for (name, _) in point.extra_labels.iter() {
+                labels.remove(*name);
+            }
+        }
+
+        let mut key = String::new();
+        match &options.measurement {
+            InfluxMeasurement::Family => escape(&family.family_name, &mut key),
+            InfluxMeasurement::Fixed(measurement) => escape(measurement, &mut key),
+        }
+
+        write_tags(&labels, &mut key);
+        let timestamp = (timestamp * 1e6) as i64;
+        match &mut line {
+            Some((current_key, current_timestamp, fields))
+                if options.flattening == InfluxFlattening::Fields
+                    && *current_key == key
+                    && *current_timestamp == timestamp =>
+            {
+                fields.push(',');
+                fields.push_str(&field);
+            }
+            _ => {
+                if let Some(line) = line.replace((key, timestamp, field)) {
+                    write_line(&mut lines, line);
+                }
+            }
+        }
+    });
+
+    if let Some(line) = line {
+        write_line(&mut lines, line);
+    }
+
+    lines
+}
+
+fn write_line(lines: &mut String, (key, timestamp, fields): (String, i64, String)) {
+    let _ = writeln!(lines, "{} {} {}", key, fields, timestamp);
+}
+
+fn write_tags(labels: &BTreeMap<String, String>, out: &mut String) {
+    for (name, value) in labels.iter().filter(|(_, v)| !v.is_empty()) {
+        out.push(',');
+        escape(name, out);
+        out.push('=');
+        escape(value, out);
+    }
+}
@@ -0,0 +1,26 @@
+//! Converters between expositions and the formats used by other monitoring systems
+
+#[cfg(feature = "serde_json")]
+pub mod cloudwatch;
+pub mod columnar;
+#[cfg(feature = "serde_json")]
+pub mod datadog;
+pub mod exposition;
+pub mod graphite;
+pub mod influx;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+#[cfg(feature = "serde_json")]
+pub mod prometheus_api;
+pub mod recorder;
+#[cfg(feature = "remote-write")]
+pub mod remote_write;
+#[cfg(feature = "remote-write")]
+mod snappy;
+pub mod statsd;
+#[cfg(feature = "serde_json")]
+pub mod victoriametrics;
+pub mod wavefront;
+
+#[cfg(test)]
+mod tests;
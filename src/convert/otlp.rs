@@ -0,0 +1,580 @@
+//! Converts expositions into the OpenTelemetry (OTLP) metrics data model, so that scraped metrics
+//! can be pushed to an OTLP endpoint (e.g. the OpenTelemetry Collector)
+//! https://opentelemetry.io/docs/specs/otel/compatibility/prometheus_and_openmetrics/
+//!
+//! The types here mirror the `opentelemetry.proto.metrics.v1` protobuf messages (from
+//! `opentelemetry-proto`), restricted to what an exposition can hold: attributes are always
+//! strings, and there are no exponential histograms. `ExportMetricsServiceRequest` encodes to the
+//! same bytes as its protobuf counterpart, ready to be POSTed to `/v1/metrics`
+//!
+//! There's only partial support for the `opentelemetry_sdk` data model: no conversion into
+//! `ResourceMetrics` is provided, as this crate doesn't depend on the SDK. To push a scrape
+//! through an SDK exporter pipeline, the types map one to one onto
+//! `opentelemetry_sdk::metrics::data` (`Metric` onto `Metric`, `MetricData::Sum` onto `Sum<f64>`
+//! and so on), with each point's `start_time` and `time` as the `SystemTime`s the SDK uses, so
+//! the caller can convert them without serializing anything in between
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    internal::protobuf::Encoder, CanonicalValue, Exemplar as ExpositionExemplar, HistogramValue,
+    MetricNumber, MetricsExposition, OpenMetricsType, OpenMetricsValue, SummaryValue, Timestamp,
+};
+
+/// How the values of a Sum or Histogram relate to previous ones. Exposition values are always
+/// cumulative
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AggregationTemporality {
+    #[default]
+    Unspecified = 0,
+    Delta = 1,
+    Cumulative = 2,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: String,
+}
+
+impl KeyValue {
+    pub fn new(key: &str, value: &str) -> Self {
+        Self {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    /// The exemplar labels, other than the trace and span IDs
+    pub filtered_attributes: Vec<KeyValue>,
+    pub time_unix_nano: u64,
+    pub value: MetricNumber,
+    pub span_id: Vec<u8>,
+    pub trace_id: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NumberDataPoint {
+    pub attributes: Vec<KeyValue>,
+
+    /// When the series started (i.e. its `_created` time), or 0 if that isn't known
+    pub start_time_unix_nano: u64,
+    pub time_unix_nano: u64,
+    pub value: MetricNumber,
+    pub exemplars: Vec<Exemplar>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HistogramDataPoint {
+    pub attributes: Vec<KeyValue>,
+    pub start_time_unix_nano: u64,
+    pub time_unix_nano: u64,
+    pub count: u64,
+    pub sum: Option<f64>,
+
+    /// The count of each bucket on its own (unlike the cumulative `_bucket` series), with one more
+    /// count than there are bounds, for the implicit `+Inf` bucket
+    pub bucket_counts: Vec<u64>,
+    pub explicit_bounds: Vec<f64>,
+    pub exemplars: Vec<Exemplar>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ValueAtQuantile {
+    pub quantile: f64,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SummaryDataPoint {
+    pub attributes: Vec<KeyValue>,
+    pub start_time_unix_nano: u64,
+    pub time_unix_nano: u64,
+    pub count: u64,
+    pub sum: f64,
+    pub quantile_values: Vec<ValueAtQuantile>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricData {
+    Gauge(Vec<NumberDataPoint>),
+    Sum {
+        data_points: Vec<NumberDataPoint>,
+        aggregation_temporality: AggregationTemporality,
+        is_monotonic: bool,
+    },
+    Histogram {
+        data_points: Vec<HistogramDataPoint>,
+        aggregation_temporality: AggregationTemporality,
+    },
+    Summary(Vec<SummaryDataPoint>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub description: String,
+
+    /// The unit, in UCUM (e.g. `s` rather than `seconds`)
+    pub unit: String,
+    pub data: MetricData,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct InstrumentationScope {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ScopeMetrics {
+    pub scope: InstrumentationScope,
+    pub metrics: Vec<Metric>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ResourceMetrics {
+    pub resource_attributes: Vec<KeyValue>,
+    pub scope_metrics: Vec<ScopeMetrics>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ExportMetricsServiceRequest {
+    pub resource_metrics: Vec<ResourceMetrics>,
+}
+
+fn system_time(unix_nano: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(unix_nano)
+}
+
+fn start_time(start_time_unix_nano: u64) -> Option<SystemTime> {
+    (start_time_unix_nano != 0).then(|| system_time(start_time_unix_nano))
+}
+
+impl NumberDataPoint {
+    /// When the series started, if that's known
+    pub fn start_time(&self) -> Option<SystemTime> {
+        start_time(self.start_time_unix_nano)
+    }
+
+    pub fn time(&self) -> SystemTime {
+        system_time(self.time_unix_nano)
+    }
+}
+
+impl HistogramDataPoint {
+    /// When the series started, if that's known
+    pub fn start_time(&self) -> Option<SystemTime> {
+        start_time(self.start_time_unix_nano)
+    }
+
+    pub fn time(&self) -> SystemTime {
+        system_time(self.time_unix_nano)
+    }
+}
+
+impl SummaryDataPoint {
+    /// When the series started, if that's known
+    pub fn start_time(&self) -> Option<SystemTime> {
+        start_time(self.start_time_unix_nano)
+    }
+
+    pub fn time(&self) -> SystemTime {
+        system_time(self.time_unix_nano)
+    }
+}
+
+impl Exemplar {
+    pub fn time(&self) -> SystemTime {
+        system_time(self.time_unix_nano)
+    }
+}
+
+fn encode_attributes(encoder: &mut Encoder, field: u32, attributes: &[KeyValue]) {
+    for attribute in attributes {
+        encoder.put_message(field, |e| {
+            e.put_string(1, &attribute.key);
+            // An AnyValue holding a string_value
+            e.put_message(2, |e| e.put_string(1, &attribute.value));
+        });
+    }
+}
+
+fn encode_number(encoder: &mut Encoder, double_field: u32, int_field: u32, value: MetricNumber) {
+    match value {
+        MetricNumber::Float(f) => encoder.put_explicit_double(double_field, f),
+        MetricNumber::Int(i) => encoder.put_explicit_sfixed64(int_field, i),
+    }
+}
+
+fn encode_exemplars(encoder: &mut Encoder, field: u32, exemplars: &[Exemplar]) {
+    for exemplar in exemplars {
+        encoder.put_message(field, |e| {
+            encode_attributes(e, 7, &exemplar.filtered_attributes);
+            e.put_fixed64(2, exemplar.time_unix_nano);
+            encode_number(e, 3, 6, exemplar.value);
+            e.put_bytes(4, &exemplar.span_id);
+            e.put_bytes(5, &exemplar.trace_id);
+        });
+    }
+}
+
+fn encode_number_points(encoder: &mut Encoder, points: &[NumberDataPoint]) {
+    for point in points {
+        encoder.put_message(1, |e| {
+            encode_attributes(e, 7, &point.attributes);
+            e.put_fixed64(2, point.start_time_unix_nano);
+            e.put_fixed64(3, point.time_unix_nano);
+            encode_number(e, 4, 6, point.value);
+            encode_exemplars(e, 5, &point.exemplars);
+        });
+    }
+}
+
+fn encode_metric(encoder: &mut Encoder, metric: &Metric) {
+    encoder.put_string(1, &metric.name);
+    encoder.put_string(2, &metric.description);
+    encoder.put_string(3, &metric.unit);
+    match &metric.data {
+        MetricData::Gauge(points) => encoder.put_message(5, |e| encode_number_points(e, points)),
+        MetricData::Sum {
+            data_points,
+            aggregation_temporality,
+            is_monotonic,
+        } => encoder.put_message(7, |e| {
+            encode_number_points(e, data_points);
+            e.put_uint64(2, *aggregation_temporality as u64);
+            e.put_uint64(3, *is_monotonic as u64);
+        }),
+        MetricData::Histogram {
+            data_points,
+            aggregation_temporality,
+        } => encoder.put_message(9, |e| {
+            for point in data_points {
+                e.put_message(1, |e| {
+                    encode_attributes(e, 9, &point.attributes);
+                    e.put_fixed64(2, point.start_time_unix_nano);
+                    e.put_fixed64(3, point.time_unix_nano);
+                    e.put_fixed64(4, point.count);
+                    if let Some(sum) = point.sum {
+                        e.put_explicit_double(5, sum);
+                    }
+
+                    e.put_packed_fixed64(6, &point.bucket_counts);
+                    e.put_packed_double(7, &point.explicit_bounds);
+                    encode_exemplars(e, 8, &point.exemplars);
+                });
+            }
+
+            e.put_uint64(2, *aggregation_temporality as u64);
+        }),
+        MetricData::Summary(points) => encoder.put_message(11, |e| {
+            for point in points {
+                e.put_message(1, |e| {
+                    encode_attributes(e, 7, &point.attributes);
+                    e.put_fixed64(2, point.start_time_unix_nano);
+                    e.put_fixed64(3, point.time_unix_nano);
+                    e.put_fixed64(4, point.count);
+                    e.put_double(5, point.sum);
+                    for quantile in point.quantile_values.iter() {
+                        e.put_message(6, |e| {
+                            e.put_double(1, quantile.quantile);
+                            e.put_double(2, quantile.value);
+                        });
+                    }
+                });
+            }
+        }),
+    }
+}
+
+impl ExportMetricsServiceRequest {
+    /// Encodes the request as protobuf, for the `application/x-protobuf` OTLP/HTTP encoding
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        for resource in self.resource_metrics.iter() {
+            encoder.put_message(1, |e| {
+                e.put_message(1, |e| {
+                    encode_attributes(e, 1, &resource.resource_attributes)
+                });
+                for scope in resource.scope_metrics.iter() {
+                    e.put_message(2, |e| {
+                        e.put_message(1, |e| {
+                            e.put_string(1, &scope.scope.name);
+                            e.put_string(2, &scope.scope.version);
+                        });
+
+                        for metric in scope.metrics.iter() {
+                            e.put_message(2, |e| encode_metric(e, metric));
+                        }
+                    });
+                }
+            });
+        }
+
+        encoder.into_bytes()
+    }
+}
+
+/// Maps OpenMetrics units onto their UCUM equivalents, as OTLP expects. Units without a known
+/// mapping are passed through unchanged
+fn ucum_unit(unit: &str) -> &str {
+    match unit {
+        "seconds" => "s",
+        "milliseconds" => "ms",
+        "microseconds" => "us",
+        "nanoseconds" => "ns",
+        "bytes" => "By",
+        "bits" => "bit",
+        "ratio" => "1",
+        "percent" => "%",
+        "celsius" => "Cel",
+        "meters" => "m",
+        "grams" => "g",
+        "volts" => "V",
+        "amperes" => "A",
+        "joules" => "J",
+        "hertz" => "Hz",
+        other => other,
+    }
+}
+
+fn to_nanos(seconds: Timestamp) -> u64 {
+    (seconds * 1e9) as u64
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Converts an exemplar, lifting hex encoded `trace_id` and `span_id` labels into the dedicated
+/// fields. IDs that aren't valid hex (of the right length) are left as attributes
+fn convert_exemplar(exemplar: &ExpositionExemplar, time_unix_nano: u64) -> Exemplar {
+    let mut value = MetricNumber::Float(exemplar.id);
+    value.canonicalize();
+
+    let mut converted = Exemplar {
+        filtered_attributes: Vec::new(),
+        time_unix_nano: exemplar.timestamp.map(to_nanos).unwrap_or(time_unix_nano),
+        value,
+        span_id: Vec::new(),
+        trace_id: Vec::new(),
+    };
+
+    let mut labels: Vec<(&String, &String)> = exemplar.labels.iter().collect();
+    labels.sort();
+    for (name, value) in labels {
+        match (name.as_str(), decode_hex(value)) {
+            ("trace_id", Some(id)) if id.len() == 16 => converted.trace_id = id,
+            ("span_id", Some(id)) if id.len() == 8 => converted.span_id = id,
+            _ => converted
+                .filtered_attributes
+                .push(KeyValue::new(name, value)),
+        }
+    }
+
+    converted
+}
+
+fn histogram_point(
+    histogram: &HistogramValue,
+    attributes: Vec<KeyValue>,
+    time_unix_nano: u64,
+) -> HistogramDataPoint {
+    let mut point = HistogramDataPoint {
+        attributes,
+        start_time_unix_nano: histogram.created.map(to_nanos).unwrap_or_default(),
+        time_unix_nano,
+        count: histogram.count.unwrap_or_default(),
+        sum: histogram.sum.map(|s| s.as_f64()),
+        ..Default::default()
+    };
+
+    let mut previous = 0.;
+    for bucket in histogram.buckets.iter() {
+        let count = bucket.count.as_f64();
+        point.bucket_counts.push((count - previous).max(0.) as u64);
+        previous = count;
+        if bucket.upper_bound != f64::INFINITY {
+            point.explicit_bounds.push(bucket.upper_bound);
+        }
+
+        if let Some(exemplar) = &bucket.exemplar {
+            point
+                .exemplars
+                .push(convert_exemplar(exemplar, time_unix_nano));
+        }
+    }
+
+    if histogram.count.is_none() {
+        point.count = previous as u64;
+    }
+
+    point
+}
+
+fn summary_point(
+    summary: &SummaryValue,
+    attributes: Vec<KeyValue>,
+    time_unix_nano: u64,
+) -> SummaryDataPoint {
+    SummaryDataPoint {
+        attributes,
+        start_time_unix_nano: summary.created.map(to_nanos).unwrap_or_default(),
+        time_unix_nano,
+        count: summary.count.unwrap_or_default(),
+        sum: summary.sum.map(|s| s.as_f64()).unwrap_or_default(),
+        quantile_values: summary
+            .quantiles
+            .iter()
+            .map(|q| ValueAtQuantile {
+                quantile: q.quantile,
+                value: q.value.as_f64(),
+            })
+            .collect(),
+    }
+}
+
+fn non_monotonic_sum(data_points: Vec<NumberDataPoint>) -> MetricData {
+    MetricData::Sum {
+        data_points,
+        aggregation_temporality: AggregationTemporality::Cumulative,
+        is_monotonic: false,
+    }
+}
+
+/// Converts an OpenMetrics exposition into OTLP metrics, following the OpenTelemetry
+/// compatibility spec: counters become monotonic cumulative Sums, gauges and unknown families
+/// become Gauges, infos and statesets become non monotonic Sums, and histograms and summaries
+/// keep their type. Gauge histograms have no OTLP equivalent, so are left out.
+///
+/// `_created` times become the start time of their points, and exemplar `trace_id` and `span_id`
+/// labels are moved into the exemplar's IDs. Samples without a timestamp are given
+/// `default_timestamp` (in milliseconds, like the rest of the converters). Prometheus expositions
+/// can be converted to OpenMetrics first, with `exposition::prometheus_to_openmetrics`
+/// ```
+/// use openmetrics_parser::convert::otlp::{to_resource_metrics, MetricData};
+/// use openmetrics_parser::openmetrics::parse_openmetrics;
+///
+/// let exposition = parse_openmetrics("# TYPE requests counter\n# UNIT requests seconds\nrequests_total 5\n# EOF\n").unwrap();
+/// let resource = to_resource_metrics(&exposition, &[("service.name", "api")], 1000.);
+/// let metric = &resource.scope_metrics[0].metrics[0];
+/// assert_eq!((metric.name.as_str(), metric.unit.as_str()), ("requests", "s"));
+/// assert!(matches!(metric.data, MetricData::Sum { is_monotonic: true, .. }));
+/// ```
+pub fn to_resource_metrics(
+    exposition: &MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+    resource_attributes: &[(&str, &str)],
+    default_timestamp: Timestamp,
+) -> ResourceMetrics {
+    let mut metrics = Vec::new();
+    for family in exposition.families.values() {
+        let mut number_points = Vec::new();
+        let mut histogram_points = Vec::new();
+        let mut summary_points = Vec::new();
+        for sample in family.iter_samples() {
+            let mut attributes = Vec::new();
+            if let Ok(labelset) = sample.get_labelset() {
+                for (name, value) in labelset.iter() {
+                    attributes.push(KeyValue::new(name, value));
+                }
+            }
+
+            attributes.sort_by(|a, b| a.key.cmp(&b.key));
+            // OTLP times are unsigned, so anything before the epoch is clamped to it
+            let time_unix_nano = family
+                .sample_timestamp_nanos(sample)
+                .map(|nanos| nanos.max(0) as u64)
+                .unwrap_or(to_nanos(default_timestamp / 1000.));
+
+            let (value, created, exemplar) = match &sample.value {
+                OpenMetricsValue::Histogram(h) => {
+                    histogram_points.push(histogram_point(h, attributes, time_unix_nano));
+                    continue;
+                }
+                OpenMetricsValue::Summary(s) => {
+                    summary_points.push(summary_point(s, attributes, time_unix_nano));
+                    continue;
+                }
+                OpenMetricsValue::GaugeHistogram(_) => continue,
+                OpenMetricsValue::Counter(c) => (c.value, c.created, c.exemplar.as_ref()),
+                OpenMetricsValue::Info => (MetricNumber::Int(1), None, None),
+                OpenMetricsValue::Unknown(n)
+                | OpenMetricsValue::Untyped(n)
+                | OpenMetricsValue::Gauge(n)
+                | OpenMetricsValue::StateSet(n) => (*n, None, None),
+            };
+
+            number_points.push(NumberDataPoint {
+                attributes,
+                start_time_unix_nano: created.map(to_nanos).unwrap_or_default(),
+                time_unix_nano,
+                value,
+                exemplars: exemplar
+                    .or(sample.exemplar.as_ref())
+                    .map(|e| vec![convert_exemplar(e, time_unix_nano)])
+                    .unwrap_or_default(),
+            });
+        }
+
+        let (name, data) = match family.family_type {
+            OpenMetricsType::GaugeHistogram => continue,
+            OpenMetricsType::Histogram => (
+                family.family_name.clone(),
+                MetricData::Histogram {
+                    data_points: histogram_points,
+                    aggregation_temporality: AggregationTemporality::Cumulative,
+                },
+            ),
+            OpenMetricsType::Summary => (
+                family.family_name.clone(),
+                MetricData::Summary(summary_points),
+            ),
+            OpenMetricsType::Counter => (
+                family.family_name.clone(),
+                MetricData::Sum {
+                    data_points: number_points,
+                    aggregation_temporality: AggregationTemporality::Cumulative,
+                    is_monotonic: true,
+                },
+            ),
+            OpenMetricsType::Info => (
+                format!("{}_info", family.family_name),
+                non_monotonic_sum(number_points),
+            ),
+            OpenMetricsType::StateSet => {
+                (family.family_name.clone(), non_monotonic_sum(number_points))
+            }
+            OpenMetricsType::Gauge | OpenMetricsType::Unknown => {
+                (family.family_name.clone(), MetricData::Gauge(number_points))
+            }
+        };
+
+        metrics.push(Metric {
+            name,
+            description: family.help.clone(),
+            unit: ucum_unit(&family.unit).to_owned(),
+            data,
+        });
+    }
+
+    ResourceMetrics {
+        resource_attributes: resource_attributes
+            .iter()
+            .map(|(k, v)| KeyValue::new(k, v))
+            .collect(),
+        scope_metrics: vec![ScopeMetrics {
+            scope: InstrumentationScope {
+                name: env!("CARGO_PKG_NAME").to_owned(),
+                version: env!("CARGO_PKG_VERSION").to_owned(),
+            },
+            metrics,
+        }],
+    }
+}
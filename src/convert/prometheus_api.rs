@@ -0,0 +1,195 @@
+//! Imports the results of Prometheus HTTP API queries (`/api/v1/query` and `/api/v1/query_range`),
+//! and renders expositions in the shape of those responses
+//! https://prometheus.io/docs/prometheus/latest/querying/api/#expression-query-result-formats
+
+use std::fmt;
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    format_bound, ExpositionTypeSet, MetricsExposition, ParseError, RenderableMetricValue,
+    SeriesValue, TimeSeries, Timestamp,
+};
+
+/// Parses the JSON body of an `/api/v1/query` (vector) or `/api/v1/query_range` (matrix) response
+/// into one TimeSeries per result. Timestamps are converted from seconds into milliseconds
+/// ```
+/// use openmetrics_parser::convert::prometheus_api::parse_query_response;
+///
+/// let body = r#"{"status":"success","data":{"resultType":"vector","result":[
+///     {"metric":{"__name__":"up","job":"node"},"value":[1435781451.781,"1"]}
+/// ]}}"#;
+///
+/// let series = parse_query_response(body).unwrap();
+/// assert_eq!(series[0].name(), Some("up"));
+/// assert_eq!(series[0].samples, vec![(1435781451781.0, 1.0)]);
+/// ```
+pub fn parse_query_response(body: &str) -> Result<Vec<TimeSeries>, ParseError> {
+    let response: Value = serde_json::from_str(body).map_err(|e| invalid(e.to_string()))?;
+
+    match response.get("status").and_then(Value::as_str) {
+        Some("success") => {}
+        _ => {
+            let error = response
+                .get("error")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown error");
+            return Err(invalid(format!("the query failed: {}", error)));
+        }
+    }
+
+    let data = response
+        .get("data")
+        .ok_or_else(|| invalid("no `data`".to_owned()))?;
+
+    let result_type = data.get("resultType").and_then(Value::as_str);
+    let results = data
+        .get("result")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid("no `result`".to_owned()))?;
+
+    let mut series = Vec::with_capacity(results.len());
+    for result in results {
+        let mut s = TimeSeries::default();
+        if let Some(metric) = result.get("metric").and_then(Value::as_object) {
+            for (name, value) in metric {
+                let value = value
+                    .as_str()
+                    .ok_or_else(|| invalid(format!("label `{}` must be a string", name)))?;
+                s.labels.insert(name.clone(), value.to_owned());
+            }
+        }
+
+        match result_type {
+            Some("vector") => {
+                let point = result
+                    .get("value")
+                    .ok_or_else(|| invalid("vector result has no `value`".to_owned()))?;
+                s.samples.push(parse_point(point)?);
+            }
+            Some("matrix") => {
+                let points = result
+                    .get("values")
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| invalid("matrix result has no `values`".to_owned()))?;
+                for point in points {
+                    s.samples.push(parse_point(point)?);
+                }
+            }
+            other => return Err(invalid(format!("unsupported result type: {:?}", other))),
+        }
+
+        series.push(s);
+    }
+
+    Ok(series)
+}
+
+fn invalid(reason: String) -> ParseError {
+    ParseError::Decode {
+        format: "query response",
+        reason,
+    }
+}
+
+/// Parses a `[<unix seconds>, "<value>"]` pair
+fn parse_point(point: &Value) -> Result<(Timestamp, f64), ParseError> {
+    let invalid_point = || invalid(format!("invalid sample: {}", point));
+
+    let (timestamp, value) = match point.as_array().map(|p| p.as_slice()) {
+        Some([timestamp, value]) => (timestamp, value),
+        _ => return Err(invalid_point()),
+    };
+
+    let timestamp = timestamp.as_f64().ok_or_else(invalid_point)?;
+    let value = value
+        .as_str()
+        .and_then(|v| v.parse::<f64>().ok())
+        .ok_or_else(invalid_point)?;
+
+    Ok(((timestamp * 1000.0).round(), value))
+}
+
+/// Renders every point in the exposition as an `/api/v1/query` vector result, as if each series had
+/// been queried by name at its own timestamp. Points without a timestamp are given
+/// `default_timestamp` (in milliseconds, like the rest of the converters)
+/// ```
+/// use openmetrics_parser::convert::prometheus_api::render_query_response;
+/// use openmetrics_parser::prometheus::parse_prometheus;
+///
+/// let exposition = parse_prometheus("# TYPE up gauge\nup{job=\"node\"} 1 1435781451781\n").unwrap();
+/// assert_eq!(
+///     render_query_response(&exposition, 0.),
+///     r#"{"data":{"result":[{"metric":{"__name__":"up","job":"node"},"value":[1435781451.781,"1"]}],"resultType":"vector"},"status":"success"}"#
+/// );
+/// ```
+pub fn render_query_response<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    default_timestamp: Timestamp,
+) -> String
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let mut result = Vec::new();
+    exposition.for_each_point(default_timestamp, |_, labels, timestamp, point| {
+        // Values are strings in the API, so NaN and infinities survive
+        result.push(json!({
+            "metric": labels,
+            "value": [timestamp / 1000., format_bound(point.value)],
+        }));
+    });
+
+    json!({
+        "status": "success",
+        "data": {"resultType": "vector", "result": result},
+    })
+    .to_string()
+}
+
+/// Renders the metadata of every family in the exposition as an `/api/v1/targets/metadata`
+/// response, as if they were all scraped from the target with the given labels
+/// ```
+/// use openmetrics_parser::convert::prometheus_api::render_targets_metadata;
+/// use openmetrics_parser::prometheus::parse_prometheus;
+///
+/// let exposition = parse_prometheus("# HELP up Whether the target is up\n# TYPE up gauge\nup 1\n").unwrap();
+/// assert_eq!(
+///     render_targets_metadata(&exposition, &[("job", "node")]),
+///     r#"{"data":[{"help":"Whether the target is up","metric":"up","target":{"job":"node"},"type":"gauge","unit":""}],"status":"success"}"#
+/// );
+/// ```
+pub fn render_targets_metadata<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    target_labels: &[(&str, &str)],
+) -> String
+where
+    TypeSet: fmt::Display,
+{
+    let target: Map<String, Value> = target_labels
+        .iter()
+        .map(|(k, v)| (k.to_string(), Value::from(*v)))
+        .collect();
+
+    let data: Vec<Value> = exposition
+        .families
+        .values()
+        .map(|family| {
+            // The API calls untyped metrics unknown
+            let family_type = match family.family_type.to_string().as_str() {
+                "untyped" => String::from("unknown"),
+                other => other.to_owned(),
+            };
+
+            json!({
+                "target": target,
+                "metric": family.family_name,
+                "type": family_type,
+                "help": family.help,
+                "unit": family.unit,
+            })
+        })
+        .collect();
+
+    json!({"status": "success", "data": data}).to_string()
+}
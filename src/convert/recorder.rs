@@ -0,0 +1,105 @@
+//! Replays expositions as metric events, to feed metrics scraped from elsewhere into an
+//! application's own metrics pipeline (e.g. a `metrics::Recorder` from the metrics-rs facade)
+//!
+//! The metrics-rs bridge is partial: there's no feature that replays into a `metrics::Recorder`
+//! directly, as this crate doesn't depend on `metrics`. Instead, implement `MetricsRecorder` on a
+//! thin adapter over the pipeline; for metrics-rs, each event maps onto registering the key with
+//! the recorder and calling `Counter::absolute`, `Gauge::set` or `Histogram::record_many` on the
+//! handle it returns
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::{
+    ExpositionTypeSet, MetricsExposition, RenderableMetricValue, SeriesValue, METRIC_NAME_LABEL,
+};
+
+/// Receives the events replayed from an exposition. Labels are sorted by name
+pub trait MetricsRecorder {
+    /// Sets a counter to its (cumulative) total
+    fn counter(&mut self, name: &str, labels: &[(&str, &str)], value: f64);
+
+    fn gauge(&mut self, name: &str, labels: &[(&str, &str)], value: f64);
+
+    /// Records `count` observations of `value` in a histogram
+    fn histogram(&mut self, name: &str, labels: &[(&str, &str)], value: f64, count: u64);
+}
+
+/// Replays every point in the exposition into `recorder`. Counters (along with summary `_sum`s
+/// and `_count`s) become counter events, gauges, unknown values and summary quantiles become
+/// gauge events.
+///
+/// Histograms are replayed as observations of each bucket's upper bound, recorded under the
+/// family name, so they land in the same buckets if the recorder uses the same bounds (the
+/// observations themselves are gone, so their `_sum` is skipped, as is the `+Inf` bucket, which
+/// has no bound to record)
+/// ```
+/// use openmetrics_parser::convert::recorder::{replay, MetricsRecorder};
+/// use openmetrics_parser::prometheus::parse_prometheus;
+///
+/// #[derive(Default)]
+/// struct Events(Vec<String>);
+///
+/// impl MetricsRecorder for Events {
+///     fn counter(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+///         self.0.push(format!("counter {} {:?} {}", name, labels, value));
+///     }
+///
+///     fn gauge(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+///         self.0.push(format!("gauge {} {:?} {}", name, labels, value));
+///     }
+///
+///     fn histogram(&mut self, name: &str, labels: &[(&str, &str)], value: f64, count: u64) {
+///         self.0.push(format!("histogram {} {:?} {}x{}", name, labels, value, count));
+///     }
+/// }
+///
+/// let exposition = parse_prometheus("# TYPE latency histogram\nlatency_bucket{le=\"0.1\"} 2\nlatency_bucket{le=\"1\"} 5\nlatency_bucket{le=\"+Inf\"} 5\nlatency_sum 2.1\nlatency_count 5\n").unwrap();
+/// let mut events = Events::default();
+/// replay(&exposition, &mut events);
+/// assert_eq!(events.0, vec!["histogram latency [] 0.1x2", "histogram latency [] 1x3"]);
+/// ```
+pub fn replay<TypeSet, ValueType, R>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    recorder: &mut R,
+) where
+    TypeSet: Clone + ExpositionTypeSet + Display,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+    R: MetricsRecorder + ?Sized,
+{
+    // The series (the labels without `le`, but with `__name__`) and cumulative count of the last
+    // bucket, to work out how many observations each bucket holds on its own
+    let mut previous_bucket: Option<(BTreeMap<String, String>, f64)> = None;
+    exposition.for_each_point(0., |family, mut labels, _, point| {
+        let family_type = family.family_type.to_string();
+        let histogram = family_type == "histogram" || family_type == "gaugehistogram";
+        let bound = labels.remove("le");
+        let series = labels.clone();
+        labels.remove(METRIC_NAME_LABEL);
+        let labels: Vec<(&str, &str)> = labels
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+
+        match bound {
+            Some(bound) => {
+                let below = match &previous_bucket {
+                    Some((previous, count)) if *previous == series => *count,
+                    _ => 0.,
+                };
+
+                previous_bucket = Some((series, point.value));
+                let count = point.value - below;
+                match bound.parse::<f64>() {
+                    Ok(bound) if bound.is_finite() && count > 0. => {
+                        recorder.histogram(&family.family_name, &labels, bound, count as u64)
+                    }
+                    _ => {}
+                }
+            }
+            // The buckets already hold every observation
+            None if histogram => {}
+            None if point.monotonic => recorder.counter(&point.name, &labels, point.value),
+            None => recorder.gauge(&point.name, &labels, point.value),
+        }
+    });
+}
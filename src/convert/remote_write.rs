@@ -0,0 +1,734 @@
+//! Converts expositions into Prometheus Remote Write requests, so that scraped metrics can be
+//! pushed straight into anything that accepts remote write (Mimir, Thanos, VictoriaMetrics etc)
+//! https://prometheus.io/docs/specs/remote_write_spec/
+//!
+//! The types here mirror the `prometheus.WriteRequest` protobuf messages (from `remote.proto` and
+//! `types.proto`), and encode to (and decode from) the same bytes. Requests can also be turned
+//! back into an exposition, for building remote write receivers on top of this crate's model
+//!
+//! Native histograms are sent as histogram samples, in a series of their own named after the
+//! family, alongside the series of any classic buckets
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    convert::snappy,
+    internal::protobuf::{encode_native_histogram, Decoder, Encoder, NativeHistogramDecoder},
+    CanonicalValue, ExpositionTypeSet, HistogramBucket, HistogramValue, MetricFamily, MetricNumber,
+    MetricsExposition, NativeHistogram, OrderedMap, ParseError, PrometheusCounterValue,
+    PrometheusType, PrometheusValue, Quantile, RenderableMetricValue, SeriesValue, SummaryValue,
+    Timestamp, TimestampUnit, METRIC_NAME_LABEL,
+};
+
+/// The type of a metric family, as it's sent in remote write metadata
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetricType {
+    #[default]
+    Unknown = 0,
+    Counter = 1,
+    Gauge = 2,
+    Histogram = 3,
+    GaugeHistogram = 4,
+    Summary = 5,
+    Info = 6,
+    StateSet = 7,
+}
+
+impl MetricType {
+    /// Maps the type name used in `# TYPE` lines onto a MetricType. Untyped (and anything else
+    /// unrecognised) is Unknown
+    pub fn from_type_name(name: &str) -> Self {
+        match name {
+            "counter" => MetricType::Counter,
+            "gauge" => MetricType::Gauge,
+            "histogram" => MetricType::Histogram,
+            "gaugehistogram" => MetricType::GaugeHistogram,
+            "summary" => MetricType::Summary,
+            "info" => MetricType::Info,
+            "stateset" => MetricType::StateSet,
+            _ => MetricType::Unknown,
+        }
+    }
+
+    fn from_value(value: u64) -> Self {
+        match value {
+            1 => MetricType::Counter,
+            2 => MetricType::Gauge,
+            3 => MetricType::Histogram,
+            4 => MetricType::GaugeHistogram,
+            5 => MetricType::Summary,
+            6 => MetricType::Info,
+            7 => MetricType::StateSet,
+            _ => MetricType::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Label {
+    pub name: String,
+    pub value: String,
+}
+
+impl Label {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Sample {
+    pub value: f64,
+
+    /// Milliseconds since the epoch
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Exemplar {
+    pub labels: Vec<Label>,
+    pub value: f64,
+
+    /// Milliseconds since the epoch
+    pub timestamp: i64,
+}
+
+/// Whether a native histogram may have been reset since the previous sample of its series
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResetHint {
+    #[default]
+    Unknown = 0,
+    Yes = 1,
+    No = 2,
+
+    /// The histogram is a gauge histogram, which can go down without being reset
+    Gauge = 3,
+}
+
+impl ResetHint {
+    fn from_value(value: u64) -> Self {
+        match value {
+            1 => ResetHint::Yes,
+            2 => ResetHint::No,
+            3 => ResetHint::Gauge,
+            _ => ResetHint::Unknown,
+        }
+    }
+}
+
+/// A native histogram sample
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Histogram {
+    pub count: u64,
+    pub sum: f64,
+    pub native: NativeHistogram,
+    pub reset_hint: ResetHint,
+
+    /// Milliseconds since the epoch
+    pub timestamp: i64,
+}
+
+/// A single series. Its labels include `__name__`, and are sorted by name
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TimeSeries {
+    pub labels: Vec<Label>,
+    pub samples: Vec<Sample>,
+    pub exemplars: Vec<Exemplar>,
+    pub histograms: Vec<Histogram>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MetricMetadata {
+    pub metric_type: MetricType,
+    pub metric_family_name: String,
+    pub help: String,
+    pub unit: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WriteRequest {
+    pub timeseries: Vec<TimeSeries>,
+    pub metadata: Vec<MetricMetadata>,
+}
+
+fn encode_labels(encoder: &mut Encoder, field: u32, labels: &[Label]) {
+    for label in labels {
+        encoder.put_message(field, |e| {
+            e.put_string(1, &label.name);
+            e.put_string(2, &label.value);
+        });
+    }
+}
+
+impl WriteRequest {
+    /// Encodes the request as protobuf. Remote write bodies are snappy compressed on top of this
+    /// (with the block format, not the framed one), which is left to the caller
+    pub fn encode_to_vec(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        for series in self.timeseries.iter() {
+            encoder.put_message(1, |e| {
+                encode_labels(e, 1, &series.labels);
+                for sample in series.samples.iter() {
+                    e.put_message(2, |e| {
+                        e.put_double(1, sample.value);
+                        e.put_int64(2, sample.timestamp);
+                    });
+                }
+
+                for exemplar in series.exemplars.iter() {
+                    e.put_message(3, |e| {
+                        encode_labels(e, 1, &exemplar.labels);
+                        e.put_double(2, exemplar.value);
+                        e.put_int64(3, exemplar.timestamp);
+                    });
+                }
+
+                for histogram in series.histograms.iter() {
+                    e.put_message(4, |e| {
+                        if histogram.native.is_float() {
+                            e.put_double(2, histogram.count as f64);
+                        } else {
+                            e.put_uint64(1, histogram.count);
+                        }
+
+                        e.put_double(3, histogram.sum);
+                        encode_native_histogram(e, 4, &histogram.native);
+                        e.put_uint64(14, histogram.reset_hint as u64);
+                        e.put_int64(15, histogram.timestamp);
+                    });
+                }
+            });
+        }
+
+        for metadata in self.metadata.iter() {
+            encoder.put_message(3, |e| {
+                e.put_uint64(1, metadata.metric_type as u64);
+                e.put_string(2, &metadata.metric_family_name);
+                e.put_string(4, &metadata.help);
+                e.put_string(5, &metadata.unit);
+            });
+        }
+
+        encoder.into_bytes()
+    }
+
+    /// Decodes a protobuf encoded (but not compressed) request. Fields this crate doesn't model
+    /// (e.g. the exemplars of native histograms) are skipped
+    pub fn decode(bytes: &[u8]) -> Result<Self, ParseError> {
+        let mut request = WriteRequest::default();
+        let mut decoder = Decoder::new(bytes);
+        while let Some((field, value)) = decoder.next_field()? {
+            match field {
+                1 => request.timeseries.push(decode_series(value.as_message()?)?),
+                3 => request.metadata.push(decode_metadata(value.as_message()?)?),
+                _ => {}
+            }
+        }
+
+        Ok(request)
+    }
+
+    /// Decodes a request from a remote write body, which is snappy compressed protobuf
+    pub fn decode_snappy(body: &[u8]) -> Result<Self, ParseError> {
+        Self::decode(&snappy::decompress(body)?)
+    }
+}
+
+fn decode_label(mut decoder: Decoder) -> Result<Label, ParseError> {
+    let mut label = Label::default();
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => label.name = value.as_str()?.to_owned(),
+            2 => label.value = value.as_str()?.to_owned(),
+            _ => {}
+        }
+    }
+
+    Ok(label)
+}
+
+fn decode_series(mut decoder: Decoder) -> Result<TimeSeries, ParseError> {
+    let mut series = TimeSeries::default();
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => series.labels.push(decode_label(value.as_message()?)?),
+            2 => {
+                let mut sample = Sample::default();
+                let mut decoder = value.as_message()?;
+                while let Some((field, value)) = decoder.next_field()? {
+                    match field {
+                        1 => sample.value = value.as_f64()?,
+                        2 => sample.timestamp = value.as_i64()?,
+                        _ => {}
+                    }
+                }
+
+                series.samples.push(sample);
+            }
+            3 => {
+                let mut exemplar = Exemplar::default();
+                let mut decoder = value.as_message()?;
+                while let Some((field, value)) = decoder.next_field()? {
+                    match field {
+                        1 => exemplar.labels.push(decode_label(value.as_message()?)?),
+                        2 => exemplar.value = value.as_f64()?,
+                        3 => exemplar.timestamp = value.as_i64()?,
+                        _ => {}
+                    }
+                }
+
+                series.exemplars.push(exemplar);
+            }
+            4 => series
+                .histograms
+                .push(decode_histogram(value.as_message()?)?),
+            _ => {}
+        }
+    }
+
+    Ok(series)
+}
+
+fn decode_histogram(mut decoder: Decoder) -> Result<Histogram, ParseError> {
+    let mut histogram = Histogram::default();
+    let mut native = NativeHistogramDecoder::new(4);
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => histogram.count = value.as_u64()?,
+            2 => histogram.count = value.as_f64()? as u64,
+            3 => histogram.sum = value.as_f64()?,
+            14 => histogram.reset_hint = ResetHint::from_value(value.as_u64()?),
+            15 => histogram.timestamp = value.as_i64()?,
+            _ => {
+                native.read_field(field, value)?;
+            }
+        }
+    }
+
+    histogram.native = native.finish()?.unwrap_or_default();
+    Ok(histogram)
+}
+
+fn decode_metadata(mut decoder: Decoder) -> Result<MetricMetadata, ParseError> {
+    let mut metadata = MetricMetadata::default();
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => metadata.metric_type = MetricType::from_value(value.as_u64()?),
+            2 => metadata.metric_family_name = value.as_str()?.to_owned(),
+            4 => metadata.help = value.as_str()?.to_owned(),
+            5 => metadata.unit = value.as_str()?.to_owned(),
+            _ => {}
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Flattens the exposition into a remote write request, with one series per point (so a histogram
+/// becomes its `_bucket`, `_sum` and `_count` series), plus the metadata of each family. Native
+/// histograms add a series of histogram samples named after their family.
+///
+/// `external_labels` are added to every series that doesn't already have a label with the same
+/// name, as Prometheus does. Points without a timestamp are given `default_timestamp` (in
+/// milliseconds, like the rest of the converters)
+/// ```
+/// use openmetrics_parser::convert::remote_write::{to_remote_write_request, Label, Sample};
+/// use openmetrics_parser::prometheus::parse_prometheus;
+///
+/// let exposition = parse_prometheus("# TYPE up gauge\nup{job=\"node\"} 1\n").unwrap();
+/// let request = to_remote_write_request(&exposition, &[("cluster", "a")], 1000.);
+/// assert_eq!(
+///     request.timeseries[0].labels,
+///     vec![Label::new("__name__", "up"), Label::new("cluster", "a"), Label::new("job", "node")]
+/// );
+/// assert_eq!(request.timeseries[0].samples, vec![Sample { value: 1., timestamp: 1000 }]);
+/// ```
+pub fn to_remote_write_request<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    external_labels: &[(&str, &str)],
+    default_timestamp: Timestamp,
+) -> WriteRequest
+where
+    TypeSet: Clone + ExpositionTypeSet + std::fmt::Display,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let mut request = WriteRequest::default();
+    exposition.for_each_point(default_timestamp, |_, mut labels, timestamp, point| {
+        for (name, value) in external_labels {
+            labels
+                .entry(name.to_string())
+                .or_insert_with(|| value.to_string());
+        }
+
+        let exemplars = point
+            .exemplar
+            .iter()
+            .map(|exemplar| {
+                let mut labels: Vec<Label> = exemplar
+                    .labels
+                    .iter()
+                    .map(|(k, v)| Label::new(k, v))
+                    .collect();
+                labels.sort_by(|a, b| a.name.cmp(&b.name));
+
+                // Exemplar timestamps are in seconds in both text formats
+                Exemplar {
+                    labels,
+                    value: exemplar.id,
+                    timestamp: exemplar
+                        .timestamp
+                        .map(|t| {
+                            TimestampUnit::Seconds.convert(t, TimestampUnit::Milliseconds) as i64
+                        })
+                        .unwrap_or(timestamp as i64),
+                }
+            })
+            .collect();
+
+        request.timeseries.push(TimeSeries {
+            labels: labels.iter().map(|(k, v)| Label::new(k, v)).collect(),
+            samples: vec![Sample {
+                value: point.value,
+                timestamp: timestamp as i64,
+            }],
+            exemplars,
+            ..Default::default()
+        });
+    });
+
+    for family in exposition.families.values() {
+        let reset_hint = match family.family_type.to_string().as_str() {
+            "gaugehistogram" => ResetHint::Gauge,
+            _ => ResetHint::Unknown,
+        };
+
+        for sample in family.iter_samples() {
+            let histogram = match sample.value.native_histogram() {
+                Some(histogram) => histogram,
+                None => continue,
+            };
+
+            let mut labels = BTreeMap::new();
+            labels.insert(METRIC_NAME_LABEL.to_owned(), family.family_name.clone());
+            if let Ok(labelset) = sample.get_labelset() {
+                for (name, value) in labelset.iter() {
+                    labels.insert(name.clone(), value.clone());
+                }
+            }
+
+            for (name, value) in external_labels {
+                labels
+                    .entry(name.to_string())
+                    .or_insert_with(|| value.to_string());
+            }
+
+            let timestamp = MetricsExposition::<TypeSet, ValueType>::timestamp_millis(
+                sample,
+                default_timestamp,
+            );
+            request.timeseries.push(TimeSeries {
+                labels: labels.iter().map(|(k, v)| Label::new(k, v)).collect(),
+                histograms: vec![Histogram {
+                    count: histogram.count.unwrap_or_default(),
+                    sum: histogram.sum.map(|s| s.as_f64()).unwrap_or_default(),
+                    native: histogram.native.clone().unwrap_or_default(),
+                    reset_hint,
+                    timestamp: timestamp as i64,
+                }],
+                ..Default::default()
+            });
+        }
+    }
+
+    request.metadata = exposition
+        .families
+        .values()
+        .map(|family| MetricMetadata {
+            metric_type: MetricType::from_type_name(&family.family_type.to_string()),
+            metric_family_name: family.family_name.clone(),
+            help: family.help.clone(),
+            unit: family.unit.clone(),
+        })
+        .collect();
+
+    request
+}
+
+fn number(value: f64) -> MetricNumber {
+    let mut number = MetricNumber::Float(value);
+    number.canonicalize();
+    number
+}
+
+/// What part of a family's value a series holds
+enum Role<'a> {
+    Value,
+    Native(&'a Histogram),
+    Bucket(f64),
+    Quantile(f64),
+    Sum,
+    Count,
+}
+
+/// The latest value of a single labelset of a family, as it's put back together
+struct Builder {
+    label_values: Vec<String>,
+    timestamp: Timestamp,
+    value: PrometheusValue,
+}
+
+/// Works out which family a series belongs to (e.g. `foo_bucket` is part of the histogram `foo`),
+/// using the request metadata where there is some, and the `le`/`quantile` labels otherwise
+fn classify(
+    name: &str,
+    labels: &mut BTreeMap<String, String>,
+    histograms: &[String],
+    summaries: &[String],
+) -> Result<(String, PrometheusType, Role<'static>), ParseError> {
+    let bound = |labels: &mut BTreeMap<String, String>, label: &str| -> Result<f64, ParseError> {
+        let text = labels.remove(label).unwrap_or_default();
+        text.parse().map_err(|_| {
+            ParseError::InvalidMetric(format!("Invalid `{}` label on {}: {}", label, name, text))
+        })
+    };
+
+    let base = |suffix: &str| name.strip_suffix(suffix).map(|base| base.to_owned());
+    if let Some(base) = base("_bucket").filter(|b| histograms.contains(b)) {
+        let bound = bound(labels, "le")?;
+        return Ok((base, PrometheusType::Histogram, Role::Bucket(bound)));
+    }
+
+    if summaries.iter().any(|s| s == name) {
+        let bound = bound(labels, "quantile")?;
+        return Ok((
+            name.to_owned(),
+            PrometheusType::Summary,
+            Role::Quantile(bound),
+        ));
+    }
+
+    for (suffix, role) in [("_sum", Role::Sum), ("_count", Role::Count)] {
+        if let Some(base) = base(suffix) {
+            if histograms.contains(&base) {
+                return Ok((base, PrometheusType::Histogram, role));
+            } else if summaries.contains(&base) {
+                return Ok((base, PrometheusType::Summary, role));
+            }
+        }
+    }
+
+    Ok((name.to_owned(), PrometheusType::Untyped, Role::Value))
+}
+
+/// Rebuilds a Prometheus exposition out of a remote write request, using the latest sample of each
+/// series. Series are grouped back into families by their metric name, with `_bucket`, `_sum`
+/// and `_count` series folded into the histogram or summary they came from. Families are typed
+/// from the request metadata, falling back to spotting the `le` and `quantile` labels. Series of
+/// native histogram samples fill in the `native` buckets of the histogram named after them
+/// ```
+/// use openmetrics_parser::convert::remote_write::{from_remote_write_request, to_remote_write_request};
+/// use openmetrics_parser::prometheus::parse_prometheus;
+///
+/// let text = "# TYPE rpc summary\nrpc{quantile=\"0.5\"} 0.2 1000\nrpc_sum 3.5 1000\nrpc_count 10 1000\n";
+/// let request = to_remote_write_request(&parse_prometheus(text).unwrap(), &[], 0.);
+/// let exposition = from_remote_write_request(&request).unwrap();
+/// assert_eq!(exposition.to_string(), text);
+/// ```
+pub fn from_remote_write_request(
+    request: &WriteRequest,
+) -> Result<MetricsExposition<PrometheusType, PrometheusValue>, ParseError> {
+    let metadata: HashMap<&str, &MetricMetadata> = request
+        .metadata
+        .iter()
+        .map(|m| (m.metric_family_name.as_str(), m))
+        .collect();
+
+    let typed = |metric_type: MetricType| -> Vec<String> {
+        request
+            .metadata
+            .iter()
+            .filter(|m| m.metric_type == metric_type)
+            .map(|m| m.metric_family_name.clone())
+            .collect()
+    };
+
+    let mut histograms = typed(MetricType::Histogram);
+    let mut summaries = typed(MetricType::Summary);
+    if request.metadata.is_empty() {
+        for series in request.timeseries.iter() {
+            let name = series.labels.iter().find(|l| l.name == METRIC_NAME_LABEL);
+            let has_label = |label: &str| series.labels.iter().any(|l| l.name == label);
+            match name.map(|l| l.value.as_str()) {
+                Some(name) if !series.histograms.is_empty() => histograms.push(name.to_owned()),
+                Some(name) if has_label("le") => {
+                    if let Some(base) = name.strip_suffix("_bucket") {
+                        histograms.push(base.to_owned());
+                    }
+                }
+                Some(name) if has_label("quantile") => summaries.push(name.to_owned()),
+                _ => {}
+            }
+        }
+    }
+
+    let mut families: OrderedMap<(PrometheusType, OrderedMap<Builder>, Vec<String>)> =
+        OrderedMap::new();
+    for series in request.timeseries.iter() {
+        // Native histograms take the place of the series' float samples
+        let histogram = series.histograms.iter().max_by_key(|h| h.timestamp);
+        let sample = series.samples.iter().max_by_key(|s| s.timestamp);
+        let (timestamp, value) = match (histogram, sample) {
+            (Some(histogram), _) => (histogram.timestamp, histogram.count as f64),
+            (None, Some(sample)) => (sample.timestamp, sample.value),
+            (None, None) => continue,
+        };
+
+        let mut labels: BTreeMap<String, String> = series
+            .labels
+            .iter()
+            .map(|l| (l.name.clone(), l.value.clone()))
+            .collect();
+        let name = labels.remove(METRIC_NAME_LABEL).ok_or_else(|| {
+            ParseError::InvalidMetric(format!("Series has no metric name: {:?}", labels))
+        })?;
+
+        let (family_name, mut family_type, role) = match histogram {
+            Some(histogram) => (name, PrometheusType::Histogram, Role::Native(histogram)),
+            None => classify(&name, &mut labels, &histograms, &summaries)?,
+        };
+        if let (PrometheusType::Untyped, Some(metadata)) =
+            (&family_type, metadata.get(family_name.as_str()))
+        {
+            family_type = match metadata.metric_type {
+                MetricType::Counter => PrometheusType::Counter,
+                MetricType::Gauge => PrometheusType::Gauge,
+                _ => PrometheusType::Untyped,
+            };
+        }
+
+        let exemplar = series
+            .exemplars
+            .iter()
+            .max_by_key(|e| e.timestamp)
+            .map(|e| {
+                crate::Exemplar::new(
+                    e.labels
+                        .iter()
+                        .map(|l| (l.name.clone(), l.value.clone()))
+                        .collect(),
+                    e.value,
+                    Some(
+                        TimestampUnit::Milliseconds
+                            .convert(e.timestamp as f64, TimestampUnit::Seconds),
+                    ),
+                )
+            });
+
+        if !families.contains_key(&family_name) {
+            let label_names = labels.keys().cloned().collect();
+            families.insert(
+                family_name.clone(),
+                (family_type.clone(), OrderedMap::new(), label_names),
+            );
+        }
+
+        let (existing_type, samples, label_names) = families.get_mut(&family_name).unwrap();
+        if *existing_type != family_type {
+            return Err(ParseError::InvalidMetric(format!(
+                "Series of `{}` are both {:?} and {:?}",
+                family_name, existing_type, family_type
+            )));
+        }
+
+        if !labels.keys().eq(label_names.iter()) {
+            return Err(ParseError::InvalidMetric(format!(
+                "All the series of `{}` must have the same label names",
+                family_name
+            )));
+        }
+
+        let key = labels.values().cloned().collect::<Vec<_>>().join("\u{0}");
+        if !samples.contains_key(&key) {
+            let value = match family_type {
+                PrometheusType::Histogram => PrometheusValue::Histogram(HistogramValue::default()),
+                PrometheusType::Summary => PrometheusValue::Summary(SummaryValue::default()),
+                PrometheusType::Counter => PrometheusValue::Counter(PrometheusCounterValue {
+                    value: MetricNumber::Int(0),
+                    exemplar: None,
+                }),
+                PrometheusType::Gauge => PrometheusValue::Gauge(MetricNumber::Int(0)),
+                _ => PrometheusValue::Untyped(MetricNumber::Int(0)),
+            };
+
+            samples.insert(
+                key.clone(),
+                Builder {
+                    label_values: labels.values().cloned().collect(),
+                    timestamp: timestamp as Timestamp,
+                    value,
+                },
+            );
+        }
+
+        let builder = samples.get_mut(&key).unwrap();
+        builder.timestamp = builder.timestamp.max(timestamp as Timestamp);
+        match (&mut builder.value, role) {
+            (PrometheusValue::Histogram(h), Role::Bucket(upper_bound)) => {
+                h.buckets.push(HistogramBucket {
+                    count: number(value),
+                    upper_bound,
+                    raw_upper_bound: None,
+                    exemplar,
+                })
+            }
+            (PrometheusValue::Summary(s), Role::Quantile(quantile)) => s.quantiles.push(Quantile {
+                quantile,
+                raw_quantile: None,
+                value: number(value),
+            }),
+            (PrometheusValue::Histogram(h), Role::Native(histogram)) => {
+                h.count = Some(histogram.count);
+                h.sum = Some(number(histogram.sum));
+                h.native = Some(histogram.native.clone());
+            }
+            (PrometheusValue::Histogram(h), Role::Sum) => h.sum = Some(number(value)),
+            (PrometheusValue::Summary(s), Role::Sum) => s.sum = Some(number(value)),
+            (PrometheusValue::Histogram(h), Role::Count) => h.count = Some(value as u64),
+            (PrometheusValue::Summary(s), Role::Count) => s.count = Some(value as u64),
+            (PrometheusValue::Counter(c), _) => {
+                c.value = number(value);
+                c.exemplar = exemplar;
+            }
+            (PrometheusValue::Gauge(n), _) | (PrometheusValue::Untyped(n), _) => *n = number(value),
+            _ => unreachable!(),
+        }
+    }
+
+    let mut exposition = MetricsExposition::new();
+    for (name, (family_type, samples, label_names)) in families {
+        let help = metadata
+            .get(name.as_str())
+            .map(|m| m.help.clone())
+            .unwrap_or_default();
+        let samples = samples.into_iter().map(|(_, builder)| {
+            crate::Sample::new(builder.label_values, Some(builder.timestamp), builder.value)
+        });
+
+        let family = MetricFamily::new(name.clone(), label_names, family_type, help, String::new())
+            .with_samples(samples)?;
+        exposition.families.insert(name, family);
+    }
+
+    Ok(exposition)
+}
+
+/// Decodes a remote write body (snappy compressed protobuf) straight into an exposition
+/// (see `from_remote_write_request`)
+pub fn parse_remote_write(
+    body: &[u8],
+) -> Result<MetricsExposition<PrometheusType, PrometheusValue>, ParseError> {
+    from_remote_write_request(&WriteRequest::decode_snappy(body)?)
+}
@@ -0,0 +1,98 @@
+//! Decompression of snappy's block format, which Remote Write bodies are compressed with
+//! https://github.com/google/snappy/blob/main/format_description.txt
+
+use crate::{internal::protobuf::Decoder, ParseError};
+
+fn corrupt(reason: &str) -> ParseError {
+    ParseError::Decode {
+        format: "snappy block",
+        reason: reason.to_owned(),
+    }
+}
+
+fn read_le(input: &[u8], len: usize) -> Result<(usize, &[u8]), ParseError> {
+    if input.len() < len {
+        return Err(corrupt("truncated tag"));
+    }
+
+    let value = input[..len]
+        .iter()
+        .rev()
+        .fold(0usize, |acc, b| (acc << 8) | *b as usize);
+    Ok((value, &input[len..]))
+}
+
+/// Decompresses a single snappy block (not the framed, streaming format)
+pub(crate) fn decompress(input: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let mut decoder = Decoder::new(input);
+    let expected_len = decoder.read_varint()? as usize;
+    let mut input = decoder.remaining();
+
+    // The length comes from the input, so don't trust it for more than snappy's best ratio
+    let mut output = Vec::with_capacity(expected_len.min(input.len().saturating_mul(6)));
+    while let Some((&tag, rest)) = input.split_first() {
+        input = rest;
+        let (len, offset) = match tag & 0b11 {
+            0 => {
+                let len = match (tag >> 2) as usize {
+                    len @ 0..=59 => len + 1,
+                    extra => {
+                        let (len, rest) = read_le(input, extra - 59)?;
+                        input = rest;
+                        len + 1
+                    }
+                };
+
+                if input.len() < len {
+                    return Err(corrupt("truncated literal"));
+                }
+
+                if output.len() + len > expected_len {
+                    return Err(corrupt("longer than its declared length"));
+                }
+
+                output.extend_from_slice(&input[..len]);
+                input = &input[len..];
+                continue;
+            }
+            1 => {
+                let (low, rest) = read_le(input, 1)?;
+                input = rest;
+                (
+                    4 + ((tag >> 2) & 0b111) as usize,
+                    ((tag as usize >> 5) << 8) | low,
+                )
+            }
+            2 => {
+                let (offset, rest) = read_le(input, 2)?;
+                input = rest;
+                (1 + (tag >> 2) as usize, offset)
+            }
+            _ => {
+                let (offset, rest) = read_le(input, 4)?;
+                input = rest;
+                (1 + (tag >> 2) as usize, offset)
+            }
+        };
+
+        if offset == 0 || offset > output.len() {
+            return Err(corrupt("copy from outside the output"));
+        }
+
+        if output.len() + len > expected_len {
+            return Err(corrupt("longer than its declared length"));
+        }
+
+        // Copies can overlap the bytes they produce, so go a byte at a time
+        let start = output.len() - offset;
+        for i in 0..len {
+            output.push(output[start + i]);
+        }
+    }
+
+    if output.len() != expected_len {
+        return Err(corrupt("wrong decompressed length"));
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,203 @@
+//! Converts expositions into StatsD lines, for pipelines built around a StatsD (or DogStatsD) agent
+//! https://github.com/statsd/statsd/blob/master/docs/metric_types.md
+//!
+//! StatsD only has counters, gauges and timers, and its counters are per flush interval rather
+//! than cumulative, so the conversion is best effort: every point that can't be sent is listed
+//! in the result along with why
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::{
+    convert::graphite::sanitize, ExpositionTypeSet, MetricsExposition, RenderableMetricValue,
+    SeriesValue, METRIC_NAME_LABEL,
+};
+
+/// What happens to histogram buckets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatsdHistograms {
+    /// Buckets are left out (and reported as skipped). The `_sum` and `_count` are still sent
+    #[default]
+    Drop,
+
+    /// Each bucket is sent as a timing of its upper bound, sampled at `1/count` so that StatsD
+    /// counts it as all of the bucket's observations. The `+Inf` bucket has no bound to send,
+    /// so observations that land in it are reported as skipped
+    Timers,
+}
+
+#[derive(Debug, Clone)]
+pub struct StatsdOptions {
+    pub histograms: StatsdHistograms,
+
+    /// Whether labels are sent as DogStatsD tags (`|#name:value`). Otherwise they're folded into
+    /// the metric name as `.name=value` segments, as plain StatsD has no tags
+    pub dogstatsd_tags: bool,
+}
+
+impl Default for StatsdOptions {
+    fn default() -> Self {
+        Self {
+            histograms: StatsdHistograms::default(),
+            dogstatsd_tags: true,
+        }
+    }
+}
+
+impl StatsdOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_histograms(mut self, histograms: StatsdHistograms) -> Self {
+        self.histograms = histograms;
+        self
+    }
+
+    pub fn with_dogstatsd_tags(mut self, dogstatsd_tags: bool) -> Self {
+        self.dogstatsd_tags = dogstatsd_tags;
+        self
+    }
+}
+
+/// Why a point couldn't be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// StatsD has no NaN or infinities
+    NonFinite,
+
+    /// The point was a histogram bucket, and buckets are being dropped
+    DroppedBucket,
+
+    /// The observations in the `+Inf` bucket have no bound to be sent as a timing
+    UnboundedBucket,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedPoint {
+    /// The full labelset of the point, including `__name__`
+    pub labels: BTreeMap<String, String>,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatsdConversion {
+    /// The StatsD lines, newline separated
+    pub lines: String,
+    pub skipped: Vec<SkippedPoint>,
+}
+
+/// Converts every point in the exposition to a StatsD line: monotonic values (counters, and
+/// histogram and summary `_sum`s and `_count`s) become counters, everything else becomes a gauge.
+///
+/// Counters are sent as their cumulative total, whereas StatsD adds up what it's sent each
+/// interval, so only send counters from expositions that already hold deltas. Negative gauges
+/// are sent as a reset to zero followed by the value, as StatsD reads a leading `-` as a decrement
+/// ```
+/// use openmetrics_parser::prometheus::parse_prometheus;
+/// use openmetrics_parser::convert::statsd::{to_statsd_lines, StatsdOptions};
+///
+/// let exposition = parse_prometheus("# TYPE temp gauge\ntemp{room=\"a\"} -2.5\n").unwrap();
+/// let conversion = to_statsd_lines(&exposition, &StatsdOptions::new());
+/// assert_eq!(conversion.lines, "temp:0|g|#room:a\ntemp:-2.5|g|#room:a\n");
+/// assert!(conversion.skipped.is_empty());
+/// ```
+pub fn to_statsd_lines<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    options: &StatsdOptions,
+) -> StatsdConversion
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let mut conversion = StatsdConversion::default();
+
+    // The series (the labels without `le`, but with `__name__`) and cumulative count of the last bucket, to work out how many
+    // observations each bucket holds on its own
+    let mut previous_bucket: Option<(BTreeMap<String, String>, f64)> = None;
+    exposition.for_each_point(0., |_, labels, _, point| {
+        let skip = |skipped: &mut Vec<SkippedPoint>, reason| {
+            skipped.push(SkippedPoint {
+                labels: labels.clone(),
+                reason,
+            })
+        };
+
+        if !point.value.is_finite() {
+            skip(&mut conversion.skipped, SkipReason::NonFinite);
+            return;
+        }
+
+        let mut series = labels.clone();
+        series.remove(METRIC_NAME_LABEL);
+        let bound = series.remove("le");
+        let mut suffix = String::new();
+        if options.dogstatsd_tags {
+            for (i, (name, value)) in series.iter().enumerate() {
+                suffix.push_str(if i == 0 { "|#" } else { "," });
+                // Commas and pipes would end the tag
+                let _ = write!(suffix, "{}:{}", name, value.replace([',', '|'], "_"));
+            }
+        }
+
+        let mut name = String::new();
+        sanitize(&point.name, &mut name);
+        if !options.dogstatsd_tags {
+            for (label, value) in series.iter() {
+                name.push('.');
+                sanitize(label, &mut name);
+                name.push('=');
+                sanitize(value, &mut name);
+            }
+        }
+
+        let bound = match bound {
+            Some(bound) => bound,
+            None if point.monotonic => {
+                let _ = writeln!(conversion.lines, "{}:{}|c{}", name, point.value, suffix);
+                return;
+            }
+            None => {
+                if point.value < 0. {
+                    let _ = writeln!(conversion.lines, "{}:0|g{}", name, suffix);
+                }
+
+                let _ = writeln!(conversion.lines, "{}:{}|g{}", name, point.value, suffix);
+                return;
+            }
+        };
+
+        if options.histograms == StatsdHistograms::Drop {
+            skip(&mut conversion.skipped, SkipReason::DroppedBucket);
+            return;
+        }
+
+        let cumulative = point.value;
+        let mut key = labels.clone();
+        key.remove("le");
+        let below = match &previous_bucket {
+            Some((previous, count)) if *previous == key => *count,
+            _ => 0.,
+        };
+
+        previous_bucket = Some((key, cumulative));
+        let count = cumulative - below;
+        if count <= 0. {
+            return;
+        }
+
+        match bound.parse::<f64>() {
+            Ok(bound) if bound.is_finite() => {
+                let _ = write!(conversion.lines, "{}:{}|ms", name, bound);
+                if count != 1. {
+                    let _ = write!(conversion.lines, "|@{}", 1. / count);
+                }
+
+                let _ = writeln!(conversion.lines, "{}", suffix);
+            }
+            _ => skip(&mut conversion.skipped, SkipReason::UnboundedBucket),
+        }
+    });
+
+    conversion
+}
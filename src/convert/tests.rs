@@ -0,0 +1,647 @@
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_prometheus_api_matrix() {
+    use super::prometheus_api::parse_query_response;
+    use crate::{MetricNumber, PrometheusValue, TimeSeries};
+
+    let body = r#"{
+        "status": "success",
+        "data": {
+            "resultType": "matrix",
+            "result": [
+                {"metric": {"__name__": "up", "job": "node", "instance": "a"}, "values": [[1.5, "1"], [16.5, "0"]]},
+                {"metric": {"__name__": "up", "job": "node", "instance": "b"}, "values": [[1.5, "NaN"]]}
+            ]
+        }
+    }"#;
+
+    let series = parse_query_response(body).unwrap();
+    assert_eq!(series.len(), 2);
+    assert_eq!(series[0].samples, vec![(1500.0, 1.0), (16500.0, 0.0)]);
+    assert!(series[1].samples[0].1.is_nan());
+
+    let exposition = TimeSeries::to_prometheus_exposition(&series).unwrap();
+    let family = &exposition.families["up"];
+    assert_eq!(family.get_label_names(), &["instance", "job"]);
+    let sample = family
+        .get_sample_by_label_values(&["a".to_owned(), "node".to_owned()])
+        .unwrap();
+    assert_eq!(sample.timestamp, Some(16500.0));
+    assert_eq!(
+        sample.value,
+        PrometheusValue::Untyped(MetricNumber::Float(0.0))
+    );
+
+    assert!(parse_query_response(r#"{"status":"error","error":"bad query"}"#).is_err());
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_victoriametrics_round_trip() {
+    use super::victoriametrics::{parse_export, render_export};
+    use crate::prometheus::parse_prometheus;
+
+    let exposition = parse_prometheus(
+        "# TYPE rpc histogram\nrpc_bucket{job=\"a\",le=\"0.5\"} 1\nrpc_bucket{job=\"a\",le=\"+Inf\"} 2\nrpc_sum{job=\"a\"} 1.5\nrpc_count{job=\"a\"} 2\n",
+    )
+    .unwrap();
+
+    let mut series = exposition.to_timeseries(1000.);
+    series.sort_by(|a, b| a.labels.cmp(&b.labels));
+    assert_eq!(series.len(), 4);
+    assert_eq!(series[0].name(), Some("rpc_bucket"));
+    assert_eq!(series[0].labels["le"], "+Inf");
+
+    let export = render_export(&series);
+    assert_eq!(export.lines().count(), 4);
+    assert_eq!(parse_export(&export).unwrap(), series);
+
+    let parsed = parse_export(
+        "{\"metric\":{\"__name__\":\"up\"},\"values\":[1,null],\"timestamps\":[1549891472010,1549891487724]}\n",
+    )
+    .unwrap();
+    assert_eq!(parsed[0].samples[0], (1549891472010., 1.));
+    assert!(parsed[0].samples[1].1.is_nan());
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_datadog_payload() {
+    use super::datadog::{render_series_payload, DatadogMetricType};
+    use crate::openmetrics::parse_openmetrics;
+
+    let exposition = parse_openmetrics(
+        "# TYPE req counter\n# UNIT req seconds\nreq_total{code=\"200\"} 5 10\n# EOF\n",
+    )
+    .unwrap();
+
+    let payload: serde_json::Value = serde_json::from_str(&render_series_payload(
+        &exposition,
+        0.,
+        DatadogMetricType::Count,
+    ))
+    .unwrap();
+
+    let series = &payload["series"][0];
+    assert_eq!(series["metric"], "req_total");
+    assert_eq!(series["type"], 1);
+    assert_eq!(series["unit"], "second");
+    assert_eq!(series["tags"][0], "code:200");
+    assert_eq!(series["points"][0]["timestamp"], 10);
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_cloudwatch_emf() {
+    use super::cloudwatch::{render_emf, EmfOptions};
+    use crate::openmetrics::parse_openmetrics;
+
+    let mut exposition = parse_openmetrics(
+        "# TYPE latency histogram\nlatency_bucket{host=\"a\",path=\"/\",le=\"+Inf\"} 2\nlatency_sum{host=\"a\",path=\"/\"} 0.5\nlatency_count{host=\"a\",path=\"/\"} 2\n# TYPE other gauge\nother 1\n# EOF\n",
+    )
+    .unwrap();
+    exposition.families.get_mut("latency").unwrap().unit = "seconds".to_owned();
+
+    let options = EmfOptions::new("app")
+        .with_families(&["latency"])
+        .with_dimensions(&["path"]);
+    let emf = render_emf(&exposition, &options, 0.);
+    let blobs: Vec<serde_json::Value> = emf
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(blobs.len(), 3);
+
+    let sum = blobs
+        .iter()
+        .find(|b| b.get("latency_sum").is_some())
+        .unwrap();
+    let definition = &sum["_aws"]["CloudWatchMetrics"][0];
+    assert_eq!(definition["Namespace"], "app");
+    assert_eq!(definition["Dimensions"][0], serde_json::json!(["path"]));
+    assert_eq!(definition["Metrics"][0]["Unit"], "Seconds");
+    assert_eq!(sum["path"], "/");
+    assert!(sum.get("host").is_none());
+
+    let count = blobs
+        .iter()
+        .find(|b| b.get("latency_count").is_some())
+        .unwrap();
+    assert!(count["_aws"]["CloudWatchMetrics"][0]["Metrics"][0]
+        .get("Unit")
+        .is_none());
+}
+
+#[test]
+fn test_wavefront_source_selection() {
+    use super::wavefront::{render_wavefront, WavefrontOptions};
+    use crate::prometheus::parse_prometheus;
+
+    let exposition =
+        parse_prometheus("up{host=\"web-1\",job=\"a\"} 1 5000\nup{host=\"web-2\",job=\"b\"} NaN\n")
+            .unwrap();
+
+    let options = WavefrontOptions::new()
+        .with_source_labels(&["job"])
+        .with_default_source("fallback");
+    let lines = render_wavefront(&exposition, &options, 0.);
+    assert_eq!(lines, "\"up\" 1 5 source=\"a\" \"host\"=\"web-1\"\n");
+}
+
+#[test]
+fn test_exposition_conversion() {
+    use crate::openmetrics::parse_openmetrics;
+    use crate::prometheus::parse_prometheus;
+    use crate::{MetricsExposition, PrometheusType, PrometheusValue};
+
+    let prometheus = parse_prometheus("# HELP jobs_total Jobs run\n# TYPE jobs_total counter\njobs_total{queue=\"a\"} 3 2000\n\n# TYPE latency histogram\nlatency_bucket{le=\"1\"} 1 2000\nlatency_bucket{le=\"+Inf\"} 2 2000\nlatency_sum 1.5 2000\nlatency_count 2 2000\n").unwrap();
+
+    let openmetrics = super::exposition::prometheus_to_openmetrics(&prometheus).unwrap();
+    let reparsed = parse_openmetrics(&openmetrics.to_openmetrics_string()).unwrap();
+    assert_eq!(reparsed.families["jobs"].help, "Jobs run");
+    assert_eq!(
+        reparsed.families["latency"]
+            .iter_samples()
+            .next()
+            .unwrap()
+            .timestamp,
+        Some(2.)
+    );
+
+    let back: MetricsExposition<PrometheusType, PrometheusValue> =
+        (&openmetrics).try_into().unwrap();
+    assert_eq!(back.to_string(), prometheus.to_string());
+
+    let info = parse_openmetrics("# TYPE build info\nbuild_info{version=\"1\"} 1\n# TYPE q gaugehistogram\nq_bucket{le=\"+Inf\"} 1\nq_gcount 1\nq_gsum 0\n# EOF\n").unwrap();
+    assert!(super::exposition::openmetrics_to_prometheus(&info).is_err());
+
+    let info =
+        parse_openmetrics("# TYPE build info\nbuild_info{version=\"1\"} 1\n# EOF\n").unwrap();
+    let converted = super::exposition::openmetrics_to_prometheus(&info).unwrap();
+    assert_eq!(
+        converted.to_string(),
+        "# TYPE build_info gauge\nbuild_info{version=\"1\"} 1\n"
+    );
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_prometheus_api_render() {
+    use super::prometheus_api::{parse_query_response, render_query_response};
+    use crate::prometheus::parse_prometheus;
+
+    let exposition = parse_prometheus("# TYPE latency histogram\nlatency_bucket{le=\"1\"} 1\nlatency_bucket{le=\"+Inf\"} 2\nlatency_sum 1.5\nlatency_count 2\n\n# TYPE temp gauge\ntemp{room=\"a\"} NaN 2000\n").unwrap();
+
+    let series = parse_query_response(&render_query_response(&exposition, 1000.)).unwrap();
+    assert_eq!(series.len(), 5);
+
+    let inf = series
+        .iter()
+        .find(|s| s.labels.get("le").map(String::as_str) == Some("+Inf"))
+        .unwrap();
+    assert_eq!(inf.name(), Some("latency_bucket"));
+    assert_eq!(inf.samples, vec![(1000., 2.)]);
+
+    let temp = series.iter().find(|s| s.name() == Some("temp")).unwrap();
+    assert_eq!(temp.samples[0].0, 2000.);
+    assert!(temp.samples[0].1.is_nan());
+}
+
+#[cfg(feature = "remote-write")]
+#[test]
+fn test_remote_write_encoding() {
+    use super::remote_write::{to_remote_write_request, MetricType};
+    use crate::openmetrics::parse_openmetrics;
+
+    let exposition = parse_openmetrics("# TYPE up gauge\nup 1 1\n# TYPE latency histogram\nlatency_bucket{le=\"0.5\"} 1 2 # {trace_id=\"abc\"} 0.2 1.5\nlatency_bucket{le=\"+Inf\"} 2 2\nlatency_sum 1.5 2\nlatency_count 2 2\n# EOF\n").unwrap();
+    let request = to_remote_write_request(&exposition, &[], 0.);
+    assert_eq!(request.timeseries.len(), 5);
+    assert_eq!(request.metadata[1].metric_type, MetricType::Histogram);
+
+    let bucket = &request.timeseries[1];
+    assert_eq!(bucket.samples[0].timestamp, 2000);
+    assert_eq!(bucket.exemplars[0].value, 0.2);
+    assert_eq!(bucket.exemplars[0].timestamp, 1500);
+
+    let mut up = request.clone();
+    up.timeseries.truncate(1);
+    up.metadata.clear();
+
+    let mut expected = vec![0x0a, 0x1e, 0x0a, 0x0e, 0x0a, 0x08];
+    expected.extend_from_slice(b"__name__");
+    expected.extend_from_slice(&[0x12, 0x02]);
+    expected.extend_from_slice(b"up");
+    expected.extend_from_slice(&[0x12, 0x0c, 0x09]);
+    expected.extend_from_slice(&1f64.to_le_bytes());
+    expected.extend_from_slice(&[0x10, 0xe8, 0x07]);
+    assert_eq!(up.encode_to_vec(), expected);
+}
+
+#[cfg(feature = "remote-write")]
+#[test]
+fn test_remote_write_decoding() {
+    use super::remote_write::{parse_remote_write, to_remote_write_request, WriteRequest};
+    use crate::prometheus::parse_prometheus;
+
+    assert_eq!(
+        super::snappy::decompress(&[9, 0x08, b'a', b'b', b'c', 0x09, 3]).unwrap(),
+        b"abcabcabc"
+    );
+    assert!(super::snappy::decompress(&[9, 0x08, b'a', b'b', b'c', 0x09, 4]).is_err());
+    assert!(super::snappy::decompress(&[2, 0x08, b'a', b'b', b'c']).is_err());
+
+    // A huge declared length mustn't be allocated up front
+    let bomb = [
+        0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0x00, b'a',
+    ];
+    assert!(WriteRequest::decode_snappy(&bomb).is_err());
+    assert!(parse_remote_write(&bomb).is_err());
+
+    let text = "# HELP jobs_total Jobs run\n# TYPE jobs_total counter\njobs_total{queue=\"a\"} 3 2000\njobs_total{queue=\"b\"} 4 2000\n\n# TYPE latency histogram\nlatency_bucket{le=\"1\"} 1 2000\nlatency_bucket{le=\"+Inf\"} 2 2000\nlatency_sum 1.5 2000\nlatency_count 2 2000\n";
+    let request = to_remote_write_request(&parse_prometheus(text).unwrap(), &[], 0.);
+    let encoded = request.encode_to_vec();
+    assert_eq!(WriteRequest::decode(&encoded).unwrap(), request);
+
+    // A snappy block made of a single literal
+    let mut body = vec![];
+    let mut len = encoded.len();
+    while len >= 0x80 {
+        body.push((len as u8) | 0x80);
+        len >>= 7;
+    }
+    body.push(len as u8);
+    body.push(61 << 2);
+    body.extend_from_slice(&((encoded.len() - 1) as u16).to_le_bytes());
+    body.extend_from_slice(&encoded);
+
+    assert_eq!(parse_remote_write(&body).unwrap().to_string(), text);
+
+    // Without metadata, histograms are still spotted by their `le` label, but counters are untyped
+    let mut request = request;
+    request.metadata.clear();
+    let exposition = super::remote_write::from_remote_write_request(&request).unwrap();
+    assert_eq!(
+        exposition.families["latency"].family_type,
+        crate::PrometheusType::Histogram
+    );
+    assert_eq!(
+        exposition.families["jobs_total"].family_type,
+        crate::PrometheusType::Untyped
+    );
+}
+
+#[cfg(feature = "remote-write")]
+#[test]
+fn test_remote_write_native_histograms() {
+    use super::remote_write::{
+        from_remote_write_request, to_remote_write_request, ResetHint, WriteRequest,
+    };
+    use crate::prometheus::parse_prometheus;
+    use crate::{BucketSpan, MetricNumber, NativeHistogram, PrometheusValue};
+
+    let mut native = NativeHistogram::new(0);
+    native.positive_spans = vec![BucketSpan::new(1, 2)];
+    native.positive_counts = vec![MetricNumber::Int(2), MetricNumber::Int(1)];
+
+    let text = "# TYPE latency histogram\nlatency_bucket{job=\"a\",le=\"+Inf\"} 3 2000\nlatency_sum{job=\"a\"} 4.5 2000\nlatency_count{job=\"a\"} 3 2000\n";
+    let mut exposition = parse_prometheus(text).unwrap();
+    let family = exposition.families.get_mut("latency").unwrap();
+    if let PrometheusValue::Histogram(h) = &mut family.iter_samples_mut().next().unwrap().value {
+        h.native = Some(native.clone());
+    }
+
+    let request = to_remote_write_request(&exposition, &[("cluster", "x")], 0.);
+    assert_eq!(request.timeseries.len(), 4);
+    let series = &request.timeseries[3];
+    assert_eq!(series.labels[0].value, "latency");
+    assert!(series.samples.is_empty());
+    assert_eq!(series.histograms[0].count, 3);
+    assert_eq!(series.histograms[0].sum, 4.5);
+    assert_eq!(series.histograms[0].timestamp, 2000);
+    assert_eq!(series.histograms[0].reset_hint, ResetHint::Unknown);
+    assert_eq!(series.histograms[0].native, native);
+    assert_eq!(
+        WriteRequest::decode(&request.encode_to_vec()).unwrap(),
+        request
+    );
+
+    // With or without metadata, the histogram series lands back in the histogram
+    let mut request = request;
+    for _ in 0..2 {
+        let rebuilt = from_remote_write_request(&request).unwrap();
+        let family = &rebuilt.families["latency"];
+        assert_eq!(family.family_type, crate::PrometheusType::Histogram);
+        match &family.iter_samples().next().unwrap().value {
+            PrometheusValue::Histogram(h) => {
+                assert_eq!(h.native, Some(native.clone()));
+                assert_eq!(h.count, Some(3));
+                assert_eq!(h.buckets.len(), 1);
+            }
+            value => panic!("Expected a histogram, got {:?}", value),
+        }
+
+        request.metadata.clear();
+    }
+}
+
+#[cfg(feature = "otlp")]
+#[test]
+fn test_otlp_conversion() {
+    use super::otlp::{
+        to_resource_metrics, AggregationTemporality, ExportMetricsServiceRequest, KeyValue,
+        MetricData,
+    };
+    use crate::{openmetrics::parse_openmetrics, MetricNumber};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let exposition = parse_openmetrics(
+        "# TYPE requests counter\nrequests_total{code=\"200\"} 5\nrequests_created{code=\"200\"} 1\n# TYPE latency histogram\nlatency_bucket{le=\"0.5\"} 2 # {trace_id=\"0102030405060708090a0b0c0d0e0f10\",span_id=\"zz\"} 0.25\nlatency_bucket{le=\"1\"} 3\nlatency_bucket{le=\"+Inf\"} 5\nlatency_sum 4.5\nlatency_count 5\n# TYPE queue gaugehistogram\nqueue_bucket{le=\"+Inf\"} 1\nqueue_gsum 1\nqueue_gcount 1\n# EOF\n",
+    )
+    .unwrap();
+
+    let resource = to_resource_metrics(&exposition, &[], 2000.);
+    let metrics = &resource.scope_metrics[0].metrics;
+    assert_eq!(metrics.len(), 2);
+
+    match &metrics[0].data {
+        MetricData::Sum {
+            data_points,
+            aggregation_temporality,
+            is_monotonic,
+        } => {
+            assert_eq!(*aggregation_temporality, AggregationTemporality::Cumulative);
+            assert!(is_monotonic);
+            assert_eq!(
+                data_points[0].attributes,
+                vec![KeyValue::new("code", "200")]
+            );
+            assert_eq!(data_points[0].start_time_unix_nano, 1_000_000_000);
+            assert_eq!(data_points[0].time_unix_nano, 2_000_000_000);
+            assert_eq!(data_points[0].value, MetricNumber::Int(5));
+            assert_eq!(
+                data_points[0].start_time(),
+                Some(UNIX_EPOCH + Duration::from_secs(1))
+            );
+            assert_eq!(data_points[0].time(), UNIX_EPOCH + Duration::from_secs(2));
+        }
+        other => panic!("Expected a sum, got {:?}", other),
+    }
+
+    match &metrics[1].data {
+        MetricData::Histogram { data_points, .. } => {
+            let point = &data_points[0];
+            assert_eq!(point.bucket_counts, vec![2, 1, 2]);
+            assert_eq!(point.explicit_bounds, vec![0.5, 1.]);
+            assert_eq!(point.count, 5);
+            assert_eq!(point.sum, Some(4.5));
+            assert_eq!(point.start_time(), None);
+
+            // The span ID isn't valid hex, so stays an attribute
+            let exemplar = &point.exemplars[0];
+            assert_eq!(exemplar.trace_id, (1..=16).collect::<Vec<u8>>());
+            assert!(exemplar.span_id.is_empty());
+            assert_eq!(
+                exemplar.filtered_attributes,
+                vec![KeyValue::new("span_id", "zz")]
+            );
+        }
+        other => panic!("Expected a histogram, got {:?}", other),
+    }
+
+    let request = ExportMetricsServiceRequest {
+        resource_metrics: vec![resource],
+    };
+
+    let bytes = request.encode_to_vec();
+    assert_eq!(bytes[0], 0x0a);
+    assert!(bytes.len() > 100);
+}
+
+#[test]
+fn test_influx_line_protocol() {
+    use super::influx::{
+        to_influx_line_protocol, InfluxFlattening, InfluxMeasurement, InfluxOptions,
+    };
+    use crate::prometheus::parse_prometheus;
+
+    let exposition = parse_prometheus(
+        "# TYPE latency histogram\nlatency_bucket{path=\"/a b\",le=\"0.5\"} 2 1000\nlatency_bucket{path=\"/a b\",le=\"+Inf\"} 3 1000\nlatency_sum{path=\"/a b\"} 1.5 1000\nlatency_count{path=\"/a b\"} 3 1000\n# TYPE temp gauge\ntemp{room=\"\"} NaN\n",
+    )
+    .unwrap();
+
+    let lines = to_influx_line_protocol(&exposition, &InfluxOptions::new(), 0.);
+    assert_eq!(
+        lines,
+        "latency,path=/a\\ b 0.5=2,+Inf=3,sum=1.5,count=3 1000000000\n"
+    );
+
+    let options = InfluxOptions::new()
+        .with_measurement(InfluxMeasurement::Fixed("prometheus".to_owned()))
+        .with_flattening(InfluxFlattening::Tags);
+    let lines = to_influx_line_protocol(&exposition, &options, 0.);
+    assert_eq!(
+        lines,
+        "prometheus,le=0.5,path=/a\\ b latency_bucket=2 1000000000\nprometheus,le=+Inf,path=/a\\ b latency_bucket=3 1000000000\nprometheus,path=/a\\ b latency_sum=1.5 1000000000\nprometheus,path=/a\\ b latency_count=3 1000000000\n"
+    );
+}
+
+#[test]
+fn test_graphite_lines() {
+    use super::graphite::{render_graphite, GraphiteLabels, GraphiteOptions};
+    use crate::prometheus::parse_prometheus;
+
+    let exposition = parse_prometheus(
+        "# TYPE requests_total counter\nrequests_total{host=\"web.1\",path=\"\"} 5 2000\n# TYPE temp gauge\ntemp NaN\n",
+    )
+    .unwrap();
+
+    let lines = render_graphite(&exposition, &GraphiteOptions::new().with_prefix("prod"), 0.);
+    assert_eq!(lines, "prod.requests_total.host=web_1 5 2\n");
+
+    let options = GraphiteOptions::new().with_labels(GraphiteLabels::Tags);
+    let lines = render_graphite(&exposition, &options, 0.);
+    assert_eq!(lines, "requests_total;host=web_1 5 2\n");
+}
+
+#[test]
+fn test_statsd_conversion() {
+    use super::statsd::{to_statsd_lines, SkipReason, StatsdHistograms, StatsdOptions};
+    use crate::prometheus::parse_prometheus;
+
+    let exposition = parse_prometheus(
+        "# TYPE latency histogram\nlatency_bucket{le=\"0.1\"} 1\nlatency_bucket{le=\"0.5\"} 4\nlatency_bucket{le=\"+Inf\"} 5\nlatency_sum 1.2\nlatency_count 5\n# TYPE temp gauge\ntemp{room=\"a\"} NaN\n",
+    )
+    .unwrap();
+
+    let conversion = to_statsd_lines(&exposition, &StatsdOptions::new());
+    assert_eq!(conversion.lines, "latency_sum:1.2|c\nlatency_count:5|c\n");
+    assert_eq!(conversion.skipped.len(), 4);
+    assert_eq!(conversion.skipped[0].reason, SkipReason::DroppedBucket);
+    assert_eq!(conversion.skipped[3].reason, SkipReason::NonFinite);
+    assert_eq!(conversion.skipped[3].labels["room"], "a");
+
+    let options = StatsdOptions::new()
+        .with_histograms(StatsdHistograms::Timers)
+        .with_dogstatsd_tags(false);
+    let conversion = to_statsd_lines(&exposition, &options);
+    assert_eq!(
+        conversion.lines,
+        "latency_bucket:0.1|ms\nlatency_bucket:0.5|ms|@0.3333333333333333\nlatency_sum:1.2|c\nlatency_count:5|c\n"
+    );
+
+    let reasons: Vec<SkipReason> = conversion.skipped.iter().map(|s| s.reason).collect();
+    assert_eq!(
+        reasons,
+        vec![SkipReason::UnboundedBucket, SkipReason::NonFinite]
+    );
+}
+
+#[test]
+fn test_columnar_batch() {
+    use super::columnar::to_columnar_batch;
+    use crate::prometheus::parse_prometheus;
+
+    let exposition = parse_prometheus(
+        "# TYPE latency histogram\nlatency_bucket{le=\"1\"} 2 5000\nlatency_bucket{le=\"+Inf\"} 3 5000\nlatency_sum 1.5 5000\nlatency_count 3 5000\n# TYPE up gauge\nup{job=\"a\"} 1\n",
+    )
+    .unwrap();
+
+    let batch = to_columnar_batch(&exposition, 1000.);
+    assert_eq!(batch.num_rows(), 5);
+    assert_eq!(
+        batch.names.values,
+        vec!["latency_bucket", "latency_sum", "latency_count", "up"]
+    );
+    assert_eq!(
+        batch.names.keys,
+        vec![Some(0), Some(0), Some(1), Some(2), Some(3)]
+    );
+
+    // Every label column has a row for every point, with nulls where the label is missing
+    assert_eq!(batch.labels.keys().collect::<Vec<_>>(), vec!["job", "le"]);
+    assert_eq!(batch.labels["le"].get(1), Some("+Inf"));
+    assert_eq!(batch.labels["le"].get(4), None);
+    assert_eq!(
+        batch.labels["job"].keys,
+        vec![None, None, None, None, Some(0)]
+    );
+    assert_eq!(batch.values, vec![2., 3., 1.5, 3., 1.]);
+    assert_eq!(batch.timestamps, vec![5000, 5000, 5000, 5000, 1000]);
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_victoriametrics_import() {
+    use super::victoriametrics::{parse_export, render_import};
+    use crate::openmetrics::parse_openmetrics;
+
+    let exposition = parse_openmetrics(
+        "# TYPE requests counter\nrequests_total{code=\"200\"} 5 1.5\nrequests_total{code=\"500\"} 1 1.5\n# EOF\n",
+    )
+    .unwrap();
+
+    let series = parse_export(&render_import(&exposition, 0.)).unwrap();
+    assert_eq!(series.len(), 2);
+    assert_eq!(series[0].name(), Some("requests_total"));
+    assert_eq!(series[1].labels["code"], "500");
+
+    // OpenMetrics timestamps are in seconds, VictoriaMetrics ones in milliseconds
+    assert_eq!(series[1].samples, vec![(1500., 1.)]);
+}
+
+#[test]
+fn test_recorder_replay() {
+    use super::recorder::{replay, MetricsRecorder};
+    use crate::openmetrics::parse_openmetrics;
+
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl MetricsRecorder for Events {
+        fn counter(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+            self.0
+                .push(format!("counter {} {:?} {}", name, labels, value));
+        }
+
+        fn gauge(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+            self.0
+                .push(format!("gauge {} {:?} {}", name, labels, value));
+        }
+
+        fn histogram(&mut self, name: &str, labels: &[(&str, &str)], value: f64, count: u64) {
+            self.0.push(format!(
+                "histogram {} {:?} {}x{}",
+                name, labels, value, count
+            ));
+        }
+    }
+
+    let exposition = parse_openmetrics(
+        "# TYPE requests counter\nrequests_total{code=\"200\"} 5\n# TYPE rpc summary\nrpc{quantile=\"0.5\"} 0.2\nrpc_sum 3.5\nrpc_count 10\n# TYPE latency histogram\nlatency_bucket{le=\"1\"} 1\nlatency_bucket{le=\"2\"} 1\nlatency_bucket{le=\"+Inf\"} 2\nlatency_sum 4\nlatency_count 2\n# EOF\n",
+    )
+    .unwrap();
+
+    let mut events = Events::default();
+    replay(&exposition, &mut events);
+    assert_eq!(
+        events.0,
+        vec![
+            "counter requests_total [(\"code\", \"200\")] 5",
+            "gauge rpc [(\"quantile\", \"0.5\")] 0.2",
+            "counter rpc_sum [] 3.5",
+            "counter rpc_count [] 10",
+            "histogram latency [] 1x1",
+        ]
+    );
+}
+
+#[cfg(feature = "serde_json")]
+#[test]
+fn test_datadog_flattening() {
+    use super::datadog::{
+        render_series_payload_with_options, DatadogHistograms, DatadogMetricType, DatadogOptions,
+        DatadogSummaries,
+    };
+    use crate::prometheus::parse_prometheus;
+
+    let exposition = parse_prometheus(
+        "# TYPE latency histogram\nlatency_bucket{le=\"1\"} 1\nlatency_bucket{le=\"+Inf\"} 2\nlatency_sum 1.5\nlatency_count 2\n# TYPE rpc summary\nrpc{quantile=\"0.5\"} 0.2\nrpc_sum 3.5\nrpc_count 10\n",
+    )
+    .unwrap();
+
+    let metrics =
+        |options: &DatadogOptions| -> Vec<(String, u64)> {
+            let payload: serde_json::Value = serde_json::from_str(
+                &render_series_payload_with_options(&exposition, 0., options),
+            )
+            .unwrap();
+
+            payload["series"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|s| {
+                    (
+                        s["metric"].as_str().unwrap().to_owned(),
+                        s["type"].as_u64().unwrap(),
+                    )
+                })
+                .collect()
+        };
+
+    assert_eq!(metrics(&DatadogOptions::new()).len(), 7);
+
+    let options = DatadogOptions::new()
+        .with_monotonic_type(DatadogMetricType::Count)
+        .with_histograms(DatadogHistograms::SumAndCount)
+        .with_summaries(DatadogSummaries::SumAndCount);
+    assert_eq!(
+        metrics(&options),
+        vec![
+            ("latency_sum".to_owned(), 1),
+            ("latency_count".to_owned(), 1),
+            ("rpc_sum".to_owned(), 1),
+            ("rpc_count".to_owned(), 1),
+        ]
+    );
+}
@@ -0,0 +1,138 @@
+//! Converts to and from the JSON line format used by VictoriaMetrics' `/api/v1/export` and `/api/v1/import`
+//! https://docs.victoriametrics.com/#how-to-export-data-in-json-line-format
+//!
+//! Each line holds a single series: `{"metric":{"__name__":"up","job":"node"},"values":[1,0],"timestamps":[1549891472010,1549891487724]}`,
+//! with timestamps in milliseconds
+
+use serde_json::{json, Map, Number, Value};
+
+use crate::{
+    ExpositionTypeSet, MetricsExposition, ParseError, RenderableMetricValue, SeriesValue,
+    TimeSeries, Timestamp,
+};
+
+/// Parses a JSON line export into one TimeSeries per line. Blank lines are skipped, and `null`
+/// values (which is how non finite values end up in JSON) are read as NaN
+pub fn parse_export(export: &str) -> Result<Vec<TimeSeries>, ParseError> {
+    let mut series = Vec::new();
+    for (i, line) in export.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let invalid = |reason: &str| {
+            ParseError::Decode {
+                format: "export",
+                reason: reason.to_owned(),
+            }
+            .at(i + 1, 1)
+        };
+
+        let line: Value = serde_json::from_str(line).map_err(|e| invalid(&e.to_string()))?;
+
+        let mut s = TimeSeries::default();
+        let metric = line
+            .get("metric")
+            .and_then(Value::as_object)
+            .ok_or_else(|| invalid("missing `metric`"))?;
+        for (name, value) in metric {
+            let value = value
+                .as_str()
+                .ok_or_else(|| invalid("label values must be strings"))?;
+            s.labels.insert(name.clone(), value.to_owned());
+        }
+
+        let values = line
+            .get("values")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid("missing `values`"))?;
+        let timestamps = line
+            .get("timestamps")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid("missing `timestamps`"))?;
+        if values.len() != timestamps.len() {
+            return Err(invalid("`values` and `timestamps` have different lengths"));
+        }
+
+        for (value, timestamp) in values.iter().zip(timestamps.iter()) {
+            let timestamp = timestamp
+                .as_f64()
+                .ok_or_else(|| invalid("timestamps must be numbers"))?;
+            let value = match value {
+                Value::Null => f64::NAN,
+                value => value
+                    .as_f64()
+                    .ok_or_else(|| invalid("values must be numbers"))?,
+            };
+
+            s.samples.push((timestamp, value));
+        }
+
+        series.push(s);
+    }
+
+    Ok(series)
+}
+
+/// Renders series in the JSON line format, one line per series. Non finite values can't be
+/// represented in JSON, and so are written as `null`
+pub fn render_export(series: &[TimeSeries]) -> String {
+    let mut export = String::new();
+    for s in series {
+        let metric: Map<String, Value> = s
+            .labels
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+            .collect();
+
+        let values: Vec<Value> = s
+            .samples
+            .iter()
+            .map(|(_, v)| {
+                Number::from_f64(*v)
+                    .map(Value::Number)
+                    .unwrap_or(Value::Null)
+            })
+            .collect();
+
+        let timestamps: Vec<Value> = s
+            .samples
+            .iter()
+            .map(|(t, _)| Value::from(*t as i64))
+            .collect();
+
+        let line = json!({
+            "metric": metric,
+            "values": values,
+            "timestamps": timestamps,
+        });
+
+        export.push_str(&line.to_string());
+        export.push('\n');
+    }
+
+    export
+}
+
+/// Flattens an exposition into the JSON line format, ready to be POSTed to `/api/v1/import`.
+/// Points without a timestamp are given `default_timestamp` (in milliseconds)
+/// ```
+/// use openmetrics_parser::prometheus::parse_prometheus;
+/// use openmetrics_parser::convert::victoriametrics::render_import;
+///
+/// let exposition = parse_prometheus("# TYPE up gauge\nup{job=\"node\"} 1\n").unwrap();
+/// assert_eq!(
+///     render_import(&exposition, 1549891472010.),
+///     "{\"metric\":{\"__name__\":\"up\",\"job\":\"node\"},\"timestamps\":[1549891472010],\"values\":[1.0]}\n"
+/// );
+/// ```
+pub fn render_import<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    default_timestamp: Timestamp,
+) -> String
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    render_export(&exposition.to_timeseries(default_timestamp))
+}
@@ -0,0 +1,103 @@
+//! Renders expositions in the Wavefront (Tanzu Observability) data format
+//! https://docs.wavefront.com/wavefront_data_format.html
+//!
+//! Each point is a line of the form `<metric> <value> [<timestamp>] source=<source> [<tag>=<value> ...]`,
+//! with the timestamp in seconds
+
+use std::fmt::Write;
+
+use crate::{
+    ExpositionTypeSet, MetricsExposition, RenderableMetricValue, SeriesValue, Timestamp,
+    METRIC_NAME_LABEL,
+};
+
+/// Controls how the `source` of each point is picked
+#[derive(Debug, Clone)]
+pub struct WavefrontOptions {
+    /// Labels to take the source from, in order of preference. The label used is dropped from the point tags
+    pub source_labels: Vec<String>,
+
+    /// The source used for points that have none of the `source_labels`
+    pub default_source: String,
+}
+
+impl Default for WavefrontOptions {
+    fn default() -> Self {
+        Self {
+            source_labels: vec![String::from("instance"), String::from("host")],
+            default_source: String::from("unknown"),
+        }
+    }
+}
+
+impl WavefrontOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_source_labels(mut self, labels: &[&str]) -> Self {
+        self.source_labels = labels.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn with_default_source(mut self, source: &str) -> Self {
+        self.default_source = source.to_owned();
+        self
+    }
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "\\\""))
+}
+
+/// Renders every point in the exposition as a Wavefront line. Points without a timestamp are given
+/// `default_timestamp` (in milliseconds, like the rest of the converters). Wavefront can't store
+/// NaN or infinite values, so those points are skipped
+/// ```
+/// use openmetrics_parser::prometheus::parse_prometheus;
+/// use openmetrics_parser::convert::wavefront::{render_wavefront, WavefrontOptions};
+///
+/// let exposition = parse_prometheus("temp{instance=\"pi\",room=\"a\"} 21.5\n").unwrap();
+/// let lines = render_wavefront(&exposition, &WavefrontOptions::new(), 1000.);
+/// assert_eq!(lines, "\"temp\" 21.5 1 source=\"pi\" \"room\"=\"a\"\n");
+/// ```
+pub fn render_wavefront<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+    options: &WavefrontOptions,
+    default_timestamp: Timestamp,
+) -> String
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let mut lines = String::new();
+    exposition.for_each_point(default_timestamp, |_, mut labels, timestamp, point| {
+        if !point.value.is_finite() {
+            return;
+        }
+
+        labels.remove(METRIC_NAME_LABEL);
+        let source = options
+            .source_labels
+            .iter()
+            .find_map(|l| labels.remove(l))
+            .unwrap_or_else(|| options.default_source.clone());
+
+        let _ = write!(
+            lines,
+            "{} {} {} source={}",
+            quote(&point.name),
+            point.value,
+            (timestamp / 1000.) as i64,
+            quote(&source)
+        );
+
+        for (name, value) in labels.iter() {
+            let _ = write!(lines, " {}={}", quote(name), quote(value));
+        }
+
+        lines.push('\n');
+    });
+
+    lines
+}
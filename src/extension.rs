@@ -0,0 +1,11 @@
+//! The machinery the OpenMetrics and Prometheus parsers are built on, for defining custom dialects
+//! of the text format (e.g. with vendor specific family types). Implement `MetricsType` and
+//! `MetricsDialect` for your set of family types, and parse with `parse_openmetrics_dialect`.
+//! Samples can be folded into families with `MetricFamilyMarshal::process_with_handlers`, by
+//! registering a `MetricProcesser` for each suffix that a family type accepts
+
+pub use crate::internal::{
+    CounterValueMarshal, LabelNames, MetricFamilyMarshal, MetricHandlers, MetricMarshal,
+    MetricProcesser, MetricValueMarshal, MetricsDialect, MetricsType,
+};
+pub use crate::openmetrics::parse_openmetrics_dialect;
@@ -1,11 +1,11 @@
-use std::fmt;
+use std::{cell::RefCell, fmt};
 
 use crate::{
-    CounterValue, Exemplar, HistogramValue, MetricNumber, ParseError, PrometheusCounterValue,
-    SummaryValue, Timestamp,
+    CounterValue, Exemplar, HistogramValue, LabelValues, MetadataKind, MetricNumber, ParseError,
+    ParserOptions, PrometheusCounterValue, SummaryValue, Timestamp,
 };
 
-use super::MetricsType;
+use super::{MarshalledMetric, MetricsType};
 
 #[derive(Debug)]
 pub enum MetricValueMarshal {
@@ -57,8 +57,13 @@ where
     pub help: Option<String>,
     pub unit: Option<String>,
     pub metrics: Vec<MetricMarshal>,
-    pub seen_label_sets: Vec<Vec<String>>,
-    pub current_label_set: Option<Vec<String>>,
+    pub seen_label_sets: Vec<LabelValues>,
+    pub current_label_set: Option<LabelValues>,
+    pub options: ParserOptions,
+
+    /// Broken rules that `options` only asks to be warned about. Validation only borrows the
+    /// family, hence the `RefCell`
+    pub warnings: RefCell<Vec<ParseError>>,
 }
 
 impl<T> MetricFamilyMarshal<T>
@@ -66,6 +71,10 @@ where
     T: MetricsType + Clone + Default + fmt::Debug,
 {
     pub fn empty() -> MetricFamilyMarshal<T> {
+        MetricFamilyMarshal::with_options(ParserOptions::default())
+    }
+
+    pub fn with_options(options: ParserOptions) -> MetricFamilyMarshal<T> {
         MetricFamilyMarshal {
             name: None,
             label_names: None,
@@ -75,6 +84,8 @@ where
             metrics: Vec::new(),
             seen_label_sets: Vec::new(),
             current_label_set: None,
+            options,
+            warnings: RefCell::new(Vec::new()),
         }
     }
 
@@ -82,16 +93,69 @@ where
         &mut self,
         label_values: &[String],
     ) -> Option<&mut MetricMarshal> {
-        return self
-            .metrics
+        self.metrics
             .iter_mut()
-            .find(|m| m.label_values == label_values);
+            .find(|m| m.label_values.as_slice() == label_values)
     }
 
     pub fn add_metric(&mut self, metric: MetricMarshal) {
         self.metrics.push(metric);
     }
 
+    /// Records the exact timestamp of the metrics added since there were `since` of them, i.e.
+    /// the one started by the sample just processed, if it started one
+    pub(crate) fn set_timestamp_nanos(&mut self, since: usize, nanos: Option<i64>) {
+        for metric in &mut self.metrics[since..] {
+            metric.timestamp_nanos = nanos;
+        }
+    }
+
+    /// Records where the metrics added since there were `since` of them were started, so errors
+    /// found in them when the family is validated can point at that sample
+    pub(crate) fn set_location(&mut self, since: usize, line: usize, column: usize) {
+        for metric in &mut self.metrics[since..] {
+            metric.location = Some((line, column));
+        }
+    }
+
+    /// The family's label names paired with the metric's label values
+    pub fn metric_labels(&self, metric: &MetricMarshal) -> Vec<(String, String)> {
+        let names = self
+            .label_names
+            .as_ref()
+            .map(|names| names.names.as_slice());
+        names
+            .unwrap_or_default()
+            .iter()
+            .cloned()
+            .zip(metric.label_values.iter().cloned())
+            .collect()
+    }
+
+    /// Validates `metric`, putting any error or warning it raises in the family and the metric's
+    /// labelset, and on the sample that started it
+    pub(crate) fn validate_metric(&self, metric: &MetricMarshal) -> Result<(), ParseError>
+    where
+        MetricMarshal: MarshalledMetric<T>,
+    {
+        let name = self.name.as_deref().unwrap_or_default();
+        let locate = |error: ParseError| {
+            let error = error.in_metric(name, &self.metric_labels(metric));
+            match metric.location {
+                Some((line, column)) => error.at(line, column),
+                None => error,
+            }
+        };
+
+        let warnings_before = self.warnings.borrow().len();
+        metric.validate(self).map_err(locate)?;
+
+        let mut warnings = self.warnings.borrow_mut();
+        let found = warnings.split_off(warnings_before);
+        warnings.extend(found.into_iter().map(locate));
+        Ok(())
+    }
+
     pub fn try_set_label_names(
         &mut self,
         sample_name: &str,
@@ -113,24 +177,64 @@ where
     }
 
     pub fn set_or_test_name(&mut self, name: String) -> Result<(), ParseError> {
-        let name = Some(name);
-        if self.name.is_some() && self.name != name {
-            return Err(ParseError::InvalidMetric(format!(
-                "Invalid metric name in family. Family name is {}, but got a metric called {}",
-                self.name.as_ref().unwrap(),
-                name.as_ref().unwrap()
-            )));
+        if let Some(family_name) = &self.name {
+            if family_name != &name {
+                return Err(ParseError::InvalidMetric(format!(
+                    "Invalid metric name in family. Family name is {}, but got a metric called {}",
+                    family_name, name
+                )));
+            }
         }
 
-        self.name = name;
+        self.name = Some(name);
+        Ok(())
+    }
+
+    fn duplicate_metadata(&self, kind: MetadataKind) -> ParseError {
+        ParseError::DuplicateMetadata {
+            family: self.name.clone().unwrap_or_default(),
+            kind,
+        }
+    }
+
+    /// Checks the histogram of one of the family's metrics, as strictly as the family's options
+    /// ask, with any errors (and warnings) put in the family and the metric's labelset
+    pub fn check_histogram(
+        &self,
+        metric: &MetricMarshal,
+        histogram: &HistogramValue,
+        gauge_histogram: bool,
+        warnings: &mut Vec<ParseError>,
+    ) -> Result<(), ParseError> {
+        let name = self.name.as_deref().unwrap_or_default();
+        let labels = self.metric_labels(metric);
+
+        let warnings_before = warnings.len();
+        histogram
+            .check_invariants_with_options(gauge_histogram, &self.options, warnings)
+            .map_err(|error| error.in_metric(name, &labels))?;
+
+        let found = warnings.split_off(warnings_before);
+        warnings.extend(found.into_iter().map(|w| w.in_metric(name, &labels)));
+        Ok(())
+    }
+
+    /// Handles a second `kind` line in the family, which is only a warning if `allowed`
+    fn repeated_metadata(&mut self, kind: MetadataKind, allowed: bool) -> Result<(), ParseError> {
+        let error = self.duplicate_metadata(kind);
+        if !allowed {
+            return Err(error);
+        }
+
+        self.warnings.get_mut().push(error);
         Ok(())
     }
 
     pub fn try_add_help(&mut self, help: String) -> Result<(), ParseError> {
-        if self.help.is_some() {
-            return Err(ParseError::InvalidMetric(
-                "Got two help lines in the same metric family".to_string(),
-            ));
+        if let Some(existing) = &self.help {
+            let allowed = self.options.allow_duplicate_help
+                || (self.options.allow_duplicate_metadata && existing == &help);
+            return self.repeated_metadata(MetadataKind::Help, allowed);
         }
 
         self.help = Some(help);
@@ -143,10 +247,9 @@ where
             return Ok(());
         }
 
-        if self.unit.is_some() {
-            return Err(ParseError::InvalidMetric(
-                "Got two unit lines in the same metric family".to_string(),
-            ));
+        if let Some(existing) = &self.unit {
+            let allowed = self.options.allow_duplicate_metadata && existing == &unit;
+            return self.repeated_metadata(MetadataKind::Unit, allowed);
         }
 
         if !self
@@ -166,18 +269,167 @@ where
 
         Ok(())
     }
+}
 
+/// The handlers for each family type: for each set of types, the sample suffixes they accept (e.g. `_bucket`),
+/// the labels those samples must have (e.g. `le`), and how to fold those samples into the family's metrics
+pub type MetricHandlers<T> = Vec<(
+    Vec<T>,
+    Vec<(&'static str, Vec<&'static str>, MetricProcesser)>,
+)>;
+
+impl<T> MetricFamilyMarshal<T>
+where
+    T: MetricsType + Clone + Default + PartialEq + fmt::Debug,
+{
     pub fn try_add_type(&mut self, family_type: T) -> Result<(), ParseError> {
-        if self.family_type.is_some() {
-            return Err(ParseError::InvalidMetric(
-                "Got two type lines in the same metric family".to_string(),
-            ));
+        if let Some(existing) = &self.family_type {
+            let allowed = self.options.allow_duplicate_metadata && existing == &family_type;
+            return self.repeated_metadata(MetadataKind::Type, allowed);
         }
 
         self.family_type = Some(family_type);
 
         Ok(())
     }
+
+    /// Routes a sample to the first handler that matches the family type and the sample's suffix, after
+    /// checking that it fits the family (label names, metric name, timestamps). If `reject_interleaved`
+    /// is set, samples from a labelset that has already been finished with are rejected, as OpenMetrics requires
+    #[allow(clippy::too_many_arguments)]
+    pub fn process_with_handlers(
+        &mut self,
+        handlers: MetricHandlers<T>,
+        metric_name: &str,
+        metric_value: MetricNumber,
+        label_names: Vec<String>,
+        label_values: LabelValues,
+        timestamp: Option<Timestamp>,
+        exemplar: Option<Exemplar>,
+        reject_interleaved: bool,
+    ) -> Result<(), ParseError> {
+        let metric_type = self.family_type.as_ref().cloned().unwrap_or_default();
+
+        for (test_type, actions) in handlers {
+            if test_type.contains(&metric_type) {
+                for (suffix, mandatory_labels, action) in actions {
+                    if !metric_name.ends_with(suffix) {
+                        continue;
+                    }
+
+                    let mut actual_label_names = label_names.clone();
+                    let mut actual_label_values = label_values.clone();
+                    for label in mandatory_labels {
+                        if !label_names.contains(&label.to_owned()) {
+                            return Err(ParseError::InvalidMetric(format!(
+                                "Missing mandatory label for metric: {}",
+                                label
+                            )));
+                        }
+
+                        let index = actual_label_names.iter().position(|s| s == label).unwrap();
+
+                        actual_label_names.remove(index);
+                        actual_label_values.remove(index);
+                    }
+
+                    if reject_interleaved {
+                        match &self.current_label_set {
+                            None => self.current_label_set = Some(actual_label_values.clone()),
+                            Some(s) => {
+                                if s != &actual_label_values
+                                    && self.seen_label_sets.contains(&actual_label_values)
+                                {
+                                    let family = self
+                                        .name
+                                        .as_deref()
+                                        .unwrap_or_else(|| metric_name.trim_end_matches(suffix));
+                                    return Err(ParseError::InterwovenLabelSets {
+                                        family: family.to_owned(),
+                                        labels: actual_label_values.into_vec(),
+                                        after: s.to_vec(),
+                                    });
+                                }
+                            }
+                        }
+                    }
+
+                    self.current_label_set = Some(actual_label_values.clone());
+                    self.seen_label_sets.push(actual_label_values.clone());
+
+                    let name = &metric_name.to_owned();
+                    self.try_set_label_names(
+                        name,
+                        LabelNames::new(name, metric_type.clone(), actual_label_names.clone()),
+                    )?;
+
+                    let metric_name = metric_name.trim_end_matches(suffix);
+                    match &self.name {
+                        Some(name) if name != metric_name => {
+                            return Err(ParseError::InvalidMetric(format!(
+                                "Invalid Name in metric family: {} != {}",
+                                metric_name, name
+                            )));
+                        }
+                        Some(_) => {}
+                        None => self.name = Some(metric_name.to_owned()),
+                    }
+
+                    let (existing_metric, created) = match self
+                        .get_metric_by_labelset_mut(&actual_label_values)
+                    {
+                        Some(metric) => {
+                            match (metric.timestamp.as_ref(), timestamp.as_ref()) {
+                                (Some(metric_timestamp), Some(timestamp)) if timestamp < metric_timestamp => return Err(ParseError::InvalidMetric(format!("Timestamps went backwarts in family - saw {} and then saw{}", metric_timestamp, timestamp))),
+                                (Some(_), None) | (None, Some(_)) => return Err(ParseError::InvalidMetric("Missing timestamp in family (one metric had a timestamp, another didn't)".to_string())),
+                                (Some(metric_timestamp), Some(timestamp)) if timestamp >= metric_timestamp && !metric_type.can_have_multiple_lines() => return Ok(()),
+                                _ => (metric, false)
+                            }
+                        }
+                        None => {
+                            let new_metric = self
+                                .family_type
+                                .as_ref()
+                                .cloned()
+                                .unwrap_or_default()
+                                .get_type_value();
+                            self.add_metric(MetricMarshal::new(
+                                actual_label_values.clone(),
+                                timestamp,
+                                new_metric,
+                            ));
+                            (
+                                self.get_metric_by_labelset_mut(&actual_label_values)
+                                    .unwrap(),
+                                true,
+                            )
+                        }
+                    };
+
+                    return action.0(
+                        existing_metric,
+                        metric_value,
+                        label_names,
+                        label_values,
+                        exemplar,
+                        created,
+                    )
+                    .map_err(|error| {
+                        let labels: Vec<_> = actual_label_names
+                            .into_iter()
+                            .zip(actual_label_values.into_vec())
+                            .collect();
+                        error.in_metric(metric_name, &labels)
+                    });
+                }
+            }
+        }
+
+        Err(ParseError::InvalidMetric(format!(
+            "Found weird metric name for type ({:?}): {}",
+            metric_type, metric_name
+        )))
+    }
 }
 
 #[derive(Debug)]
@@ -217,21 +469,32 @@ where
 
 #[derive(Debug)]
 pub struct MetricMarshal {
-    pub label_values: Vec<String>,
+    pub label_values: LabelValues,
     pub timestamp: Option<Timestamp>,
+
+    /// The timestamp exactly, in nanoseconds, if the parser could work it out (see
+    /// `Sample::timestamp_nanos`)
+    pub timestamp_nanos: Option<i64>,
     pub value: MetricValueMarshal,
+    pub exemplar: Option<Exemplar>,
+
+    /// The line and column of the sample that started the metric, if the parser knows it
+    pub location: Option<(usize, usize)>,
 }
 
 impl MetricMarshal {
     pub fn new(
-        label_values: Vec<String>,
+        label_values: LabelValues,
         timestamp: Option<Timestamp>,
         value: MetricValueMarshal,
     ) -> MetricMarshal {
         MetricMarshal {
             label_values,
             timestamp,
+            timestamp_nanos: None,
             value,
+            exemplar: None,
+            location: None,
         }
     }
 }
@@ -242,7 +505,7 @@ type MetricProccessFunc = dyn Fn(
     &mut MetricMarshal,
     MetricNumber,
     Vec<String>,
-    Vec<String>,
+    LabelValues,
     Option<Exemplar>,
     bool,
 ) -> Result<(), ParseError>;
@@ -254,7 +517,7 @@ impl MetricProcesser {
                 &mut MetricMarshal,
                 MetricNumber,
                 Vec<String>,
-                Vec<String>,
+                LabelValues,
                 Option<Exemplar>,
                 bool,
             ) -> Result<(), ParseError>
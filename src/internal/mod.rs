@@ -1,4 +1,6 @@
 mod marshals;
+#[cfg(any(feature = "protobuf", feature = "remote-write", feature = "otlp"))]
+pub mod protobuf;
 mod traits;
 mod utils;
 
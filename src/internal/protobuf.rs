@@ -0,0 +1,496 @@
+//! Just enough of the protobuf wire format to read and write the handful of fixed Prometheus
+//! (and OTLP) messages, without pulling in a protobuf code generator
+//! https://protobuf.dev/programming-guides/encoding/
+
+#[cfg(any(feature = "protobuf", feature = "remote-write"))]
+use crate::{BucketSpan, MetricNumber, NativeHistogram, ParseError};
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_BYTES: u8 = 2;
+#[cfg(any(feature = "protobuf", feature = "remote-write"))]
+const WIRE_FIXED32: u8 = 5;
+
+/// Builds up an encoded message. Fields that hold their default value are skipped, as proto3 does
+#[cfg(any(feature = "protobuf", feature = "remote-write", feature = "otlp"))]
+#[derive(Debug, Default)]
+pub(crate) struct Encoder {
+    buf: Vec<u8>,
+}
+
+#[cfg(any(feature = "protobuf", feature = "remote-write", feature = "otlp"))]
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    fn put_varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.buf.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+
+        self.buf.push(value as u8);
+    }
+
+    fn put_key(&mut self, field: u32, wire_type: u8) {
+        self.put_varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    pub fn put_uint64(&mut self, field: u32, value: u64) {
+        if value != 0 {
+            self.put_key(field, WIRE_VARINT);
+            self.put_varint(value);
+        }
+    }
+
+    #[cfg(any(feature = "protobuf", feature = "remote-write"))]
+    pub fn put_int64(&mut self, field: u32, value: i64) {
+        self.put_uint64(field, value as u64);
+    }
+
+    /// Writes a zigzag encoded sint64 (or sint32, which has the same encoding)
+    #[cfg(any(feature = "protobuf", feature = "remote-write"))]
+    pub fn put_sint64(&mut self, field: u32, value: i64) {
+        self.put_uint64(field, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    #[cfg(any(feature = "protobuf", feature = "remote-write"))]
+    pub fn put_packed_sint64(&mut self, field: u32, values: &[i64]) {
+        let mut packed = Encoder::new();
+        for value in values {
+            packed.put_varint(((value << 1) ^ (value >> 63)) as u64);
+        }
+
+        self.put_bytes(field, &packed.buf);
+    }
+
+    pub fn put_double(&mut self, field: u32, value: f64) {
+        // Only +0 is the default, -0 and NaN still have to be written
+        if value.to_bits() != 0 {
+            self.put_key(field, WIRE_FIXED64);
+            self.buf.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    pub fn put_bytes(&mut self, field: u32, value: &[u8]) {
+        if !value.is_empty() {
+            self.put_key(field, WIRE_BYTES);
+            self.put_varint(value.len() as u64);
+            self.buf.extend_from_slice(value);
+        }
+    }
+
+    pub fn put_string(&mut self, field: u32, value: &str) {
+        self.put_bytes(field, value.as_bytes());
+    }
+
+    pub fn put_packed_double(&mut self, field: u32, values: &[f64]) {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.put_bytes(field, &bytes);
+    }
+
+    /// Writes an embedded message. Unlike scalars, empty messages are still written, since they're
+    /// usually elements of a repeated field
+    pub fn put_message<F: FnOnce(&mut Encoder)>(&mut self, field: u32, f: F) {
+        let mut message = Encoder::new();
+        f(&mut message);
+        self.put_key(field, WIRE_BYTES);
+        self.put_varint(message.buf.len() as u64);
+        self.buf.extend_from_slice(&message.buf);
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl Encoder {
+    /// Writes a message prefixed by its length, rather than a field key, as used to delimit a
+    /// stream of messages
+    pub fn put_delimited<F: FnOnce(&mut Encoder)>(&mut self, f: F) {
+        let mut message = Encoder::new();
+        f(&mut message);
+        self.put_varint(message.buf.len() as u64);
+        self.buf.extend_from_slice(&message.buf);
+    }
+}
+
+#[cfg(feature = "otlp")]
+impl Encoder {
+    fn put_fixed(&mut self, field: u32, bits: u64) {
+        self.put_key(field, WIRE_FIXED64);
+        self.buf.extend_from_slice(&bits.to_le_bytes());
+    }
+
+    pub fn put_fixed64(&mut self, field: u32, value: u64) {
+        if value != 0 {
+            self.put_fixed(field, value);
+        }
+    }
+
+    /// Writes a double even if it's zero, for `oneof` and `optional` fields, which have presence
+    pub fn put_explicit_double(&mut self, field: u32, value: f64) {
+        self.put_fixed(field, value.to_bits());
+    }
+
+    /// Writes an sfixed64 even if it's zero, for `oneof` and `optional` fields
+    pub fn put_explicit_sfixed64(&mut self, field: u32, value: i64) {
+        self.put_fixed(field, value as u64);
+    }
+
+    pub fn put_packed_fixed64(&mut self, field: u32, values: &[u64]) {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.put_bytes(field, &bytes);
+    }
+}
+
+/// The value of a single field, as it was on the wire
+#[cfg(any(feature = "protobuf", feature = "remote-write"))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum WireValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    /// No message read here has 32 bit fields, so their value is skipped
+    Fixed32,
+}
+
+#[cfg(any(feature = "protobuf", feature = "remote-write"))]
+impl<'a> WireValue<'a> {
+    fn mismatch(&self, expected: &str) -> ParseError {
+        invalid(format!("expected a {}, got {:?}", expected, self))
+    }
+
+    pub fn as_u64(&self) -> Result<u64, ParseError> {
+        match self {
+            WireValue::Varint(v) => Ok(*v),
+            _ => Err(self.mismatch("varint")),
+        }
+    }
+
+    pub fn as_i64(&self) -> Result<i64, ParseError> {
+        self.as_u64().map(|v| v as i64)
+    }
+
+    /// Reads a zigzag encoded sint64 (or sint32)
+    pub fn as_sint64(&self) -> Result<i64, ParseError> {
+        self.as_u64().map(|v| (v >> 1) as i64 ^ -((v & 1) as i64))
+    }
+
+    /// Reads a repeated sint64 field, whether it was packed or not
+    pub fn as_packed_sint64(&self) -> Result<Vec<i64>, ParseError> {
+        let mut decoder = match self {
+            WireValue::Bytes(bytes) => Decoder::new(bytes),
+            _ => return Ok(vec![self.as_sint64()?]),
+        };
+
+        let mut values = Vec::new();
+        while !decoder.remaining().is_empty() {
+            values.push(WireValue::Varint(decoder.read_varint()?).as_sint64()?);
+        }
+
+        Ok(values)
+    }
+
+    /// Reads a repeated double field, whether it was packed or not
+    pub fn as_packed_double(&self) -> Result<Vec<f64>, ParseError> {
+        match self {
+            WireValue::Bytes(bytes) if bytes.len() % 8 == 0 => Ok(bytes
+                .chunks_exact(8)
+                .map(|chunk| f64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect()),
+            WireValue::Bytes(_) => Err(truncated()),
+            _ => Ok(vec![self.as_f64()?]),
+        }
+    }
+
+    pub fn as_f64(&self) -> Result<f64, ParseError> {
+        match self {
+            WireValue::Fixed64(v) => Ok(f64::from_bits(*v)),
+            _ => Err(self.mismatch("double")),
+        }
+    }
+
+    pub fn as_bytes(&self) -> Result<&'a [u8], ParseError> {
+        match self {
+            WireValue::Bytes(b) => Ok(b),
+            _ => Err(self.mismatch("length delimited field")),
+        }
+    }
+
+    pub fn as_str(&self) -> Result<&'a str, ParseError> {
+        std::str::from_utf8(self.as_bytes()?)
+            .map_err(|e| invalid(format!("invalid UTF-8 in string field: {}", e)))
+    }
+
+    /// The fields of an embedded message
+    pub fn as_message(&self) -> Result<Decoder<'a>, ParseError> {
+        self.as_bytes().map(Decoder::new)
+    }
+}
+
+/// Reads the fields of an encoded message, in order
+#[cfg(any(feature = "protobuf", feature = "remote-write"))]
+#[derive(Debug, Clone)]
+pub(crate) struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+#[cfg(any(feature = "protobuf", feature = "remote-write"))]
+fn invalid(reason: String) -> ParseError {
+    ParseError::Decode {
+        format: "protobuf message",
+        reason,
+    }
+}
+
+#[cfg(any(feature = "protobuf", feature = "remote-write"))]
+fn truncated() -> ParseError {
+    invalid("truncated".to_owned())
+}
+
+#[cfg(any(feature = "protobuf", feature = "remote-write"))]
+impl<'a> Decoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    /// The bytes that haven't been read yet
+    pub fn remaining(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64, ParseError> {
+        let mut value = 0u64;
+        for (i, byte) in self.buf.iter().enumerate().take(10) {
+            value |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.buf = &self.buf[i + 1..];
+                return Ok(value);
+            }
+        }
+
+        Err(truncated())
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ParseError> {
+        if self.buf.len() < len {
+            return Err(truncated());
+        }
+
+        let (taken, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(taken)
+    }
+
+    /// Reads a length prefixed chunk, as used to delimit a stream of messages
+    pub fn read_delimited(&mut self) -> Result<&'a [u8], ParseError> {
+        let len = self.read_varint()? as usize;
+        self.take(len)
+    }
+
+    /// Reads the next field number and value, or None at the end of the message
+    pub fn next_field(&mut self) -> Result<Option<(u32, WireValue<'a>)>, ParseError> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+
+        let key = self.read_varint()?;
+        let field = (key >> 3) as u32;
+        let value = match (key & 0x7) as u8 {
+            WIRE_VARINT => WireValue::Varint(self.read_varint()?),
+            WIRE_FIXED64 => {
+                WireValue::Fixed64(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+            }
+            WIRE_BYTES => WireValue::Bytes(self.read_delimited()?),
+            WIRE_FIXED32 => {
+                self.take(4)?;
+                WireValue::Fixed32
+            }
+            wire_type => return Err(invalid(format!("unsupported wire type: {}", wire_type))),
+        };
+
+        Ok(Some((field, value)))
+    }
+}
+
+/// Native histograms take up ten consecutive fields, in the same order in both the exposition
+/// `Histogram` and the remote write `Histogram` messages, starting from the `schema`. Counts are
+/// sent as deltas between buckets if they're all integers, or as floats otherwise
+#[cfg(any(feature = "protobuf", feature = "remote-write"))]
+pub(crate) fn encode_native_histogram(
+    encoder: &mut Encoder,
+    schema_field: u32,
+    native: &NativeHistogram,
+) {
+    let float = native.is_float();
+
+    encoder.put_sint64(schema_field, native.schema as i64);
+    encoder.put_double(schema_field + 1, native.zero_threshold);
+    match native.zero_count {
+        MetricNumber::Int(count) if !float => encoder.put_uint64(schema_field + 2, count as u64),
+        count => encoder.put_double(schema_field + 3, count.as_f64()),
+    }
+
+    // A histogram with no buckets at all still needs something to mark it as native, which
+    // is done with an empty span, as the client libraries do
+    let empty = native.positive_spans.is_empty()
+        && native.negative_spans.is_empty()
+        && native.zero_threshold == 0.
+        && native.zero_count.as_f64() == 0.;
+    let marker = [BucketSpan::new(0, 0)];
+    let sides = [
+        (
+            schema_field + 4,
+            &native.negative_spans[..],
+            &native.negative_counts,
+        ),
+        (
+            schema_field + 7,
+            if empty {
+                &marker[..]
+            } else {
+                &native.positive_spans[..]
+            },
+            &native.positive_counts,
+        ),
+    ];
+
+    for (spans_field, spans, counts) in sides {
+        for span in spans {
+            encoder.put_message(spans_field, |e| {
+                e.put_sint64(1, span.offset as i64);
+                e.put_uint64(2, span.length as u64);
+            });
+        }
+
+        if float {
+            let counts: Vec<f64> = counts.iter().map(|c| c.as_f64()).collect();
+            encoder.put_packed_double(spans_field + 2, &counts);
+        } else {
+            let mut previous = 0;
+            let deltas: Vec<i64> = counts
+                .iter()
+                .map(|count| {
+                    let count = count.as_f64() as i64;
+                    // Counts are never negative, so the difference always fits. Wrapping keeps a
+                    // malformed negative count from panicking here; the decoder rejects it instead
+                    let delta = count.wrapping_sub(previous);
+                    previous = count;
+                    delta
+                })
+                .collect();
+            encoder.put_packed_sint64(spans_field + 1, &deltas);
+        }
+    }
+}
+
+/// Picks the native histogram fields out of a histogram message, as they're read
+#[cfg(any(feature = "protobuf", feature = "remote-write"))]
+#[derive(Debug)]
+pub(crate) struct NativeHistogramDecoder {
+    schema_field: u32,
+    native: Option<NativeHistogram>,
+    deltas: [Vec<i64>; 2],
+    counts: [Vec<f64>; 2],
+}
+
+#[cfg(any(feature = "protobuf", feature = "remote-write"))]
+impl NativeHistogramDecoder {
+    pub fn new(schema_field: u32) -> Self {
+        Self {
+            schema_field,
+            native: None,
+            deltas: Default::default(),
+            counts: Default::default(),
+        }
+    }
+
+    /// Reads the field if it's part of the native histogram, returning whether it was
+    pub fn read_field(&mut self, field: u32, value: WireValue) -> Result<bool, ParseError> {
+        let offset = match field.checked_sub(self.schema_field) {
+            Some(offset) if offset < 10 => offset,
+            _ => return Ok(false),
+        };
+
+        let native = self.native.get_or_insert_with(NativeHistogram::default);
+        match offset {
+            0 => native.schema = value.as_sint64()? as i32,
+            1 => native.zero_threshold = value.as_f64()?,
+            2 => native.zero_count = MetricNumber::Int(value.as_u64()? as i64),
+            3 => native.zero_count = MetricNumber::Float(value.as_f64()?),
+            _ => {
+                // Negative buckets come first, then the positive ones
+                let side = (offset as usize - 4) / 3;
+                match (offset - 4) % 3 {
+                    0 => {
+                        let mut span = BucketSpan::default();
+                        let mut decoder = value.as_message()?;
+                        while let Some((field, value)) = decoder.next_field()? {
+                            match field {
+                                1 => span.offset = value.as_sint64()? as i32,
+                                2 => span.length = value.as_u64()? as u32,
+                                _ => {}
+                            }
+                        }
+
+                        // Empty spans only mark a histogram without buckets as native
+                        if span != BucketSpan::default() {
+                            let spans = match side {
+                                0 => &mut native.negative_spans,
+                                _ => &mut native.positive_spans,
+                            };
+                            spans.push(span);
+                        }
+                    }
+                    1 => self.deltas[side].extend(value.as_packed_sint64()?),
+                    _ => self.counts[side].extend(value.as_packed_double()?),
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// The native histogram, if the message had one
+    pub fn finish(self) -> Result<Option<NativeHistogram>, ParseError> {
+        let mut native = match self.native {
+            Some(native) => native,
+            None => return Ok(None),
+        };
+
+        for (side, (deltas, counts)) in self.deltas.into_iter().zip(self.counts).enumerate() {
+            let (spans, bucket_counts) = match side {
+                0 => (&native.negative_spans, &mut native.negative_counts),
+                _ => (&native.positive_spans, &mut native.positive_counts),
+            };
+
+            if deltas.is_empty() {
+                bucket_counts.extend(counts.into_iter().map(MetricNumber::Float));
+            } else {
+                let mut count: i64 = 0;
+                for delta in deltas {
+                    count = count.checked_add(delta).ok_or_else(|| {
+                        ParseError::InvalidMetric(
+                            "Native histogram bucket count overflowed".to_owned(),
+                        )
+                    })?;
+                    bucket_counts.push(MetricNumber::Int(count));
+                }
+            }
+
+            let buckets: u64 = spans.iter().map(|s| s.length as u64).sum();
+            if buckets != bucket_counts.len() as u64 {
+                return Err(ParseError::InvalidMetric(format!(
+                    "Native histogram spans cover {} buckets, but have {} counts",
+                    buckets,
+                    bucket_counts.len()
+                )));
+            }
+        }
+
+        Ok(Some(native))
+    }
+}
@@ -1,17 +1,54 @@
 use std::fmt;
 
-use crate::{Exemplar, MetricNumber, ParseError, Timestamp};
+use crate::{Exemplar, LabelValues, MetricNumber, ParseError, Timestamp};
 
 use super::{MetricFamilyMarshal, MetricValueMarshal};
 
+/// The rules for a family type (e.g. `counter`), that the parsers use to decide which samples it can contain
 pub trait MetricsType {
+    /// Whether the sample called `metric_name` can have an exemplar
     fn can_have_exemplar(&self, metric_name: &str) -> bool;
+
+    /// Whether the family can have a `# UNIT`
     fn can_have_units(&self) -> bool;
+
+    /// Whether a metric in the family is made up of multiple samples (e.g. a histogram's `_bucket`s, `_sum`, and `_count`)
     fn can_have_multiple_lines(&self) -> bool;
+
+    /// Labels that identify the samples within a metric, rather than the metric itself (e.g. `le` on `_bucket`s)
     fn get_ignored_labels(&self, metric_name: &str) -> &[&str];
+
+    /// The empty value a new metric of this type starts out as
     fn get_type_value(&self) -> MetricValueMarshal;
 }
 
+/// A dialect of the OpenMetrics text format: a set of family types, and how samples are folded into
+/// them. `OpenMetricsType` is the standard one, and a custom dialect (e.g. with vendor specific types)
+/// can be parsed with `openmetrics::parse_openmetrics_dialect`, which reuses the grammar and grouping logic
+pub trait MetricsDialect: MetricsType + Clone + Default + PartialEq + fmt::Debug {
+    type Value: RenderableMetricValue + Clone;
+
+    /// Parses the type in a `# TYPE` line
+    fn parse_type(type_name: &str) -> Result<Self, ParseError>;
+
+    /// Folds a sample into the family, usually with `MetricFamilyMarshal::process_with_handlers`
+    fn process_new_metric(
+        family: &mut MetricFamilyMarshal<Self>,
+        metric_name: &str,
+        value: MetricNumber,
+        label_names: Vec<String>,
+        label_values: LabelValues,
+        timestamp: Option<Timestamp>,
+        exemplar: Option<Exemplar>,
+    ) -> Result<(), ParseError>;
+
+    /// Checks the finished family
+    fn validate_family(family: &MetricFamilyMarshal<Self>) -> Result<(), ParseError>;
+
+    /// Converts a finished metric into the dialect's value type
+    fn convert_value(value: MetricValueMarshal) -> Self::Value;
+}
+
 pub trait MarshalledMetricFamily {
     type Error;
     fn process_new_metric(
@@ -19,7 +56,7 @@ pub trait MarshalledMetricFamily {
         metric_name: &str,
         value: MetricNumber,
         label_names: Vec<String>,
-        label_values: Vec<String>,
+        label_values: LabelValues,
         timestamp: Option<Timestamp>,
         exemplar: Option<Exemplar>,
     ) -> Result<(), Self::Error>;
@@ -35,11 +72,13 @@ where
 }
 
 pub trait RenderableMetricValue {
+    /// Writes out the value's lines. `timestamp` is the sample's timestamp, already formatted
+    /// for its format (so parsed timestamps keep every digit they were written with)
     fn render(
         &self,
         f: &mut fmt::Formatter<'_>,
         metric_name: &str,
-        timestamp: Option<&Timestamp>,
+        timestamp: Option<&str>,
         label_names: &[&str],
         label_values: &[&str],
     ) -> fmt::Result;
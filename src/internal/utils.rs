@@ -1,3 +1,10 @@
+use std::{borrow::Cow, fs, io, path::Path};
+
+use crate::{
+    escape_label_value, format_bound, HistogramBucket, HistogramValue, MetricNumber, ParseError,
+    ParserOptions, TimestampUnit,
+};
+
 pub fn render_label_values(label_names: &[&str], label_values: &[&str]) -> String {
     if label_names.is_empty() {
         return String::new();
@@ -8,10 +15,565 @@ pub fn render_label_values(label_names: &[&str], label_values: &[&str]) -> Strin
     build.push('{');
     let mut labels = Vec::new();
     for (name, value) in label_names.iter().zip(label_values.iter()) {
-        labels.push(format!("{}=\"{}\"", name, value));
+        labels.push(format!("{}=\"{}\"", name, escape_label_value(value)));
     }
     build.push_str(&labels.join(","));
     build.push('}');
 
     build
 }
+
+/// Reads an exposition from a file, as UTF-8 unless it starts with a UTF-16 byte order mark.
+/// Byte order marks are dropped, and errors put in the file
+pub fn read_exposition_file(path: &Path) -> Result<String, ParseError> {
+    let invalid = |e: &dyn std::fmt::Display| {
+        ParseError::Io(io::Error::new(io::ErrorKind::InvalidData, e.to_string())).in_file(path)
+    };
+
+    let bytes = fs::read(path).map_err(|e| ParseError::Io(e).in_file(path))?;
+    let utf16 = |bytes: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+        if bytes.len() % 2 != 0 {
+            return Err(invalid(&"UTF-16 text with an odd number of bytes"));
+        }
+
+        let units: Vec<_> = bytes
+            .chunks_exact(2)
+            .map(|pair| from_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16(&units).map_err(|e| invalid(&e))
+    };
+
+    match bytes.as_slice() {
+        [0xef, 0xbb, 0xbf, rest @ ..] => String::from_utf8(rest.to_vec()).map_err(|e| invalid(&e)),
+        [0xff, 0xfe, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xfe, 0xff, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        _ => String::from_utf8(bytes).map_err(|e| invalid(&e)),
+    }
+}
+
+/// Adds a bucket to a histogram, rejecting (or merging, if `merge_duplicates` is set) buckets
+/// whose upper bound has already been seen
+pub fn push_bucket(
+    histogram: &mut HistogramValue,
+    bucket: HistogramBucket,
+    merge_duplicates: bool,
+) -> Result<(), ParseError> {
+    match histogram
+        .buckets
+        .iter_mut()
+        .find(|b| b.upper_bound == bucket.upper_bound)
+    {
+        Some(existing) if merge_duplicates => {
+            if bucket.count.as_f64() > existing.count.as_f64() {
+                existing.count = bucket.count;
+            }
+
+            if existing.exemplar.is_none() {
+                existing.exemplar = bucket.exemplar;
+            }
+
+            Ok(())
+        }
+        Some(_) => Err(ParseError::DuplicateBucket(bucket.upper_bound)),
+        None => {
+            histogram.buckets.push(bucket);
+            Ok(())
+        }
+    }
+}
+
+/// Rewrites any `le` or `quantile` label values into their canonical form (see `format_bound`).
+/// Values that aren't numbers are left alone for the type specific validation to deal with
+pub fn normalize_bound_labels(label_names: &[String], label_values: &mut [String]) {
+    for (name, value) in label_names.iter().zip(label_values.iter_mut()) {
+        if name != "le" && name != "quantile" {
+            continue;
+        }
+
+        if let Ok(bound) = value.parse::<f64>() {
+            *value = format_bound(bound);
+        }
+    }
+}
+
+/// Applies the lenient input rewrites enabled in the given options, before the input
+/// gets to the grammar
+pub fn normalize_input<'a>(input: &'a str, options: &ParserOptions) -> Cow<'a, str> {
+    let mut input = Cow::Borrowed(input);
+    if options.allow_crlf && input.contains('\r') {
+        input = Cow::Owned(input.replace("\r\n", "\n"));
+    }
+
+    if options.trim_whitespace {
+        // A last line without a newline is kept that way, so truncated input is still rejected
+        let mut trimmed = String::with_capacity(input.len());
+        for line in input.split_inclusive('\n') {
+            let content = line.trim_end_matches('\n').trim_end_matches('\r');
+            let content = content.trim_end_matches([' ', '\t']);
+            if !content.is_empty() {
+                trimmed.push_str(content);
+                if line.ends_with('\n') {
+                    trimmed.push('\n');
+                }
+            }
+        }
+
+        input = Cow::Owned(trimmed);
+    }
+
+    if options.collapse_whitespace {
+        let mut collapsed = String::with_capacity(input.len());
+        for line in input.split_inclusive('\n') {
+            let content = line.trim_end_matches('\n');
+            if content.trim_start_matches([' ', '\t']).starts_with('#') {
+                collapse_descriptor(content, &mut collapsed);
+            } else {
+                collapse_sample(content, &mut collapsed);
+            }
+
+            if line.ends_with('\n') {
+                collapsed.push('\n');
+            }
+        }
+
+        if collapsed != *input {
+            input = Cow::Owned(collapsed);
+        }
+    }
+
+    input
+}
+
+const BLANKS: [char; 2] = [' ', '\t'];
+
+/// Writes out a `# HELP`, `# TYPE`, `# UNIT` or `# EOF` line with single spaces between its
+/// tokens. Whatever follows the name (e.g. help text) is kept as it is
+fn collapse_descriptor(line: &str, out: &mut String) {
+    let mut tokens = Vec::with_capacity(3);
+    let mut rest = line;
+    while tokens.len() < 3 {
+        let token = rest.trim_start_matches(BLANKS);
+        if token.is_empty() {
+            break;
+        }
+
+        let end = token.find(BLANKS).unwrap_or(token.len());
+        tokens.push(&token[..end]);
+        rest = &token[end..];
+    }
+
+    match tokens.as_slice() {
+        ["#", "HELP" | "TYPE" | "UNIT", _] => {
+            out.push_str(&tokens.join(" "));
+            if !rest.is_empty() {
+                out.push(' ');
+                out.push_str(rest.trim_start_matches(BLANKS));
+            }
+        }
+        ["#", "EOF"] => out.push_str("# EOF"),
+        _ => out.push_str(line),
+    }
+}
+
+/// Writes out a sample line with single spaces between its tokens, and none inside its
+/// labelsets, leaving quoted strings as they are
+fn collapse_sample(line: &str, out: &mut String) {
+    let mut prev = None;
+    let (mut blank, mut quoted, mut escaped) = (false, false, false);
+    for c in line.chars() {
+        if quoted {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                quoted = false;
+            }
+        } else if BLANKS.contains(&c) {
+            blank = true;
+            continue;
+        } else {
+            let joined = matches!(prev, None | Some('{' | ',' | '='))
+                || matches!(c, '}' | ',' | '=')
+                || (c == '{' && prev.is_some_and(|p: char| p.is_alphanumeric() || p == '_'));
+            if blank && !joined {
+                out.push(' ');
+            }
+
+            blank = false;
+            quoted = c == '"';
+        }
+
+        out.push(c);
+        prev = Some(c);
+    }
+}
+
+/// The line of the original input that a line of the normalized input (see `normalize_input`)
+/// came from, as trimming whitespace drops blank lines
+pub fn original_line(input: &str, options: &ParserOptions, line: usize) -> usize {
+    if !options.trim_whitespace {
+        return line;
+    }
+
+    let mut kept = 0;
+    for (i, original) in input.split_inclusive('\n').enumerate() {
+        if !original
+            .trim_end_matches(['\n', '\r', ' ', '\t'])
+            .is_empty()
+        {
+            kept += 1;
+            if kept == line {
+                return i + 1;
+            }
+        }
+    }
+
+    line
+}
+
+/// Attaches a place in the input to the warnings from `from` onwards, i.e. the ones found since
+pub fn locate_warnings(warnings: &mut Vec<ParseError>, from: usize, line: usize, column: usize) {
+    let found = warnings.split_off(from);
+    warnings.extend(found.into_iter().map(|warning| warning.at(line, column)));
+}
+
+/// Metric names are ASCII letters, digits, underscores and colons, and can't start with a digit
+pub fn is_metric_name_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_' || b == b':'
+}
+
+pub fn is_metric_name_char(b: u8) -> bool {
+    is_metric_name_start(b) || b.is_ascii_digit()
+}
+
+/// Label names are the same as metric names, without the colons
+pub fn is_label_name_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_'
+}
+
+pub fn is_label_name_char(b: u8) -> bool {
+    is_label_name_start(b) || b.is_ascii_digit()
+}
+
+fn is_valid_name(name: &str, start: fn(u8) -> bool, rest: fn(u8) -> bool) -> bool {
+    match name.as_bytes().split_first() {
+        Some((first, rest_bytes)) => start(*first) && rest_bytes.iter().all(|b| rest(*b)),
+        None => false,
+    }
+}
+
+pub fn is_valid_metric_name(name: &str) -> bool {
+    is_valid_name(name, is_metric_name_start, is_metric_name_char)
+}
+
+pub fn is_valid_label_name(name: &str) -> bool {
+    is_valid_name(name, is_label_name_start, is_label_name_char)
+}
+
+/// A counter error without any context, which the family it's found in fills in
+/// (see `ParseError::in_metric`)
+pub fn invalid_counter(reason: String) -> ParseError {
+    ParseError::InvalidCounter {
+        reason,
+        family: String::new(),
+        labels: Vec::new(),
+    }
+}
+
+/// A summary error without any context, in the same way as `invalid_counter`
+pub fn invalid_summary(reason: String) -> ParseError {
+    ParseError::InvalidSummary {
+        reason,
+        family: String::new(),
+        labels: Vec::new(),
+    }
+}
+
+/// NaN counter totals are rejected unless `allow_nan_counters` is set. They're reported apart
+/// from negative totals, which `counter_negativity` decides on
+pub fn check_counter_nan(value: f64, allow_nan_counters: bool) -> Result<(), ParseError> {
+    if value.is_nan() && !allow_nan_counters {
+        return Err(invalid_counter("Counter totals can't be NaN".to_string()));
+    }
+
+    Ok(())
+}
+
+pub fn check_counter_negativity(
+    value: f64,
+    options: &ParserOptions,
+    warnings: &mut Vec<ParseError>,
+) -> Result<(), ParseError> {
+    if value < 0. {
+        options.counter_negativity.enforce(
+            invalid_counter(format!(
+                "Counter totals must be non negative (got: {})",
+                value
+            )),
+            warnings,
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn check_stateset_value(
+    value: f64,
+    options: &ParserOptions,
+    warnings: &mut Vec<ParseError>,
+) -> Result<(), ParseError> {
+    if value != 0. && (value - 1.).abs() > f64::EPSILON {
+        options.stateset_values.enforce(
+            ParseError::InvalidStateSet {
+                reason: format!("Stateset value must be 0 or 1 (got: {})", value),
+                family: String::new(),
+                labels: Vec::new(),
+            },
+            warnings,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Parses a sample value as an integer if it is one, and as a float otherwise
+pub fn parse_metric_number(text: &str) -> Option<MetricNumber> {
+    match parse_i64(text) {
+        Some(i) => Some(MetricNumber::Int(i)),
+        None => parse_f64(text).map(MetricNumber::Float),
+    }
+}
+
+/// Parses an integer exactly as `str::parse::<i64>` would
+pub fn parse_i64(text: &str) -> Option<i64> {
+    #[cfg(feature = "fast-float")]
+    {
+        let (negative, digits) = match text.as_bytes() {
+            [b'-', digits @ ..] => (true, digits),
+            [b'+', digits @ ..] => (false, digits),
+            digits => (false, digits),
+        };
+
+        // 18 digits can't overflow, so anything longer takes the slow path
+        if !digits.is_empty() && digits.len() <= 18 {
+            let mut value = 0i64;
+            for &digit in digits {
+                if !digit.is_ascii_digit() {
+                    return None;
+                }
+                value = value * 10 + (digit - b'0') as i64;
+            }
+
+            return Some(if negative { -value } else { value });
+        }
+    }
+
+    text.parse().ok()
+}
+
+/// Parses a float as `str::parse::<f64>` would, or failing that as a hexadecimal float (e.g.
+/// `0x1p-3`), which Go clients can write out
+pub fn parse_f64(text: &str) -> Option<f64> {
+    #[cfg(feature = "fast-float")]
+    if let Some(f) = fast_f64(text.as_bytes()) {
+        return Some(f);
+    }
+
+    match text.parse() {
+        Ok(f) => Some(f),
+        Err(_) => parse_hex_f64(text),
+    }
+}
+
+/// Parses a timestamp written in `unit` into nanoseconds since the epoch, straight from its
+/// decimal digits so that none of them are lost to a float. Digits past the nanosecond are
+/// truncated, and timestamps that don't fit in an i64 give None
+pub fn parse_timestamp_nanos(text: &str, unit: TimestampUnit) -> Option<i64> {
+    let (negative, digits) = match text.as_bytes().first()? {
+        b'-' => (true, &text[1..]),
+        b'+' => (false, &text[1..]),
+        _ => (false, text),
+    };
+
+    if digits.starts_with("0x") || digits.starts_with("0X") {
+        let nanos = unit.convert(parse_hex_f64(text)?, TimestampUnit::Nanoseconds);
+        return (nanos.abs() < i64::MAX as f64).then_some(nanos as i64);
+    }
+
+    let (mantissa, exponent) = match digits.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<i32>().ok()?),
+        None => (digits, 0),
+    };
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+    // The timestamp is `value` * 10^`scale` nanoseconds. Digits that don't fit in `value` are
+    // too small to matter once it's that large, so are dropped
+    let mut value: i128 = 0;
+    let fraction_digits = i32::try_from(fraction.len()).ok()?;
+    let mut scale = exponent.checked_add(TimestampUnit::Nanoseconds.digits() - unit.digits())?;
+    scale = scale.checked_sub(fraction_digits)?;
+    for digit in integer.bytes().chain(fraction.bytes()) {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+
+        if value < i128::MAX / 100 {
+            value = value * 10 + i128::from(digit - b'0');
+        } else {
+            scale = scale.checked_add(1)?;
+        }
+    }
+
+    let nanos = match scale {
+        _ if value == 0 => 0,
+        0..=38 => value.checked_mul(10i128.pow(scale as u32))?,
+        39.. => return None,
+        -38..=-1 => value / 10i128.pow(-scale as u32),
+        _ => 0,
+    };
+
+    i64::try_from(if negative { -nanos } else { nanos }).ok()
+}
+
+/// Parses a hexadecimal float as Go's `strconv.ParseFloat` does: a `0x` prefix, hex digits with an
+/// optional point, and a mandatory binary exponent, e.g. `-0x1.8p1` (-3)
+fn parse_hex_f64(text: &str) -> Option<f64> {
+    let (negative, text) = match text.as_bytes().first()? {
+        b'-' => (true, &text[1..]),
+        b'+' => (false, &text[1..]),
+        _ => (false, text),
+    };
+
+    let text = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))?;
+    let (mantissa, exponent) = text.split_once(['p', 'P'])?;
+    let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if integer.is_empty() && fraction.is_empty() {
+        return None;
+    }
+
+    let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    // Anything past this overflows (or underflows) whatever the mantissa is
+    const MAX_EXPONENT: i64 = 100_000;
+    let mut exponent = match exponent.parse::<i64>() {
+        Ok(exponent) => exponent.clamp(-MAX_EXPONENT, MAX_EXPONENT),
+        Err(_) if exponent.starts_with('-') => -MAX_EXPONENT,
+        Err(_) => MAX_EXPONENT,
+    };
+
+    // The mantissa keeps 60 bits, enough to round correctly once the digits that don't fit are
+    // folded into its lowest bit
+    let mut bits = 0u64;
+    let mut sticky = false;
+    for (i, c) in integer.chars().chain(fraction.chars()).enumerate() {
+        let digit = c.to_digit(16)? as u64;
+        let in_fraction = i >= integer.len();
+        if bits >> 56 == 0 {
+            bits = bits << 4 | digit;
+            if in_fraction {
+                exponent -= 4;
+            }
+        } else {
+            sticky |= digit != 0;
+            if !in_fraction {
+                exponent += 4;
+            }
+        }
+    }
+
+    let mut value = (bits | sticky as u64) as f64;
+    while exponent > 0 {
+        let step = exponent.min(1000);
+        value *= 2f64.powi(step as i32);
+        exponent -= step;
+    }
+    while exponent < 0 {
+        let step = exponent.max(-1000);
+        value *= 2f64.powi(step as i32);
+        exponent -= step;
+    }
+
+    Some(if negative { -value } else { value })
+}
+
+/// Clinger's fast path: a decimal whose mantissa fits in 53 bits, scaled by an exactly
+/// representable power of ten, is correctly rounded by a single multiplication or division.
+/// Anything else (long mantissas, big exponents, `NaN`, `Inf`...) returns None
+#[cfg(feature = "fast-float")]
+fn fast_f64(text: &[u8]) -> Option<f64> {
+    const POWERS: [f64; 23] = [
+        1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15, 1e16,
+        1e17, 1e18, 1e19, 1e20, 1e21, 1e22,
+    ];
+
+    let (negative, mut rest) = match text {
+        [b'-', rest @ ..] => (true, rest),
+        [b'+', rest @ ..] => (false, rest),
+        rest => (false, rest),
+    };
+
+    let mut mantissa = 0u64;
+    let mut digits = 0;
+    let mut exponent = 0i32;
+
+    while let [digit @ b'0'..=b'9', tail @ ..] = rest {
+        mantissa = mantissa.checked_mul(10)? + (digit - b'0') as u64;
+        digits += 1;
+        rest = tail;
+    }
+
+    if let [b'.', tail @ ..] = rest {
+        rest = tail;
+        while let [digit @ b'0'..=b'9', tail @ ..] = rest {
+            mantissa = mantissa.checked_mul(10)? + (digit - b'0') as u64;
+            digits += 1;
+            exponent -= 1;
+            rest = tail;
+        }
+    }
+
+    if digits == 0 || mantissa >= 1 << 53 {
+        return None;
+    }
+
+    if let [b'e' | b'E', tail @ ..] = rest {
+        let (negative, mut tail) = match tail {
+            [b'-', tail @ ..] => (true, tail),
+            [b'+', tail @ ..] => (false, tail),
+            tail => (false, tail),
+        };
+
+        if tail.is_empty() || tail.len() > 4 {
+            return None;
+        }
+
+        let mut explicit = 0i32;
+        while let [digit @ b'0'..=b'9', rest @ ..] = tail {
+            explicit = explicit * 10 + (digit - b'0') as i32;
+            tail = rest;
+        }
+
+        if !tail.is_empty() {
+            return None;
+        }
+
+        exponent += if negative { -explicit } else { explicit };
+    } else if !rest.is_empty() {
+        return None;
+    }
+
+    let value = mantissa as f64;
+    let value = match exponent {
+        0..=22 => value * POWERS[exponent as usize],
+        -22..=-1 => value / POWERS[-exponent as usize],
+        _ => return None,
+    };
+
+    Some(if negative { -value } else { value })
+}
@@ -5,9 +5,15 @@ extern crate pest_derive;
 #[cfg(test)]
 extern crate serde;
 
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod convert;
+pub mod extension;
 mod internal;
 pub mod openmetrics;
 pub mod prometheus;
 mod public;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 pub use public::*;
 pub use internal::RenderableMetricValue;
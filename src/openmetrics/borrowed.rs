@@ -0,0 +1,86 @@
+use std::borrow::Cow;
+
+use super::{visit_openmetrics, OpenMetricsVisitor, RawExemplar, RawFamily, RawSample};
+use crate::{MetricNumber, ParseError, Timestamp};
+
+/// A family borrowed from the exposition it was parsed from (see `parse_openmetrics_borrowed`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricFamilyRef<'a> {
+    pub name: &'a str,
+    pub family_type: &'a str,
+    pub help: Cow<'a, str>,
+    pub unit: &'a str,
+    pub samples: Vec<SampleRef<'a>>,
+}
+
+/// A sample borrowed from the exposition it was parsed from. Label values are only copied if
+/// they have escapes in them
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleRef<'a> {
+    pub name: &'a str,
+    pub labels: Vec<(&'a str, Cow<'a, str>)>,
+    pub value: MetricNumber,
+    pub timestamp: Option<Timestamp>,
+    pub exemplar: Option<RawExemplar<'a>>,
+}
+
+impl SampleRef<'_> {
+    /// The value of the label with the given name, if the sample has it
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(label, _)| *label == name)
+            .map(|(_, value)| value.as_ref())
+    }
+}
+
+/// Parses an exposition into families that borrow their names, label names and (unless they
+/// have to be unescaped) label values from it, rather than copying each one into a `String`. As
+/// with `visit_openmetrics`, only the syntax is checked, so these are best suited to input that's
+/// known to be valid, or that's only passed through
+/// ```
+/// use openmetrics_parser::openmetrics::parse_openmetrics_borrowed;
+///
+/// let exposition = "# TYPE foo gauge\nfoo{a=\"b\"} 1\n# EOF\n".to_string();
+/// let families = parse_openmetrics_borrowed(&exposition).unwrap();
+/// assert_eq!(families[0].name, "foo");
+/// assert_eq!(families[0].samples[0].label("a"), Some("b"));
+/// ```
+pub fn parse_openmetrics_borrowed(
+    exposition: &str,
+) -> Result<Vec<MetricFamilyRef<'_>>, ParseError> {
+    #[derive(Default)]
+    struct Families<'a>(Vec<MetricFamilyRef<'a>>);
+
+    impl<'a> OpenMetricsVisitor<'a> for Families<'a> {
+        fn on_family_start(&mut self, family: &RawFamily<'a>) {
+            self.0.push(MetricFamilyRef {
+                name: family.name,
+                family_type: family.family_type,
+                help: family.help.clone(),
+                unit: family.unit,
+                samples: Vec::new(),
+            });
+        }
+
+        fn on_sample(&mut self, sample: &RawSample<'a>) {
+            // Samples always come after their family has started
+            self.0.last_mut().unwrap().samples.push(SampleRef {
+                name: sample.name,
+                labels: sample.labels.clone(),
+                value: sample.value,
+                timestamp: sample.timestamp,
+                exemplar: None,
+            });
+        }
+
+        fn on_exemplar(&mut self, exemplar: &RawExemplar<'a>) {
+            let family = self.0.last_mut().unwrap();
+            family.samples.last_mut().unwrap().exemplar = Some(exemplar.clone());
+        }
+    }
+
+    let mut families = Families::default();
+    visit_openmetrics(exposition, &mut families)?;
+    Ok(families.0)
+}
@@ -0,0 +1,494 @@
+use pest::{iterators::Pair, Parser};
+
+use super::parsers::{OpenMetricsParser, Rule};
+use crate::{
+    internal::{
+        is_label_name_char, is_label_name_start, is_metric_name_char, is_metric_name_start,
+    },
+    MetadataKind, ParseError,
+};
+
+/// The lines of an exposition, split up into their parts (still escaped) by either the pest
+/// grammar or the hand-written lexer (see `ParserOptions::fast_lexer`)
+#[derive(Debug, Default)]
+pub(super) struct Lexed<'i> {
+    pub(super) lines: Vec<LexedLine<'i>>,
+
+    /// The line and column of the `EOF` in the `# EOF` line, if there was one
+    pub(super) eof: Option<(usize, usize)>,
+
+    /// Everything after the `EOF` in the `# EOF` line, including the newline ending it
+    pub(super) after_eof: &'i str,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct LexedLine<'i> {
+    pub(super) line: Line<'i>,
+    pub(super) number: usize,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum Line<'i> {
+    Descriptor {
+        kind: MetadataKind,
+        name: &'i str,
+        // The help text, type or unit
+        text: &'i str,
+    },
+    Sample(SampleLine<'i>),
+    /// Any other line starting with a `#`, in full
+    Comment(&'i str),
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct SampleLine<'i> {
+    pub(super) name: &'i str,
+    pub(super) labels: Vec<(&'i str, &'i str)>,
+    pub(super) value: &'i str,
+    pub(super) timestamp: Option<&'i str>,
+    pub(super) exemplar: Option<ExemplarLine<'i>>,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct ExemplarLine<'i> {
+    pub(super) labels: Vec<(&'i str, &'i str)>,
+    pub(super) value: &'i str,
+    pub(super) timestamp: Option<&'i str>,
+}
+
+/// Splits up an exposition with the pest grammar, the reference for what's valid
+pub(super) fn lex_with_grammar(input: &str) -> Result<Lexed<'_>, ParseError> {
+    fn labels(pair: Pair<'_, Rule>) -> Vec<(&str, &str)> {
+        pair.into_inner()
+            .map(|label| {
+                let mut label = label.into_inner();
+                (
+                    label.next().unwrap().as_str(),
+                    label.next().unwrap().as_str(),
+                )
+            })
+            .collect()
+    }
+
+    fn line(pair: Pair<'_, Rule>) -> Line<'_> {
+        match pair.as_rule() {
+            Rule::metricdescriptor => {
+                let mut descriptor = pair.into_inner();
+                let kind = match descriptor.next().unwrap().as_rule() {
+                    Rule::kw_help => MetadataKind::Help,
+                    Rule::kw_type => MetadataKind::Type,
+                    Rule::kw_unit => MetadataKind::Unit,
+                    _ => unreachable!(),
+                };
+
+                Line::Descriptor {
+                    kind,
+                    name: descriptor.next().unwrap().as_str(),
+                    text: descriptor.next().map(|s| s.as_str()).unwrap_or_default(),
+                }
+            }
+            Rule::sample => {
+                let mut sample = SampleLine {
+                    name: "",
+                    labels: Vec::new(),
+                    value: "",
+                    timestamp: None,
+                    exemplar: None,
+                };
+
+                for part in pair.into_inner() {
+                    match part.as_rule() {
+                        Rule::metricname => sample.name = part.as_str(),
+                        Rule::labels => sample.labels = labels(part),
+                        Rule::number => sample.value = part.as_str(),
+                        Rule::timestamp => sample.timestamp = Some(part.as_str()),
+                        Rule::exemplar => {
+                            let mut exemplar = part.into_inner();
+                            sample.exemplar = Some(ExemplarLine {
+                                labels: labels(exemplar.next().unwrap()),
+                                value: exemplar.next().unwrap().as_str(),
+                                timestamp: exemplar.next().map(|t| t.as_str()),
+                            });
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+
+                Line::Sample(sample)
+            }
+            Rule::comment => Line::Comment(pair.as_str()),
+            _ => unreachable!(),
+        }
+    }
+
+    let exposition = OpenMetricsParser::parse(Rule::exposition_without_eof, input)?
+        .next()
+        .unwrap();
+
+    let mut lexed = Lexed::default();
+    for span in exposition.into_inner() {
+        match span.as_rule() {
+            Rule::metricfamily => {
+                for child in span.into_inner() {
+                    let number = child.line_col().0;
+                    lexed.lines.push(LexedLine {
+                        line: line(child),
+                        number,
+                    });
+                }
+            }
+            Rule::kw_eof => {
+                lexed.eof = Some(span.line_col());
+
+                lexed.after_eof = &input[span.as_span().end()..];
+            }
+            Rule::EOI => {}
+            _ => unreachable!(),
+        }
+    }
+
+    Ok(lexed)
+}
+
+/// Splits up an exposition by hand, a line at a time, accepting exactly what the grammar does.
+/// Syntax errors say what was expected where, but not as thoroughly as the grammar's
+pub(super) fn lex_by_hand(input: &str) -> Result<Lexed<'_>, ParseError> {
+    let mut lexed = Lexed::default();
+    let mut rest = input;
+    let mut number = 0;
+    while !rest.is_empty() {
+        number += 1;
+        let start = input.len() - rest.len();
+        let (text, ended) = match rest.find('\n') {
+            Some(end) => (&rest[..end], true),
+            None => (rest, false),
+        };
+        rest = &rest[(text.len() + ended as usize)..];
+
+        if let Some(after) = text.strip_prefix("# EOF") {
+            if after.is_empty() || after.starts_with(' ') || after == "\r" {
+                // The grammar wants at least one family (or comment) before the EOF
+                if lexed.lines.is_empty() {
+                    let error = ParseError::InvalidMetric("Expected a metric family".to_owned());
+                    return Err(error.at(number, 3));
+                }
+
+                lexed.eof = Some((number, 3));
+                lexed.after_eof = &input[(start + "# EOF".len())..];
+                break;
+            }
+        }
+
+        let line = Cursor::new(text).line().map_err(|(column, expected)| {
+            ParseError::InvalidMetric(format!("Expected {}", expected)).at(number, column)
+        })?;
+        if !ended {
+            let column = text.chars().count() + 1;
+            return Err(
+                ParseError::InvalidMetric("Expected a newline".to_owned()).at(number, column)
+            );
+        }
+
+        lexed.lines.push(LexedLine { line, number });
+    }
+
+    Ok(lexed)
+}
+
+/// Where the lexer has got to in a line. Errors are the column they were found at and what was
+/// expected there
+struct Cursor<'i> {
+    text: &'i str,
+    position: usize,
+
+    // Where the line's content ends: like the grammar, lines can end with `\r\n`, but a `\r`
+    // is part of any help text or comment it follows
+    end: usize,
+}
+
+type LexResult<T> = Result<T, (usize, &'static str)>;
+
+impl<'i> Cursor<'i> {
+    fn new(text: &'i str) -> Self {
+        Cursor {
+            text,
+            position: 0,
+            end: text.strip_suffix('\r').unwrap_or(text).len(),
+        }
+    }
+
+    fn rest(&self) -> &'i str {
+        &self.text[self.position..self.end]
+    }
+
+    fn error<T>(&self, expected: &'static str) -> LexResult<T> {
+        Err((self.text[..self.position].chars().count() + 1, expected))
+    }
+
+    fn eat(&mut self, prefix: &str) -> bool {
+        let found = self.rest().starts_with(prefix);
+        if found {
+            self.position += prefix.len();
+        }
+
+        found
+    }
+
+    fn expect(&mut self, prefix: &str, expected: &'static str) -> LexResult<()> {
+        if self.eat(prefix) {
+            Ok(())
+        } else {
+            self.error(expected)
+        }
+    }
+
+    fn end(&self) -> LexResult<()> {
+        if self.rest().is_empty() {
+            Ok(())
+        } else {
+            self.error("the end of the line")
+        }
+    }
+
+    /// Takes the longest run of bytes matching `first` then `rest`, failing if it's empty
+    fn take(
+        &mut self,
+        first: fn(u8) -> bool,
+        rest: fn(u8) -> bool,
+        expected: &'static str,
+    ) -> LexResult<&'i str> {
+        let bytes = self.rest().as_bytes();
+        if !bytes.first().is_some_and(|&b| first(b)) {
+            return self.error(expected);
+        }
+
+        let len = 1 + bytes[1..].iter().take_while(|&&b| rest(b)).count();
+        let taken = &self.rest()[..len];
+        self.position += len;
+        Ok(taken)
+    }
+
+    /// Takes everything up to the next space, or the end of the line
+    fn token(&mut self) -> &'i str {
+        let len = self.rest().find(' ').unwrap_or(self.rest().len());
+        let token = &self.rest()[..len];
+        self.position += len;
+        token
+    }
+
+    fn line(mut self) -> LexResult<Line<'i>> {
+        if !self.eat("#") {
+            return self.sample().map(Line::Sample);
+        }
+
+        let kind = if self.eat(" TYPE") {
+            MetadataKind::Type
+        } else if self.eat(" HELP") {
+            MetadataKind::Help
+        } else if self.eat(" UNIT") {
+            MetadataKind::Unit
+        } else {
+            return Ok(Line::Comment(self.text));
+        };
+
+        // The keywords only start a descriptor when they're followed by a space or the end of
+        // the line, e.g. `# TYPEWRITER` is a comment
+        if !self.rest().is_empty() && !self.rest().starts_with(' ') {
+            return Ok(Line::Comment(self.text));
+        }
+
+        self.expect(" ", "a space")?;
+        let name = self.metric_name()?;
+        let text = match kind {
+            MetadataKind::Type => {
+                self.expect(" ", "a space")?;
+                self.take(
+                    |b| b.is_ascii_lowercase(),
+                    |b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_',
+                    "a metric type",
+                )?
+            }
+            MetadataKind::Help => {
+                self.expect(" ", "a space")?;
+                let help = &self.text[self.position..];
+                self.position = self.end;
+                help
+            }
+            MetadataKind::Unit => {
+                if self.eat(" ") {
+                    let unit = self.rest();
+                    let is_unit_char = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b':';
+                    if !unit.bytes().all(is_unit_char) {
+                        return self.error("a unit");
+                    }
+
+                    self.position = self.end;
+                    unit
+                } else {
+                    ""
+                }
+            }
+        };
+
+        self.end()?;
+        Ok(Line::Descriptor { kind, name, text })
+    }
+
+    fn metric_name(&mut self) -> LexResult<&'i str> {
+        self.take(is_metric_name_start, is_metric_name_char, "a metric name")
+    }
+
+    fn sample(&mut self) -> LexResult<SampleLine<'i>> {
+        let name = self.metric_name()?;
+        let labels = if self.rest().starts_with('{') {
+            self.labels()?
+        } else {
+            Vec::new()
+        };
+
+        self.expect(" ", "a space")?;
+        let value = self.number()?;
+
+        let mut timestamp = None;
+        if self.rest().starts_with(' ') && !self.rest().starts_with(" #") {
+            self.position += 1;
+            timestamp = Some(self.real_number("a timestamp")?);
+        }
+
+        let mut exemplar = None;
+        if self.eat(" #") {
+            self.expect(" ", "a space")?;
+            if !self.rest().starts_with('{') {
+                return self.error("exemplar labels");
+            }
+
+            let labels = self.labels()?;
+            self.expect(" ", "a space")?;
+            let value = self.number()?;
+            let timestamp = match self.eat(" ") {
+                true => Some(self.real_number("a timestamp")?),
+                false => None,
+            };
+
+            exemplar = Some(ExemplarLine {
+                labels,
+                value,
+                timestamp,
+            });
+        }
+
+        self.end()?;
+        Ok(SampleLine {
+            name,
+            labels,
+            value,
+            timestamp,
+            exemplar,
+        })
+    }
+
+    fn labels(&mut self) -> LexResult<Vec<(&'i str, &'i str)>> {
+        self.expect("{", "labels")?;
+        let mut labels = Vec::new();
+        if self.eat("}") {
+            return Ok(labels);
+        }
+
+        loop {
+            let name = self.take(is_label_name_start, is_label_name_char, "a label name")?;
+            self.expect("=", "=")?;
+            self.expect("\"", "a quoted label value")?;
+
+            // Up to the closing quote, skipping over escaped characters. Carriage returns can't
+            // be in label values
+            let bytes = self.rest().as_bytes();
+            let mut len = 0;
+            while len < bytes.len() && bytes[len] != b'"' && bytes[len] != b'\r' {
+                let escaped =
+                    bytes[len] == b'\\' && matches!(bytes.get(len + 1), Some(b'\\' | b'n' | b'"'));
+                len += if escaped { 2 } else { 1 };
+            }
+
+            let value = &self.rest()[..len];
+            self.position += value.len();
+            self.expect("\"", "the end of the label value")?;
+            labels.push((name, value));
+
+            if self.eat("}") {
+                return Ok(labels);
+            }
+
+            self.expect(",", "a comma")?;
+        }
+    }
+
+    fn number(&mut self) -> LexResult<&'i str> {
+        let start = self.position;
+        let token = self.token();
+        let lower = token.to_ascii_lowercase();
+        let special =
+            matches!(lower.trim_start_matches(['+', '-']), "inf" | "infinity") || lower == "nan";
+
+        if special || is_real_number(token) {
+            Ok(token)
+        } else {
+            self.position = start;
+            self.error("a number")
+        }
+    }
+
+    fn real_number(&mut self, expected: &'static str) -> LexResult<&'i str> {
+        let start = self.position;
+        let token = self.token();
+        if is_real_number(token) {
+            Ok(token)
+        } else {
+            self.position = start;
+            self.error(expected)
+        }
+    }
+}
+
+/// Whether the text is a number in the grammar's `realnumber` form, e.g. `-1.5e3`, `.5` or
+/// `0x1p-3`
+fn is_real_number(text: &str) -> bool {
+    fn digits(text: &str, hex: bool) -> usize {
+        text.bytes()
+            .take_while(|b| b.is_ascii_digit() || (hex && b.is_ascii_hexdigit()))
+            .count()
+    }
+
+    let text = text.strip_prefix(['+', '-']).unwrap_or(text);
+    let (text, hex) = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(text) => (text, true),
+        None => (text, false),
+    };
+
+    let integer = digits(text, hex);
+    let mut rest = &text[integer..];
+    let mut fraction = 0;
+    if let Some(after) = rest.strip_prefix('.') {
+        fraction = digits(after, hex);
+        rest = &after[fraction..];
+    }
+
+    if integer == 0 && fraction == 0 {
+        return false;
+    }
+
+    let exponent = if hex {
+        rest.strip_prefix(['p', 'P'])
+    } else {
+        rest.strip_prefix(['e', 'E'])
+    };
+
+    match exponent {
+        Some(exponent) => {
+            let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            let len = digits(exponent, false);
+            len > 0 && len == exponent.len()
+        }
+        // Hexadecimal numbers need an exponent
+        None => rest.is_empty() && !hex,
+    }
+}
@@ -1,6 +1,21 @@
 #[cfg(test)]
 mod tests;
 
+mod borrowed;
+mod lexer;
+#[cfg(feature = "parallel")]
+mod parallel;
 mod parsers;
+mod reader;
+mod render;
+mod streaming;
+mod visitor;
+pub use borrowed::*;
+#[cfg(feature = "parallel")]
+pub use parallel::*;
 pub use parsers::*;
 pub use pest::Parser;
+pub use reader::*;
+pub use render::*;
+pub use streaming::*;
+pub use visitor::*;
@@ -0,0 +1,129 @@
+use std::thread;
+
+use super::parsers::{parse_openmetrics_with_options, ChunkedParser};
+use super::reader::FamilySplitter;
+use crate::{MetricsExposition, OpenMetricsType, OpenMetricsValue, ParseError, ParserOptions};
+
+// Below this, a chunk isn't worth a thread of its own
+const MIN_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Parses an exposition (see `parse_openmetrics`) on several threads, each parsing a chunk of its
+/// families. Families are only merged once every chunk has parsed cleanly: anything else, e.g. a
+/// family split over chunks or an error, falls back to parsing on one thread, so the result is
+/// always the same as `parse_openmetrics`'s
+/// ```
+/// use openmetrics_parser::openmetrics::parse_openmetrics_parallel;
+///
+/// let exposition = "# TYPE foo gauge\nfoo 1\n# TYPE bar gauge\nbar 2\n# EOF\n";
+/// let parsed = parse_openmetrics_parallel(exposition).unwrap();
+/// assert_eq!(parsed.families.len(), 2);
+/// ```
+pub fn parse_openmetrics_parallel(
+    exposition_bytes: &str,
+) -> Result<MetricsExposition<OpenMetricsType, OpenMetricsValue>, ParseError> {
+    parse_openmetrics_parallel_with_options(exposition_bytes, ParserOptions::default())
+}
+
+pub fn parse_openmetrics_parallel_with_options(
+    exposition_bytes: &str,
+    options: ParserOptions,
+) -> Result<MetricsExposition<OpenMetricsType, OpenMetricsValue>, ParseError> {
+    // The limits are checked against the exposition as a whole, not each chunk
+    if options.limits.max_total_bytes.is_some() || options.limits.max_families.is_some() {
+        return parse_openmetrics_with_options(exposition_bytes, options);
+    }
+
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    let chunks = split_chunks(exposition_bytes, &options, threads, MIN_CHUNK_BYTES);
+    match parse_chunks(&chunks, &options) {
+        Some(exposition) => Ok(exposition),
+        None => parse_openmetrics_with_options(exposition_bytes, options),
+    }
+}
+
+/// Parses each chunk on its own thread, returning None if any of them fails or two of them have
+/// the same family
+pub(super) fn parse_chunks(
+    chunks: &[&str],
+    options: &ParserOptions,
+) -> Option<MetricsExposition<OpenMetricsType, OpenMetricsValue>> {
+    if chunks.len() < 2 {
+        return None;
+    }
+
+    let parsed: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let options = options.clone();
+                let last = index == chunks.len() - 1;
+                scope.spawn(move || {
+                    let mut parser = ChunkedParser::<OpenMetricsType>::new(options, false);
+                    parser.parse_chunk(chunk).ok()?;
+                    if last {
+                        parser.finish().ok().map(|output| {
+                            output
+                                .exposition
+                                .families
+                                .into_iter()
+                                .map(|(_, family)| family)
+                                .collect()
+                        })
+                    } else {
+                        Some(parser.take_families())
+                    }
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().ok().flatten())
+            .collect()
+    });
+
+    let mut exposition = MetricsExposition::new();
+    for families in parsed {
+        for family in families? {
+            if exposition.families.contains_key(&family.family_name) {
+                return None;
+            }
+
+            exposition
+                .families
+                .insert(family.family_name.clone(), family);
+        }
+    }
+
+    Some(exposition)
+}
+
+/// Splits an exposition between families into at most `max_chunks` chunks of roughly the same
+/// size, and at least `min_bytes` each
+pub(super) fn split_chunks<'a>(
+    exposition_bytes: &'a str,
+    options: &ParserOptions,
+    max_chunks: usize,
+    min_bytes: usize,
+) -> Vec<&'a str> {
+    let count = max_chunks
+        .min(exposition_bytes.len() / min_bytes.max(1))
+        .max(1);
+    let target = exposition_bytes.len() / count;
+
+    let mut splitter = FamilySplitter::new(options.allow_late_metadata);
+    let mut chunks = Vec::with_capacity(count);
+    let (mut start, mut offset) = (0, 0);
+    for line in exposition_bytes.split_inclusive('\n') {
+        if splitter.starts_family(line) && offset - start >= target && chunks.len() + 1 < count {
+            chunks.push(&exposition_bytes[start..offset]);
+            start = offset;
+        }
+
+        offset += line.len();
+    }
+
+    chunks.push(&exposition_bytes[start..]);
+    chunks
+}
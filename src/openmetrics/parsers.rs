@@ -1,21 +1,35 @@
+use super::lexer::{lex_by_hand, lex_with_grammar, ExemplarLine, LexedLine, Line, SampleLine};
 use crate::{
     internal::{
-        CounterValueMarshal, LabelNames, MarshalledMetric, MarshalledMetricFamily,
-        MetricFamilyMarshal, MetricMarshal, MetricProcesser, MetricValueMarshal, MetricsType,
+        check_counter_nan, check_counter_negativity, check_stateset_value, invalid_counter,
+        invalid_summary, locate_warnings, normalize_bound_labels, normalize_input, original_line,
+        parse_f64, parse_metric_number, parse_timestamp_nanos, push_bucket, read_exposition_file,
+        CounterValueMarshal, MarshalledMetric, MarshalledMetricFamily, MetricFamilyMarshal,
+        MetricMarshal, MetricProcesser, MetricValueMarshal, MetricsDialect, MetricsType,
     },
     public::*,
 };
-use pest::Parser;
+use pest::error::LineColLocation;
+use std::collections::HashSet;
 use std::convert::TryFrom;
 use std::fmt;
+use std::path::Path;
 
 #[derive(Parser)]
 #[grammar = "openmetrics/openmetrics.pest"]
-struct OpenMetricsParser;
+pub(super) struct OpenMetricsParser;
 
 impl From<pest::error::Error<Rule>> for ParseError {
     fn from(err: pest::error::Error<Rule>) -> Self {
-        ParseError::ParseError(err.to_string())
+        let (line, column) = match err.line_col {
+            LineColLocation::Pos(start) | LineColLocation::Span(start, _) => start,
+        };
+
+        ParseError::Syntax {
+            message: err.variant.message().into_owned(),
+            source: Box::new(err),
+        }
+        .at(line, column)
     }
 }
 
@@ -128,15 +142,11 @@ impl fmt::Display for OpenMetricsType {
     }
 }
 
-impl Default for OpenMetricsType {
-    fn default() -> Self {
-        OpenMetricsType::Unknown
-    }
-}
-
 impl From<MetricMarshal> for Sample<OpenMetricsValue> {
     fn from(s: MetricMarshal) -> Sample<OpenMetricsValue> {
         Sample::new(s.label_values, s.timestamp, s.value.into())
+            .with_exact_timestamp(s.timestamp_nanos)
+            .with_exemplar(s.exemplar)
     }
 }
 
@@ -146,10 +156,15 @@ impl MarshalledMetric<OpenMetricsType> for MetricMarshal {
         if family.label_names.is_none() && !self.label_values.is_empty()
             || (family.label_names.as_ref().unwrap().names.len() != self.label_values.len())
         {
-            return Err(ParseError::InvalidMetric(format!(
-                "Metrics in family have different label sets: {:?} {:?}",
-                &family.label_names, self.label_values
-            )));
+            return Err(ParseError::LabelSetMismatch {
+                family: family.name.clone().unwrap_or_default(),
+                label_names: family
+                    .label_names
+                    .as_ref()
+                    .map(|names| names.names.clone())
+                    .unwrap_or_default(),
+                label_values: self.label_values.to_vec(),
+            });
         }
 
         if family.unit.is_some() && family.metrics.is_empty() {
@@ -158,76 +173,23 @@ impl MarshalledMetric<OpenMetricsType> for MetricMarshal {
             ));
         }
 
+        let options = &family.options;
+        let warnings = &mut family.warnings.borrow_mut();
         match &self.value {
-            MetricValueMarshal::Histogram(histogram_value)
-            | MetricValueMarshal::GaugeHistogram(histogram_value) => {
-                let gauge_histogram = matches!(&self.value, MetricValueMarshal::GaugeHistogram(_));
-
-                if histogram_value.buckets.is_empty() {
-                    return Err(ParseError::InvalidMetric(
-                        "Histograms must have at least one bucket".to_owned(),
-                    ));
-                }
-
-                if !histogram_value
-                    .buckets
-                    .iter()
-                    .any(|b| b.upper_bound == f64::INFINITY)
-                {
-                    return Err(ParseError::InvalidMetric(format!(
-                        "Histograms must have a +INF bucket: {:?}",
-                        histogram_value.buckets
-                    )));
-                }
-
-                let buckets = &histogram_value.buckets;
-
-                let has_negative_bucket = buckets.iter().any(|f| f.upper_bound < 0.);
-
-                if has_negative_bucket {
-                    if histogram_value.sum.is_some() && !gauge_histogram {
-                        return Err(ParseError::InvalidMetric(
-                            "Histograms cannot have a sum with a negative bucket".to_owned(),
-                        ));
-                    }
-                } else if histogram_value.sum.is_some()
-                    && histogram_value.sum.as_ref().unwrap().as_f64() < 0.
-                {
-                    return Err(ParseError::InvalidMetric(
-                        "Histograms cannot have a negative sum without a negative bucket"
-                            .to_owned(),
-                    ));
-                }
-
-                if histogram_value.sum.is_some() && histogram_value.count.is_none() {
-                    return Err(ParseError::InvalidMetric(
-                        "Count must be present if sum is present".to_owned(),
-                    ));
-                }
-
-                if histogram_value.sum.is_none() && histogram_value.count.is_some() {
-                    return Err(ParseError::InvalidMetric(
-                        "Sum must be present if count is present".to_owned(),
-                    ));
-                }
-
-                let mut last = f64::NEG_INFINITY;
-                for bucket in buckets {
-                    if bucket.count.as_f64() < last {
-                        return Err(ParseError::InvalidMetric(
-                            "Histograms must be cumulative".to_owned(),
-                        ));
-                    }
-
-                    last = bucket.count.as_f64();
-                }
+            MetricValueMarshal::Histogram(histogram_value) => {
+                family.check_histogram(self, histogram_value, false, warnings)?;
             }
-            MetricValueMarshal::Counter(counter_value) => {
-                if counter_value.value.is_none() {
-                    return Err(ParseError::InvalidMetric(
-                        "Counter is missing a _total".to_string(),
-                    ));
+            MetricValueMarshal::GaugeHistogram(histogram_value) => {
+                family.check_histogram(self, histogram_value, true, warnings)?;
+            }
+            MetricValueMarshal::Counter(counter_value) => match counter_value.value {
+                None => {
+                    return Err(invalid_counter("Counter is missing a _total".to_string()));
                 }
+                Some(value) => check_counter_negativity(value.as_f64(), options, warnings)?,
+            },
+            MetricValueMarshal::StateSet(Some(value)) => {
+                check_stateset_value(value.as_f64(), options, warnings)?;
             }
             _ => {}
         }
@@ -261,7 +223,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
         }
 
         for metric in self.metrics.iter() {
-            metric.validate(self)?;
+            self.validate_metric(metric)?;
         }
 
         Ok(())
@@ -272,10 +234,12 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
         metric_name: &str,
         metric_value: MetricNumber,
         label_names: Vec<String>,
-        label_values: Vec<String>,
+        label_values: LabelValues,
         timestamp: Option<Timestamp>,
         exemplar: Option<Exemplar>,
     ) -> Result<(), Self::Error> {
+        let merge_duplicate_buckets = self.options.merge_duplicate_buckets;
+        let allow_nan_counters = self.options.allow_nan_counters;
         let handlers = vec![
             (
                 vec![OpenMetricsType::Histogram],
@@ -284,20 +248,20 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                         "_bucket",
                         vec!["le"],
                         MetricProcesser::new(
-                            |existing_metric: &mut MetricMarshal,
-                             metric_value: MetricNumber,
-                             label_names: Vec<String>,
-                             label_values: Vec<String>,
-                             exemplar: Option<Exemplar>,
-                             _: bool| {
-                                let bucket_bound: f64 = {
+                            move |existing_metric: &mut MetricMarshal,
+                                  metric_value: MetricNumber,
+                                  label_names: Vec<String>,
+                                  label_values: LabelValues,
+                                  exemplar: Option<Exemplar>,
+                                  _: bool| {
+                                let (bucket_bound, raw_bound): (f64, String) = {
                                     let bound_index =
                                         label_names.iter().position(|s| s == "le").unwrap();
 
                                     let bound = &label_values[bound_index];
-                                    match bound.parse() {
-                                        Ok(f) => f,
-                                        Err(_) => {
+                                    match parse_f64(bound) {
+                                        Some(f) => (f, bound.clone()),
+                                        None => {
                                             return Err(ParseError::InvalidMetric(format!(
                                                 "Invalid histogram bound: {}",
                                                 bound
@@ -309,18 +273,17 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                                 let bucket = HistogramBucket {
                                     count: metric_value,
                                     upper_bound: bucket_bound,
+                                    raw_upper_bound: Some(raw_bound),
                                     exemplar,
                                 };
 
                                 if let MetricValueMarshal::Histogram(value) =
                                     &mut existing_metric.value
                                 {
-                                    value.buckets.push(bucket);
+                                    push_bucket(value, bucket, merge_duplicate_buckets)
                                 } else {
                                     unreachable!();
                                 }
-
-                                Ok(())
                             },
                         ),
                     ),
@@ -331,7 +294,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 if let MetricValueMarshal::Histogram(histogram_value) =
@@ -376,7 +339,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 if let MetricValueMarshal::Histogram(histogram_value) =
@@ -405,7 +368,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 if let MetricValueMarshal::Histogram(histogram_value) =
@@ -433,20 +396,20 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                         "_bucket",
                         vec!["le"],
                         MetricProcesser::new(
-                            |existing_metric: &mut MetricMarshal,
-                             metric_value: MetricNumber,
-                             label_names: Vec<String>,
-                             label_values: Vec<String>,
-                             exemplar: Option<Exemplar>,
-                             _: bool| {
-                                let bucket_bound: f64 = {
+                            move |existing_metric: &mut MetricMarshal,
+                                  metric_value: MetricNumber,
+                                  label_names: Vec<String>,
+                                  label_values: LabelValues,
+                                  exemplar: Option<Exemplar>,
+                                  _: bool| {
+                                let (bucket_bound, raw_bound): (f64, String) = {
                                     let bound_index =
                                         label_names.iter().position(|s| s == "le").unwrap();
 
                                     let bound = &label_values[bound_index];
-                                    match bound.parse() {
-                                        Ok(f) => f,
-                                        Err(_) => {
+                                    match parse_f64(bound) {
+                                        Some(f) => (f, bound.clone()),
+                                        None => {
                                             return Err(ParseError::InvalidMetric(format!("Expected histogram bucket bound to be an f64 (got: {})", bound)));
                                         }
                                     }
@@ -455,18 +418,17 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                                 let bucket = HistogramBucket {
                                     count: metric_value,
                                     upper_bound: bucket_bound,
+                                    raw_upper_bound: Some(raw_bound),
                                     exemplar,
                                 };
 
                                 if let MetricValueMarshal::GaugeHistogram(value) =
                                     &mut existing_metric.value
                                 {
-                                    value.buckets.push(bucket);
+                                    push_bucket(value, bucket, merge_duplicate_buckets)
                                 } else {
                                     unreachable!();
                                 }
-
-                                Ok(())
                             },
                         ),
                     ),
@@ -477,7 +439,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 if let MetricValueMarshal::GaugeHistogram(histogram_value) =
@@ -522,7 +484,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 if let MetricValueMarshal::GaugeHistogram(histogram_value) =
@@ -550,12 +512,12 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                         "_total",
                         vec![],
                         MetricProcesser::new(
-                            |existing_metric: &mut MetricMarshal,
-                             metric_value: MetricNumber,
-                             _: Vec<String>,
-                             _: Vec<String>,
-                             _: Option<Exemplar>,
-                             _: bool| {
+                            move |existing_metric: &mut MetricMarshal,
+                                  metric_value: MetricNumber,
+                                  _: Vec<String>,
+                                  _: LabelValues,
+                                  exemplar: Option<Exemplar>,
+                                  _: bool| {
                                 if let MetricValueMarshal::Counter(counter_value) =
                                     &mut existing_metric.value
                                 {
@@ -563,15 +525,10 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                                         return Err(ParseError::DuplicateMetric);
                                     }
 
-                                    let value = metric_value.as_f64();
-                                    if value < 0. || value.is_nan() {
-                                        return Err(ParseError::InvalidMetric(format!(
-                                            "Counter totals must be non negative (got: {})",
-                                            metric_value.as_f64()
-                                        )));
-                                    }
+                                    check_counter_nan(metric_value.as_f64(), allow_nan_counters)?;
 
                                     counter_value.value = Some(metric_value);
+                                    counter_value.exemplar = exemplar;
                                 } else {
                                     unreachable!();
                                 }
@@ -587,7 +544,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 if let MetricValueMarshal::Counter(counter_value) =
@@ -616,8 +573,8 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                         |existing_metric: &mut MetricMarshal,
                          metric_value: MetricNumber,
                          _: Vec<String>,
-                         _: Vec<String>,
-                         _: Option<Exemplar>,
+                         _: LabelValues,
+                         exemplar: Option<Exemplar>,
                          _: bool| {
                             if let MetricValueMarshal::Gauge(gauge_value) =
                                 &mut existing_metric.value
@@ -628,6 +585,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
 
                                 existing_metric.value =
                                     MetricValueMarshal::Gauge(Some(metric_value));
+                                existing_metric.exemplar = exemplar;
                             } else {
                                 unreachable!();
                             }
@@ -646,7 +604,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                         |existing_metric: &mut MetricMarshal,
                          metric_value: MetricNumber,
                          _: Vec<String>,
-                         _: Vec<String>,
+                         _: LabelValues,
                          _: Option<Exemplar>,
                          _: bool| {
                             if let MetricValueMarshal::StateSet(stateset_value) =
@@ -662,15 +620,6 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                                     ));
                                 }
 
-                                if metric_value.as_f64() != 0.
-                                    && (metric_value.as_f64() - 1.).abs() > f64::EPSILON
-                                {
-                                    return Err(ParseError::InvalidMetric(format!(
-                                        "Stateset value must be 0 or 1 (got: {})",
-                                        metric_value.as_f64()
-                                    )));
-                                }
-
                                 existing_metric.value =
                                     MetricValueMarshal::StateSet(Some(metric_value));
                             } else {
@@ -691,7 +640,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                         |existing_metric: &mut MetricMarshal,
                          metric_value: MetricNumber,
                          _: Vec<String>,
-                         _: Vec<String>,
+                         _: LabelValues,
                          _: Option<Exemplar>,
                          _: bool| {
                             if let MetricValueMarshal::Unknown(unknown_value) =
@@ -721,7 +670,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                         |_: &mut MetricMarshal,
                          metric_value: MetricNumber,
                          _: Vec<String>,
-                         _: Vec<String>,
+                         _: LabelValues,
                          _: Option<Exemplar>,
                          created: bool| {
                             let metric_value = if let Some(value) = metric_value.as_i64() {
@@ -759,7 +708,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 if let MetricValueMarshal::Summary(summary_value) =
@@ -767,14 +716,14 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                                 {
                                     let metric_value = if let Some(value) = metric_value.as_i64() {
                                         if value < 0 {
-                                            return Err(ParseError::InvalidMetric(format!(
+                                            return Err(invalid_summary(format!(
                                                 "Summary counts must be positive (got: {})",
                                                 value
                                             )));
                                         }
                                         value as u64
                                     } else {
-                                        return Err(ParseError::InvalidMetric(format!(
+                                        return Err(invalid_summary(format!(
                                             "Summary counts must be integers (got: {})",
                                             metric_value.as_f64()
                                         )));
@@ -794,18 +743,44 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                         ),
                     ),
                     (
-                        "_sum",
+                        "_created",
                         vec![],
                         MetricProcesser::new(
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
+                             _: LabelValues,
+                             _: Option<Exemplar>,
+                             _: bool| {
+                                if let MetricValueMarshal::Summary(summary_value) =
+                                    &mut existing_metric.value
+                                {
+                                    if summary_value.created.is_some() {
+                                        return Err(ParseError::DuplicateMetric);
+                                    }
+
+                                    summary_value.created = Some(metric_value.as_f64());
+                                } else {
+                                    unreachable!();
+                                }
+
+                                Ok(())
+                            },
+                        ),
+                    ),
+                    (
+                        "_sum",
+                        vec![],
+                        MetricProcesser::new(
+                            |existing_metric: &mut MetricMarshal,
+                             metric_value: MetricNumber,
                              _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 let value = metric_value.as_f64();
                                 if value < 0. || value.is_nan() {
-                                    return Err(ParseError::InvalidMetric(format!(
+                                    return Err(invalid_summary(format!(
                                         "Summary sums must be non negative (got: {})",
                                         metric_value.as_f64()
                                     )));
@@ -833,25 +808,25 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              label_names: Vec<String>,
-                             label_values: Vec<String>,
+                             label_values: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 let value = metric_value.as_f64();
                                 if !value.is_nan() && value < 0. {
-                                    return Err(ParseError::InvalidMetric(
+                                    return Err(invalid_summary(
                                         "Summary quantiles can't be negative".to_owned(),
                                     ));
                                 }
 
-                                let bucket_bound: f64 = {
+                                let (bucket_bound, raw_bound): (f64, String) = {
                                     let bound_index =
                                         label_names.iter().position(|s| s == "quantile").unwrap();
                                     let bound = &label_values[bound_index];
 
-                                    match bound.parse() {
-                                        Ok(f) => f,
-                                        Err(_) => {
-                                            return Err(ParseError::InvalidMetric(format!(
+                                    match parse_f64(bound) {
+                                        Some(f) => (f, bound.clone()),
+                                        None => {
+                                            return Err(invalid_summary(format!(
                                                 "Summary bounds must be numbers (got: {})",
                                                 bound
                                             )));
@@ -860,7 +835,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
                                 };
 
                                 if !(0. ..=1.).contains(&bucket_bound) || bucket_bound.is_nan() {
-                                    return Err(ParseError::InvalidMetric(format!(
+                                    return Err(invalid_summary(format!(
                                         "Summary bounds must be between 0 and 1 (got: {})",
                                         bucket_bound
                                     )));
@@ -868,6 +843,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
 
                                 let quantile = Quantile {
                                     quantile: bucket_bound,
+                                    raw_quantile: Some(raw_bound),
                                     value: metric_value,
                                 };
 
@@ -889,211 +865,437 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<OpenMetricsType> {
 
         let metric_type = self.family_type.as_ref().cloned().unwrap_or_default();
 
-        if !metric_type.can_have_exemplar(metric_name) && exemplar.is_some() {
-            return Err(ParseError::InvalidMetric(format!(
+        let experimental_exemplar =
+            self.options.gauge_exemplars && metric_type == OpenMetricsType::Gauge;
+        let exemplar = if self.options.allow_gcount_exemplars
+            && metric_type == OpenMetricsType::GaugeHistogram
+            && metric_name.ends_with("_gcount")
+        {
+            // Tolerated, but a gauge histogram has nowhere to keep them
+            None
+        } else if !metric_type.can_have_exemplar(metric_name)
+            && !experimental_exemplar
+            && exemplar.is_some()
+        {
+            let error = ParseError::InvalidMetric(format!(
                 "Metric Type {:?} is not allowed exemplars",
                 metric_type
-            )));
-        }
-
-        for (test_type, actions) in handlers {
-            if test_type.contains(&metric_type) {
-                for (suffix, mandatory_labels, action) in actions {
-                    if !metric_name.ends_with(suffix) {
-                        continue;
-                    }
+            ));
+            if !self.options.drop_invalid_exemplars {
+                return Err(error);
+            }
 
-                    let mut actual_label_names = label_names.clone();
-                    let mut actual_label_values = label_values.clone();
-                    for label in mandatory_labels {
-                        if !label_names.contains(&label.to_owned()) {
-                            return Err(ParseError::InvalidMetric(format!(
-                                "Missing mandatory label for metric: {}",
-                                label
-                            )));
-                        }
+            self.warnings.get_mut().push(error);
+            None
+        } else {
+            exemplar
+        };
 
-                        let index = actual_label_names.iter().position(|s| s == label).unwrap();
+        self.process_with_handlers(
+            handlers,
+            metric_name,
+            metric_value,
+            label_names,
+            label_values,
+            timestamp,
+            exemplar,
+            !self.options.allow_interleaved_labelsets,
+        )
+    }
+}
 
-                        actual_label_names.remove(index);
-                        actual_label_values.remove(index);
-                    }
+impl MetricsDialect for OpenMetricsType {
+    type Value = OpenMetricsValue;
 
-                    match &self.current_label_set {
-                        None => self.current_label_set = Some(actual_label_values.clone()),
-                        Some(s) => {
-                            if s != &actual_label_values
-                                && self.seen_label_sets.contains(&actual_label_values)
-                            {
-                                return Err(ParseError::InvalidMetric(format!(
-                                    "Interwoven labelsets: Found {:?} after {:?}",
-                                    s,
-                                    self.current_label_set.as_ref().unwrap()
-                                )));
-                            }
-                        }
-                    }
+    fn parse_type(type_name: &str) -> Result<Self, ParseError> {
+        OpenMetricsType::try_from(type_name)
+    }
 
-                    self.current_label_set = Some(actual_label_values.clone());
-                    self.seen_label_sets.push(actual_label_values.clone());
-
-                    let name = &metric_name.to_owned();
-                    self.try_set_label_names(
-                        name,
-                        LabelNames::new(name, metric_type, actual_label_names),
-                    )?;
-
-                    let metric_name = metric_name.trim_end_matches(suffix);
-                    if self.name.is_some() && self.name.as_ref().unwrap() != metric_name {
-                        return Err(ParseError::InvalidMetric(format!(
-                            "Invalid Name in metric family: {} != {}",
-                            metric_name,
-                            self.name.as_ref().unwrap()
-                        )));
-                    } else if self.name.is_none() {
-                        self.name = Some(metric_name.to_owned());
-                    }
+    fn process_new_metric(
+        family: &mut MetricFamilyMarshal<Self>,
+        metric_name: &str,
+        value: MetricNumber,
+        label_names: Vec<String>,
+        label_values: LabelValues,
+        timestamp: Option<Timestamp>,
+        exemplar: Option<Exemplar>,
+    ) -> Result<(), ParseError> {
+        MarshalledMetricFamily::process_new_metric(
+            family,
+            metric_name,
+            value,
+            label_names,
+            label_values,
+            timestamp,
+            exemplar,
+        )
+    }
 
-                    let (existing_metric, created) = match self
-                        .get_metric_by_labelset_mut(&actual_label_values)
-                    {
-                        Some(metric) => {
-                            match (metric.timestamp.as_ref(), timestamp.as_ref()) {
-                                (Some(metric_timestamp), Some(timestamp)) if timestamp < metric_timestamp => return Err(ParseError::InvalidMetric(format!("Timestamps went backwarts in family - saw {} and then saw{}", metric_timestamp, timestamp))),
-                                (Some(_), None) | (None, Some(_)) => return Err(ParseError::InvalidMetric("Missing timestamp in family (one metric had a timestamp, another didn't)".to_string())),
-                                (Some(metric_timestamp), Some(timestamp)) if timestamp >= metric_timestamp && !metric_type.can_have_multiple_lines() => return Ok(()),
-                                _ => (metric, false)
-                            }
-                        }
-                        None => {
-                            let new_metric = self
-                                .family_type
-                                .as_ref()
-                                .unwrap_or(&OpenMetricsType::Unknown)
-                                .get_type_value();
-                            self.add_metric(MetricMarshal::new(
-                                actual_label_values.clone(),
-                                timestamp,
-                                new_metric,
-                            ));
-                            (
-                                self.get_metric_by_labelset_mut(&actual_label_values)
-                                    .unwrap(),
-                                true,
-                            )
-                        }
-                    };
-
-                    return action.0(
-                        existing_metric,
-                        metric_value,
-                        label_names,
-                        label_values,
-                        exemplar,
-                        created,
-                    );
-                }
-            }
-        }
+    fn validate_family(family: &MetricFamilyMarshal<Self>) -> Result<(), ParseError> {
+        MarshalledMetricFamily::validate(family)
+    }
 
-        return Err(ParseError::InvalidMetric(format!(
-            "Found weird metric name for type ({:?}): {}",
-            metric_type, metric_name
-        )));
+    fn convert_value(value: MetricValueMarshal) -> Self::Value {
+        value.into()
     }
 }
 
-impl From<MetricFamilyMarshal<OpenMetricsType>>
-    for MetricFamily<OpenMetricsType, OpenMetricsValue>
+fn family_from_marshal<T>(marshal: MetricFamilyMarshal<T>) -> MetricFamily<T, T::Value>
+where
+    T: MetricsDialect,
 {
-    fn from(marshal: MetricFamilyMarshal<OpenMetricsType>) -> Self {
-        assert!(marshal.name.is_some());
-
-        MetricFamily::new(
-            marshal.name.unwrap(),
-            marshal
-                .label_names
-                .map(|names| names.names)
-                .unwrap_or_default(),
-            marshal.family_type.unwrap_or_default(),
-            marshal.help.unwrap_or_default(),
-            marshal.unit.unwrap_or_default(),
-        )
-        .with_samples(marshal.metrics.into_iter().map(|m| m.into()))
-        .unwrap()
-    }
+    assert!(marshal.name.is_some());
+
+    MetricFamily::new(
+        marshal.name.unwrap(),
+        marshal
+            .label_names
+            .map(|names| names.names)
+            .unwrap_or_default(),
+        marshal.family_type.unwrap_or_default(),
+        marshal.help.unwrap_or_default(),
+        marshal.unit.unwrap_or_default(),
+    )
+    .with_samples(marshal.metrics.into_iter().map(|m| {
+        Sample::new(m.label_values, m.timestamp, T::convert_value(m.value))
+            .with_exact_timestamp(m.timestamp_nanos)
+            .with_exemplar(m.exemplar)
+    }))
+    .unwrap()
 }
 
 pub fn parse_openmetrics(
     exposition_bytes: &str,
 ) -> Result<MetricsExposition<OpenMetricsType, OpenMetricsValue>, ParseError> {
-    use pest::iterators::Pair;
+    parse_openmetrics_with_options(exposition_bytes, ParserOptions::default())
+}
 
-    fn parse_metric_descriptor(
-        pair: Pair<Rule>,
-        family: &mut MetricFamilyMarshal<OpenMetricsType>,
-    ) -> Result<(), ParseError> {
-        assert_eq!(pair.as_rule(), Rule::metricdescriptor);
+pub fn parse_openmetrics_with_options(
+    exposition_bytes: &str,
+    options: ParserOptions,
+) -> Result<MetricsExposition<OpenMetricsType, OpenMetricsValue>, ParseError> {
+    parse_openmetrics_dialect(exposition_bytes, options)
+}
+
+/// Parses an exposition that may not be valid UTF-8, decoding it with the given policy first
+/// ```
+/// use openmetrics_parser::{openmetrics::parse_openmetrics_bytes, EncodingPolicy};
+///
+/// let exposition = b"# TYPE foo gauge\nfoo{a=\"caf\xe9\"} 1\n# EOF\n";
+/// assert!(parse_openmetrics_bytes(exposition, EncodingPolicy::Strict).is_err());
+/// let parsed = parse_openmetrics_bytes(exposition, EncodingPolicy::Latin1).unwrap();
+/// assert!(parsed.to_string().contains("caf\u{e9}"));
+/// ```
+pub fn parse_openmetrics_bytes(
+    exposition_bytes: &[u8],
+    encoding: EncodingPolicy,
+) -> Result<MetricsExposition<OpenMetricsType, OpenMetricsValue>, ParseError> {
+    parse_openmetrics_bytes_with_options(exposition_bytes, encoding, ParserOptions::default())
+}
+
+pub fn parse_openmetrics_bytes_with_options(
+    exposition_bytes: &[u8],
+    encoding: EncodingPolicy,
+    options: ParserOptions,
+) -> Result<MetricsExposition<OpenMetricsType, OpenMetricsValue>, ParseError> {
+    parse_openmetrics_with_options(&encoding.decode(exposition_bytes)?, options)
+}
+
+impl MetricsExposition<OpenMetricsType, OpenMetricsValue> {
+    /// Reads and parses an OpenMetrics exposition from a file (see `parse_openmetrics`), with any
+    /// byte order mark dropped. Errors are put in the file, e.g.
+    /// `metrics.txt: line 3: Found two metrics with the same labelset`
+    pub fn from_openmetrics_file<P: AsRef<Path>>(path: P) -> Result<Self, ParseError> {
+        let path = path.as_ref();
+        let exposition = read_exposition_file(path)?;
+        parse_openmetrics(&exposition).map_err(|error| error.in_file(path))
+    }
+}
+
+/// Parses an exposition like `parse_openmetrics_with_options`, along with a report of anything
+/// that was accepted but looked wrong (see `ParseReport`)
+/// ```
+/// use openmetrics_parser::{openmetrics::parse_openmetrics_with_report, ParserOptions, Strictness};
+///
+/// let exposition = "# TYPE foo counter\nfoo_total -1\n# EOF\n";
+/// let options = ParserOptions::new().with_counter_negativity(Strictness::Warn);
+/// let (_, report) = parse_openmetrics_with_report(exposition, options).unwrap();
+/// assert_eq!(report.warnings.len(), 1);
+/// ```
+pub fn parse_openmetrics_with_report(
+    exposition_bytes: &str,
+    options: ParserOptions,
+) -> Result<
+    (
+        MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+        ParseReport,
+    ),
+    ParseError,
+> {
+    parse_dialect(exposition_bytes, options, false).map(|output| {
+        let report = ParseReport {
+            warnings: output.warnings,
+        };
 
-        let mut descriptor = pair.into_inner();
-        let descriptor_type = descriptor.next().unwrap();
-        let metric_name = descriptor.next().unwrap().as_str().to_string();
+        (output.exposition, report)
+    })
+}
+
+/// Parses as much of an exposition as possible: a family that fails to parse or validate is
+/// skipped, and its error recorded, rather than failing the whole exposition. Syntax errors can't
+/// be recovered from, as the grammar covers the whole exposition, so they leave the exposition
+/// empty
+/// ```
+/// use openmetrics_parser::{openmetrics::parse_openmetrics_partial, ParserOptions};
+///
+/// let exposition = "# TYPE foo counter\nfoo_total -1\n# TYPE bar gauge\nbar 1\n# EOF\n";
+/// let (parsed, errors) = parse_openmetrics_partial(exposition, ParserOptions::new());
+/// assert!(parsed.families.contains_key("bar") && !parsed.families.contains_key("foo"));
+/// assert_eq!(errors.len(), 1);
+/// ```
+pub fn parse_openmetrics_partial(
+    exposition_bytes: &str,
+    options: ParserOptions,
+) -> (
+    MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+    Vec<ParseError>,
+) {
+    match parse_dialect(exposition_bytes, options, true) {
+        Ok(output) => (output.exposition, output.errors),
+        Err(error) => (MetricsExposition::new(), vec![error]),
+    }
+}
 
-        match descriptor_type.as_rule() {
-            Rule::kw_help => {
-                let help_text = descriptor.next().map(|s| s.as_str()).unwrap_or_default();
-                family.set_or_test_name(metric_name)?;
-                family.try_add_help(help_text.to_string())?;
+/// Parses an exposition in a custom dialect of the OpenMetrics text format, i.e. with a different set of
+/// family types (see `MetricsDialect`). `# TYPE` lines may contain any lowercase type name, which is
+/// handed to `MetricsDialect::parse_type`
+pub fn parse_openmetrics_dialect<T>(
+    exposition_bytes: &str,
+    options: ParserOptions,
+) -> Result<MetricsExposition<T, T::Value>, ParseError>
+where
+    T: MetricsDialect,
+{
+    parse_dialect(exposition_bytes, options, false).map(|output| output.exposition)
+}
+
+/// Everything a parse produces: the exposition, the broken rules that were only warned about, and
+/// (when continuing on errors) the errors that families were skipped for
+pub(super) struct ParseOutput<T: MetricsDialect> {
+    pub(super) exposition: MetricsExposition<T, T::Value>,
+    pub(super) warnings: Vec<ParseError>,
+    pub(super) errors: Vec<ParseError>,
+}
+
+/// Parses an exposition in the given dialect. If `continue_on_error` is set, families that fail to
+/// parse are skipped and their errors collected, rather than failing the whole parse
+fn parse_dialect<T>(
+    exposition_bytes: &str,
+    options: ParserOptions,
+    continue_on_error: bool,
+) -> Result<ParseOutput<T>, ParseError>
+where
+    T: MetricsDialect,
+{
+    let mut parser = ChunkedParser::new(options, continue_on_error);
+    parser.parse_chunk(exposition_bytes)?;
+    parser.finish()
+}
+
+/// Parses an exposition a chunk at a time, so that only the chunk being parsed has to be held as
+/// text. Chunks must end between families, and not before a family's late metadata (see
+/// `FamilySplitter`)
+pub(super) struct ChunkedParser<T: MetricsDialect> {
+    options: ParserOptions,
+    continue_on_error: bool,
+    output: ParseOutput<T>,
+    found_eof: bool,
+
+    // Whether the last chunk had a syntax error that was skipped, which hides whether it ended
+    // with an EOF
+    skipped_chunk: bool,
+
+    // How many lines, and bytes, of the exposition the chunks so far have covered
+    lines: usize,
+    bytes: usize,
+
+    // The families already taken out of the exposition, which can't be added to any more
+    taken: HashSet<String>,
+}
+
+impl<T: MetricsDialect> ChunkedParser<T> {
+    pub(super) fn new(options: ParserOptions, continue_on_error: bool) -> Self {
+        ChunkedParser {
+            options,
+            continue_on_error,
+            output: ParseOutput {
+                exposition: MetricsExposition::new(),
+                warnings: Vec::new(),
+                errors: Vec::new(),
+            },
+            found_eof: false,
+            skipped_chunk: false,
+            lines: 0,
+            bytes: 0,
+            taken: HashSet::new(),
+        }
+    }
+
+    /// Takes the families parsed so far out of the exposition, e.g. to hand them on before the
+    /// rest of the exposition has arrived
+    pub(super) fn take_families(&mut self) -> Vec<MetricFamily<T, T::Value>> {
+        let families = self.output.exposition.families.drain();
+        families
+            .map(|(name, family)| {
+                self.taken.insert(name);
+                family
+            })
+            .collect()
+    }
+
+    pub(super) fn parse_chunk(&mut self, chunk: &str) -> Result<(), ParseError> {
+        if chunk.is_empty() {
+            return Ok(());
+        }
+
+        self.options
+            .limits
+            .check_input(chunk, self.lines, self.bytes)?;
+        self.bytes += chunk.len();
+
+        if self.found_eof {
+            let at = (self.lines + 1, 1);
+            self.lines += chunk.bytes().filter(|&b| b == b'\n').count();
+            return match check_after_eof(chunk, &self.options, &mut self.output.warnings, at) {
+                Err(error) => self.fail(error),
+                Ok(()) => Ok(()),
+            };
+        }
+
+        // Errors are found in the normalized chunk, so have to be moved back to the original
+        // lines, in the exposition as a whole
+        let (options, lines) = (&self.options, self.lines);
+        let original =
+            |error: ParseError| error.map_line(|line| lines + original_line(chunk, options, line));
+
+        let warnings_before = self.output.warnings.len();
+        let errors_before = self.output.errors.len();
+        let parsed = parse_normalized_chunk(
+            chunk,
+            &self.options,
+            self.continue_on_error,
+            &self.taken,
+            &mut self.output,
+        );
+
+        let warnings = self.output.warnings.split_off(warnings_before);
+        self.output
+            .warnings
+            .extend(warnings.into_iter().map(original));
+        let errors = self.output.errors.split_off(errors_before);
+        self.output.errors.extend(errors.into_iter().map(original));
+
+        self.lines += chunk.bytes().filter(|&b| b == b'\n').count();
+        match parsed.map_err(original)? {
+            Some(found_eof) => {
+                self.found_eof = found_eof;
+                self.skipped_chunk = false;
             }
-            Rule::kw_type => {
-                let family_type = descriptor.next().unwrap().as_str();
-                family.set_or_test_name(metric_name)?;
-                family.try_add_type(OpenMetricsType::try_from(family_type)?)?;
+            None => self.skipped_chunk = true,
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn finish(mut self) -> Result<ParseOutput<T>, ParseError> {
+        if !self.found_eof && !self.skipped_chunk {
+            let error = ParseError::MissingEof;
+            if self.options.allow_missing_eof {
+                self.output.warnings.push(error);
+            } else {
+                self.fail(error)?;
             }
-            Rule::kw_unit => {
-                let unit = descriptor.next().map(|s| s.as_str()).unwrap_or_default();
-                if family.name.is_none() || &metric_name != family.name.as_ref().unwrap() {
+        }
+
+        Ok(self.output)
+    }
+
+    fn fail(&mut self, error: ParseError) -> Result<(), ParseError> {
+        if !self.continue_on_error {
+            return Err(error);
+        }
+
+        self.output.errors.push(error);
+        Ok(())
+    }
+}
+
+/// Parses a chunk of an exposition into `output`, returning whether it ended with an EOF, or
+/// `None` if it was skipped for a syntax error. `taken` are the families that were parsed from
+/// earlier chunks but are no longer in the exposition
+fn parse_normalized_chunk<T>(
+    exposition_bytes: &str,
+    options: &ParserOptions,
+    continue_on_error: bool,
+    taken: &HashSet<String>,
+    output: &mut ParseOutput<T>,
+) -> Result<Option<bool>, ParseError>
+where
+    T: MetricsDialect,
+{
+    fn parse_metric_descriptor<T: MetricsDialect>(
+        kind: MetadataKind,
+        metric_name: &str,
+        text: &str,
+        family: &mut MetricFamilyMarshal<T>,
+    ) -> Result<(), ParseError> {
+        match kind {
+            MetadataKind::Help => {
+                family.set_or_test_name(metric_name.to_string())?;
+                family.try_add_help(
+                    unescape_help(text, ExpositionFormat::OpenMetrics).into_owned(),
+                )?;
+            }
+            MetadataKind::Type => {
+                family.set_or_test_name(metric_name.to_string())?;
+                family.try_add_type(T::parse_type(text)?)?;
+            }
+            MetadataKind::Unit => {
+                if family.name.as_deref() != Some(metric_name) {
                     return Err(ParseError::InvalidMetric(
                         "UNIT metric name doesn't match family".to_owned(),
                     ));
                 }
-                family.try_add_unit(unit.to_string())?;
+                family.try_add_unit(text.to_string())?;
             }
-            _ => unreachable!(),
         }
 
         Ok(())
     }
 
-    fn parse_exemplar(pair: Pair<Rule>) -> Result<Exemplar, ParseError> {
-        let mut inner = pair.into_inner();
-
-        let labels = inner.next().unwrap();
-        assert_eq!(labels.as_rule(), Rule::labels);
-
-        let labels = parse_labels(labels)?
+    fn parse_exemplar(exemplar: &ExemplarLine) -> Result<Exemplar, ParseError> {
+        let labels = parse_labels(&exemplar.labels)?
             .into_iter()
-            .map(|(a, b)| (a.to_owned(), b.to_owned()))
+            .map(|(a, b)| (a.to_owned(), b))
             .collect();
 
-        let id = inner.next().unwrap().as_str();
-        let id = match id.parse() {
-            Ok(i) => i,
-            Err(_) => {
+        let id = match parse_f64(exemplar.value) {
+            Some(i) => i,
+            None => {
                 return Err(ParseError::InvalidMetric(format!(
                     "Exemplar value must be a number (got: {})",
-                    id
+                    exemplar.value
                 )))
             }
         };
 
-        let timestamp = match inner.next() {
-            Some(timestamp) => match timestamp.as_str().parse() {
-                Ok(f) => Some(f),
-                Err(_) => {
+        let timestamp = match exemplar.timestamp {
+            Some(timestamp) => match parse_f64(timestamp) {
+                Some(f) => Some(f),
+                None => {
                     return Err(ParseError::InvalidMetric(format!(
                         "Exemplar timestamp must be a number (got: {})",
-                        timestamp.as_str()
+                        timestamp
                     )))
                 }
             },
@@ -1103,17 +1305,12 @@ pub fn parse_openmetrics(
         Ok(Exemplar::new(labels, id, timestamp))
     }
 
-    fn parse_labels(pair: Pair<Rule>) -> Result<Vec<(&str, &str)>, ParseError> {
-        assert_eq!(pair.as_rule(), Rule::labels);
-
-        let mut label_pairs = pair.into_inner();
-        let mut labels: Vec<(&str, &str)> = Vec::new();
-
-        while label_pairs.peek().is_some() && label_pairs.peek().unwrap().as_rule() == Rule::label {
-            let mut label = label_pairs.next().unwrap().into_inner();
-            let name = label.next().unwrap().as_str();
-            let value = label.next().unwrap().as_str();
+    fn parse_labels<'i>(
+        label_pairs: &[(&'i str, &str)],
+    ) -> Result<Vec<(&'i str, String)>, ParseError> {
+        let mut labels: Vec<(&str, String)> = Vec::with_capacity(label_pairs.len());
 
+        for &(name, value) in label_pairs {
             if labels.iter().any(|(n, _)| n == &name) {
                 return Err(ParseError::InvalidMetric(format!(
                     "Found label `{}` twice in the same labelset",
@@ -1121,7 +1318,7 @@ pub fn parse_openmetrics(
                 )));
             }
 
-            labels.push((name, value));
+            labels.push((name, unescape_label_value(value).into_owned()));
         }
 
         labels.sort_by_key(|l| l.0);
@@ -1129,63 +1326,53 @@ pub fn parse_openmetrics(
         Ok(labels)
     }
 
-    fn parse_sample(
-        pair: Pair<Rule>,
-        family: &mut MetricFamilyMarshal<OpenMetricsType>,
+    fn parse_sample<T: MetricsDialect>(
+        sample: &SampleLine,
+        family: &mut MetricFamilyMarshal<T>,
     ) -> Result<(), ParseError> {
-        assert_eq!(pair.as_rule(), Rule::sample);
-
-        let mut descriptor = pair.into_inner();
-        let metric_name = descriptor.next().unwrap().as_str();
-
-        let labels = if descriptor.peek().unwrap().as_rule() == Rule::labels {
-            parse_labels(descriptor.next().unwrap())?
-        } else {
-            Vec::new()
-        };
+        if let Some(filter) = &family.options.sample_filter {
+            if !filter.matches_escaped(sample.name, sample.labels.iter().copied()) {
+                return Ok(());
+            }
+        }
 
-        let (label_names, label_values) = {
-            let mut names = Vec::new();
-            let mut values = Vec::new();
-            for (name, value) in labels.into_iter() {
+        let (label_names, mut label_values) = {
+            let mut names = Vec::with_capacity(sample.labels.len());
+            let mut values = LabelValues::with_capacity(sample.labels.len());
+            for (name, value) in parse_labels(&sample.labels)?.into_iter() {
                 names.push(name.to_owned());
-                values.push(value.to_owned());
+                values.push(value);
             }
 
             (names, values)
         };
 
-        let value = descriptor.next().unwrap().as_str();
-        let value = match value.parse() {
-            Ok(f) => MetricNumber::Int(f),
-            Err(_) => match value.parse() {
-                Ok(f) => MetricNumber::Float(f),
-                Err(_) => {
-                    return Err(ParseError::InvalidMetric(format!(
-                        "Metric Value must be a number (got: {})",
-                        value
-                    )));
-                }
-            },
-        };
+        if family.options.normalize_bounds {
+            normalize_bound_labels(&label_names, &mut label_values);
+        }
 
-        let mut timestamp = None;
-        let mut exemplar = None;
+        family.options.limits.check_labels(&label_values)?;
 
-        if descriptor.peek().is_some()
-            && descriptor.peek().as_ref().unwrap().as_rule() == Rule::timestamp
-        {
-            timestamp = Some(descriptor.next().unwrap().as_str().parse().unwrap());
-        }
+        let value = match parse_metric_number(sample.value) {
+            Some(value) => value,
+            None => {
+                return Err(ParseError::InvalidMetric(format!(
+                    "Metric Value must be a number (got: {})",
+                    sample.value
+                )));
+            }
+        };
 
-        if descriptor.peek().is_some()
-            && descriptor.peek().as_ref().unwrap().as_rule() == Rule::exemplar
-        {
-            exemplar = Some(parse_exemplar(descriptor.next().unwrap())?);
+        let timestamp = sample.timestamp.map(|t| parse_f64(t).unwrap());
+        let exemplar = sample.exemplar.as_ref().map(parse_exemplar).transpose()?;
+        if let Some(exemplar) = &exemplar {
+            exemplar.check_label_length(&family.options, family.warnings.get_mut())?;
         }
 
-        family.process_new_metric(
-            metric_name,
+        let metrics = family.metrics.len();
+        T::process_new_metric(
+            family,
+            sample.name,
             value,
             label_names,
             label_values,
@@ -1193,84 +1380,318 @@ pub fn parse_openmetrics(
             exemplar,
         )?;
 
+        let unit = ExpositionFormat::OpenMetrics.timestamp_unit();
+        let nanos = sample
+            .timestamp
+            .and_then(|t| parse_timestamp_nanos(t, unit));
+        family.set_timestamp_nanos(metrics, nanos);
+
         Ok(())
     }
 
-    fn parse_metric_family(
-        pair: Pair<Rule>,
-    ) -> Result<MetricFamily<OpenMetricsType, OpenMetricsValue>, ParseError> {
-        assert_eq!(pair.as_rule(), Rule::metricfamily);
+    fn skip_comment(
+        comment: &str,
+        options: &ParserOptions,
+        warnings: &mut Vec<ParseError>,
+    ) -> Result<(), ParseError> {
+        let error =
+            ParseError::InvalidMetric(format!("Unknown metadata line: {}", comment.trim_end()));
+        if !options.ignore_unknown_metadata {
+            return Err(error);
+        }
 
-        let mut metric_family = MetricFamilyMarshal::empty();
+        warnings.push(error);
+        Ok(())
+    }
 
-        for child in pair.into_inner() {
-            match child.as_rule() {
-                Rule::metricdescriptor => {
+    fn parse_metric_family<T: MetricsDialect>(
+        children: &[LexedLine],
+        options: &ParserOptions,
+        warnings: &mut Vec<ParseError>,
+    ) -> Result<MetricFamily<T, T::Value>, ParseError> {
+        let mut metric_family = MetricFamilyMarshal::with_options(options.clone());
+
+        // Errors in the family as a whole are put on its first line
+        let family_line = children[0].number;
+
+        let mut samples = 0;
+        for child in children {
+            let line = child.number;
+            let warnings_before = metric_family.warnings.get_mut().len();
+            let metrics_before = metric_family.metrics.len();
+            let parsed = match &child.line {
+                Line::Descriptor { kind, name, text } => {
                     if metric_family.metrics.is_empty() {
-                        parse_metric_descriptor(child, &mut metric_family)?;
+                        parse_metric_descriptor(*kind, name, text, &mut metric_family)
                     } else {
-                        return Err(ParseError::InvalidMetric(
+                        Err(ParseError::InvalidMetric(
                             "Metric Descriptor after samples".to_owned(),
-                        ));
+                        ))
                     }
                 }
-                Rule::sample => {
-                    parse_sample(child, &mut metric_family)?;
+                Line::Sample(sample) => {
+                    samples += 1;
+                    ParseLimits::check(
+                        Limit::SamplesPerFamily,
+                        options.limits.max_samples_per_family,
+                        samples,
+                    )
+                    .and_then(|_| parse_sample(sample, &mut metric_family))
                 }
-                _ => unreachable!(),
-            }
+                Line::Comment(comment) => {
+                    skip_comment(comment, options, metric_family.warnings.get_mut())
+                }
+            };
+
+            parsed.map_err(|error| error.at(line, 1))?;
+            locate_warnings(metric_family.warnings.get_mut(), warnings_before, line, 1);
+            metric_family.set_location(metrics_before, line, 1);
         }
 
-        metric_family.validate()?;
+        let warnings_before = metric_family.warnings.get_mut().len();
+        T::validate_family(&metric_family).map_err(|error| error.at(family_line, 1))?;
+        locate_warnings(
+            metric_family.warnings.get_mut(),
+            warnings_before,
+            family_line,
+            1,
+        );
+        warnings.append(metric_family.warnings.get_mut());
+
+        let mut family = family_from_marshal(metric_family);
+        if let Some(scheme) = options.name_escaping {
+            family
+                .unescape_names(scheme)
+                .map_err(|error| error.at(family_line, 1))?;
+        }
 
-        Ok(metric_family.into())
+        Ok(family)
     }
 
-    let exposition_marshal = OpenMetricsParser::parse(Rule::exposition, exposition_bytes)?
-        .next()
-        .unwrap();
-    let mut exposition = MetricsExposition::new();
+    /// How many of the lines at the start of `children` are really late metadata for the family
+    /// whose lines were `previous`, i.e. descriptors with that family's name
+    fn late_metadata_len(children: &[LexedLine], previous: &[LexedLine]) -> usize {
+        children
+            .iter()
+            .take_while(|child| match &child.line {
+                Line::Descriptor { name, .. } => previous.iter().all(|line| match &line.line {
+                    Line::Descriptor { name: other, .. } => other == name,
+                    Line::Sample(sample) => sample.name.starts_with(name),
+                    Line::Comment(_) => true,
+                }),
+                _ => false,
+            })
+            .count()
+    }
 
-    assert_eq!(exposition_marshal.as_rule(), Rule::exposition);
+    /// The name a family's lines give it: its descriptors' name, or failing that its first
+    /// sample's. Comments don't have one
+    fn family_name<'a>(children: &'a [LexedLine]) -> Option<&'a str> {
+        children.iter().find_map(|child| match &child.line {
+            Line::Descriptor { name, .. } => Some(*name),
+            Line::Sample(sample) => Some(sample.name),
+            Line::Comment(_) => None,
+        })
+    }
 
-    let mut found_eof = false;
-    for span in exposition_marshal.into_inner() {
-        match span.as_rule() {
-            Rule::metricfamily => {
-                let family = parse_metric_family(span)?;
+    /// Parses the lines of a family and adds it to the exposition, folding any late metadata at
+    /// the start of `children` into the previous family
+    fn add_family<'i, T: MetricsDialect>(
+        mut children: Vec<LexedLine<'i>>,
+        options: &ParserOptions,
+        exposition: &mut MetricsExposition<T, T::Value>,
+        taken: &HashSet<String>,
+        previous: &mut Option<(String, Vec<LexedLine<'i>>, usize)>,
+        warnings: &mut Vec<ParseError>,
+    ) -> Result<(), ParseError> {
+        // Comments between families don't make up a family of their own
+        if children
+            .iter()
+            .all(|child| matches!(child.line, Line::Comment(_)))
+        {
+            for child in children {
+                if let Line::Comment(comment) = child.line {
+                    let warnings_before = warnings.len();
+                    skip_comment(comment, options, warnings)
+                        .map_err(|error| error.at(child.number, 1))?;
+                    locate_warnings(warnings, warnings_before, child.number, 1);
+                }
+            }
+
+            return Ok(());
+        }
 
-                if exposition.families.contains_key(&family.family_name) {
+        let line = children[0].number;
+
+        // Late metadata is folded into the family it describes, which is parsed again
+        // with all of its descriptors ahead of its samples
+        if let Some((name, lines, warnings_before)) = previous.take() {
+            let late = late_metadata_len(&children, &lines);
+            if late > 0 {
+                let rest = children.split_off(late);
+                let (mut merged, samples): (Vec<_>, Vec<_>) = lines
+                    .into_iter()
+                    .partition(|line| matches!(line.line, Line::Descriptor { .. }));
+                merged.append(&mut children);
+                merged.extend(samples);
+
+                // Its name can change along with its type (e.g. `foo_total` to `foo`)
+                exposition.families.remove(&name);
+                warnings.truncate(warnings_before);
+                let family = parse_metric_family(&merged, options, warnings)?;
+                if exposition.families.contains_key(&family.family_name)
+                    || taken.contains(&family.family_name)
+                {
                     return Err(ParseError::InvalidMetric(format!(
                         "Found a metric family called {}, after that family was finalised",
                         family.family_name
-                    )));
+                    ))
+                    .at(line, 1));
                 }
 
+                *previous = Some((family.family_name.clone(), merged, warnings_before));
                 exposition
                     .families
                     .insert(family.family_name.clone(), family);
-            }
-            Rule::kw_eof => {
-                found_eof = true;
 
-                if span.as_span().end() != exposition_bytes.len()
-                    && !(span.as_span().end() == exposition_bytes.len() - 1
-                        && exposition_bytes.ends_with('\n'))
-                {
-                    return Err(ParseError::InvalidMetric(
-                        "Found text after the EOF token".to_string(),
-                    ));
+                if rest.is_empty() {
+                    return Ok(());
                 }
+
+                children = rest;
             }
-            _ => unreachable!(),
         }
+
+        let warnings_before = warnings.len();
+        let family = parse_metric_family(&children, options, warnings)?;
+
+        if exposition.families.contains_key(&family.family_name)
+            || taken.contains(&family.family_name)
+        {
+            return Err(ParseError::InvalidMetric(format!(
+                "Found a metric family called {}, after that family was finalised",
+                family.family_name
+            ))
+            .at(line, 1));
+        }
+
+        if options.allow_late_metadata {
+            *previous = Some((family.family_name.clone(), children, warnings_before));
+        }
+
+        exposition
+            .families
+            .insert(family.family_name.clone(), family);
+
+        Ok(())
     }
 
-    if !found_eof {
-        return Err(ParseError::InvalidMetric(
-            "Didn't find an EOF token".to_string(),
-        ));
+    let exposition_bytes = normalize_input(exposition_bytes, options);
+    let exposition_bytes = exposition_bytes.as_ref();
+
+    // A missing EOF is checked for once the last chunk is parsed, so that it can be recovered
+    // from (and reported as such)
+    let lexed = if options.fast_lexer {
+        lex_by_hand(exposition_bytes)
+    } else {
+        lex_with_grammar(exposition_bytes)
+    };
+
+    let lexed = match lexed {
+        Ok(lexed) => lexed,
+        Err(error) if continue_on_error => {
+            output.errors.push(error);
+            return Ok(None);
+        }
+        Err(error) => return Err(error),
+    };
+
+    // As in the grammar, a family runs up to the first descriptor after its samples
+    let mut families: Vec<Vec<LexedLine>> = Vec::new();
+    let mut in_samples = false;
+    for line in lexed.lines {
+        match line.line {
+            Line::Descriptor { .. } if in_samples || families.is_empty() => {
+                in_samples = false;
+                families.push(Vec::new());
+            }
+            Line::Sample(_) if families.is_empty() => {
+                in_samples = true;
+                families.push(Vec::new());
+            }
+            Line::Comment(_) if families.is_empty() => families.push(Vec::new()),
+            Line::Sample(_) => in_samples = true,
+            _ => {}
+        }
+
+        families.last_mut().unwrap().push(line);
+    }
+
+    // The previous family's name and lines, and how many warnings there were before it
+    let mut previous = None;
+    for children in families {
+        if family_name(&children).is_some_and(|name| !options.family_filter.matches(name)) {
+            previous = None;
+            continue;
+        }
+
+        let line = children[0].number;
+        let added = add_family(
+            children,
+            options,
+            &mut output.exposition,
+            taken,
+            &mut previous,
+            &mut output.warnings,
+        );
+
+        match added {
+            Err(error) if continue_on_error && !error.is_fatal() => output.errors.push(error),
+            added => added?,
+        }
+
+        ParseLimits::check(
+            Limit::Families,
+            options.limits.max_families,
+            output.exposition.families.len() + taken.len(),
+        )
+        .map_err(|error| error.at(line, 1))?;
+    }
+
+    if let Some(eof) = lexed.eof {
+        let after = lexed.after_eof;
+        let after = after.strip_prefix('\n').unwrap_or(after);
+        if let Err(error) = check_after_eof(after, options, &mut output.warnings, eof) {
+            if !continue_on_error {
+                return Err(error);
+            }
+
+            output.errors.push(error);
+        }
+    }
+
+    Ok(Some(lexed.eof.is_some()))
+}
+
+/// Checks the text after the `# EOF` line, which there can't be any of unless the options
+/// allow it (see `ParserOptions::allow_blank_after_eof` and `ParserOptions::text_after_eof`)
+fn check_after_eof(
+    after: &str,
+    options: &ParserOptions,
+    warnings: &mut Vec<ParseError>,
+    (line, column): (usize, usize),
+) -> Result<(), ParseError> {
+    let after = if options.allow_blank_after_eof {
+        after.trim_start_matches([' ', '\t', '\r', '\n'])
+    } else {
+        after
+    };
+
+    if after.is_empty() {
+        return Ok(());
     }
 
-    Ok(exposition)
+    let error =
+        ParseError::InvalidMetric("Found text after the EOF token".to_string()).at(line, column);
+    options.text_after_eof.enforce(error, warnings)
 }
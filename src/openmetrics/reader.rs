@@ -0,0 +1,112 @@
+use std::io::BufRead;
+
+use super::parsers::ChunkedParser;
+use crate::{MetricsExposition, OpenMetricsType, OpenMetricsValue, ParseError, ParserOptions};
+
+/// Parses an exposition from a reader, e.g. a file or a response body, without first reading the
+/// whole of it into memory: it's read, and parsed, a family at a time
+/// ```
+/// use openmetrics_parser::openmetrics::parse_openmetrics_from_reader;
+///
+/// let exposition = "# TYPE foo gauge\nfoo 1\n# TYPE bar gauge\nbar 2\n# EOF\n";
+/// let parsed = parse_openmetrics_from_reader(exposition.as_bytes()).unwrap();
+/// assert_eq!(parsed.families.len(), 2);
+/// ```
+pub fn parse_openmetrics_from_reader<R: BufRead>(
+    reader: R,
+) -> Result<MetricsExposition<OpenMetricsType, OpenMetricsValue>, ParseError> {
+    parse_openmetrics_from_reader_with_options(reader, ParserOptions::default())
+}
+
+pub fn parse_openmetrics_from_reader_with_options<R: BufRead>(
+    mut reader: R,
+    options: ParserOptions,
+) -> Result<MetricsExposition<OpenMetricsType, OpenMetricsValue>, ParseError> {
+    let mut splitter = FamilySplitter::new(options.allow_late_metadata);
+    let mut parser = ChunkedParser::new(options, false);
+    let mut chunk = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(ParseError::Io)? == 0 {
+            break;
+        }
+
+        if splitter.starts_family(&line) {
+            parser.parse_chunk(&chunk)?;
+            chunk.clear();
+        }
+
+        chunk.push_str(&line);
+    }
+
+    parser.parse_chunk(&chunk)?;
+    parser.finish().map(|output| output.exposition)
+}
+
+/// Finds the lines of an exposition that start a new family, where it can be split into chunks
+/// that parse the same as the exposition as a whole: a descriptor after samples, unless it's late
+/// metadata for the family it follows
+pub(super) struct FamilySplitter {
+    allow_late_metadata: bool,
+    in_samples: bool,
+    found_eof: bool,
+
+    // The names in the current family's descriptors and samples, for spotting late metadata
+    descriptor_names: Vec<String>,
+    sample_names: Vec<String>,
+}
+
+impl FamilySplitter {
+    pub(super) fn new(allow_late_metadata: bool) -> Self {
+        FamilySplitter {
+            allow_late_metadata,
+            in_samples: false,
+            found_eof: false,
+            descriptor_names: Vec::new(),
+            sample_names: Vec::new(),
+        }
+    }
+
+    /// Whether the given line starts a new family, i.e. the lines before it can be parsed
+    pub(super) fn starts_family(&mut self, line: &str) -> bool {
+        let line = line.trim_end();
+        if self.found_eof || line.is_empty() {
+            return false;
+        }
+
+        if line == "# EOF" {
+            self.found_eof = true;
+            return false;
+        }
+
+        if !line.starts_with('#') {
+            let name = line.split(['{', ' ', '\t']).next().unwrap_or_default();
+            if !self.sample_names.iter().any(|n| n == name) {
+                self.sample_names.push(name.to_owned());
+            }
+
+            self.in_samples = true;
+            return false;
+        }
+
+        let name = match line.split(' ').collect::<Vec<_>>().as_slice() {
+            ["#", "HELP" | "TYPE" | "UNIT", name, ..] => name.to_string(),
+            // Comments stay with the family before them
+            _ => return false,
+        };
+
+        let late_metadata = self.allow_late_metadata
+            && self.descriptor_names.iter().all(|n| *n == name)
+            && self.sample_names.iter().all(|n| n.starts_with(&name));
+        let starts_family = self.in_samples && !late_metadata;
+        if starts_family {
+            self.in_samples = false;
+            self.descriptor_names.clear();
+            self.sample_names.clear();
+        }
+
+        self.descriptor_names.push(name);
+        starts_family
+    }
+}
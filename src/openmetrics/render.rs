@@ -0,0 +1,178 @@
+//! Renders expositions back out in the OpenMetrics text format
+//! https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md#text-format
+
+use std::{
+    fmt::{self, Write},
+    io,
+};
+
+use crate::{
+    families_in_order, MetricsExposition, OpenMetricsType, OpenMetricsValue, RenderOptions,
+};
+
+/// Writes the exposition into `f` as OpenMetrics text, with families in the exposition's order
+/// and the trailing `# EOF`
+pub fn write_openmetrics<W: Write>(
+    f: &mut W,
+    exposition: &MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+) -> fmt::Result {
+    write_openmetrics_with_options(f, exposition, &RenderOptions::default())
+}
+
+pub fn write_openmetrics_with_options<W: Write>(
+    f: &mut W,
+    exposition: &MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+    options: &RenderOptions,
+) -> fmt::Result {
+    options.for_each_family(exposition, |family| write!(f, "{}", family))?;
+    if options.eof {
+        f.write_str("# EOF\n")?;
+    }
+
+    Ok(())
+}
+
+/// Renders the exposition as OpenMetrics text, including the descriptors (`# HELP`, `# TYPE`,
+/// `# UNIT`) of each family, the suffixed samples of each type (`_total`, `_bucket` etc),
+/// exemplars, and the trailing `# EOF`
+/// ```
+/// use openmetrics_parser::openmetrics::{parse_openmetrics, render_openmetrics};
+///
+/// let text = "# TYPE requests counter\nrequests_total{path=\"/\"} 5\nrequests_created{path=\"/\"} 1000\n# EOF\n";
+/// let exposition = parse_openmetrics(text).unwrap();
+/// assert_eq!(render_openmetrics(&exposition), text);
+/// ```
+pub fn render_openmetrics(
+    exposition: &MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+) -> String {
+    render_openmetrics_with_options(exposition, &RenderOptions::default())
+}
+
+/// Renders the exposition as OpenMetrics text, as tweaked by `options`
+/// ```
+/// use openmetrics_parser::{openmetrics::{parse_openmetrics, render_openmetrics_with_options}, RenderOptions};
+///
+/// let a = parse_openmetrics("# TYPE up gauge\nup{b=\"1\",a=\"2\"} 1\nup{a=\"1\",b=\"1\"} 1.0\n# EOF\n").unwrap();
+/// let b = parse_openmetrics("# TYPE up gauge\nup{a=\"1\",b=\"1\"} 1\nup{a=\"2\",b=\"1\"} 1\n# EOF\n").unwrap();
+///
+/// let options = RenderOptions::new().with_canonical(true);
+/// assert_eq!(
+///     render_openmetrics_with_options(&a, &options),
+///     render_openmetrics_with_options(&b, &options)
+/// );
+/// ```
+pub fn render_openmetrics_with_options(
+    exposition: &MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+    options: &RenderOptions,
+) -> String {
+    let mut rendered = String::new();
+    // Writing into a String can't fail
+    let _ = write_openmetrics_with_options(&mut rendered, exposition, options);
+    rendered
+}
+
+/// Streams the exposition into `writer` as OpenMetrics text, one line at a time, without
+/// building the whole rendering in memory first. `writer` isn't buffered here, so wrap
+/// unbuffered writers (files, sockets) in an `io::BufWriter`. Likewise, to serve a
+/// `Content-Encoding: gzip` response, pass a compressing writer (e.g. `flate2::write::GzEncoder`)
+/// and the exposition is compressed as it's rendered
+/// ```
+/// use openmetrics_parser::openmetrics::{encode_openmetrics, parse_openmetrics};
+///
+/// let exposition = parse_openmetrics("# TYPE up gauge\nup 1\n# EOF\n").unwrap();
+/// let mut output = Vec::new();
+/// encode_openmetrics(&mut output, &exposition).unwrap();
+/// assert_eq!(output, b"# TYPE up gauge\nup 1\n# EOF\n");
+/// ```
+pub fn encode_openmetrics<W: io::Write + ?Sized>(
+    writer: &mut W,
+    exposition: &MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+) -> io::Result<()> {
+    encode_openmetrics_with_options(writer, exposition, &RenderOptions::default())
+}
+
+pub fn encode_openmetrics_with_options<W: io::Write + ?Sized>(
+    writer: &mut W,
+    exposition: &MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+    options: &RenderOptions,
+) -> io::Result<()> {
+    options.for_each_family(exposition, |family| write!(writer, "{}", family))?;
+    if options.eof {
+        writer.write_all(b"# EOF\n")?;
+    }
+
+    Ok(())
+}
+
+/// Splices the families of the exposition into an already rendered OpenMetrics exposition, just
+/// before its `# EOF`, so that they're served as part of it. This is how parsed families are
+/// re-exposed through another encoder, e.g. a proxy exporter that serves the output of the
+/// `prometheus-client` crate's `encode` along with the families it scraped. The caller has to
+/// make sure the two don't share any family names.
+///
+/// That's only a partial integration with `prometheus-client`, at the level of its text output:
+/// there are no adapters that register parsed families as its collectors (`ConstCounter` and so
+/// on), since this crate doesn't depend on it
+/// ```
+/// use openmetrics_parser::{openmetrics::{parse_openmetrics, splice_openmetrics}, RenderOptions};
+///
+/// // As encoded by prometheus_client::encoding::text::encode
+/// let mut encoded = String::from("# TYPE proxy_scrapes counter\nproxy_scrapes_total 3\n# EOF\n");
+/// let scraped = parse_openmetrics("# TYPE up gauge\nup 1\n# EOF\n").unwrap();
+/// splice_openmetrics(&mut encoded, &scraped, &RenderOptions::new());
+/// assert_eq!(encoded, "# TYPE proxy_scrapes counter\nproxy_scrapes_total 3\n# TYPE up gauge\nup 1\n# EOF\n");
+/// ```
+pub fn splice_openmetrics(
+    encoded: &mut String,
+    exposition: &MetricsExposition<OpenMetricsType, OpenMetricsValue>,
+    options: &RenderOptions,
+) {
+    if let Some(body) = encoded.strip_suffix("# EOF\n") {
+        encoded.truncate(body.len());
+    }
+
+    let options = options.clone().with_eof(true);
+    // Writing into a String can't fail
+    let _ = write_openmetrics_with_options(encoded, exposition, &options);
+}
+
+impl MetricsExposition<OpenMetricsType, OpenMetricsValue> {
+    /// Renders the exposition as OpenMetrics text (see `openmetrics::render_openmetrics`)
+    pub fn to_openmetrics_string(&self) -> String {
+        render_openmetrics(self)
+    }
+
+    /// Renders the exposition as OpenMetrics text into a buffer that's preallocated using
+    /// `rendered_size_hint`, so large expositions don't keep reallocating as they're rendered
+    pub fn render_to_vec(&self) -> Vec<u8> {
+        let mut rendered = Vec::with_capacity(self.rendered_size_hint());
+        // Writing into a Vec can't fail
+        let _ = encode_openmetrics(&mut rendered, self);
+        rendered
+    }
+
+    /// Renders the exposition lazily as OpenMetrics text, one family per chunk, followed by a
+    /// final `# EOF` chunk (unless `RenderOptions::eof` is off). Only one family is rendered at a time, so this can back a chunked
+    /// HTTP response for a huge exposition with bounded memory
+    /// ```
+    /// use openmetrics_parser::{openmetrics::parse_openmetrics, RenderOptions};
+    ///
+    /// let exposition = parse_openmetrics("# TYPE b gauge\nb 2\n# TYPE a gauge\na 1\n# EOF\n").unwrap();
+    /// let chunks: Vec<Vec<u8>> = exposition.render_chunks(&RenderOptions::new()).collect();
+    /// assert_eq!(
+    ///     chunks,
+    ///     vec![b"# TYPE b gauge\nb 2\n".to_vec(), b"# TYPE a gauge\na 1\n".to_vec(), b"# EOF\n".to_vec()]
+    /// );
+    /// ```
+    pub fn render_chunks(&self, options: &RenderOptions) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let families = families_in_order(self, options);
+        let eof = options.eof;
+        let options = options.clone();
+        families
+            .into_iter()
+            .map(move |family| {
+                options.with_rendered_family(family, |family| family.to_string().into_bytes())
+            })
+            .chain(eof.then(|| b"# EOF\n".to_vec()))
+    }
+}
@@ -0,0 +1,195 @@
+use std::{collections::VecDeque, io};
+
+use super::{parsers::ChunkedParser, reader::FamilySplitter};
+use crate::{OpenMetricsMetricFamily, OpenMetricsType, ParseError, ParserOptions};
+
+/// Parses an exposition as it arrives, e.g. in the frames of a chunked HTTP response, handing
+/// back each family as soon as the next one starts. Families are checked as they are in
+/// `parse_openmetrics_with_options`, including against the families that came before them, but
+/// aren't kept once they're handed back. After an error, the rest of the exposition can't be
+/// parsed
+/// ```
+/// use openmetrics_parser::openmetrics::StreamingParser;
+///
+/// let mut parser = StreamingParser::new();
+/// assert!(parser.feed(b"# TYPE foo gauge\nfoo 1\n# TY").unwrap().is_empty());
+///
+/// let families = parser.feed(b"PE bar gauge\nbar 1\n# EOF\n").unwrap();
+/// assert_eq!(families[0].family_name, "foo");
+///
+/// let families = parser.finish().unwrap();
+/// assert_eq!(families[0].family_name, "bar");
+/// ```
+pub struct StreamingParser {
+    parser: ChunkedParser<OpenMetricsType>,
+    splitter: FamilySplitter,
+
+    // The lines of the family being fed, and the start of a line that hasn't ended yet
+    chunk: String,
+    partial_line: Vec<u8>,
+}
+
+impl Default for StreamingParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamingParser {
+    pub fn new() -> Self {
+        Self::with_options(ParserOptions::default())
+    }
+
+    pub fn with_options(options: ParserOptions) -> Self {
+        StreamingParser {
+            splitter: FamilySplitter::new(options.allow_late_metadata),
+            parser: ChunkedParser::new(options, false),
+            chunk: String::new(),
+            partial_line: Vec::new(),
+        }
+    }
+
+    /// Feeds the next part of the exposition, which can end anywhere (even in the middle of a
+    /// UTF-8 character), and returns the families it completed
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Vec<OpenMetricsMetricFamily>, ParseError> {
+        let end = match bytes.iter().rposition(|&b| b == b'\n') {
+            Some(end) => end + 1,
+            None => {
+                self.partial_line.extend_from_slice(bytes);
+                return Ok(Vec::new());
+            }
+        };
+
+        self.partial_line.extend_from_slice(&bytes[..end]);
+        let lines = std::mem::replace(&mut self.partial_line, bytes[end..].to_vec());
+        let lines = String::from_utf8(lines)
+            .map_err(|e| ParseError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+
+        for line in lines.split_inclusive('\n') {
+            if self.splitter.starts_family(line) {
+                self.parser.parse_chunk(&self.chunk)?;
+                self.chunk.clear();
+            }
+
+            self.chunk.push_str(line);
+        }
+
+        Ok(self.parser.take_families())
+    }
+
+    /// Parses whatever is left of the exposition, and returns the families it held
+    pub fn finish(mut self) -> Result<Vec<OpenMetricsMetricFamily>, ParseError> {
+        let rest = std::mem::take(&mut self.partial_line);
+        let rest = String::from_utf8(rest)
+            .map_err(|e| ParseError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+        if self.splitter.starts_family(&rest) {
+            self.parser.parse_chunk(&self.chunk)?;
+            self.chunk.clear();
+        }
+
+        self.chunk.push_str(&rest);
+        self.parser.parse_chunk(&self.chunk)?;
+
+        let families = self.parser.take_families();
+        self.parser.finish()?;
+        Ok(families)
+    }
+}
+
+/// Parses an exposition a family at a time, as they're asked for: only the families of the
+/// latest chunk of lines are held at once, and parsing stops as soon as the caller does.
+/// Families are checked as they are in `parse_openmetrics_with_options`, so an exposition that
+/// breaks a rule ends with an error after the families before it (including a missing `# EOF`)
+/// ```
+/// use openmetrics_parser::openmetrics::OpenMetricsFamilyIter;
+///
+/// let exposition = "# TYPE foo gauge\nfoo 1\n# TYPE bar gauge\nbar 1\n# EOF\n";
+/// let foo = OpenMetricsFamilyIter::new(exposition)
+///     .find(|family| family.as_ref().map_or(true, |f| f.family_name == "foo"))
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!(foo.family_name, "foo");
+/// ```
+pub struct OpenMetricsFamilyIter<'a> {
+    input: &'a str,
+
+    // Where the next line starts, and where the lines of the family being read start
+    position: usize,
+    chunk_start: usize,
+
+    splitter: FamilySplitter,
+    // Gone once the input has been parsed, or failed to parse
+    parser: Option<ChunkedParser<OpenMetricsType>>,
+    families: VecDeque<OpenMetricsMetricFamily>,
+    error: Option<ParseError>,
+}
+
+impl<'a> OpenMetricsFamilyIter<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self::with_options(input, ParserOptions::default())
+    }
+
+    pub fn with_options(input: &'a str, options: ParserOptions) -> Self {
+        OpenMetricsFamilyIter {
+            input,
+            position: 0,
+            chunk_start: 0,
+            splitter: FamilySplitter::new(options.allow_late_metadata),
+            parser: Some(ChunkedParser::new(options, false)),
+            families: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    /// Reads lines up to the end of the next family, or of the input
+    fn parse_next_chunk(
+        &mut self,
+        parser: &mut ChunkedParser<OpenMetricsType>,
+    ) -> Result<bool, ParseError> {
+        while self.position < self.input.len() {
+            let rest = &self.input[self.position..];
+            let end = rest.find('\n').map_or(rest.len(), |end| end + 1);
+            let line = &rest[..end];
+
+            let line_start = self.position;
+            self.position += end;
+            if self.splitter.starts_family(line) {
+                parser.parse_chunk(&self.input[self.chunk_start..line_start])?;
+                self.chunk_start = line_start;
+                return Ok(true);
+            }
+        }
+
+        parser.parse_chunk(&self.input[self.chunk_start..])?;
+        Ok(false)
+    }
+}
+
+impl Iterator for OpenMetricsFamilyIter<'_> {
+    type Item = Result<OpenMetricsMetricFamily, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(family) = self.families.pop_front() {
+                return Some(Ok(family));
+            }
+
+            if let Some(error) = self.error.take() {
+                return Some(Err(error));
+            }
+
+            let mut parser = self.parser.take()?;
+            match self.parse_next_chunk(&mut parser) {
+                Ok(more) => {
+                    self.families.extend(parser.take_families());
+                    if more {
+                        self.parser = Some(parser);
+                    } else if let Err(error) = parser.finish() {
+                        self.error = Some(error);
+                    }
+                }
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
+}
@@ -65,3 +65,1762 @@ fn run_openmetrics_validation() {
         }
     }
 }
+
+#[test]
+fn test_duplicate_buckets() {
+    use crate::{OpenMetricsValue, ParseError, ParserOptions};
+
+    let exposition = "# TYPE foo histogram
+foo_bucket{le=\"1\"} 2
+foo_bucket{le=\"1.0\"} 3
+foo_bucket{le=\"+Inf\"} 4
+# EOF
+";
+
+    let error = crate::openmetrics::parse_openmetrics(exposition).unwrap_err();
+    assert!(matches!(error.kind(), ParseError::DuplicateBucket(b) if *b == 1.));
+
+    let parsed = crate::openmetrics::parse_openmetrics_with_options(
+        exposition,
+        ParserOptions::new().with_merge_duplicate_buckets(true),
+    )
+    .unwrap();
+
+    let sample = parsed.families["foo"].iter_samples().next().unwrap();
+    match &sample.value {
+        OpenMetricsValue::Histogram(h) => {
+            assert_eq!(h.buckets.len(), 2);
+            assert_eq!(h.buckets[0].count.as_f64(), 3.);
+        }
+        v => panic!("expected a histogram, got {:?}", v),
+    }
+}
+
+#[test]
+fn test_gauge_exemplars() {
+    use crate::ParserOptions;
+
+    let exposition = "# TYPE in_flight gauge
+in_flight 3 # {trace_id=\"abc\"} 0.5
+# EOF
+";
+
+    assert!(crate::openmetrics::parse_openmetrics(exposition).is_err());
+
+    let parsed = crate::openmetrics::parse_openmetrics_with_options(
+        exposition,
+        ParserOptions::new().with_gauge_exemplars(true),
+    )
+    .unwrap();
+
+    let family = &parsed.families["in_flight"];
+    let sample = family.iter_samples().next().unwrap();
+    let exemplar = sample.exemplar.as_ref().unwrap();
+    assert_eq!(exemplar.labels["trace_id"], "abc");
+    assert_eq!(exemplar.id, 0.5);
+
+    assert!(family
+        .to_string()
+        .contains("in_flight 3 # {trace_id=\"abc\"} 0.5\n"));
+}
+
+#[test]
+fn test_exemplar_placement() {
+    use crate::openmetrics::{parse_openmetrics, parse_openmetrics_with_options};
+    use crate::{OpenMetricsValue, ParserOptions};
+
+    let exposition = "# TYPE requests counter
+requests_total 7 # {trace_id=\"abc\"} 1
+# EOF
+";
+    let parsed = parse_openmetrics(exposition).unwrap();
+    match &parsed.families["requests"]
+        .iter_samples()
+        .next()
+        .unwrap()
+        .value
+    {
+        OpenMetricsValue::Counter(c) => {
+            assert_eq!(c.exemplar.as_ref().unwrap().labels["trace_id"], "abc")
+        }
+        v => panic!("Expected a counter, got {:?}", v),
+    }
+    assert_eq!(parsed.to_openmetrics_string(), exposition);
+
+    let exposition = "# TYPE queue gaugehistogram
+queue_bucket{le=\"+Inf\"} 3 # {trace_id=\"abc\"} 1
+queue_gsum 2
+queue_gcount 3 # {trace_id=\"def\"} 1
+# EOF
+";
+    assert!(parse_openmetrics(exposition).is_err());
+
+    let parsed = parse_openmetrics_with_options(
+        exposition,
+        ParserOptions::new().with_allow_gcount_exemplars(true),
+    )
+    .unwrap();
+    assert_eq!(
+        parsed.to_openmetrics_string(),
+        exposition.replace("queue_gcount 3 # {trace_id=\"def\"} 1", "queue_gcount 3")
+    );
+
+    // Only _gcount is tolerated
+    assert!(parse_openmetrics_with_options(
+        "# TYPE queue gaugehistogram\nqueue_bucket{le=\"+Inf\"} 3\nqueue_gsum 2 # {} 1\n# EOF\n",
+        ParserOptions::new().with_allow_gcount_exemplars(true),
+    )
+    .is_err());
+}
+
+#[test]
+fn test_crlf_line_endings() {
+    use crate::ParserOptions;
+
+    let exposition = "# HELP foo A counter\r\n# TYPE foo counter\r\nfoo_total 1\r\n# EOF\r\n";
+
+    assert!(crate::openmetrics::parse_openmetrics(exposition).is_err());
+
+    let parsed = crate::openmetrics::parse_openmetrics_with_options(
+        exposition,
+        ParserOptions::new().with_allow_crlf(true),
+    )
+    .unwrap();
+    assert_eq!(parsed.families["foo"].help, "A counter");
+}
+
+#[test]
+fn test_trailing_whitespace() {
+    use crate::ParserOptions;
+
+    let exposition = "# TYPE foo counter \nfoo_total 1  \n\n# TYPE bar gauge\nbar 2\t\n# EOF\n";
+
+    assert!(crate::openmetrics::parse_openmetrics(exposition).is_err());
+
+    let parsed = crate::openmetrics::parse_openmetrics_with_options(
+        exposition,
+        ParserOptions::new().with_trim_whitespace(true),
+    )
+    .unwrap();
+    assert_eq!(parsed.families.len(), 2);
+}
+
+#[test]
+fn test_lenient_options() {
+    use crate::openmetrics::parse_openmetrics_with_options;
+    use crate::{OpenMetricsType, OpenMetricsValue, ParserOptions};
+
+    let missing_eof = "# TYPE foo counter\nfoo_total 1\n";
+    assert!(crate::openmetrics::parse_openmetrics(missing_eof).is_err());
+    let options = ParserOptions::new().with_allow_missing_eof(true);
+    assert!(parse_openmetrics_with_options(missing_eof, options.clone()).is_ok());
+    assert!(parse_openmetrics_with_options("# TYPE foo counter\nfoo_total 1", options).is_err());
+
+    let nan_counter = "# TYPE foo counter\nfoo_total NaN\n# EOF\n";
+    let error = crate::openmetrics::parse_openmetrics(nan_counter).unwrap_err();
+    assert!(error.to_string().contains("can't be NaN"), "{}", error);
+    let options = ParserOptions::new().with_allow_nan_counters(true);
+    assert!(parse_openmetrics_with_options(nan_counter, options).is_ok());
+
+    let interleaved = "# TYPE foo counter
+foo_total{a=\"1\"} 1
+foo_total{a=\"2\"} 2
+foo_created{a=\"1\"} 3
+# EOF
+";
+    assert!(crate::openmetrics::parse_openmetrics(interleaved).is_err());
+    let options = ParserOptions::new().with_allow_interleaved_labelsets(true);
+    let parsed = parse_openmetrics_with_options(interleaved, options).unwrap();
+    match &parsed.families["foo"].iter_samples().next().unwrap().value {
+        OpenMetricsValue::Counter(c) => assert_eq!(c.created, Some(3.)),
+        v => panic!("expected a counter, got {:?}", v),
+    }
+
+    let late_metadata = "foo_total 1
+# TYPE foo counter
+# HELP foo A counter
+# TYPE bar gauge
+bar 2
+# EOF
+";
+    assert!(crate::openmetrics::parse_openmetrics(late_metadata).is_err());
+    let options = ParserOptions::new().with_allow_late_metadata(true);
+    let parsed = parse_openmetrics_with_options(late_metadata, options).unwrap();
+    assert_eq!(parsed.families.len(), 2);
+    assert_eq!(parsed.families["foo"].family_type, OpenMetricsType::Counter);
+    assert_eq!(parsed.families["foo"].help, "A counter");
+
+    let everything = "foo_total NaN\r\n# TYPE foo counter\r\n";
+    assert!(parse_openmetrics_with_options(everything, ParserOptions::lenient()).is_ok());
+}
+
+#[test]
+fn test_labelsets_grouped_by_suffix() {
+    use crate::openmetrics::{parse_openmetrics, parse_openmetrics_with_options};
+    use crate::ParserOptions;
+
+    // As some Java exporters write them
+    let exposition = "# TYPE foo histogram
+foo_bucket{a=\"1\",le=\"1\"} 1
+foo_bucket{a=\"2\",le=\"1\"} 2
+foo_bucket{a=\"1\",le=\"+Inf\"} 3
+foo_bucket{a=\"2\",le=\"+Inf\"} 4
+foo_count{a=\"1\"} 3
+foo_count{a=\"2\"} 4
+foo_sum{a=\"1\"} 1
+foo_sum{a=\"2\"} 2
+# EOF
+";
+    assert!(parse_openmetrics(exposition).is_err());
+
+    let options = ParserOptions::new().with_allow_interleaved_labelsets(true);
+    let parsed = parse_openmetrics_with_options(exposition, options).unwrap();
+    assert_eq!(
+        parsed.to_openmetrics_string(),
+        "# TYPE foo histogram
+foo_bucket{a=\"1\",le=\"1\"} 1
+foo_bucket{a=\"1\",le=\"+Inf\"} 3
+foo_sum{a=\"1\"} 1
+foo_count{a=\"1\"} 3
+foo_bucket{a=\"2\",le=\"1\"} 2
+foo_bucket{a=\"2\",le=\"+Inf\"} 4
+foo_sum{a=\"2\"} 2
+foo_count{a=\"2\"} 4
+# EOF
+"
+    );
+}
+
+#[test]
+fn test_duplicate_metadata() {
+    use crate::openmetrics::{parse_openmetrics, parse_openmetrics_with_report};
+    use crate::{ParseError, ParserOptions};
+
+    let exposition = "# HELP foo A counter
+# TYPE foo counter
+# UNIT foo seconds
+foo_total 1
+# HELP foo A counter
+# TYPE foo counter
+# UNIT foo seconds
+# EOF
+";
+    assert!(parse_openmetrics(exposition).is_err());
+
+    let options = ParserOptions::new()
+        .with_allow_late_metadata(true)
+        .with_allow_duplicate_metadata(true);
+    let (parsed, report) = parse_openmetrics_with_report(exposition, options.clone()).unwrap();
+    assert_eq!(parsed.families["foo"].unit, "seconds");
+    assert_eq!(report.warnings.len(), 3);
+    assert!(matches!(
+        report.warnings[1].kind(),
+        ParseError::DuplicateMetadata { .. }
+    ));
+
+    // Only identical repeats are accepted
+    let exposition = "# TYPE foo counter\n# TYPE foo gauge\nfoo 1\n# EOF\n";
+    assert!(parse_openmetrics_with_report(exposition, options.clone()).is_err());
+    let exposition = "# HELP foo A\n# HELP foo B\nfoo 1\n# EOF\n";
+    assert!(parse_openmetrics_with_report(exposition, options).is_err());
+}
+
+#[test]
+fn test_exemplar_label_length() {
+    use crate::openmetrics::{parse_openmetrics, parse_openmetrics_with_report};
+    use crate::{ParserOptions, Strictness};
+
+    let exposition = |trace_id: &str| {
+        format!(
+            "# TYPE foo histogram\nfoo_bucket{{le=\"+Inf\"}} 1 # {{trace_id=\"{}\"}} 1\n# EOF\n",
+            trace_id
+        )
+    };
+
+    // "trace_id" and its value add up to exactly 128 characters
+    assert!(parse_openmetrics(&exposition(&"a".repeat(120))).is_ok());
+    assert!(parse_openmetrics(&exposition(&"\u{e9}".repeat(120))).is_ok());
+    assert!(parse_openmetrics(&exposition(&"a".repeat(121))).is_err());
+
+    let options = ParserOptions::new().with_exemplar_label_length(Strictness::Warn);
+    let (parsed, report) =
+        parse_openmetrics_with_report(&exposition(&"a".repeat(121)), options).unwrap();
+    assert_eq!(report.warnings.len(), 1);
+    assert!(parsed.to_openmetrics_string().contains(&"a".repeat(121)));
+}
+
+#[test]
+fn test_strictness() {
+    use crate::openmetrics::parse_openmetrics_with_report;
+    use crate::{ParserOptions, Strictness};
+
+    let exposition = "# TYPE foo histogram
+foo_bucket{le=\"1\"} 2
+foo_bucket{le=\"+Inf\"} 1
+foo_sum 3
+# TYPE bar counter
+bar_total -1
+# TYPE baz stateset
+baz{baz=\"a\"} 2
+# EOF
+";
+
+    assert!(parse_openmetrics_with_report(exposition, ParserOptions::new()).is_err());
+
+    let options = ParserOptions::new()
+        .with_histogram_cumulativity(Strictness::Warn)
+        .with_sum_count_pairing(Strictness::Ignore)
+        .with_counter_negativity(Strictness::Warn)
+        .with_stateset_values(Strictness::Warn);
+    let (parsed, report) = parse_openmetrics_with_report(exposition, options.clone()).unwrap();
+    assert_eq!(parsed.families.len(), 3);
+    let warnings: Vec<_> = report.warnings.iter().map(|w| w.to_string()).collect();
+    assert_eq!(warnings.len(), 3, "{:?}", warnings);
+    assert!(warnings[0].contains("cumulative"));
+    assert!(warnings[1].contains("non negative"));
+    assert!(warnings[2].contains("0 or 1"));
+
+    let options = options.with_sum_count_pairing(Strictness::Error);
+    assert!(parse_openmetrics_with_report(exposition, options).is_err());
+}
+
+#[test]
+fn test_missing_eof() {
+    use crate::openmetrics::parse_openmetrics_with_report;
+    use crate::ParserOptions;
+
+    let options = ParserOptions::new().with_allow_missing_eof(true);
+    let (parsed, report) =
+        parse_openmetrics_with_report("# TYPE foo gauge\nfoo 1\n", options.clone()).unwrap();
+    assert_eq!(parsed.families.len(), 1);
+    assert_eq!(report.warnings.len(), 1);
+    assert!(report.warnings[0].to_string().contains("EOF"));
+
+    let (_, report) =
+        parse_openmetrics_with_report("# TYPE foo gauge\nfoo 1\n# EOF\n", options.clone()).unwrap();
+    assert!(report.is_clean());
+
+    // Cut off mid-line, whether or not the line would parse on its own
+    for truncated in [
+        "# TYPE foo gauge\nfoo 1",
+        "# TYPE foo gauge\nfoo{a=\"b",
+        "# TYPE foo gauge\nfoo 1\n# TYPE bar",
+    ] {
+        assert!(parse_openmetrics_with_report(truncated, options.clone()).is_err());
+        assert!(parse_openmetrics_with_report(truncated, ParserOptions::lenient()).is_err());
+    }
+}
+
+#[test]
+fn test_text_after_eof() {
+    use crate::openmetrics::parse_openmetrics_with_report;
+    use crate::{ParserOptions, Strictness};
+
+    let blank = "# TYPE foo gauge\nfoo 1\n# EOF\n\n \t\n";
+    let garbage = "# TYPE foo gauge\nfoo 1\n# EOF\n\0\0\0\0";
+    for fast_lexer in [false, true] {
+        let options = ParserOptions::new().with_fast_lexer(fast_lexer);
+        assert!(parse_openmetrics_with_report(blank, options.clone()).is_err());
+
+        let options = options.with_allow_blank_after_eof(true);
+        let (_, report) = parse_openmetrics_with_report(blank, options.clone()).unwrap();
+        assert!(report.is_clean());
+        assert!(parse_openmetrics_with_report(garbage, options.clone()).is_err());
+
+        let options = options.with_text_after_eof(Strictness::Warn);
+        let (parsed, report) = parse_openmetrics_with_report(garbage, options.clone()).unwrap();
+        assert_eq!(parsed.families.len(), 1);
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].to_string().contains("after the EOF"));
+
+        let on_the_line = "# TYPE foo gauge\nfoo 1\n# EOF padding\n";
+        let (_, report) = parse_openmetrics_with_report(on_the_line, options).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+    }
+}
+
+#[test]
+fn test_partial_parse() {
+    use crate::openmetrics::parse_openmetrics_partial;
+    use crate::ParserOptions;
+
+    let exposition = "# TYPE foo histogram
+foo_bucket{le=\"1\"} 2
+foo_bucket{le=\"+Inf\"} 1
+# TYPE bar gauge
+bar 1
+# TYPE bar gauge
+bar 2
+# TYPE baz counter
+baz_total 3
+";
+
+    let (parsed, errors) = parse_openmetrics_partial(exposition, ParserOptions::new());
+    assert_eq!(parsed.families.keys().collect::<Vec<_>>(), ["bar", "baz"]);
+    let errors: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert!(errors[0].contains("cumulative"));
+    assert!(errors[1].contains("finalised"));
+    assert!(errors[2].contains("EOF"));
+
+    let (parsed, errors) = parse_openmetrics_partial("foo{ 1\n# EOF\n", ParserOptions::new());
+    assert!(parsed.families.is_empty());
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn test_parse_report() {
+    use crate::openmetrics::parse_openmetrics_with_report;
+    use crate::ParserOptions;
+
+    let exposition = "# generated by an exporter
+# HELP foo A counter
+# HELP foo The same counter
+# TYPE foo counter
+foo_total 1
+foo_created 2 # {trace_id=\"abc\"} 1
+# end of foo
+# EOF
+";
+
+    assert!(crate::openmetrics::parse_openmetrics(exposition).is_err());
+
+    let options = ParserOptions::new()
+        .with_allow_duplicate_help(true)
+        .with_drop_invalid_exemplars(true)
+        .with_ignore_unknown_metadata(true);
+    let (parsed, report) = parse_openmetrics_with_report(exposition, options).unwrap();
+    let family = &parsed.families["foo"];
+    assert_eq!(family.help, "A counter");
+    assert!(family.to_string().find("trace_id").is_none());
+
+    let warnings: Vec<_> = report.warnings.iter().map(|w| w.to_string()).collect();
+    assert_eq!(warnings.len(), 4, "{:?}", warnings);
+    assert!(warnings.iter().any(|w| w.contains("two help lines")));
+    assert!(warnings.iter().any(|w| w.contains("exemplars")));
+    assert!(warnings
+        .iter()
+        .any(|w| w.contains("# generated by an exporter")));
+    assert!(warnings.iter().any(|w| w.contains("# end of foo")));
+
+    let (_, report) =
+        parse_openmetrics_with_report("# TYPE foo gauge\nfoo 1\n# EOF\n", ParserOptions::lenient())
+            .unwrap();
+    assert!(report.is_clean());
+}
+
+#[test]
+fn test_error_lines() {
+    use crate::openmetrics::{parse_openmetrics_partial, parse_openmetrics_with_report};
+    use crate::ParserOptions;
+
+    let exposition = "# TYPE foo histogram
+foo_bucket{le=\"1\"} 2
+foo_bucket{le=\"+Inf\"} 1
+# TYPE bar gauge
+bar 1
+bar 2
+# EOF
+";
+
+    let error = crate::openmetrics::parse_openmetrics(exposition).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "line 2: Histograms must be cumulative in foo"
+    );
+
+    let (_, errors) = parse_openmetrics_partial(exposition, ParserOptions::new());
+    assert_eq!(errors.len(), 2);
+    assert_eq!((errors[1].line(), errors[1].column()), (Some(6), Some(1)));
+
+    // Blank lines dropped by trimming still count
+    let exposition = "# TYPE foo gauge\n\n\nfoo 1 # {} 1\n# EOF\n";
+    let options = ParserOptions::new()
+        .with_trim_whitespace(true)
+        .with_drop_invalid_exemplars(true);
+    let (_, report) = parse_openmetrics_with_report(exposition, options).unwrap();
+    assert_eq!(report.warnings[0].line(), Some(4));
+}
+
+#[test]
+fn test_typed_errors() {
+    use crate::{MetadataKind, ParseError};
+
+    let error = crate::openmetrics::parse_openmetrics(
+        "# HELP foo a\n# HELP foo b\n# TYPE foo gauge\nfoo 1\n# EOF\n",
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error.kind(),
+        ParseError::DuplicateMetadata { family, kind: MetadataKind::Help } if family == "foo"
+    ));
+
+    let error = crate::openmetrics::parse_openmetrics(
+        "# TYPE foo histogram\nfoo_bucket{a=\"x\",le=\"1\"} 2\nfoo_bucket{a=\"x\",le=\"+Inf\"} 1\n# EOF\n",
+    )
+    .unwrap_err();
+    match error.kind() {
+        ParseError::InvalidHistogram { family, labels, .. } => {
+            assert_eq!(family, "foo");
+            assert_eq!(labels, &[("a".to_owned(), "x".to_owned())]);
+        }
+        other => panic!("Unexpected error: {:?}", other),
+    }
+
+    let error = crate::openmetrics::parse_openmetrics(
+        "# TYPE foo gauge\nfoo{a=\"1\"} 1\nfoo{a=\"2\"} 1\nfoo{a=\"1\"} 2 1\n# EOF\n",
+    )
+    .unwrap_err();
+    match error.kind() {
+        ParseError::InterwovenLabelSets {
+            family,
+            labels,
+            after,
+        } => {
+            assert_eq!(family, "foo");
+            assert_eq!(labels, &["1"]);
+            assert_eq!(after, &["2"]);
+        }
+        other => panic!("Unexpected error: {:?}", other),
+    }
+
+    let error = crate::openmetrics::parse_openmetrics(
+        "# TYPE foo counter\nfoo_total{a=\"x\"} 1\nfoo_total{a=\"y\"} -1\n# EOF\n",
+    )
+    .unwrap_err();
+    match error.kind() {
+        ParseError::InvalidCounter { family, labels, .. } => {
+            assert_eq!(family, "foo");
+            assert_eq!(labels, &[("a".to_owned(), "y".to_owned())]);
+        }
+        other => panic!("Unexpected error: {:?}", other),
+    }
+
+    let error = crate::openmetrics::parse_openmetrics(
+        "# TYPE foo summary\nfoo{a=\"x\",quantile=\"2\"} 1\n# EOF\n",
+    )
+    .unwrap_err();
+    match error.kind() {
+        ParseError::InvalidSummary { family, labels, .. } => {
+            assert_eq!(family, "foo");
+            assert_eq!(labels, &[("a".to_owned(), "x".to_owned())]);
+        }
+        other => panic!("Unexpected error: {:?}", other),
+    }
+
+    let error = crate::openmetrics::parse_openmetrics(
+        "# TYPE foo gauge\nfoo{a=\"1\"} 1\nfoo{a=\"2\",b=\"3\"} 1\n# EOF\n",
+    )
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "line 3: Metrics in foo have different label sets: expected values for [\"a\"], got [\"2\", \"3\"]"
+    );
+    match error.kind() {
+        ParseError::LabelSetMismatch {
+            family,
+            label_names,
+            label_values,
+        } => {
+            assert_eq!(family, "foo");
+            assert_eq!(label_names, &["a"]);
+            assert_eq!(label_values, &["2", "3"]);
+        }
+        other => panic!("Unexpected error: {:?}", other),
+    }
+
+    let error = crate::openmetrics::parse_openmetrics("# TYPE foo gauge\nfoo 1\n").unwrap_err();
+    assert!(matches!(error.kind(), ParseError::MissingEof));
+}
+
+#[test]
+fn test_error_source() {
+    use std::error::Error;
+
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+    assert_send_sync::<crate::ParseError>();
+
+    let error =
+        crate::openmetrics::parse_openmetrics("# TYPE foo gauge\nfoo{ 1\n# EOF\n").unwrap_err();
+    let source = error.source().unwrap();
+    let pest_error = source
+        .downcast_ref::<pest::error::Error<super::parsers::Rule>>()
+        .unwrap();
+    assert!(matches!(
+        pest_error.line_col,
+        pest::error::LineColLocation::Pos((2, _))
+    ));
+
+    let error = crate::openmetrics::parse_openmetrics("# TYPE foo gauge\nfoo 1\nfoo 2\n# EOF\n")
+        .unwrap_err();
+    assert!(error.source().is_none());
+}
+
+#[test]
+fn test_parse_from_reader() {
+    use crate::openmetrics::{
+        parse_openmetrics_from_reader_with_options, parse_openmetrics_with_options,
+    };
+    use crate::ParserOptions;
+
+    let expositions = [
+        "# TYPE foo counter\nfoo_total 1\n# comment\n# TYPE bar gauge\nbar 1\nbar{a=\"b\"} 2\n# EOF\n",
+        "# TYPE foo gauge\nfoo 1\n# HELP foo late\n# TYPE bar gauge\nbar 1\n# EOF\n",
+        "# TYPE foo gauge\r\nfoo 1  \n\n# TYPE bar gauge\nbar 1\nbar 2\n# EOF\n",
+        "# TYPE foo gauge\nfoo 1\n# TYPE foo gauge\nfoo 2\n# EOF\n",
+        "# TYPE foo gauge\nfoo 1\n# EOF\n# TYPE bar gauge\nbar 1\n",
+        "# TYPE foo gauge\nfoo 1\n# TYPE bar gauge\nbar 1\n",
+        "# TYPE foo gauge\nfoo 1\n# TYPE bar gauge\nbar{ 1\n# EOF\n",
+    ];
+
+    for options in [ParserOptions::new(), ParserOptions::lenient()] {
+        for exposition in expositions {
+            let whole = parse_openmetrics_with_options(exposition, options.clone());
+            let read =
+                parse_openmetrics_from_reader_with_options(exposition.as_bytes(), options.clone());
+            match (whole, read) {
+                (Ok(whole), Ok(read)) => {
+                    assert_eq!(whole.to_string(), read.to_string(), "{}", exposition)
+                }
+                (Err(whole), Err(read)) => assert_eq!(
+                    (whole.to_string(), whole.line()),
+                    (read.to_string(), read.line()),
+                    "{}",
+                    exposition
+                ),
+                (whole, read) => panic!("{}: {:?} != {:?}", exposition, whole, read),
+            }
+        }
+    }
+
+    let error = crate::openmetrics::parse_openmetrics_from_reader(&[0xff, b'\n'][..]).unwrap_err();
+    assert!(matches!(error, crate::ParseError::Io(_)));
+}
+
+#[test]
+fn test_streaming_parser() {
+    use crate::openmetrics::StreamingParser;
+
+    let exposition = "# TYPE foo histogram\nfoo_bucket{le=\"+Inf\"} 1\nfoo_count 1\nfoo_sum 1\n# TYPE bar gauge\nbar{a=\"ü\"} 1\n# TYPE baz gauge\nbaz 1\n# EOF\n";
+    let expected = crate::openmetrics::parse_openmetrics(exposition).unwrap();
+
+    let mut parser = StreamingParser::new();
+    let mut families = Vec::new();
+    for byte in exposition.as_bytes().chunks(1) {
+        families.extend(parser.feed(byte).unwrap());
+    }
+
+    assert_eq!(families.len(), 2);
+    families.extend(parser.finish().unwrap());
+
+    let rendered: Vec<_> = families.iter().map(|f| f.to_string()).collect();
+    let expected: Vec<_> = expected.families.values().map(|f| f.to_string()).collect();
+    assert_eq!(rendered, expected);
+
+    // Families are still checked against the ones that have been handed back
+    let mut parser = StreamingParser::new();
+    assert_eq!(
+        parser
+            .feed(b"# TYPE foo gauge\nfoo 1\n# TYPE bar gauge\nbar 1\n")
+            .unwrap()
+            .len(),
+        1
+    );
+    let families = parser.feed(b"# TYPE foo gauge\nfoo 2\n# EOF\n").unwrap();
+    assert_eq!(families[0].family_name, "bar");
+    let error = parser.finish().unwrap_err();
+    assert_eq!(error.line(), Some(5));
+    assert!(error.to_string().contains("finalised"));
+}
+
+#[test]
+fn test_visitor() {
+    use crate::openmetrics::{
+        visit_openmetrics, OpenMetricsVisitor, RawExemplar, RawFamily, RawSample,
+    };
+    use std::borrow::Cow;
+
+    #[derive(Default)]
+    struct Events(Vec<String>);
+
+    impl OpenMetricsVisitor<'_> for Events {
+        fn on_family_start(&mut self, family: &RawFamily<'_>) {
+            self.0.push(format!(
+                "start {} {} {:?}",
+                family.name, family.family_type, family.help
+            ));
+        }
+
+        fn on_sample(&mut self, sample: &RawSample<'_>) {
+            // Label values are only copied when they have to be unescaped
+            let borrowed = sample
+                .labels
+                .iter()
+                .all(|(_, v)| matches!(v, Cow::Borrowed(_)));
+            self.0.push(format!(
+                "sample {} {:?} {}",
+                sample.name, sample.labels, borrowed
+            ));
+        }
+
+        fn on_exemplar(&mut self, exemplar: &RawExemplar<'_>) {
+            self.0.push(format!("exemplar {}", exemplar.value));
+        }
+
+        fn on_family_end(&mut self, family: &RawFamily<'_>) {
+            self.0.push(format!("end {}", family.name));
+        }
+    }
+
+    let exposition = "# TYPE empty gauge
+# HELP foo Some \\\\ help
+# TYPE foo counter
+foo_total{a=\"b\"} 1 # {id=\"x\"} 2.5
+foo_total{a=\"\\\"c\\\"\"} 2
+# comment
+# HELP bar Bar
+bar 1
+# EOF
+";
+
+    let mut events = Events::default();
+    visit_openmetrics(exposition, &mut events).unwrap();
+    assert_eq!(
+        events.0,
+        vec![
+            "start empty gauge \"\"",
+            "end empty",
+            "start foo counter \"Some \\\\ help\"",
+            "sample foo_total [(\"a\", \"b\")] true",
+            "exemplar 2.5",
+            "sample foo_total [(\"a\", \"\\\"c\\\"\")] false",
+            "end foo",
+            "start bar unknown \"Bar\"",
+            "sample bar [] true",
+            "end bar",
+        ]
+    );
+
+    let error = visit_openmetrics("foo 1\nfoo{ 1\n# EOF\n", &mut Events::default()).unwrap_err();
+    assert_eq!(error.line(), Some(2));
+    assert!(visit_openmetrics("foo 1\n", &mut Events::default()).is_err());
+}
+
+#[test]
+fn test_family_iter() {
+    use crate::openmetrics::OpenMetricsFamilyIter;
+
+    let exposition = "# TYPE foo gauge\nfoo 1\n# TYPE bar counter\nbar_total 1\n# TYPE baz gauge\nbaz 1\n# EOF\n";
+    let names: Vec<_> = OpenMetricsFamilyIter::new(exposition)
+        .map(|family| family.unwrap().family_name)
+        .collect();
+    assert_eq!(names, ["foo", "bar", "baz"]);
+
+    // Families before a broken one are still handed out
+    let exposition = "# TYPE foo gauge\nfoo 1\n# TYPE bar counter\nbar_total -1\n# EOF\n";
+    let mut families = OpenMetricsFamilyIter::new(exposition);
+    assert_eq!(families.next().unwrap().unwrap().family_name, "foo");
+    assert_eq!(families.next().unwrap().unwrap_err().line(), Some(4));
+    assert!(families.next().is_none());
+
+    let results: Vec<_> = OpenMetricsFamilyIter::new("# TYPE foo gauge\nfoo 1\n").collect();
+    assert_eq!(results.len(), 2);
+    assert!(matches!(results[1], Err(crate::ParseError::MissingEof)));
+}
+
+#[test]
+fn test_from_file() {
+    use crate::{
+        MetricsExposition, OpenMetricsType, OpenMetricsValue, PrometheusType, PrometheusValue,
+    };
+
+    let dir = std::env::temp_dir().join(format!("openmetrics-parser-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join("bom.txt");
+    fs::write(&path, "\u{feff}# TYPE foo gauge\nfoo 1\n# EOF\n").unwrap();
+    let parsed =
+        MetricsExposition::<OpenMetricsType, OpenMetricsValue>::from_openmetrics_file(&path)
+            .unwrap();
+    assert!(parsed.families.contains_key("foo"));
+
+    let path = dir.join("utf16.txt");
+    let utf16: Vec<u8> = "\u{feff}# TYPE foo gauge\nfoo 1\n"
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    fs::write(&path, utf16).unwrap();
+    let parsed =
+        MetricsExposition::<PrometheusType, PrometheusValue>::from_prometheus_file(&path).unwrap();
+    assert!(parsed.families.contains_key("foo"));
+
+    let path = dir.join("broken.txt");
+    fs::write(&path, "# TYPE foo gauge\nfoo 1\nfoo 1\n# EOF\n").unwrap();
+    let error =
+        MetricsExposition::<OpenMetricsType, OpenMetricsValue>::from_openmetrics_file(&path)
+            .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "{}: line 3: Found two metrics with the same labelset",
+            path.display()
+        )
+    );
+    assert_eq!(error.line(), Some(3));
+
+    let error = MetricsExposition::<OpenMetricsType, OpenMetricsValue>::from_openmetrics_file(
+        dir.join("missing.txt"),
+    )
+    .unwrap_err();
+    assert!(
+        matches!(&error, crate::ParseError::File { error, .. } if matches!(**error, crate::ParseError::Io(_)))
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_borrowed_parse() {
+    use crate::{openmetrics::parse_openmetrics_borrowed, MetricNumber};
+    use std::borrow::Cow;
+
+    let exposition = "# TYPE foo counter\n# HELP foo Foo\nfoo_total{a=\"b\",c=\"\\\"d\\\"\"} 1 # {id=\"x\"} 1\nfoo_total 2\n# TYPE bar gauge\nbar 1.5 100\n# EOF\n";
+    let families = parse_openmetrics_borrowed(exposition).unwrap();
+    assert_eq!(families.len(), 2);
+
+    let foo = &families[0];
+    assert_eq!(
+        (foo.name, foo.family_type, foo.help.as_ref()),
+        ("foo", "counter", "Foo")
+    );
+    assert_eq!(foo.samples.len(), 2);
+    assert!(matches!(foo.samples[0].labels[0].1, Cow::Borrowed("b")));
+    assert!(matches!(&foo.samples[0].labels[1].1, Cow::Owned(value) if value == "\"d\""));
+    assert_eq!(foo.samples[0].exemplar.as_ref().unwrap().labels[0].0, "id");
+    assert!(foo.samples[1].exemplar.is_none());
+
+    let bar = &families[1].samples[0];
+    assert_eq!(
+        (bar.value, bar.timestamp),
+        (MetricNumber::Float(1.5), Some(100.))
+    );
+
+    assert!(parse_openmetrics_borrowed("foo{ 1\n# EOF\n").is_err());
+}
+
+#[test]
+fn test_fast_lexer() {
+    use crate::openmetrics::parse_openmetrics_with_options;
+    use crate::{ParseError, ParserOptions};
+
+    let expositions = [
+        "# TYPE foo counter\n# HELP foo Some \\\\ \\n help\n# UNIT foo seconds\nfoo_total{a=\"b\",c=\"\\\"d\\\\\"} 1 123.5 # {id=\"x\"} 2.5 100\nfoo_created 1e3\n# EOF\n",
+        "# TYPE foo histogram\nfoo_bucket{le=\"1.0\"} 0\nfoo_bucket{le=\"+Inf\"} 1\nfoo_count 1\nfoo_sum -1.5e-3\n# EOF\n",
+        "# TYPE foo gauge\nfoo +Inf\nfoo{a=\"1\"} -inf\nfoo{a=\"2\"} NaN\nfoo{a=\"3\"} 1.\n# EOF\n",
+        "# TYPE foo gauge\nfoo{} 1\n# UNIT foo\n# EOF\n",
+        "# TYPE foo gauge\nfoo +Infinity\n# EOF\n",
+        "# TYPE foo gauge\nfoo 1E5\n# EOF\n",
+        "# TYPE foo gauge\nfoo  1\n# EOF\n",
+        "# TYPE foo gauge\nfoo 1 \n# EOF\n",
+        "# TYPE foo gauge\nfoo 1 abc\n# EOF\n",
+        "# TYPE foo gauge\nfoo{a=\"b\" } 1\n# EOF\n",
+        "# TYPE foo gauge\nfoo{a=\"b\",} 1\n# EOF\n",
+        "# TYPE foo gauge\nfoo{1a=\"b\"} 1\n# EOF\n",
+        "# TYPE foo gauge\nfoo{a=\"\\x\"} 1\n# EOF\n",
+        "# TYPE foo gauge\nfoo{a=\"é\\\\\"} 1\n# EOF\n",
+        "# TYPE foo gauge\nfoo 1 # {a=\"b\"} 1\n# EOF\n",
+        "# TYPE foo counter\nfoo_total 1 #{a=\"b\"} 1\n# EOF\n",
+        "# TYPE foo Gauge\nfoo 1\n# EOF\n",
+        "# TYPE foo\nfoo 1\n# EOF\n",
+        "# TYPEWRITER\n# TYPE foo gauge\nfoo 1\n# EOF\n",
+        "# HELP foo\nfoo 1\n# EOF\n",
+        "# UNIT foo a b\nfoo 1\n# EOF\n",
+        "# a comment\n# TYPE foo gauge\nfoo 1\n#\n# TYPE bar gauge\nbar 1\n# EOF\n",
+        "# TYPE foo gauge\nfoo 1\n\n# EOF\n",
+        " foo 1\n# EOF\n",
+        "# TYPE foo gauge\nfoo 1\r\n# HELP bar x\r\n# TYPE bar gauge\r\nbar 1\r\n# EOF\r\n",
+        "# EOF\n",
+        "# TYPE foo gauge\nfoo 1\n# EOF",
+        "# TYPE foo gauge\nfoo 1\n# EOF\n\n",
+        "# TYPE foo gauge\nfoo 1\n# EOF extra\n",
+        "# TYPE foo gauge\nfoo 1\n# EOFX\n# EOF\n",
+        "# TYPE foo gauge\nfoo 1\n# EOF\n# TYPE bar gauge\nbar 1\n",
+        "# TYPE foo gauge\nfoo 1\n",
+        "# TYPE foo gauge\nfoo 1",
+        "# TYPE foo gauge\nfoo 1\n# TYPE foo gauge\nfoo 2\n# EOF\n",
+        "foo_total 1\n# TYPE foo counter\n# TYPE bar gauge\nbar 1\n# EOF\n",
+        "# TYPE a gauge\n# TYPE b gauge\nb 1\n# EOF\n",
+    ];
+
+    let fast = |options: ParserOptions| options.with_fast_lexer(true);
+    for options in [ParserOptions::new(), ParserOptions::lenient()] {
+        for exposition in expositions {
+            let grammar = parse_openmetrics_with_options(exposition, options.clone());
+            let by_hand = parse_openmetrics_with_options(exposition, fast(options.clone()));
+            match (grammar, by_hand) {
+                (Ok(grammar), Ok(by_hand)) => {
+                    assert_eq!(grammar.to_string(), by_hand.to_string(), "{:?}", exposition)
+                }
+                (Err(grammar), Err(by_hand)) => {
+                    assert_eq!(grammar.line(), by_hand.line(), "{:?}", exposition);
+                    // Only syntax errors differ
+                    if !matches!(grammar.kind(), ParseError::Syntax { .. }) {
+                        assert_eq!(grammar.to_string(), by_hand.to_string());
+                    }
+                }
+                (grammar, by_hand) => {
+                    panic!("{:?}: {:?} != {:?}", exposition, grammar, by_hand)
+                }
+            }
+        }
+    }
+
+    let error = parse_openmetrics_with_options("foo{a=1} 1\n# EOF\n", fast(ParserOptions::new()))
+        .unwrap_err();
+    assert_eq!(error.to_string(), "line 1: Expected a quoted label value");
+    assert_eq!(error.column(), Some(7));
+}
+
+#[test]
+fn test_prefix_families() {
+    let exposition = "# TYPE foo counter\nfoo_total{a=\"1\"} 1\n# TYPE state stateset\nstate{b=\"x\",state=\"on\"} 1\nstate{b=\"x\",state=\"off\"} 0\n# EOF\n";
+
+    let mut parsed = crate::openmetrics::parse_openmetrics(exposition).unwrap();
+    parsed.prefix_families("myapp_").unwrap();
+
+    assert!(parsed.families.contains_key("myapp_foo"));
+    let stateset = &parsed.families["myapp_state"];
+    assert_eq!(stateset.get_label_names(), &["b", "myapp_state"]);
+
+    let rendered = parsed.to_string();
+    assert!(rendered.contains("myapp_state{b=\"x\",myapp_state=\"on\"} 1\n"));
+
+    // A clash part way through leaves every family as it was
+    let exposition =
+        "# TYPE foo counter\nfoo_total 1\n# TYPE s stateset\ns{s=\"a\",xs=\"b\"} 1\n# EOF\n";
+    let mut parsed = crate::openmetrics::parse_openmetrics(exposition).unwrap();
+    let before = parsed.to_string();
+    assert!(parsed.prefix_families("x").is_err());
+    assert_eq!(parsed.families.len(), 2);
+    assert_eq!(parsed.to_string(), before);
+}
+
+#[test]
+fn test_custom_dialect() {
+    use crate::extension::*;
+    use crate::{
+        Exemplar, LabelValues, MetricNumber, OpenMetricsValue, ParseError, ParserOptions, Timestamp,
+    };
+
+    /// OpenMetrics, with an extra `delta` type for counters that reset on every scrape
+    #[derive(Debug, Clone, Default, PartialEq)]
+    enum VendorType {
+        #[default]
+        Unknown,
+        Delta,
+    }
+
+    impl MetricsType for VendorType {
+        fn can_have_exemplar(&self, _: &str) -> bool {
+            false
+        }
+
+        fn can_have_units(&self) -> bool {
+            true
+        }
+
+        fn can_have_multiple_lines(&self) -> bool {
+            false
+        }
+
+        fn get_ignored_labels(&self, _: &str) -> &[&str] {
+            &[]
+        }
+
+        fn get_type_value(&self) -> MetricValueMarshal {
+            match self {
+                VendorType::Unknown => MetricValueMarshal::Unknown(None),
+                VendorType::Delta => MetricValueMarshal::Gauge(None),
+            }
+        }
+    }
+
+    impl MetricsDialect for VendorType {
+        type Value = OpenMetricsValue;
+
+        fn parse_type(type_name: &str) -> Result<Self, ParseError> {
+            match type_name {
+                "unknown" => Ok(VendorType::Unknown),
+                "delta" => Ok(VendorType::Delta),
+                _ => Err(ParseError::InvalidMetric(format!(
+                    "Invalid metric type: {}",
+                    type_name
+                ))),
+            }
+        }
+
+        fn process_new_metric(
+            family: &mut MetricFamilyMarshal<Self>,
+            metric_name: &str,
+            value: MetricNumber,
+            label_names: Vec<String>,
+            label_values: LabelValues,
+            timestamp: Option<Timestamp>,
+            exemplar: Option<Exemplar>,
+        ) -> Result<(), ParseError> {
+            let set_value = || {
+                MetricProcesser::new(|metric: &mut MetricMarshal, value, _, _, _, _| {
+                    metric.value = match metric.value {
+                        MetricValueMarshal::Gauge(_) => MetricValueMarshal::Gauge(Some(value)),
+                        _ => MetricValueMarshal::Unknown(Some(value)),
+                    };
+                    Ok(())
+                })
+            };
+
+            let handlers = vec![
+                (
+                    vec![VendorType::Delta],
+                    vec![("_delta", vec![], set_value())],
+                ),
+                (vec![VendorType::Unknown], vec![("", vec![], set_value())]),
+            ];
+
+            family.process_with_handlers(
+                handlers,
+                metric_name,
+                value,
+                label_names,
+                label_values,
+                timestamp,
+                exemplar,
+                true,
+            )
+        }
+
+        fn validate_family(_: &MetricFamilyMarshal<Self>) -> Result<(), ParseError> {
+            Ok(())
+        }
+
+        fn convert_value(value: MetricValueMarshal) -> Self::Value {
+            value.into()
+        }
+    }
+
+    let exposition = parse_openmetrics_dialect::<VendorType>(
+        "# TYPE requests delta\nrequests_delta{path=\"/\"} 3\n# EOF\n",
+        ParserOptions::default(),
+    )
+    .unwrap();
+
+    let family = &exposition.families["requests"];
+    assert_eq!(family.family_type, VendorType::Delta);
+    assert!(parse_openmetrics_dialect::<VendorType>(
+        "# TYPE requests counter\nrequests_total 3\n# EOF\n",
+        ParserOptions::default()
+    )
+    .is_err());
+}
+
+#[test]
+fn test_gauge_histogram_snapshot() {
+    use crate::{openmetrics::parse_openmetrics, OpenMetricsType, OpenMetricsValue};
+
+    let exposition = parse_openmetrics(
+        "# TYPE queue gaugehistogram\nqueue_bucket{le=\"1\"} 2\nqueue_bucket{le=\"5\"} 3\nqueue_bucket{le=\"+Inf\"} 7\nqueue_gcount 7\nqueue_gsum 20\n# EOF\n",
+    )
+    .unwrap();
+
+    let snapshot = exposition.families["queue"]
+        .gauge_histogram_snapshot()
+        .unwrap();
+    assert_eq!(snapshot.family_type, OpenMetricsType::Histogram);
+
+    let histogram = match &snapshot.iter_samples().next().unwrap().value {
+        OpenMetricsValue::Histogram(h) => h,
+        v => panic!("Expected a histogram, got {:?}", v),
+    };
+    assert_eq!(histogram.count, Some(7));
+    assert_eq!(
+        histogram.bucket_occupancy(),
+        vec![(1., 2.), (5., 1.), (f64::INFINITY, 4.)]
+    );
+
+    assert!(snapshot.gauge_histogram_snapshot().is_err());
+}
+
+#[test]
+fn test_render_round_trip() {
+    use crate::openmetrics::{parse_openmetrics, render_openmetrics};
+    use crate::{assert_semantic_eq, CompareOptions};
+
+    let exposition = "# HELP requests Requests served\n# TYPE requests counter\n# UNIT requests requests\nrequests_total{path=\"/\"} 5 10 # {trace=\"a\"} 1 9\nrequests_created{path=\"/\"} 1 10\n# TYPE queue gaugehistogram\nqueue_bucket{le=\"1\"} 2\nqueue_bucket{le=\"+Inf\"} 3\nqueue_gcount 3\nqueue_gsum 2\n# TYPE build info\nbuild_info{version=\"1.0\"} 1\n# TYPE state stateset\nstate{state=\"on\"} 1\nstate{state=\"off\"} 0\n# TYPE latency histogram\nlatency_bucket{le=\"0.5\"} 1 # {trace=\"b\"} 0.2\nlatency_bucket{le=\"+Inf\"} 2\nlatency_sum 1.5\nlatency_count 2\n# EOF\n";
+
+    let parsed = parse_openmetrics(exposition).unwrap();
+    let rendered = render_openmetrics(&parsed);
+    assert!(rendered.ends_with("# EOF\n"));
+    assert!(rendered.contains("queue_gsum 2\n"));
+    assert!(rendered.contains("build_info{version=\"1.0\"} 1\n"));
+
+    let reparsed = parse_openmetrics(&rendered).unwrap();
+    assert_semantic_eq(&parsed, &reparsed, &CompareOptions::new());
+}
+
+#[test]
+fn test_render_options() {
+    use crate::openmetrics::{parse_openmetrics, render_openmetrics_with_options};
+    use crate::RenderOptions;
+
+    let exposition = "# TYPE latency histogram\nlatency_bucket{le=\"1\"} 1 5 # {trace=\"a\"} 0.5\nlatency_bucket{le=\"+Inf\"} 2 5\nlatency_sum 1.5 5\nlatency_count 2 5\nlatency_created 1 5\n# EOF\n";
+    let parsed = parse_openmetrics(exposition).unwrap();
+
+    let stripped = render_openmetrics_with_options(
+        &parsed,
+        &RenderOptions::new()
+            .with_exemplars(false)
+            .with_timestamps(false)
+            .with_created(false),
+    );
+    assert_eq!(
+        stripped,
+        "# TYPE latency histogram\nlatency_bucket{le=\"1\"} 1\nlatency_bucket{le=\"+Inf\"} 2\nlatency_sum 1.5\nlatency_count 2\n# EOF\n"
+    );
+
+    // The model itself is left alone
+    assert_eq!(parsed.to_openmetrics_string(), exposition);
+}
+
+#[test]
+fn test_created_series() {
+    use crate::openmetrics::{parse_openmetrics, render_openmetrics_with_options};
+    use crate::{OpenMetricsValue, RenderOptions};
+
+    let exposition = "# TYPE jobs counter\njobs_total 3\njobs_created 1000.5\n# EOF\n";
+    let mut parsed = parse_openmetrics(exposition).unwrap();
+
+    // Dropped on the way out, without touching the model
+    assert_eq!(
+        render_openmetrics_with_options(&parsed, &RenderOptions::new().with_created(false)),
+        "# TYPE jobs counter\njobs_total 3\n# EOF\n"
+    );
+
+    // Or synthesized from the `created` field, however it was set
+    let family = parsed.families.get_mut("jobs").unwrap();
+    if let OpenMetricsValue::Counter(c) = &mut family.iter_samples_mut().next().unwrap().value {
+        c.created = Some(2000.);
+    }
+
+    assert_eq!(
+        parsed.to_openmetrics_string(),
+        "# TYPE jobs counter\njobs_total 3\njobs_created 2000\n# EOF\n"
+    );
+}
+
+#[test]
+fn test_summary_created() {
+    use crate::openmetrics::{parse_openmetrics, render_openmetrics_with_options};
+    use crate::{OpenMetricsValue, RenderOptions};
+
+    let exposition = "# TYPE rpc summary\nrpc{quantile=\"0.5\"} 0.2\nrpc_sum 3.5\nrpc_count 10\nrpc_created 1000.5\n# EOF\n";
+    let parsed = parse_openmetrics(exposition).unwrap();
+
+    let sample = parsed.families["rpc"].iter_samples().next().unwrap();
+    match &sample.value {
+        OpenMetricsValue::Summary(s) => assert_eq!(s.created, Some(1000.5)),
+        v => panic!("Expected a summary, got {:?}", v),
+    }
+
+    assert_eq!(parsed.to_openmetrics_string(), exposition);
+    assert_eq!(
+        render_openmetrics_with_options(&parsed, &RenderOptions::new().with_created(false)),
+        "# TYPE rpc summary\nrpc{quantile=\"0.5\"} 0.2\nrpc_sum 3.5\nrpc_count 10\n# EOF\n"
+    );
+
+    assert!(parse_openmetrics(
+        "# TYPE rpc summary\nrpc_count 10\nrpc_created 1\nrpc_created 2\n# EOF\n"
+    )
+    .is_err());
+
+    // Each label set carries its own _created
+    let exposition = "# TYPE rpc summary\nrpc_count{method=\"get\"} 1\nrpc_created{method=\"get\"} 10\nrpc_count{method=\"put\"} 2\nrpc_created{method=\"put\"} 20\n# EOF\n";
+    let parsed = parse_openmetrics(exposition).unwrap();
+    let created: Vec<_> = parsed.families["rpc"]
+        .iter_samples()
+        .map(|sample| match &sample.value {
+            OpenMetricsValue::Summary(s) => s.created,
+            v => panic!("Expected a summary, got {:?}", v),
+        })
+        .collect();
+    assert_eq!(created, vec![Some(10.), Some(20.)]);
+    assert_eq!(parsed.to_openmetrics_string(), exposition);
+}
+
+#[test]
+fn test_render_to_vec() {
+    use crate::openmetrics::parse_openmetrics;
+
+    let exposition = "# HELP requests Requests served\n# TYPE requests counter\nrequests_total{path=\"/\",method=\"GET\"} 5 10\nrequests_created{path=\"/\",method=\"GET\"} 1 10\n# TYPE latency histogram\nlatency_bucket{le=\"0.5\"} 1\nlatency_bucket{le=\"+Inf\"} 2\nlatency_sum 1.5\nlatency_count 2\n# TYPE up gauge\nup 1\n# EOF\n";
+    let parsed = parse_openmetrics(exposition).unwrap();
+
+    let rendered = parsed.render_to_vec();
+    assert_eq!(rendered, parsed.to_openmetrics_string().into_bytes());
+    assert!(parsed.rendered_size_hint() >= rendered.len());
+    assert!(parsed.rendered_size_hint() <= 4 * rendered.len());
+}
+
+#[test]
+fn test_splice_openmetrics() {
+    use crate::{
+        openmetrics::{parse_openmetrics, render_openmetrics_with_options, splice_openmetrics},
+        RenderOptions,
+    };
+
+    let exposition = parse_openmetrics("# TYPE up gauge\nup 1\n# EOF\n").unwrap();
+    let options = RenderOptions::new().with_eof(false);
+    assert_eq!(
+        render_openmetrics_with_options(&exposition, &options),
+        "# TYPE up gauge\nup 1\n"
+    );
+    assert_eq!(exposition.render_chunks(&options).count(), 1);
+
+    // Splicing always ends the result with a single `# EOF`, whether or not there was one already
+    for encoded in ["# TYPE a gauge\na 1\n", "# TYPE a gauge\na 1\n# EOF\n"] {
+        let mut encoded = encoded.to_owned();
+        splice_openmetrics(&mut encoded, &exposition, &options);
+        assert_eq!(
+            encoded,
+            "# TYPE a gauge\na 1\n# TYPE up gauge\nup 1\n# EOF\n"
+        );
+    }
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn test_parallel_parse() {
+    use crate::openmetrics::{parse_openmetrics, parse_openmetrics_with_options};
+    use crate::openmetrics::{parse_openmetrics_parallel, parse_openmetrics_parallel_with_options};
+    use crate::{ParseError, ParserOptions};
+
+    use crate::openmetrics::parallel::{parse_chunks, split_chunks};
+
+    // Big enough to be split between threads
+    let mut exposition = String::new();
+    for family in 0..500 {
+        exposition.push_str(&format!(
+            "# HELP family_{0} Family {0}\n# TYPE family_{0} counter\n",
+            family
+        ));
+        for sample in 0..10 {
+            exposition.push_str(&format!(
+                "family_{}_total{{sample=\"{}\"}} {}.5 # {{trace_id=\"abc\"}} 1\n",
+                family, sample, sample
+            ));
+        }
+    }
+
+    let eof = format!("{}# EOF\n", exposition);
+    assert_eq!(
+        parse_openmetrics_parallel(&eof).unwrap().to_string(),
+        parse_openmetrics(&eof).unwrap().to_string()
+    );
+
+    // Errors are the same as parsing on one thread would give
+    let duplicate = format!("{}# TYPE family_3 gauge\nfamily_3 1\n# EOF\n", exposition);
+    assert_eq!(
+        parse_openmetrics_parallel(&duplicate)
+            .unwrap_err()
+            .to_string(),
+        parse_openmetrics(&duplicate).unwrap_err().to_string()
+    );
+
+    assert!(matches!(
+        parse_openmetrics_parallel(&exposition).unwrap_err(),
+        ParseError::MissingEof
+    ));
+
+    let lenient = ParserOptions::lenient();
+    assert_eq!(
+        parse_openmetrics_parallel_with_options(&exposition, lenient.clone())
+            .unwrap()
+            .to_string(),
+        parse_openmetrics_with_options(&exposition, lenient)
+            .unwrap()
+            .to_string()
+    );
+
+    // However many threads there are to run them on
+    let options = ParserOptions::new();
+    let chunks = split_chunks(&eof, &options, 4, 1024);
+    assert_eq!(chunks.len(), 4);
+    assert_eq!(chunks.concat(), eof);
+    assert!(chunks.iter().all(|chunk| chunk.starts_with("# HELP")));
+    assert_eq!(
+        parse_chunks(&chunks, &options).unwrap().to_string(),
+        parse_openmetrics(&eof).unwrap().to_string()
+    );
+
+    let chunks = split_chunks(&duplicate, &options, 4, 1024);
+    assert!(parse_chunks(&chunks, &options).is_none());
+    assert_eq!(
+        split_chunks(&eof, &options, 4, eof.len()),
+        vec![eof.as_str()]
+    );
+}
+
+#[test]
+fn test_limits() {
+    use crate::openmetrics::{parse_openmetrics_partial, parse_openmetrics_with_options};
+    use crate::prometheus::parse_prometheus_with_options;
+    use crate::{Limit, ParseError, ParseLimits, ParserOptions};
+
+    let exposition = "# TYPE foo gauge\nfoo{a=\"1\"} 1\nfoo{a=\"22\"} 2\n# TYPE bar gauge\nbar{a=\"1\",b=\"2\"} 1\n# EOF\n";
+    let parse = |limits: ParseLimits| {
+        parse_openmetrics_with_options(exposition, ParserOptions::new().with_limits(limits))
+    };
+
+    assert!(parse(ParseLimits::new()).is_ok());
+    assert!(parse(
+        ParseLimits::new()
+            .with_max_families(2)
+            .with_max_samples_per_family(2)
+            .with_max_labels_per_sample(2)
+            .with_max_label_value_length(2)
+            .with_max_line_length(18)
+            .with_max_total_bytes(exposition.len())
+    )
+    .is_ok());
+
+    let cases = [
+        (ParseLimits::new().with_max_families(1), Limit::Families, 4),
+        (
+            ParseLimits::new().with_max_samples_per_family(1),
+            Limit::SamplesPerFamily,
+            3,
+        ),
+        (
+            ParseLimits::new().with_max_labels_per_sample(1),
+            Limit::LabelsPerSample,
+            5,
+        ),
+        (
+            ParseLimits::new().with_max_label_value_length(1),
+            Limit::LabelValueLength,
+            3,
+        ),
+        (
+            ParseLimits::new().with_max_line_length(17),
+            Limit::LineLength,
+            5,
+        ),
+    ];
+
+    for (limits, expected, line) in cases {
+        let error = parse(limits).unwrap_err();
+        assert!(
+            matches!(error.kind(), ParseError::LimitExceeded { limit, .. } if *limit == expected),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.line(), Some(line), "{:?}", error);
+    }
+
+    let error = parse(ParseLimits::new().with_max_total_bytes(10)).unwrap_err();
+    assert_eq!(error.to_string(), "Exceeded the limit of 10 bytes in total");
+
+    // Limits stop the parse even when it would otherwise carry on past errors
+    let options = ParserOptions::new().with_limits(ParseLimits::new().with_max_families(1));
+    let (parsed, errors) = parse_openmetrics_partial(exposition, options.clone());
+    assert!(parsed.families.is_empty());
+    assert_eq!(
+        errors[0].to_string(),
+        "line 4: Exceeded the limit of 1 families"
+    );
+
+    let prometheus = "# TYPE foo gauge\nfoo 1\n# TYPE bar gauge\nbar 1\n";
+    let error = parse_prometheus_with_options(prometheus, options).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "line 3: Exceeded the limit of 1 families"
+    );
+}
+
+#[test]
+fn test_family_filter() {
+    use crate::openmetrics::parse_openmetrics_with_options;
+    use crate::prometheus::parse_prometheus_with_options;
+    use crate::{FamilyFilter, ParserOptions};
+
+    let exposition = "# TYPE container_cpu_seconds counter\ncontainer_cpu_seconds_total 1\n# TYPE container_memory_bytes gauge\ncontainer_memory_bytes 2\n# TYPE kubelet_broken histogram\nkubelet_broken_bucket{le=\"1\"} 5\n# EOF\n";
+    let names = |filter: FamilyFilter| {
+        let options = ParserOptions::new().with_family_filter(filter);
+        parse_openmetrics_with_options(exposition, options)
+            .map(|parsed| parsed.families.keys().cloned().collect::<Vec<_>>())
+    };
+
+    // Filtered out families aren't checked, so the broken histogram doesn't matter
+    assert!(names(FamilyFilter::new()).is_err());
+    assert_eq!(
+        names(FamilyFilter::new().allow("container_*")).unwrap(),
+        vec!["container_cpu_seconds", "container_memory_bytes"]
+    );
+    assert_eq!(
+        names(FamilyFilter::new().allow("container_*").deny("*_b?tes")).unwrap(),
+        vec!["container_cpu_seconds"]
+    );
+    assert_eq!(
+        names(FamilyFilter::new().with_predicate(|name| name.contains("memory"))).unwrap(),
+        vec!["container_memory_bytes"]
+    );
+    assert_eq!(
+        names(FamilyFilter::new().allow("nothing")).unwrap(),
+        Vec::<String>::new()
+    );
+
+    let filter = FamilyFilter::new().allow("*_total").allow("up");
+    assert!(filter.matches("requests_total") && filter.matches("up"));
+    assert!(!filter.matches("upper") && !filter.matches("total"));
+
+    let prometheus = "# TYPE foo gauge\nfoo 1\n# HELP bar Bar\nbar 1\nbaz 1\n";
+    let options = ParserOptions::new().with_family_filter(FamilyFilter::new().deny("ba?"));
+    let parsed = parse_prometheus_with_options(prometheus, options).unwrap();
+    assert_eq!(parsed.families.keys().collect::<Vec<_>>(), vec!["foo"]);
+}
+
+#[test]
+fn test_sample_filter() {
+    use crate::openmetrics::parse_openmetrics_with_options;
+    use crate::prometheus::parse_prometheus_with_options;
+    use crate::{ParserOptions, SampleFilter};
+
+    let exposition = "# TYPE requests counter\nrequests_total{path=\"/\",pod=\"a\"} 1\nrequests_total{path=\"/\\\"q\\\"\",pod=\"b\"} 2\n# TYPE latency histogram\nlatency_bucket{pod=\"a\",le=\"+Inf\"} 1\nlatency_count{pod=\"a\"} 1\nlatency_sum{pod=\"a\"} 1\nlatency_bucket{pod=\"b\",le=\"+Inf\"} 1\nlatency_count{pod=\"b\"} 1\nlatency_sum{pod=\"b\"} 1\n# EOF\n";
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let filter = {
+        let seen = seen.clone();
+        SampleFilter::new(move |name, labels| {
+            seen.lock().unwrap().push(format!("{}{:?}", name, labels));
+            labels.contains(&("pod", "b"))
+        })
+    };
+
+    let options = ParserOptions::new().with_sample_filter(filter.clone());
+    let parsed = parse_openmetrics_with_options(exposition, options).unwrap();
+    let rendered = parsed.to_string();
+    assert!(rendered.contains("requests_total{path=\"/\\\"q\\\"\",pod=\"b\"} 2"));
+    assert!(!rendered.contains("pod=\"a\""));
+    assert_eq!(parsed.families["latency"].iter_samples().count(), 1);
+
+    // Labels are handed over unescaped
+    let seen = seen.lock().unwrap().split_off(0);
+    assert_eq!(seen.len(), 8);
+    assert_eq!(
+        seen[1],
+        "requests_total[(\"path\", \"/\\\"q\\\"\"), (\"pod\", \"b\")]"
+    );
+
+    let prometheus = "up{pod=\"a\"} 1\nup{pod=\"b\"} 0\n";
+    let options = ParserOptions::new().with_sample_filter(filter);
+    let parsed = parse_prometheus_with_options(prometheus, options).unwrap();
+    assert_eq!(parsed.to_string(), "up{pod=\"b\"} 0\n");
+}
+
+#[test]
+fn test_number_syntax() {
+    use crate::openmetrics::parse_openmetrics_with_options;
+    use crate::prometheus::parse_prometheus;
+    use crate::{MetricNumber, OpenMetricsValue, ParserOptions, PrometheusValue};
+
+    let numbers = [
+        ("1e3", 1000.),
+        ("1E3", 1000.),
+        ("+1.5E-2", 0.015),
+        (".5", 0.5),
+        ("-.5", -0.5),
+        ("5.", 5.),
+        ("0x1p-3", 0.125),
+        ("-0X1.8P1", -3.),
+        ("0x.8p0", 0.5),
+        ("+Inf", f64::INFINITY),
+        ("-inf", f64::NEG_INFINITY),
+        ("Inf", f64::INFINITY),
+        ("+Infinity", f64::INFINITY),
+        ("NaN", f64::NAN),
+    ];
+
+    let same = |a: f64, b: f64| a == b || (a.is_nan() && b.is_nan());
+    let as_f64 = MetricNumber::as_f64;
+
+    for (number, expected) in numbers {
+        // Infinite bounds, and timestamps, aren't allowed
+        let timestamp = if expected.is_finite() { number } else { "1" };
+        let exposition = format!(
+            "# TYPE foo gauge\nfoo {} {}\n# TYPE bar histogram\nbar_bucket{{le=\"{}\"}} 0\nbar_bucket{{le=\"+Inf\"}} 0\n# EOF\n",
+            number, timestamp, timestamp
+        );
+
+        for fast_lexer in [false, true] {
+            let options = ParserOptions::new().with_fast_lexer(fast_lexer);
+            let parsed = parse_openmetrics_with_options(&exposition, options)
+                .unwrap_or_else(|e| panic!("{}: {}", number, e));
+            let sample = parsed.families["foo"].iter_samples().next().unwrap();
+            match &sample.value {
+                OpenMetricsValue::Gauge(value) => {
+                    assert!(same(as_f64(value), expected), "{}", number)
+                }
+                _ => unreachable!(),
+            }
+
+            if expected.is_finite() {
+                assert_eq!(sample.timestamp, Some(expected), "{}", number);
+            }
+
+            if expected.is_finite() {
+                match &parsed.families["bar"].iter_samples().next().unwrap().value {
+                    OpenMetricsValue::Histogram(h) => {
+                        assert_eq!(h.buckets[0].upper_bound, expected)
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+
+        let parsed = parse_prometheus(&format!("foo {} {}\n", number, timestamp))
+            .unwrap_or_else(|e| panic!("{}: {}", number, e));
+        let sample = parsed.families["foo"].iter_samples().next().unwrap();
+        match &sample.value {
+            PrometheusValue::Untyped(value) | PrometheusValue::Unknown(value) => {
+                assert!(same(as_f64(value), expected), "{}", number)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    for invalid in [
+        "0x1", "0x1.8", "1e", ".", "0xp1", "+NaN", "1.2.3", "Infinit",
+    ] {
+        let exposition = format!("# TYPE foo gauge\nfoo {}\n# EOF\n", invalid);
+        for fast_lexer in [false, true] {
+            let options = ParserOptions::new().with_fast_lexer(fast_lexer);
+            assert!(
+                parse_openmetrics_with_options(&exposition, options).is_err(),
+                "{}",
+                invalid
+            );
+        }
+        assert!(
+            parse_prometheus(&format!("foo {}\n", invalid)).is_err(),
+            "{}",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn test_whitespace_between_tokens() {
+    use crate::openmetrics::parse_openmetrics_with_options;
+    use crate::prometheus::parse_prometheus;
+    use crate::ParserOptions;
+
+    let prometheus = "#\tHELP  foo  Help\twith  blanks\n# TYPE foo\tgauge \nfoo { a = \"x  y\" ,\tb=\"2\" , }\t1  123 \nfoo\t{a=\"z\",b=\"3\"}\t2\n";
+    let parsed = parse_prometheus(prometheus).unwrap();
+    let family = &parsed.families["foo"];
+    assert_eq!(family.help, "Help\twith  blanks");
+    assert_eq!(
+        family.to_string(),
+        parse_prometheus("# HELP foo Help\twith  blanks\n# TYPE foo gauge\nfoo{a=\"x  y\",b=\"2\"} 1 123\nfoo{a=\"z\",b=\"3\"} 2\n")
+            .unwrap()
+            .families["foo"]
+            .to_string()
+    );
+
+    let openmetrics = "#  TYPE\tfoo histogram\n# HELP foo  Help\twith  blanks\nfoo_bucket {a=\"x  y\" , le=\"+Inf\"}\t1  123 # {trace=\"a b\"}  1\n#  EOF\n";
+    let expected = "# TYPE foo histogram\n# HELP foo Help\twith  blanks\nfoo_bucket{a=\"x  y\",le=\"+Inf\"} 1 123 # {trace=\"a b\"} 1\n# EOF\n";
+    let expected = parse_openmetrics_with_options(expected, ParserOptions::new()).unwrap();
+    assert!(parse_openmetrics_with_options(openmetrics, ParserOptions::new()).is_err());
+    for options in [
+        ParserOptions::new().with_collapse_whitespace(true),
+        ParserOptions::lenient(),
+    ] {
+        let parsed = parse_openmetrics_with_options(openmetrics, options.clone()).unwrap();
+        assert_eq!(parsed.to_string(), expected.to_string());
+        let parsed = parse_openmetrics_with_options(openmetrics, options.with_fast_lexer(true));
+        assert_eq!(parsed.unwrap().to_string(), expected.to_string());
+    }
+}
+
+#[test]
+fn test_non_utf8_input() {
+    use crate::openmetrics::parse_openmetrics_bytes;
+    use crate::prometheus::parse_prometheus_bytes;
+    use crate::EncodingPolicy;
+
+    let exposition = b"# TYPE foo gauge\n# HELP foo \xff\nfoo{a=\"caf\xe9 \xc3\xa9\"} 1\n# EOF\n";
+    assert!(parse_openmetrics_bytes(exposition, EncodingPolicy::Strict).is_err());
+
+    for (encoding, help, value) in [
+        (EncodingPolicy::Lossy, "\u{fffd}", "caf\u{fffd} \u{e9}"),
+        (EncodingPolicy::Latin1, "\u{ff}", "caf\u{e9} \u{e9}"),
+    ] {
+        let parsed = parse_openmetrics_bytes(exposition, encoding).unwrap();
+        let family = &parsed.families["foo"];
+        assert_eq!(family.help, help);
+        let sample = family.iter_samples().next().unwrap();
+        assert_eq!(sample.get_label_values(), [value]);
+
+        let parsed = parse_prometheus_bytes(&exposition[..exposition.len() - 6], encoding);
+        assert_eq!(parsed.unwrap().families["foo"].help, help);
+    }
+
+    // Each invalid byte is decoded on its own, including a sequence cut off at the end
+    assert_eq!(
+        EncodingPolicy::Latin1
+            .decode(b"\xc3(\xc3\xa9\xe2\x82")
+            .unwrap(),
+        "\u{c3}(\u{e9}\u{e2}\u{82}"
+    );
+
+    // Valid UTF-8 is borrowed as it is
+    let valid = "foo{a=\"\u{e9}\"} 1\n".as_bytes();
+    for encoding in [
+        EncodingPolicy::Strict,
+        EncodingPolicy::Lossy,
+        EncodingPolicy::Latin1,
+    ] {
+        assert!(matches!(
+            encoding.decode(valid),
+            Ok(std::borrow::Cow::Borrowed(_))
+        ));
+    }
+}
+
+#[test]
+fn test_timestamp_precision() {
+    use crate::openmetrics::{parse_openmetrics, parse_openmetrics_with_options};
+    use crate::prometheus::parse_prometheus;
+    use crate::{ParserOptions, Sample, TimestampUnit};
+
+    let openmetrics = [
+        ("-1.5", -1_500_000_000),
+        ("1700000000.123456789", 1_700_000_000_123_456_789),
+        ("1.7e9", 1_700_000_000_000_000_000),
+        ("-0.0000000019", -1),
+        ("0x1p-1", 500_000_000),
+        ("1e30", i64::MAX),
+    ];
+    for (timestamp, nanos) in openmetrics {
+        let exposition = format!("# TYPE foo gauge\nfoo 1 {}\n# EOF\n", timestamp);
+        for options in [
+            ParserOptions::new(),
+            ParserOptions::new().with_fast_lexer(true),
+        ] {
+            let parsed = parse_openmetrics_with_options(&exposition, options).unwrap();
+            let family = &parsed.families["foo"];
+            let sample = family.iter_samples().next().unwrap();
+            let expected = (nanos != i64::MAX).then_some(nanos);
+            assert_eq!(
+                family.sample_timestamp_nanos(sample),
+                expected,
+                "{}",
+                timestamp
+            );
+        }
+    }
+
+    let prometheus = [
+        ("-1500", -1_500_000_000),
+        ("1700000000123.456789", 1_700_000_000_123_456_789),
+    ];
+    for (timestamp, nanos) in prometheus {
+        let parsed = parse_prometheus(&format!("foo 1 {}\n", timestamp)).unwrap();
+        let family = &parsed.families["foo"];
+        let sample = family.iter_samples().next().unwrap();
+        assert_eq!(family.sample_timestamp_nanos(sample), Some(nanos));
+    }
+
+    // Rendering writes the exact value back out, rather than the nearest float
+    for exposition in [
+        "# TYPE foo gauge\nfoo 1 1700000000.123456789\n# EOF\n",
+        "# TYPE foo gauge\nfoo 1 -1.000000001\n# EOF\n",
+        "# TYPE foo gauge\nfoo 1 1.5\n# EOF\n",
+    ] {
+        let parsed = parse_openmetrics(exposition).unwrap();
+        assert_eq!(parsed.to_openmetrics_string(), exposition);
+    }
+
+    let parsed = parse_prometheus("foo 1 1700000000123.456789\n").unwrap();
+    assert_eq!(parsed.to_string(), "foo 1 1700000000123.456789\n");
+
+    // The exact value is only kept as long as the timestamp is
+    let nanos = 1_700_000_000_123_456_789;
+    let sample = Sample::new(vec![], None, crate::OpenMetricsValue::Unknown(1.into()))
+        .with_timestamp_nanos(nanos, TimestampUnit::Seconds);
+    let family = |sample: &Sample<crate::OpenMetricsValue>| {
+        crate::MetricFamily::new(
+            String::from("foo"),
+            vec![],
+            crate::OpenMetricsType::Unknown,
+            String::new(),
+            String::new(),
+        )
+        .with_samples(vec![sample.clone()])
+        .unwrap()
+    };
+    let exact = family(&sample);
+    let sample_nanos = |family: &crate::MetricFamily<_, _>| {
+        family.sample_timestamp_nanos(family.iter_samples().next().unwrap())
+    };
+    assert_eq!(sample_nanos(&exact), Some(nanos));
+    assert_eq!(exact.to_string(), "foo 1 1700000000.123456789\n");
+
+    let mut sample = sample;
+    sample.timestamp = Some(2.);
+    let moved = family(&sample);
+    assert_eq!(sample_nanos(&moved), Some(2_000_000_000));
+    assert_eq!(moved.to_string(), "foo 1 2\n");
+}
+
+#[test]
+fn test_canonical_exemplar_labels() {
+    use crate::openmetrics::{parse_openmetrics, render_openmetrics_with_options};
+    use crate::RenderOptions;
+
+    // Each parse gets its own hash order, so this would render differently most times if the
+    // labels weren't sorted
+    let exposition =
+        "# TYPE c counter\nc_total 1 # {trace_id=\"a\",span_id=\"b\",z=\"c\",y=\"d\"} 1\n# EOF\n";
+    let expected =
+        "# TYPE c counter\nc_total 1 # {span_id=\"b\",trace_id=\"a\",y=\"d\",z=\"c\"} 1\n# EOF\n";
+    for _ in 0..20 {
+        let parsed = parse_openmetrics(exposition).unwrap();
+        assert_eq!(
+            render_openmetrics_with_options(&parsed, &RenderOptions::new().with_canonical(true)),
+            expected
+        );
+        assert_eq!(parsed.canonicalize().to_openmetrics_string(), expected);
+    }
+}
+
+#[test]
+fn test_validation_error_lines() {
+    use crate::openmetrics::parse_openmetrics;
+    use crate::prometheus::parse_prometheus;
+
+    // Errors found once the family is finished point at the metric they're in, not the family
+    let mut histogram = "# TYPE h histogram\n".to_owned();
+    for j in 0..50 {
+        let (first, inf) = if j == 40 { (5, 3) } else { (1, 3) };
+        histogram += &format!(
+            "h_bucket{{j=\"{j}\",le=\"1\"}} {first}\nh_bucket{{j=\"{j}\",le=\"+Inf\"}} {inf}\nh_count{{j=\"{j}\"}} {inf}\nh_sum{{j=\"{j}\"}} 1\n"
+        );
+    }
+
+    let error = parse_openmetrics(&(histogram.clone() + "# EOF\n")).unwrap_err();
+    assert_eq!(error.line(), Some(162), "{}", error);
+    let error = parse_prometheus(&histogram).unwrap_err();
+    assert_eq!(error.line(), Some(162), "{}", error);
+
+    let counter = "c_total{a=\"1\"} 1\nc_total{a=\"2\"} -1\n";
+    let error = parse_openmetrics(&format!("# TYPE c counter\n{counter}# EOF\n")).unwrap_err();
+    assert_eq!(error.line(), Some(3), "{}", error);
+    let error = parse_prometheus(&format!("# TYPE c_total counter\n{counter}")).unwrap_err();
+    assert_eq!(error.line(), Some(3), "{}", error);
+}
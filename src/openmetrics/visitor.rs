@@ -0,0 +1,233 @@
+use std::borrow::Cow;
+
+use pest::{iterators::Pair, Parser};
+
+use super::parsers::{OpenMetricsParser, Rule};
+use crate::internal::{parse_f64, parse_metric_number};
+use crate::{
+    unescape_help, unescape_label_value, ExpositionFormat, MetricNumber, ParseError, Timestamp,
+};
+
+/// The `# TYPE`, `# HELP` and `# UNIT` lines of a family, as they were written. The type is
+/// `unknown` for families without a `# TYPE` line
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawFamily<'a> {
+    pub name: &'a str,
+    pub family_type: &'a str,
+    pub help: Cow<'a, str>,
+    pub unit: &'a str,
+}
+
+impl<'a> RawFamily<'a> {
+    fn untyped(name: &'a str) -> Self {
+        RawFamily {
+            name,
+            family_type: "unknown",
+            help: Cow::Borrowed(""),
+            unit: "",
+        }
+    }
+}
+
+/// A sample line, with its labels in the order they were written
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawSample<'a> {
+    pub name: &'a str,
+    pub labels: Vec<(&'a str, Cow<'a, str>)>,
+    pub value: MetricNumber,
+    pub timestamp: Option<Timestamp>,
+}
+
+/// The exemplar at the end of a sample line
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawExemplar<'a> {
+    pub labels: Vec<(&'a str, Cow<'a, str>)>,
+    pub value: f64,
+    pub timestamp: Option<Timestamp>,
+}
+
+/// Receives the parts of an exposition as `visit_openmetrics` comes across them, borrowed from
+/// the exposition (`'a`). Each sample's exemplar comes straight after the sample. Every method
+/// does nothing by default
+pub trait OpenMetricsVisitor<'a> {
+    fn on_family_start(&mut self, _family: &RawFamily<'a>) {}
+    fn on_sample(&mut self, _sample: &RawSample<'a>) {}
+    fn on_exemplar(&mut self, _exemplar: &RawExemplar<'a>) {}
+    fn on_family_end(&mut self, _family: &RawFamily<'a>) {}
+}
+
+/// Walks through an exposition without building it, handing each part to the visitor, with
+/// names, label values and help text borrowed from the input wherever they don't need
+/// unescaping. Only the syntax is checked (along with the EOF): the rules on families and their
+/// samples, e.g. that histograms are cumulative, are left to the visitor
+/// ```
+/// use openmetrics_parser::openmetrics::{visit_openmetrics, OpenMetricsVisitor, RawSample};
+///
+/// struct Total(f64);
+///
+/// impl OpenMetricsVisitor<'_> for Total {
+///     fn on_sample(&mut self, sample: &RawSample<'_>) {
+///         self.0 += sample.value.as_f64();
+///     }
+/// }
+///
+/// let mut total = Total(0.);
+/// visit_openmetrics("# TYPE foo gauge\nfoo 1\nfoo{a=\"b\"} 2.5\n# EOF\n", &mut total).unwrap();
+/// assert_eq!(total.0, 3.5);
+/// ```
+pub fn visit_openmetrics<'a, V: OpenMetricsVisitor<'a>>(
+    exposition: &'a str,
+    visitor: &mut V,
+) -> Result<(), ParseError> {
+    let exposition_marshal = OpenMetricsParser::parse(Rule::exposition_without_eof, exposition)?
+        .next()
+        .unwrap();
+
+    let mut found_eof = false;
+    for span in exposition_marshal.into_inner() {
+        match span.as_rule() {
+            Rule::metricfamily => visit_family(span, visitor)?,
+            Rule::kw_eof => {
+                found_eof = true;
+
+                let rest = &exposition[span.as_span().end()..];
+                if !rest.is_empty() && rest != "\n" {
+                    let (line, column) = span.line_col();
+                    return Err(ParseError::InvalidMetric(
+                        "Found text after the EOF token".to_string(),
+                    )
+                    .at(line, column));
+                }
+            }
+            Rule::EOI => {}
+            _ => unreachable!(),
+        }
+    }
+
+    if !found_eof {
+        return Err(ParseError::MissingEof);
+    }
+
+    Ok(())
+}
+
+fn visit_family<'a, V: OpenMetricsVisitor<'a>>(
+    pair: Pair<'a, Rule>,
+    visitor: &mut V,
+) -> Result<(), ParseError> {
+    // The grammar puts the descriptors of families without samples in with the next family's
+    let mut family: Option<RawFamily> = None;
+    let mut started = false;
+
+    for child in pair.into_inner() {
+        let (line, column) = child.line_col();
+        match child.as_rule() {
+            Rule::metricdescriptor => {
+                let mut descriptor = child.into_inner();
+                let kind = descriptor.next().unwrap().as_rule();
+                let name = descriptor.next().unwrap().as_str();
+                let text = descriptor.next().map(|s| s.as_str()).unwrap_or_default();
+
+                if family.as_ref().is_some_and(|family| family.name != name) {
+                    let previous = family.take().unwrap();
+                    visitor.on_family_start(&previous);
+                    visitor.on_family_end(&previous);
+                }
+
+                let family = family.get_or_insert(RawFamily::untyped(name));
+                match kind {
+                    Rule::kw_type => family.family_type = text,
+                    Rule::kw_help => {
+                        family.help = unescape_help(text, ExpositionFormat::OpenMetrics)
+                    }
+                    Rule::kw_unit => family.unit = text,
+                    _ => unreachable!(),
+                }
+            }
+            Rule::sample => {
+                let family = family.get_or_insert_with(|| {
+                    RawFamily::untyped(child.clone().into_inner().next().unwrap().as_str())
+                });
+                if !started {
+                    visitor.on_family_start(family);
+                    started = true;
+                }
+
+                visit_sample(child, visitor).map_err(|error| error.at(line, column))?;
+            }
+            Rule::comment => {}
+            _ => unreachable!(),
+        }
+    }
+
+    if let Some(family) = family {
+        if !started {
+            visitor.on_family_start(&family);
+        }
+
+        visitor.on_family_end(&family);
+    }
+
+    Ok(())
+}
+
+fn visit_sample<'a, V: OpenMetricsVisitor<'a>>(
+    pair: Pair<'a, Rule>,
+    visitor: &mut V,
+) -> Result<(), ParseError> {
+    let mut sample = RawSample {
+        name: "",
+        labels: Vec::new(),
+        value: MetricNumber::Int(0),
+        timestamp: None,
+    };
+    let mut exemplar = None;
+
+    for part in pair.into_inner() {
+        match part.as_rule() {
+            Rule::metricname => sample.name = part.as_str(),
+            Rule::labels => sample.labels = labels(part),
+            Rule::number => {
+                let value = part.as_str();
+                sample.value = match parse_metric_number(value) {
+                    Some(value) => value,
+                    None => MetricNumber::Float(number(value)?),
+                }
+            }
+            Rule::timestamp => sample.timestamp = Some(number(part.as_str())?),
+            Rule::exemplar => {
+                let mut parts = part.into_inner();
+                let labels = labels(parts.next().unwrap());
+                let value = number(parts.next().unwrap().as_str())?;
+                let timestamp = parts.next().map(|t| number(t.as_str())).transpose()?;
+                exemplar = Some(RawExemplar {
+                    labels,
+                    value,
+                    timestamp,
+                });
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    visitor.on_sample(&sample);
+    if let Some(exemplar) = exemplar {
+        visitor.on_exemplar(&exemplar);
+    }
+
+    Ok(())
+}
+
+fn labels<'i>(pair: Pair<'i, Rule>) -> Vec<(&'i str, Cow<'i, str>)> {
+    pair.into_inner()
+        .map(|label| {
+            let mut label = label.into_inner();
+            let name = label.next().unwrap().as_str();
+            (name, unescape_label_value(label.next().unwrap().as_str()))
+        })
+        .collect()
+}
+
+fn number(text: &str) -> Result<f64, ParseError> {
+    parse_f64(text).ok_or_else(|| ParseError::InvalidMetric(format!("Invalid number: {}", text)))
+}
@@ -2,5 +2,14 @@
 mod tests;
 
 mod parsers;
+#[cfg(feature = "protobuf")]
+mod protobuf;
+mod render;
 
-pub use parsers::parse_prometheus;
+pub use parsers::{
+    parse_prometheus, parse_prometheus_bytes, parse_prometheus_bytes_with_options,
+    parse_prometheus_partial, parse_prometheus_with_options, parse_prometheus_with_report,
+};
+#[cfg(feature = "protobuf")]
+pub use protobuf::{parse_protobuf, render_protobuf};
+pub use render::*;
@@ -1,11 +1,15 @@
 use std::convert::TryFrom;
+use std::path::Path;
 
-use pest::Parser;
+use pest::{error::LineColLocation, Parser};
 
 use crate::{
     internal::{
-        CounterValueMarshal, LabelNames, MarshalledMetric, MarshalledMetricFamily,
-        MetricFamilyMarshal, MetricMarshal, MetricProcesser, MetricValueMarshal, MetricsType,
+        check_counter_nan, check_counter_negativity, invalid_summary, locate_warnings,
+        normalize_bound_labels, normalize_input, original_line, parse_f64, parse_metric_number,
+        parse_timestamp_nanos, push_bucket, read_exposition_file, CounterValueMarshal,
+        MarshalledMetric, MarshalledMetricFamily, MetricFamilyMarshal, MetricMarshal,
+        MetricProcesser, MetricValueMarshal, MetricsType,
     },
     public::*,
 };
@@ -16,7 +20,15 @@ struct PrometheusParser;
 
 impl From<pest::error::Error<Rule>> for ParseError {
     fn from(err: pest::error::Error<Rule>) -> Self {
-        ParseError::ParseError(err.to_string())
+        let (line, column) = match err.line_col {
+            LineColLocation::Pos(start) | LineColLocation::Span(start, _) => start,
+        };
+
+        ParseError::Syntax {
+            message: err.variant.message().into_owned(),
+            source: Box::new(err),
+        }
+        .at(line, column)
     }
 }
 
@@ -32,7 +44,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
         }
 
         for metric in self.metrics.iter() {
-            metric.validate(self)?;
+            self.validate_metric(metric)?;
         }
 
         Ok(())
@@ -43,10 +55,12 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
         metric_name: &str,
         metric_value: MetricNumber,
         label_names: Vec<String>,
-        label_values: Vec<String>,
+        label_values: LabelValues,
         timestamp: Option<Timestamp>,
         exemplar: Option<Exemplar>,
     ) -> Result<(), Self::Error> {
+        let merge_duplicate_buckets = self.options.merge_duplicate_buckets;
+        let allow_nan_counters = self.options.allow_nan_counters;
         let handlers = vec![
             (
                 vec![PrometheusType::Histogram],
@@ -55,20 +69,20 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                         "_bucket",
                         vec!["le"],
                         MetricProcesser::new(
-                            |existing_metric: &mut MetricMarshal,
-                             metric_value: MetricNumber,
-                             label_names: Vec<String>,
-                             label_values: Vec<String>,
-                             exemplar: Option<Exemplar>,
-                             _: bool| {
-                                let bucket_bound: f64 = {
+                            move |existing_metric: &mut MetricMarshal,
+                                  metric_value: MetricNumber,
+                                  label_names: Vec<String>,
+                                  label_values: LabelValues,
+                                  exemplar: Option<Exemplar>,
+                                  _: bool| {
+                                let (bucket_bound, raw_bound): (f64, String) = {
                                     let bound_index =
                                         label_names.iter().position(|s| s == "le").unwrap();
 
                                     let bound = &label_values[bound_index];
-                                    match bound.parse() {
-                                        Ok(f) => f,
-                                        Err(_) => {
+                                    match parse_f64(bound) {
+                                        Some(f) => (f, bound.clone()),
+                                        None => {
                                             return Err(ParseError::InvalidMetric(format!(
                                                 "Invalid histogram bound: {}",
                                                 bound
@@ -80,18 +94,17 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                                 let bucket = HistogramBucket {
                                     count: metric_value,
                                     upper_bound: bucket_bound,
+                                    raw_upper_bound: Some(raw_bound),
                                     exemplar,
                                 };
 
                                 if let MetricValueMarshal::Histogram(value) =
                                     &mut existing_metric.value
                                 {
-                                    value.buckets.push(bucket);
+                                    push_bucket(value, bucket, merge_duplicate_buckets)
                                 } else {
                                     unreachable!();
                                 }
-
-                                Ok(())
                             },
                         ),
                     ),
@@ -102,7 +115,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 if let MetricValueMarshal::Histogram(histogram_value) =
@@ -147,7 +160,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 if let MetricValueMarshal::Histogram(histogram_value) =
@@ -173,7 +186,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 if let MetricValueMarshal::Histogram(histogram_value) =
@@ -219,12 +232,12 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                     "",
                     vec![],
                     MetricProcesser::new(
-                        |existing_metric: &mut MetricMarshal,
-                         metric_value: MetricNumber,
-                         _: Vec<String>,
-                         _: Vec<String>,
-                         _: Option<Exemplar>,
-                         _: bool| {
+                        move |existing_metric: &mut MetricMarshal,
+                              metric_value: MetricNumber,
+                              _: Vec<String>,
+                              _: LabelValues,
+                              exemplar: Option<Exemplar>,
+                              _: bool| {
                             if let MetricValueMarshal::Counter(counter_value) =
                                 &mut existing_metric.value
                             {
@@ -232,15 +245,10 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                                     return Err(ParseError::DuplicateMetric);
                                 }
 
-                                let value = metric_value.as_f64();
-                                if value < 0. || value.is_nan() {
-                                    return Err(ParseError::InvalidMetric(format!(
-                                        "Counter totals must be non negative (got: {})",
-                                        metric_value.as_f64()
-                                    )));
-                                }
+                                check_counter_nan(metric_value.as_f64(), allow_nan_counters)?;
 
                                 counter_value.value = Some(metric_value);
+                                counter_value.exemplar = exemplar;
                             } else {
                                 unreachable!();
                             }
@@ -259,8 +267,8 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                         |existing_metric: &mut MetricMarshal,
                          metric_value: MetricNumber,
                          _: Vec<String>,
-                         _: Vec<String>,
-                         _: Option<Exemplar>,
+                         _: LabelValues,
+                         exemplar: Option<Exemplar>,
                          _: bool| {
                             if let MetricValueMarshal::Gauge(gauge_value) =
                                 &mut existing_metric.value
@@ -271,6 +279,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
 
                                 existing_metric.value =
                                     MetricValueMarshal::Gauge(Some(metric_value));
+                                existing_metric.exemplar = exemplar;
                             } else {
                                 unreachable!();
                             }
@@ -289,7 +298,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                         |existing_metric: &mut MetricMarshal,
                          metric_value: MetricNumber,
                          _: Vec<String>,
-                         _: Vec<String>,
+                         _: LabelValues,
                          _: Option<Exemplar>,
                          _: bool| {
                             if let MetricValueMarshal::Unknown(unknown_value) =
@@ -320,7 +329,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 if let MetricValueMarshal::Summary(summary_value) =
@@ -328,14 +337,14 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                                 {
                                     let metric_value = if let Some(value) = metric_value.as_i64() {
                                         if value < 0 {
-                                            return Err(ParseError::InvalidMetric(format!(
+                                            return Err(invalid_summary(format!(
                                                 "Summary counts must be positive (got: {})",
                                                 value
                                             )));
                                         }
                                         value as u64
                                     } else {
-                                        return Err(ParseError::InvalidMetric(format!(
+                                        return Err(invalid_summary(format!(
                                             "Summary counts must be integers (got: {})",
                                             metric_value.as_f64()
                                         )));
@@ -361,7 +370,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              _: Vec<String>,
-                             _: Vec<String>,
+                             _: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 let value = metric_value.as_f64();
@@ -394,25 +403,25 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                             |existing_metric: &mut MetricMarshal,
                              metric_value: MetricNumber,
                              label_names: Vec<String>,
-                             label_values: Vec<String>,
+                             label_values: LabelValues,
                              _: Option<Exemplar>,
                              _: bool| {
                                 let value = metric_value.as_f64();
                                 if !value.is_nan() && value < 0. {
-                                    return Err(ParseError::InvalidMetric(
+                                    return Err(invalid_summary(
                                         "Summary quantiles can't be negative".to_owned(),
                                     ));
                                 }
 
-                                let bucket_bound: f64 = {
+                                let (bucket_bound, raw_bound): (f64, String) = {
                                     let bound_index =
                                         label_names.iter().position(|s| s == "quantile").unwrap();
                                     let bound = &label_values[bound_index];
 
-                                    match bound.parse() {
-                                        Ok(f) => f,
-                                        Err(_) => {
-                                            return Err(ParseError::InvalidMetric(format!(
+                                    match parse_f64(bound) {
+                                        Some(f) => (f, bound.clone()),
+                                        None => {
+                                            return Err(invalid_summary(format!(
                                                 "Summary bounds must be numbers (got: {})",
                                                 bound
                                             )));
@@ -421,7 +430,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
                                 };
 
                                 if !(0. ..=1.).contains(&bucket_bound) || bucket_bound.is_nan() {
-                                    return Err(ParseError::InvalidMetric(format!(
+                                    return Err(invalid_summary(format!(
                                         "Summary bounds must be between 0 and 1 (got: {})",
                                         bucket_bound
                                     )));
@@ -429,6 +438,7 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
 
                                 let quantile = Quantile {
                                     quantile: bucket_bound,
+                                    raw_quantile: Some(raw_bound),
                                     value: metric_value,
                                 };
 
@@ -450,111 +460,44 @@ impl MarshalledMetricFamily for MetricFamilyMarshal<PrometheusType> {
 
         let metric_type = self.family_type.as_ref().cloned().unwrap_or_default();
 
-        if !metric_type.can_have_exemplar(metric_name) && exemplar.is_some() {
-            return Err(ParseError::InvalidMetric(format!(
+        let experimental_exemplar =
+            self.options.gauge_exemplars && metric_type == PrometheusType::Gauge;
+        let exemplar = if !metric_type.can_have_exemplar(metric_name)
+            && !experimental_exemplar
+            && exemplar.is_some()
+        {
+            let error = ParseError::InvalidMetric(format!(
                 "Metric Type {:?} is not allowed exemplars",
                 metric_type
-            )));
-        }
-
-        for (test_type, actions) in handlers {
-            if test_type.contains(&metric_type) {
-                for (suffix, mandatory_labels, action) in actions {
-                    if !metric_name.ends_with(suffix) {
-                        continue;
-                    }
-
-                    let mut actual_label_names = label_names.clone();
-                    let mut actual_label_values = label_values.clone();
-                    for label in mandatory_labels {
-                        if !label_names.contains(&label.to_owned()) {
-                            return Err(ParseError::InvalidMetric(format!(
-                                "Missing mandatory label for metric: {}",
-                                label
-                            )));
-                        }
-
-                        let index = actual_label_names.iter().position(|s| s == label).unwrap();
-
-                        actual_label_names.remove(index);
-                        actual_label_values.remove(index);
-                    }
-
-                    let name = &metric_name.to_owned();
-                    self.try_set_label_names(
-                        name,
-                        LabelNames::new(name, metric_type.clone(), actual_label_names),
-                    )?;
-
-                    let metric_name = metric_name.trim_end_matches(suffix);
-                    if self.name.is_some() && self.name.as_ref().unwrap() != metric_name {
-                        return Err(ParseError::InvalidMetric(format!(
-                            "Invalid Name in metric family: {} != {}",
-                            metric_name,
-                            self.name.as_ref().unwrap()
-                        )));
-                    } else if self.name.is_none() {
-                        self.name = Some(metric_name.to_owned());
-                    }
-
-                    let (existing_metric, created) = match self
-                        .get_metric_by_labelset_mut(&actual_label_values)
-                    {
-                        Some(metric) => {
-                            match (metric.timestamp.as_ref(), timestamp.as_ref()) {
-                                (Some(metric_timestamp), Some(timestamp)) if timestamp < metric_timestamp => return Err(ParseError::InvalidMetric(format!("Timestamps went backwarts in family - saw {} and then saw{}", metric_timestamp, timestamp))),
-                                (Some(_), None) | (None, Some(_)) => return Err(ParseError::InvalidMetric("Missing timestamp in family (one metric had a timestamp, another didn't)".to_string())),
-                                (Some(metric_timestamp), Some(timestamp)) if timestamp >= metric_timestamp && !metric_type.can_have_multiple_lines() => return Ok(()),
-                                _ => (metric, false)
-                            }
-                        }
-                        None => {
-                            let new_metric = self
-                                .family_type
-                                .as_ref()
-                                .unwrap_or(&PrometheusType::Unknown)
-                                .get_type_value();
-                            self.add_metric(MetricMarshal::new(
-                                actual_label_values.clone(),
-                                timestamp,
-                                new_metric,
-                            ));
-                            (
-                                self.get_metric_by_labelset_mut(&actual_label_values)
-                                    .unwrap(),
-                                true,
-                            )
-                        }
-                    };
-
-                    return action.0(
-                        existing_metric,
-                        metric_value,
-                        label_names,
-                        label_values,
-                        exemplar,
-                        created,
-                    );
-                }
+            ));
+            if !self.options.drop_invalid_exemplars {
+                return Err(error);
             }
-        }
 
-        return Err(ParseError::InvalidMetric(format!(
-            "Found weird metric name for type ({:?}): {}",
-            metric_type, metric_name
-        )));
-    }
-}
+            self.warnings.get_mut().push(error);
+            None
+        } else {
+            exemplar
+        };
 
-impl Default for PrometheusType {
-    fn default() -> Self {
-        PrometheusType::Unknown
+        self.process_with_handlers(
+            handlers,
+            metric_name,
+            metric_value,
+            label_names,
+            label_values,
+            timestamp,
+            exemplar,
+            false,
+        )
     }
 }
 
 impl From<MetricMarshal> for Sample<PrometheusValue> {
     fn from(s: MetricMarshal) -> Sample<PrometheusValue> {
         Sample::new(s.label_values, s.timestamp, s.value.into())
+            .with_exact_timestamp(s.timestamp_nanos)
+            .with_exemplar(s.exemplar)
     }
 }
 
@@ -564,10 +507,15 @@ impl MarshalledMetric<PrometheusType> for MetricMarshal {
         if family.label_names.is_none() && !self.label_values.is_empty()
             || (family.label_names.as_ref().unwrap().names.len() != self.label_values.len())
         {
-            return Err(ParseError::InvalidMetric(format!(
-                "Metrics in family have different label sets: {:?} {:?}",
-                &family.label_names, self.label_values
-            )));
+            return Err(ParseError::LabelSetMismatch {
+                family: family.name.clone().unwrap_or_default(),
+                label_names: family
+                    .label_names
+                    .as_ref()
+                    .map(|names| names.names.clone())
+                    .unwrap_or_default(),
+                label_values: self.label_values.to_vec(),
+            });
         }
 
         if family.unit.is_some() && family.metrics.is_empty() {
@@ -576,64 +524,18 @@ impl MarshalledMetric<PrometheusType> for MetricMarshal {
             ));
         }
 
-        if let MetricValueMarshal::Histogram(histogram_value) = &self.value {
-            if histogram_value.buckets.is_empty() {
-                return Err(ParseError::InvalidMetric(
-                    "Histograms must have at least one bucket".to_owned(),
-                ));
+        let options = &family.options;
+        let warnings = &mut family.warnings.borrow_mut();
+        match &self.value {
+            MetricValueMarshal::Histogram(histogram_value) => {
+                family.check_histogram(self, histogram_value, false, warnings)?;
             }
-
-            if !histogram_value
-                .buckets
-                .iter()
-                .any(|b| b.upper_bound == f64::INFINITY)
-            {
-                return Err(ParseError::InvalidMetric(format!(
-                    "Histograms must have a +INF bucket: {:?}",
-                    histogram_value.buckets
-                )));
-            }
-
-            let buckets = &histogram_value.buckets;
-
-            let has_negative_bucket = buckets.iter().any(|f| f.upper_bound < 0.);
-
-            if has_negative_bucket {
-                if histogram_value.sum.is_some() {
-                    return Err(ParseError::InvalidMetric(
-                        "Histograms cannot have a sum with a negative bucket".to_owned(),
-                    ));
-                }
-            } else if histogram_value.sum.is_some()
-                && histogram_value.sum.as_ref().unwrap().as_f64() < 0.
-            {
-                return Err(ParseError::InvalidMetric(
-                    "Histograms cannot have a negative sum without a negative bucket".to_owned(),
-                ));
-            }
-
-            if histogram_value.sum.is_some() && histogram_value.count.is_none() {
-                return Err(ParseError::InvalidMetric(
-                    "Count must be present if sum is present".to_owned(),
-                ));
-            }
-
-            if histogram_value.sum.is_none() && histogram_value.count.is_some() {
-                return Err(ParseError::InvalidMetric(
-                    "Sum must be present if count is present".to_owned(),
-                ));
-            }
-
-            let mut last = f64::NEG_INFINITY;
-            for bucket in buckets {
-                if bucket.count.as_f64() < last {
-                    return Err(ParseError::InvalidMetric(
-                        "Histograms must be cumulative".to_owned(),
-                    ));
-                }
-
-                last = bucket.count.as_f64();
+            MetricValueMarshal::Counter(CounterValueMarshal {
+                value: Some(value), ..
+            }) => {
+                check_counter_negativity(value.as_f64(), options, warnings)?;
             }
+            _ => {}
         }
 
         Ok(())
@@ -734,6 +636,115 @@ impl TryFrom<&str> for PrometheusType {
 pub fn parse_prometheus(
     exposition_bytes: &str,
 ) -> Result<MetricsExposition<PrometheusType, PrometheusValue>, ParseError> {
+    parse_prometheus_with_options(exposition_bytes, ParserOptions::default())
+}
+
+pub fn parse_prometheus_with_options(
+    exposition_bytes: &str,
+    options: ParserOptions,
+) -> Result<MetricsExposition<PrometheusType, PrometheusValue>, ParseError> {
+    parse(exposition_bytes, options, false).map(|output| output.exposition)
+}
+
+/// Parses an exposition that may not be valid UTF-8, decoding it with the given policy first
+/// (see `parse_openmetrics_bytes`)
+pub fn parse_prometheus_bytes(
+    exposition_bytes: &[u8],
+    encoding: EncodingPolicy,
+) -> Result<MetricsExposition<PrometheusType, PrometheusValue>, ParseError> {
+    parse_prometheus_bytes_with_options(exposition_bytes, encoding, ParserOptions::default())
+}
+
+pub fn parse_prometheus_bytes_with_options(
+    exposition_bytes: &[u8],
+    encoding: EncodingPolicy,
+    options: ParserOptions,
+) -> Result<MetricsExposition<PrometheusType, PrometheusValue>, ParseError> {
+    parse_prometheus_with_options(&encoding.decode(exposition_bytes)?, options)
+}
+
+impl MetricsExposition<PrometheusType, PrometheusValue> {
+    /// Reads and parses a Prometheus exposition from a file (see `parse_prometheus`), with any
+    /// byte order mark dropped. Errors are put in the file, e.g.
+    /// `metrics.txt: line 3: Found two metrics with the same labelset`
+    pub fn from_prometheus_file<P: AsRef<Path>>(path: P) -> Result<Self, ParseError> {
+        let path = path.as_ref();
+        let exposition = read_exposition_file(path)?;
+        parse_prometheus(&exposition).map_err(|error| error.in_file(path))
+    }
+}
+
+/// Parses an exposition like `parse_prometheus_with_options`, along with a report of anything
+/// that was accepted but looked wrong (see `ParseReport`)
+pub fn parse_prometheus_with_report(
+    exposition_bytes: &str,
+    options: ParserOptions,
+) -> Result<
+    (
+        MetricsExposition<PrometheusType, PrometheusValue>,
+        ParseReport,
+    ),
+    ParseError,
+> {
+    parse(exposition_bytes, options, false).map(|output| {
+        let report = ParseReport {
+            warnings: output.warnings,
+        };
+
+        (output.exposition, report)
+    })
+}
+
+/// Parses as much of an exposition as possible: a family that fails to parse or validate is
+/// skipped, and its error recorded, rather than failing the whole exposition. Syntax errors can't
+/// be recovered from, so they leave the exposition empty
+pub fn parse_prometheus_partial(
+    exposition_bytes: &str,
+    options: ParserOptions,
+) -> (
+    MetricsExposition<PrometheusType, PrometheusValue>,
+    Vec<ParseError>,
+) {
+    match parse(exposition_bytes, options, true) {
+        Ok(output) => (output.exposition, output.errors),
+        Err(error) => (MetricsExposition::new(), vec![error]),
+    }
+}
+
+/// Everything a parse produces: the exposition, the broken rules that were only warned about, and
+/// (when continuing on errors) the errors that families were skipped for
+struct ParseOutput {
+    exposition: MetricsExposition<PrometheusType, PrometheusValue>,
+    warnings: Vec<ParseError>,
+    errors: Vec<ParseError>,
+}
+
+fn parse(
+    exposition_bytes: &str,
+    options: ParserOptions,
+    continue_on_error: bool,
+) -> Result<ParseOutput, ParseError> {
+    // Errors are found in the normalized input, so have to be moved back to the original lines
+    let original =
+        |error: ParseError| error.map_line(|line| original_line(exposition_bytes, &options, line));
+
+    options.limits.check_input(exposition_bytes, 0, 0)?;
+
+    match parse_normalized(exposition_bytes, options.clone(), continue_on_error) {
+        Ok(output) => Ok(ParseOutput {
+            exposition: output.exposition,
+            warnings: output.warnings.into_iter().map(original).collect(),
+            errors: output.errors.into_iter().map(original).collect(),
+        }),
+        Err(error) => Err(original(error)),
+    }
+}
+
+fn parse_normalized(
+    exposition_bytes: &str,
+    options: ParserOptions,
+    continue_on_error: bool,
+) -> Result<ParseOutput, ParseError> {
     use pest::iterators::Pair;
 
     fn parse_metric_descriptor(
@@ -750,7 +761,9 @@ pub fn parse_prometheus(
             Rule::kw_help => {
                 let help_text = descriptor.next().unwrap().as_str();
                 family.set_or_test_name(metric_name)?;
-                family.try_add_help(help_text.to_string())?;
+                family.try_add_help(
+                    unescape_help(help_text, ExpositionFormat::Prometheus).into_owned(),
+                )?;
             }
             Rule::kw_type => {
                 let family_type = descriptor.next().unwrap().as_str();
@@ -771,13 +784,13 @@ pub fn parse_prometheus(
 
         let labels = parse_labels(labels)?
             .into_iter()
-            .map(|(a, b)| (a.to_owned(), b.to_owned()))
+            .map(|(a, b)| (a.to_owned(), b))
             .collect();
 
         let id = inner.next().unwrap().as_str();
-        let id = match id.parse() {
-            Ok(i) => i,
-            Err(_) => {
+        let id = match parse_f64(id) {
+            Some(i) => i,
+            None => {
                 return Err(ParseError::InvalidMetric(format!(
                     "Exemplar value must be a number (got: {})",
                     id
@@ -786,9 +799,9 @@ pub fn parse_prometheus(
         };
 
         let timestamp = match inner.next() {
-            Some(timestamp) => match timestamp.as_str().parse() {
-                Ok(f) => Some(f),
-                Err(_) => {
+            Some(timestamp) => match parse_f64(timestamp.as_str()) {
+                Some(f) => Some(f),
+                None => {
                     return Err(ParseError::InvalidMetric(format!(
                         "Exemplar timestamp must be a number (got: {})",
                         timestamp.as_str()
@@ -801,16 +814,16 @@ pub fn parse_prometheus(
         Ok(Exemplar::new(labels, id, timestamp))
     }
 
-    fn parse_labels(pair: Pair<Rule>) -> Result<Vec<(&str, &str)>, ParseError> {
+    fn parse_labels(pair: Pair<'_, Rule>) -> Result<Vec<(&str, String)>, ParseError> {
         assert_eq!(pair.as_rule(), Rule::labels);
 
         let mut label_pairs = pair.into_inner();
-        let mut labels: Vec<(&str, &str)> = Vec::new();
+        let mut labels: Vec<(&str, String)> = Vec::new();
 
         while label_pairs.peek().is_some() && label_pairs.peek().unwrap().as_rule() == Rule::label {
             let mut label = label_pairs.next().unwrap().into_inner();
             let name = label.next().unwrap().as_str();
-            let value = label.next().unwrap().as_str();
+            let value = unescape_label_value(label.next().unwrap().as_str()).into_owned();
 
             if labels.iter().any(|(n, _)| n == &name) {
                 return Err(ParseError::InvalidMetric(format!(
@@ -836,35 +849,56 @@ pub fn parse_prometheus(
         let mut descriptor = pair.into_inner();
         let metric_name = descriptor.next().unwrap().as_str();
 
+        if let Some(filter) = &family.options.sample_filter {
+            let labels = descriptor
+                .peek()
+                .filter(|labels| labels.as_rule() == Rule::labels)
+                .into_iter()
+                .flat_map(|labels| labels.into_inner())
+                .filter(|label| label.as_rule() == Rule::label)
+                .map(|label| {
+                    let mut label = label.into_inner();
+                    let name = label.next().unwrap().as_str();
+                    (name, label.next().unwrap().as_str())
+                });
+
+            if !filter.matches_escaped(metric_name, labels) {
+                return Ok(());
+            }
+        }
+
         let labels = if descriptor.peek().unwrap().as_rule() == Rule::labels {
             parse_labels(descriptor.next().unwrap())?
         } else {
             Vec::new()
         };
 
-        let (label_names, label_values) = {
-            let mut names = Vec::new();
-            let mut values = Vec::new();
+        let (label_names, mut label_values) = {
+            let mut names = Vec::with_capacity(labels.len());
+            let mut values = LabelValues::with_capacity(labels.len());
             for (name, value) in labels.into_iter() {
                 names.push(name.to_owned());
-                values.push(value.to_owned());
+                values.push(value);
             }
 
             (names, values)
         };
 
+        if family.options.normalize_bounds {
+            normalize_bound_labels(&label_names, &mut label_values);
+        }
+
+        family.options.limits.check_labels(&label_values)?;
+
         let value = descriptor.next().unwrap().as_str();
-        let value = match value.parse() {
-            Ok(f) => MetricNumber::Int(f),
-            Err(_) => match value.parse() {
-                Ok(f) => MetricNumber::Float(f),
-                Err(_) => {
-                    return Err(ParseError::InvalidMetric(format!(
-                        "Metric Value must be a number (got: {})",
-                        value
-                    )));
-                }
-            },
+        let value = match parse_metric_number(value) {
+            Some(value) => value,
+            None => {
+                return Err(ParseError::InvalidMetric(format!(
+                    "Metric Value must be a number (got: {})",
+                    value
+                )));
+            }
         };
 
         let mut timestamp = None;
@@ -873,84 +907,179 @@ pub fn parse_prometheus(
         if descriptor.peek().is_some()
             && descriptor.peek().as_ref().unwrap().as_rule() == Rule::timestamp
         {
-            timestamp = Some(descriptor.next().unwrap().as_str().parse().unwrap());
+            timestamp = Some(descriptor.next().unwrap().as_str());
         }
 
         if descriptor.peek().is_some()
             && descriptor.peek().as_ref().unwrap().as_rule() == Rule::exemplar
         {
-            exemplar = Some(parse_exemplar(descriptor.next().unwrap())?);
+            let parsed = parse_exemplar(descriptor.next().unwrap())?;
+            parsed.check_label_length(&family.options, family.warnings.get_mut())?;
+            exemplar = Some(parsed);
         }
 
+        let metrics = family.metrics.len();
         family.process_new_metric(
             metric_name,
             value,
             label_names,
             label_values,
-            timestamp,
+            timestamp.map(|t| parse_f64(t).unwrap()),
             exemplar,
         )?;
 
+        let unit = ExpositionFormat::Prometheus.timestamp_unit();
+        let nanos = timestamp.and_then(|t| parse_timestamp_nanos(t, unit));
+        family.set_timestamp_nanos(metrics, nanos);
+
         Ok(())
     }
 
+    /// The name of the family's first descriptor, or sample if it has none
+    fn family_name<'i>(pair: &Pair<'i, Rule>) -> &'i str {
+        let first = pair.clone().into_inner().next().unwrap();
+        match first.as_rule() {
+            Rule::metricdescriptor => first.into_inner().nth(1).unwrap().as_str(),
+            _ => first.into_inner().next().unwrap().as_str(),
+        }
+    }
+
     fn parse_metric_family(
         pair: Pair<Rule>,
+        options: &ParserOptions,
+        warnings: &mut Vec<ParseError>,
     ) -> Result<MetricFamily<PrometheusType, PrometheusValue>, ParseError> {
         assert_eq!(pair.as_rule(), Rule::metricfamily);
 
-        let mut metric_family = MetricFamilyMarshal::empty();
+        let mut metric_family = MetricFamilyMarshal::with_options(options.clone());
 
+        // Errors in the family as a whole are put on its first line
+        let (family_line, family_column) = pair.line_col();
+
+        let mut samples = 0;
         for child in pair.into_inner() {
-            match child.as_rule() {
+            let (line, column) = child.line_col();
+            let warnings_before = metric_family.warnings.get_mut().len();
+            let metrics_before = metric_family.metrics.len();
+            let parsed = match child.as_rule() {
                 Rule::metricdescriptor => {
                     if metric_family.metrics.is_empty() {
-                        parse_metric_descriptor(child, &mut metric_family)?;
+                        parse_metric_descriptor(child, &mut metric_family)
                     } else {
-                        return Err(ParseError::InvalidMetric(
+                        Err(ParseError::InvalidMetric(
                             "Metric Descriptor after samples".to_owned(),
-                        ));
+                        ))
                     }
                 }
                 Rule::metric => {
-                    parse_sample(child, &mut metric_family)?;
+                    samples += 1;
+                    ParseLimits::check(
+                        Limit::SamplesPerFamily,
+                        options.limits.max_samples_per_family,
+                        samples,
+                    )
+                    .and_then(|_| parse_sample(child, &mut metric_family))
                 }
                 _ => unreachable!(),
-            }
+            };
+
+            parsed.map_err(|error| error.at(line, column))?;
+            locate_warnings(
+                metric_family.warnings.get_mut(),
+                warnings_before,
+                line,
+                column,
+            );
+            metric_family.set_location(metrics_before, line, column);
         }
 
-        metric_family.validate()?;
+        let warnings_before = metric_family.warnings.get_mut().len();
+        metric_family
+            .validate()
+            .map_err(|error| error.at(family_line, family_column))?;
+        locate_warnings(
+            metric_family.warnings.get_mut(),
+            warnings_before,
+            family_line,
+            family_column,
+        );
+        warnings.append(metric_family.warnings.get_mut());
+
+        let mut family: MetricFamily<PrometheusType, PrometheusValue> = metric_family.into();
+        if let Some(scheme) = options.name_escaping {
+            family
+                .unescape_names(scheme)
+                .map_err(|error| error.at(family_line, family_column))?;
+        }
 
-        Ok(metric_family.into())
+        Ok(family)
     }
 
-    let exposition_marshal = PrometheusParser::parse(Rule::exposition, exposition_bytes)?
-        .next()
-        .unwrap();
-    let mut exposition = MetricsExposition::new();
+    let exposition_bytes = normalize_input(exposition_bytes, &options);
+    let exposition_bytes = exposition_bytes.as_ref();
+
+    let mut output = ParseOutput {
+        exposition: MetricsExposition::new(),
+        warnings: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    let exposition_marshal = match PrometheusParser::parse(Rule::exposition, exposition_bytes) {
+        Ok(mut pairs) => pairs.next().unwrap(),
+        Err(error) if continue_on_error => {
+            output.errors.push(error.into());
+            return Ok(output);
+        }
+        Err(error) => return Err(error.into()),
+    };
 
     assert_eq!(exposition_marshal.as_rule(), Rule::exposition);
 
     for span in exposition_marshal.into_inner() {
         match span.as_rule() {
             Rule::metricfamily => {
-                let family = parse_metric_family(span)?;
+                if !options.family_filter.matches(family_name(&span)) {
+                    continue;
+                }
 
-                if exposition.families.contains_key(&family.family_name) {
-                    return Err(ParseError::InvalidMetric(format!(
-                        "Found a metric family called {}, after that family was finalised",
-                        family.family_name
-                    )));
+                let (line, column) = span.line_col();
+                let family =
+                    parse_metric_family(span, &options, &mut output.warnings).and_then(|family| {
+                        if output.exposition.families.contains_key(&family.family_name) {
+                            return Err(ParseError::InvalidMetric(format!(
+                                "Found a metric family called {}, after that family was finalised",
+                                family.family_name
+                            ))
+                            .at(line, column));
+                        }
+
+                        Ok(family)
+                    });
+
+                match family {
+                    Ok(family) => {
+                        output
+                            .exposition
+                            .families
+                            .insert(family.family_name.clone(), family);
+                    }
+                    Err(error) if continue_on_error && !error.is_fatal() => {
+                        output.errors.push(error)
+                    }
+                    Err(error) => return Err(error),
                 }
 
-                exposition
-                    .families
-                    .insert(family.family_name.clone(), family);
+                ParseLimits::check(
+                    Limit::Families,
+                    options.limits.max_families,
+                    output.exposition.families.len(),
+                )
+                .map_err(|error| error.at(line, column))?;
             }
             Rule::EOI => {}
             _ => unreachable!(),
         }
     }
 
-    Ok(exposition)
+    Ok(output)
 }
@@ -0,0 +1,470 @@
+//! Parses and renders the Prometheus protobuf exposition format (`application/vnd.google.protobuf;
+//! proto=io.prometheus.client.MetricFamily;encoding=delimited`), a stream of length delimited
+//! `MetricFamily` messages from `metrics.proto`
+//! https://github.com/prometheus/client_model/blob/master/io/prometheus/client/metrics.proto
+//!
+//! It's also a partial bridge to the `prometheus` (rust-prometheus) crate, through the wire
+//! format only: there are no `From` conversions to or from `prometheus::proto::MetricFamily`, as
+//! this crate doesn't depend on `prometheus`. The output of its `ProtobufEncoder` can be parsed
+//! with `parse_protobuf`, and each message written by `render_protobuf` decodes into a
+//! `prometheus::proto::MetricFamily` (e.g. with `protobuf::CodedInputStream::read_message`).
+//!
+//! Native histograms are kept in `HistogramValue::native`, alongside any classic buckets, and
+//! written back out the same way. Their exemplars are skipped
+
+use std::collections::HashMap;
+
+use crate::{
+    internal::protobuf::{
+        encode_native_histogram, Decoder, Encoder, NativeHistogramDecoder, WireValue,
+    },
+    CanonicalValue, Exemplar, HistogramBucket, HistogramValue, LabelValues, MetricFamily,
+    MetricNumber, MetricsExposition, ParseError, PrometheusCounterValue, PrometheusType,
+    PrometheusValue, Quantile, Sample, SummaryValue, Timestamp,
+};
+
+fn number(value: f64) -> MetricNumber {
+    let mut number = MetricNumber::Float(value);
+    number.canonicalize();
+    number
+}
+
+fn decode_label(value: WireValue) -> Result<(String, String), ParseError> {
+    let mut label = (String::new(), String::new());
+    let mut decoder = value.as_message()?;
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => label.0 = value.as_str()?.to_owned(),
+            2 => label.1 = value.as_str()?.to_owned(),
+            _ => {}
+        }
+    }
+
+    Ok(label)
+}
+
+/// Decodes a `google.protobuf.Timestamp` into seconds
+fn decode_timestamp(value: WireValue) -> Result<Timestamp, ParseError> {
+    let (mut seconds, mut nanos) = (0, 0);
+    let mut decoder = value.as_message()?;
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => seconds = value.as_i64()?,
+            2 => nanos = value.as_i64()?,
+            _ => {}
+        }
+    }
+
+    Ok(seconds as f64 + nanos as f64 / 1e9)
+}
+
+fn decode_exemplar(value: WireValue) -> Result<Exemplar, ParseError> {
+    let mut exemplar = Exemplar::new(HashMap::new(), 0., None);
+    let mut decoder = value.as_message()?;
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => {
+                let (name, value) = decode_label(value)?;
+                exemplar.labels.insert(name, value);
+            }
+            2 => exemplar.id = value.as_f64()?,
+            3 => exemplar.timestamp = Some(decode_timestamp(value)?),
+            _ => {}
+        }
+    }
+
+    Ok(exemplar)
+}
+
+/// Decodes the `value` field of a Gauge, Counter or Untyped message, along with a Counter's exemplar
+fn decode_simple(value: WireValue) -> Result<(f64, Option<Exemplar>), ParseError> {
+    let (mut number, mut exemplar) = (0., None);
+    let mut decoder = value.as_message()?;
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => number = value.as_f64()?,
+            2 => exemplar = Some(decode_exemplar(value)?),
+            _ => {}
+        }
+    }
+
+    Ok((number, exemplar))
+}
+
+fn decode_summary(value: WireValue) -> Result<SummaryValue, ParseError> {
+    let mut summary = SummaryValue {
+        count: Some(0),
+        sum: Some(MetricNumber::Int(0)),
+        ..Default::default()
+    };
+
+    let mut decoder = value.as_message()?;
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => summary.count = Some(value.as_u64()?),
+            2 => summary.sum = Some(number(value.as_f64()?)),
+            3 => {
+                let mut quantile = Quantile {
+                    quantile: 0.,
+                    raw_quantile: None,
+                    value: MetricNumber::Int(0),
+                };
+
+                let mut decoder = value.as_message()?;
+                while let Some((field, value)) = decoder.next_field()? {
+                    match field {
+                        1 => quantile.quantile = value.as_f64()?,
+                        2 => quantile.value = number(value.as_f64()?),
+                        _ => {}
+                    }
+                }
+
+                summary.quantiles.push(quantile);
+            }
+            4 => summary.created = Some(decode_timestamp(value)?),
+            _ => {}
+        }
+    }
+
+    Ok(summary)
+}
+
+fn decode_histogram(value: WireValue) -> Result<HistogramValue, ParseError> {
+    let mut histogram = HistogramValue {
+        count: Some(0),
+        sum: Some(MetricNumber::Int(0)),
+        ..Default::default()
+    };
+
+    let mut native = NativeHistogramDecoder::new(5);
+    let mut decoder = value.as_message()?;
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => histogram.count = Some(value.as_u64()?),
+            4 => histogram.count = Some(value.as_f64()? as u64),
+            2 => histogram.sum = Some(number(value.as_f64()?)),
+            3 => {
+                let mut bucket = HistogramBucket {
+                    count: MetricNumber::Int(0),
+                    upper_bound: 0.,
+                    raw_upper_bound: None,
+                    exemplar: None,
+                };
+
+                let mut decoder = value.as_message()?;
+                while let Some((field, value)) = decoder.next_field()? {
+                    match field {
+                        1 => bucket.count = MetricNumber::Int(value.as_i64()?),
+                        4 => bucket.count = number(value.as_f64()?),
+                        2 => bucket.upper_bound = value.as_f64()?,
+                        3 => bucket.exemplar = Some(decode_exemplar(value)?),
+                        _ => {}
+                    }
+                }
+
+                histogram.buckets.push(bucket);
+            }
+            15 => histogram.created = Some(decode_timestamp(value)?),
+            _ => {
+                native.read_field(field, value)?;
+            }
+        }
+    }
+
+    histogram.native = native.finish()?;
+
+    // Client libraries leave the +Inf bucket out, as it's always the total count
+    if !histogram
+        .buckets
+        .iter()
+        .any(|b| b.upper_bound == f64::INFINITY)
+    {
+        histogram.buckets.push(HistogramBucket {
+            count: MetricNumber::Int(histogram.count.unwrap_or_default() as i64),
+            upper_bound: f64::INFINITY,
+            raw_upper_bound: None,
+            exemplar: None,
+        });
+    }
+
+    Ok(histogram)
+}
+
+fn decode_metric(
+    value: WireValue,
+    family_type: &PrometheusType,
+) -> Result<(Vec<String>, Sample<PrometheusValue>), ParseError> {
+    let mut labels = Vec::new();
+    let mut timestamp = None;
+    let mut metric_value = None;
+
+    let mut decoder = value.as_message()?;
+    while let Some((field, value)) = decoder.next_field()? {
+        match (field, family_type) {
+            (1, _) => labels.push(decode_label(value)?),
+            (2, PrometheusType::Gauge) => {
+                metric_value = Some(PrometheusValue::Gauge(number(decode_simple(value)?.0)))
+            }
+            (3, PrometheusType::Counter) => {
+                let (total, exemplar) = decode_simple(value)?;
+                metric_value = Some(PrometheusValue::Counter(PrometheusCounterValue {
+                    value: number(total),
+                    exemplar,
+                }))
+            }
+            (4, PrometheusType::Summary) => {
+                metric_value = Some(PrometheusValue::Summary(decode_summary(value)?))
+            }
+            (5, PrometheusType::Untyped) => {
+                metric_value = Some(PrometheusValue::Untyped(number(decode_simple(value)?.0)))
+            }
+            (6, _) => timestamp = Some(value.as_i64()? as Timestamp),
+            (7, PrometheusType::Histogram) => {
+                metric_value = Some(PrometheusValue::Histogram(decode_histogram(value)?))
+            }
+            _ => {}
+        }
+    }
+
+    let value = metric_value
+        .ok_or_else(|| ParseError::InvalidMetric(format!("Metric has no {} value", family_type)))?;
+
+    labels.sort();
+    let (label_names, label_values): (_, LabelValues) = labels.into_iter().unzip();
+    Ok((label_names, Sample::new(label_values, timestamp, value)))
+}
+
+fn decode_family(
+    mut decoder: Decoder,
+) -> Result<MetricFamily<PrometheusType, PrometheusValue>, ParseError> {
+    let (mut name, mut help, mut family_type) = (String::new(), String::new(), 0);
+    let mut metrics = Vec::new();
+    while let Some((field, value)) = decoder.next_field()? {
+        match field {
+            1 => name = value.as_str()?.to_owned(),
+            2 => help = value.as_str()?.to_owned(),
+            3 => family_type = value.as_u64()?,
+            4 => metrics.push(value),
+            _ => {}
+        }
+    }
+
+    let family_type = match family_type {
+        0 => PrometheusType::Counter,
+        1 => PrometheusType::Gauge,
+        2 => PrometheusType::Summary,
+        3 => PrometheusType::Untyped,
+        4 => PrometheusType::Histogram,
+        other => {
+            return Err(ParseError::InvalidMetric(format!(
+                "Unsupported metric type for {}: {}",
+                name, other
+            )))
+        }
+    };
+
+    let mut label_names: Option<Vec<String>> = None;
+    let mut samples = Vec::with_capacity(metrics.len());
+    for metric in metrics {
+        let (names, sample) = decode_metric(metric, &family_type)?;
+        match &label_names {
+            Some(existing) if existing != &names => {
+                return Err(ParseError::InvalidMetric(format!(
+                    "All the metrics of {} must have the same label names",
+                    name
+                )))
+            }
+            Some(_) => {}
+            None => label_names = Some(names),
+        }
+
+        samples.push(sample);
+    }
+
+    MetricFamily::new(
+        name,
+        label_names.unwrap_or_default(),
+        family_type,
+        help,
+        String::new(),
+    )
+    .with_samples(samples)
+}
+
+/// Parses a stream of length delimited `MetricFamily` protobuf messages, as served with the
+/// `application/vnd.google.protobuf` content type, into the same model as `parse_prometheus`.
+/// Histograms get the `+Inf` bucket the client libraries leave out of the protobuf encoding
+pub fn parse_protobuf(
+    bytes: &[u8],
+) -> Result<MetricsExposition<PrometheusType, PrometheusValue>, ParseError> {
+    let mut exposition = MetricsExposition::new();
+    let mut decoder = Decoder::new(bytes);
+    while !decoder.remaining().is_empty() {
+        let family = decode_family(Decoder::new(decoder.read_delimited()?))?;
+        if exposition.families.contains_key(&family.family_name) {
+            return Err(ParseError::InvalidMetric(format!(
+                "Found a metric family called {}, after that family was finalised",
+                family.family_name
+            )));
+        }
+
+        exposition
+            .families
+            .insert(family.family_name.clone(), family);
+    }
+
+    Ok(exposition)
+}
+
+fn encode_timestamp(encoder: &mut Encoder, field: u32, seconds: Timestamp) {
+    encoder.put_message(field, |e| {
+        e.put_int64(1, seconds.floor() as i64);
+        e.put_int64(2, ((seconds - seconds.floor()) * 1e9).round() as i64);
+    });
+}
+
+fn encode_exemplar(encoder: &mut Encoder, field: u32, exemplar: &Exemplar) {
+    encoder.put_message(field, |e| {
+        let mut labels: Vec<(&String, &String)> = exemplar.labels.iter().collect();
+        labels.sort();
+        for (name, value) in labels {
+            e.put_message(1, |e| {
+                e.put_string(1, name);
+                e.put_string(2, value);
+            });
+        }
+
+        e.put_double(2, exemplar.id);
+        if let Some(timestamp) = exemplar.timestamp {
+            encode_timestamp(e, 3, timestamp);
+        }
+    });
+}
+
+fn encode_histogram(encoder: &mut Encoder, histogram: &HistogramValue) {
+    // The +Inf bucket is left out, as the client libraries do, since it always holds the count
+    let infinite = histogram
+        .buckets
+        .iter()
+        .find(|b| b.upper_bound == f64::INFINITY);
+    let count = histogram
+        .count
+        .or_else(|| infinite.map(|b| b.count.as_f64() as u64))
+        .unwrap_or_default();
+
+    encoder.put_message(7, |e| {
+        e.put_uint64(1, count);
+        e.put_double(2, histogram.sum.map(|s| s.as_f64()).unwrap_or_default());
+        for bucket in histogram.buckets.iter() {
+            if bucket.upper_bound == f64::INFINITY && bucket.exemplar.is_none() {
+                continue;
+            }
+
+            e.put_message(3, |e| {
+                match bucket.count {
+                    MetricNumber::Int(count) => e.put_uint64(1, count as u64),
+                    MetricNumber::Float(count) => e.put_double(4, count),
+                }
+
+                e.put_double(2, bucket.upper_bound);
+                if let Some(exemplar) = &bucket.exemplar {
+                    encode_exemplar(e, 3, exemplar);
+                }
+            });
+        }
+
+        if let Some(native) = &histogram.native {
+            encode_native_histogram(e, 5, native);
+        }
+
+        if let Some(created) = histogram.created {
+            encode_timestamp(e, 15, created);
+        }
+    });
+}
+
+fn encode_summary(encoder: &mut Encoder, summary: &SummaryValue) {
+    encoder.put_message(4, |e| {
+        e.put_uint64(1, summary.count.unwrap_or_default());
+        e.put_double(2, summary.sum.map(|s| s.as_f64()).unwrap_or_default());
+        for quantile in summary.quantiles.iter() {
+            e.put_message(3, |e| {
+                e.put_double(1, quantile.quantile);
+                e.put_double(2, quantile.value.as_f64());
+            });
+        }
+
+        if let Some(created) = summary.created {
+            encode_timestamp(e, 4, created);
+        }
+    });
+}
+
+/// Renders the exposition as a stream of length delimited `MetricFamily` protobuf messages, as
+/// served with the `application/vnd.google.protobuf` content type. Unknown families are written
+/// as untyped, and histograms leave out their `+Inf` bucket (unless it has an exemplar), like the
+/// client libraries do
+/// ```
+/// use openmetrics_parser::prometheus::{parse_prometheus, parse_protobuf, render_protobuf};
+///
+/// let text = "# HELP up Whether the target is up\n# TYPE up gauge\nup{job=\"node\"} 1\n";
+/// let bytes = render_protobuf(&parse_prometheus(text).unwrap());
+/// assert_eq!(parse_protobuf(&bytes).unwrap().to_string(), text);
+/// ```
+pub fn render_protobuf(exposition: &MetricsExposition<PrometheusType, PrometheusValue>) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    for family in exposition.families.values() {
+        encoder.put_delimited(|e| {
+            e.put_string(1, &family.family_name);
+            e.put_string(2, &family.help);
+            e.put_uint64(
+                3,
+                match family.family_type {
+                    PrometheusType::Counter => 0,
+                    PrometheusType::Gauge => 1,
+                    PrometheusType::Summary => 2,
+                    PrometheusType::Untyped | PrometheusType::Unknown => 3,
+                    PrometheusType::Histogram => 4,
+                },
+            );
+
+            for sample in family.iter_samples() {
+                e.put_message(4, |e| {
+                    for (name, value) in family
+                        .get_label_names()
+                        .iter()
+                        .zip(sample.get_label_values())
+                    {
+                        e.put_message(1, |e| {
+                            e.put_string(1, name);
+                            e.put_string(2, value);
+                        });
+                    }
+
+                    match &sample.value {
+                        PrometheusValue::Gauge(n) => {
+                            e.put_message(2, |e| e.put_double(1, n.as_f64()))
+                        }
+                        PrometheusValue::Counter(c) => e.put_message(3, |e| {
+                            e.put_double(1, c.value.as_f64());
+                            if let Some(exemplar) = &c.exemplar {
+                                encode_exemplar(e, 2, exemplar);
+                            }
+                        }),
+                        PrometheusValue::Summary(s) => encode_summary(e, s),
+                        PrometheusValue::Untyped(n) | PrometheusValue::Unknown(n) => {
+                            e.put_message(5, |e| e.put_double(1, n.as_f64()))
+                        }
+                        PrometheusValue::Histogram(h) => encode_histogram(e, h),
+                    }
+
+                    if let Some(timestamp) = sample.timestamp {
+                        e.put_int64(6, timestamp as i64);
+                    }
+                });
+            }
+        });
+    }
+
+    encoder.into_bytes()
+}
@@ -0,0 +1,71 @@
+//! Renders expositions back out in the Prometheus text format
+
+use std::io;
+
+use crate::{families_in_order, MetricsExposition, PrometheusType, PrometheusValue, RenderOptions};
+
+/// Streams the exposition into `writer` as Prometheus text, one line at a time, without
+/// building the whole rendering in memory first. The output is the same as the exposition's `Display`.
+/// `writer` isn't buffered here, so wrap unbuffered writers (files, sockets) in an `io::BufWriter`
+/// ```
+/// use openmetrics_parser::prometheus::{encode_prometheus, parse_prometheus};
+///
+/// let exposition = parse_prometheus("# TYPE up gauge\nup 1\n").unwrap();
+/// let mut output = Vec::new();
+/// encode_prometheus(&mut output, &exposition).unwrap();
+/// assert_eq!(output, b"# TYPE up gauge\nup 1\n");
+/// ```
+pub fn encode_prometheus<W: io::Write + ?Sized>(
+    writer: &mut W,
+    exposition: &MetricsExposition<PrometheusType, PrometheusValue>,
+) -> io::Result<()> {
+    encode_prometheus_with_options(writer, exposition, &RenderOptions::default())
+}
+
+pub fn encode_prometheus_with_options<W: io::Write + ?Sized>(
+    writer: &mut W,
+    exposition: &MetricsExposition<PrometheusType, PrometheusValue>,
+    options: &RenderOptions,
+) -> io::Result<()> {
+    let mut first = true;
+    options.for_each_family(exposition, |family| {
+        // Families are separated by a blank line (as in `Display`), so that a family without
+        // descriptors isn't read back as part of the one before it
+        if !first {
+            writer.write_all(b"\n")?;
+        }
+
+        first = false;
+        write!(writer, "{}", family)
+    })
+}
+
+impl MetricsExposition<PrometheusType, PrometheusValue> {
+    /// Renders the exposition as Prometheus text into a buffer that's preallocated using
+    /// `rendered_size_hint`, so large expositions don't keep reallocating as they're rendered
+    pub fn render_to_vec(&self) -> Vec<u8> {
+        let mut rendered = Vec::with_capacity(self.rendered_size_hint());
+        // Writing into a Vec can't fail
+        let _ = encode_prometheus(&mut rendered, self);
+        rendered
+    }
+
+    /// Renders the exposition lazily as Prometheus text, one family per chunk. Only one family is
+    /// rendered at a time, so this can back a chunked HTTP response for a huge exposition with
+    /// bounded memory. Concatenated, the chunks are the same as `encode_prometheus_with_options`
+    pub fn render_chunks(&self, options: &RenderOptions) -> impl Iterator<Item = Vec<u8>> + '_ {
+        let families = families_in_order(self, options);
+        let options = options.clone();
+        families.into_iter().enumerate().map(move |(i, family)| {
+            options.with_rendered_family(family, |family| {
+                let mut chunk = Vec::new();
+                if i > 0 {
+                    chunk.push(b'\n');
+                }
+
+                chunk.extend_from_slice(family.to_string().as_bytes());
+                chunk
+            })
+        })
+    }
+}
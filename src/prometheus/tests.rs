@@ -14,3 +14,336 @@ fn test_prometheus_parser() {
         }
     }
 }
+
+#[test]
+fn test_normalize_bounds() {
+    use super::parsers::parse_prometheus_with_options;
+    use crate::ParserOptions;
+
+    let exposition = "foo_bucket{le=\"1.0\"} 1\nfoo_bucket{le=\"+inf\"} 2\n";
+    let parsed =
+        parse_prometheus_with_options(exposition, ParserOptions::new().with_normalize_bounds(true))
+            .unwrap();
+
+    let rendered = parsed.families["foo_bucket"].to_string();
+    assert!(rendered.contains("foo_bucket{le=\"1\"} 1"));
+    assert!(rendered.contains("foo_bucket{le=\"+Inf\"} 2"));
+}
+
+#[test]
+fn test_escaping_round_trip() {
+    let exposition = "# HELP foo A \"quoted\" \\\\ help\\nwith a newline\n# TYPE foo gauge\nfoo{path=\"C:\\\\dir\",quote=\"\\\"\"} 1\n";
+    let parsed = parse_prometheus(exposition).unwrap();
+    let family = &parsed.families["foo"];
+    assert_eq!(family.help, "A \"quoted\" \\ help\nwith a newline");
+
+    let sample = family.iter_samples().next().unwrap();
+    let labels = sample.get_labelset().unwrap();
+    assert_eq!(labels.get_label_value("path"), Some("C:\\dir"));
+    assert_eq!(labels.get_label_value("quote"), Some("\""));
+
+    let rendered = parsed.to_string();
+    assert!(rendered.contains("# HELP foo A \"quoted\" \\\\ help\\nwith a newline\n"));
+    assert!(rendered.contains("path=\"C:\\\\dir\""));
+}
+
+#[test]
+fn test_streaming_encoder() {
+    use super::encode_prometheus;
+    use std::io;
+
+    /// Records the size of every write, to check the exposition isn't rendered in one piece
+    struct CountingWriter(Vec<usize>);
+
+    impl io::Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.push(buf.len());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let exposition = include_str!("testdata/upstream_example.txt");
+    let parsed = parse_prometheus(exposition).unwrap();
+
+    let mut writer = CountingWriter(Vec::new());
+    encode_prometheus(&mut writer, &parsed).unwrap();
+    assert!(writer.0.len() > parsed.families.len());
+
+    let mut output = Vec::new();
+    encode_prometheus(&mut output, &parsed).unwrap();
+    assert_eq!(writer.0.iter().sum::<usize>(), output.len());
+    assert!(parse_prometheus(std::str::from_utf8(&output).unwrap()).is_ok());
+}
+
+#[test]
+fn test_canonical_rendering() {
+    use super::encode_prometheus_with_options;
+    use crate::RenderOptions;
+
+    let a = parse_prometheus(
+        "# TYPE b gauge\nb{y=\"1\",x=\"2\"} 1\nb{x=\"1\",y=\"1\"} 2.0\n\n# TYPE a gauge\na 1\n",
+    )
+    .unwrap();
+    let b = parse_prometheus(
+        "# TYPE a gauge\na 1.0\n\n# TYPE b gauge\nb{x=\"1\",y=\"1\"} 2\nb{x=\"2\",y=\"1\"} 1\n",
+    )
+    .unwrap();
+
+    let render = |exposition| {
+        let mut output = Vec::new();
+        encode_prometheus_with_options(
+            &mut output,
+            exposition,
+            &RenderOptions::new().with_canonical(true),
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(render(&a), render(&b));
+    assert_eq!(
+        render(&a),
+        "# TYPE a gauge\na 1\n\n# TYPE b gauge\nb{x=\"1\",y=\"1\"} 2\nb{x=\"2\",y=\"1\"} 1\n"
+    );
+}
+
+#[test]
+fn test_number_round_trip() {
+    use crate::{MetricNumber, PrometheusValue};
+
+    let exposition = "# TYPE a gauge\na{v=\"int\"} 5\na{v=\"float\"} 5.0\na{v=\"big\"} 1e21\na{v=\"small\"} 1.5e-7\na{v=\"plain\"} 0.25\n";
+    let parsed = parse_prometheus(exposition).unwrap();
+    let rendered = parsed.to_string();
+    assert_eq!(
+        rendered,
+        "# TYPE a gauge\na{v=\"int\"} 5\na{v=\"float\"} 5.0\na{v=\"big\"} 1e21\na{v=\"small\"} 1.5e-7\na{v=\"plain\"} 0.25\n"
+    );
+
+    let reparsed = parse_prometheus(&rendered).unwrap();
+    assert_eq!(reparsed.to_string(), rendered);
+
+    let values: Vec<_> = reparsed.families["a"]
+        .iter_samples()
+        .map(|s| s.value.clone())
+        .collect();
+    assert_eq!(values[0], PrometheusValue::Gauge(MetricNumber::Int(5)));
+    assert_eq!(values[1], PrometheusValue::Gauge(MetricNumber::Float(5.)));
+}
+
+#[test]
+fn test_render_chunks() {
+    use crate::prometheus::{encode_prometheus_with_options, parse_prometheus};
+    use crate::RenderOptions;
+
+    let parsed = parse_prometheus(
+        "# TYPE b gauge\nb{x=\"1\"} 2 100\n# TYPE a gauge\na 1 100\n# TYPE c_total counter\nc_total 3 100\n",
+    )
+    .unwrap();
+
+    let options = RenderOptions::new().with_timestamps(false);
+    let chunks: Vec<Vec<u8>> = parsed.render_chunks(&options).collect();
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks[1], b"\n# TYPE a gauge\na 1\n");
+
+    let mut encoded = Vec::new();
+    encode_prometheus_with_options(&mut encoded, &parsed, &options).unwrap();
+    assert_eq!(chunks.concat(), encoded);
+}
+
+#[test]
+fn test_bound_text_round_trip() {
+    use crate::{HistogramValue, PrometheusValue};
+
+    let exposition = "# TYPE latency histogram\nlatency_bucket{le=\"0.50\"} 1\nlatency_bucket{le=\"1.0\"} 2\nlatency_bucket{le=\"+Inf\"} 3\nlatency_sum 1.5\nlatency_count 3\n\n# TYPE rpc summary\nrpc{quantile=\"0.9\"} 2\nrpc{quantile=\"1.0\"} 3\nrpc_sum 5\nrpc_count 2\n";
+    let mut parsed = parse_prometheus(exposition).unwrap();
+    assert_eq!(parsed.to_string(), exposition);
+
+    // Once the bound changes, the raw text no longer applies
+    let family = parsed.families.get_mut("latency").unwrap();
+    let sample = family.iter_samples_mut().next().unwrap();
+    if let PrometheusValue::Histogram(HistogramValue { buckets, .. }) = &mut sample.value {
+        buckets[1].upper_bound = 2.;
+    }
+
+    assert!(parsed.to_string().contains("latency_bucket{le=\"2\"} 2\n"));
+}
+
+#[cfg(feature = "protobuf")]
+#[test]
+fn test_parse_protobuf() {
+    use crate::{internal::protobuf::Encoder, prometheus::parse_protobuf};
+
+    let mut requests = Encoder::new();
+    requests.put_string(1, "requests_total");
+    requests.put_string(2, "Requests served");
+    for (code, count) in [("200", 10.), ("500", 2.)] {
+        requests.put_message(4, |metric| {
+            metric.put_message(1, |label| {
+                label.put_string(1, "code");
+                label.put_string(2, code);
+            });
+            metric.put_message(3, |counter| counter.put_double(1, count));
+            metric.put_int64(6, 1500);
+        });
+    }
+
+    let mut latency = Encoder::new();
+    latency.put_string(1, "latency");
+    latency.put_uint64(3, 4);
+    latency.put_message(4, |metric| {
+        metric.put_message(7, |histogram| {
+            histogram.put_uint64(1, 3);
+            histogram.put_double(2, 1.5);
+            histogram.put_message(3, |bucket| {
+                bucket.put_uint64(1, 2);
+                bucket.put_double(2, 0.5);
+            });
+        });
+    });
+
+    // Each family is prefixed with its varint length, rather than a field key
+    let mut bytes = Vec::new();
+    for family in [requests, latency] {
+        let family = family.into_bytes();
+        assert!(family.len() < 0x80);
+        bytes.push(family.len() as u8);
+        bytes.extend_from_slice(&family);
+    }
+
+    let parsed = parse_protobuf(&bytes).unwrap();
+    assert_eq!(
+        parsed.to_string(),
+        "# HELP requests_total Requests served\n# TYPE requests_total counter\nrequests_total{code=\"200\"} 10 1500\nrequests_total{code=\"500\"} 2 1500\n\n# TYPE latency histogram\nlatency_bucket{le=\"0.5\"} 2\nlatency_bucket{le=\"+Inf\"} 3\nlatency_sum 1.5\nlatency_count 3\n"
+    );
+
+    assert!(parse_protobuf(&[0x05, 0x0a]).is_err());
+}
+
+#[cfg(feature = "protobuf")]
+#[test]
+fn test_protobuf_round_trip() {
+    use crate::prometheus::{parse_protobuf, render_protobuf};
+
+    let text = "# HELP requests_total Requests served\n# TYPE requests_total counter\nrequests_total{code=\"200\",method=\"get\"} 10 1500\nrequests_total{code=\"500\",method=\"get\"} 0 1500\n\n# TYPE latency histogram\nlatency_bucket{le=\"0.5\"} 2 # {trace_id=\"abc\"} 0.25 1.5\nlatency_bucket{le=\"+Inf\"} 3\nlatency_sum 1.5\nlatency_count 3\n\n# TYPE rpc summary\nrpc{quantile=\"0.9\"} 2.5\nrpc_sum 5\nrpc_count 2\n\n# TYPE temp untyped\ntemp -1.5\n";
+    let exposition = parse_prometheus(text).unwrap();
+    let parsed = parse_protobuf(&render_protobuf(&exposition)).unwrap();
+    assert_eq!(parsed.to_string(), text);
+}
+
+#[cfg(feature = "protobuf")]
+#[test]
+fn test_protobuf_native_histogram() {
+    use crate::prometheus::{parse_protobuf, render_protobuf};
+    use crate::{BucketSpan, MetricNumber, NativeHistogram, PrometheusValue};
+
+    let mut native = NativeHistogram::new(1);
+    native.zero_threshold = 0.001;
+    native.zero_count = MetricNumber::Int(1);
+    native.positive_spans = vec![BucketSpan::new(-1, 2), BucketSpan::new(2, 1)];
+    native.positive_counts = vec![3, 1, 2].into_iter().map(MetricNumber::Int).collect();
+    native.negative_spans = vec![BucketSpan::new(0, 1)];
+    native.negative_counts = vec![MetricNumber::Int(1)];
+    assert_eq!(
+        native.positive_buckets(),
+        vec![
+            (-1, MetricNumber::Int(3)),
+            (0, MetricNumber::Int(1)),
+            (3, MetricNumber::Int(2))
+        ]
+    );
+
+    let text = "# TYPE latency histogram\nlatency_bucket{le=\"1\"} 5\nlatency_bucket{le=\"+Inf\"} 8\nlatency_sum 7.5\nlatency_count 8\n";
+    let mut exposition = parse_prometheus(text).unwrap();
+    let family = exposition.families.get_mut("latency").unwrap();
+    if let PrometheusValue::Histogram(h) = &mut family.iter_samples_mut().next().unwrap().value {
+        h.native = Some(native.clone());
+    }
+
+    let parsed = parse_protobuf(&render_protobuf(&exposition)).unwrap();
+    assert_eq!(parsed.to_string(), text);
+    match &parsed.families["latency"]
+        .iter_samples()
+        .next()
+        .unwrap()
+        .value
+    {
+        PrometheusValue::Histogram(h) => assert_eq!(h.native, Some(native.clone())),
+        value => panic!("Expected a histogram, got {:?}", value),
+    }
+
+    // Float counts are sent as they are, rather than as deltas
+    native.positive_counts[1] = MetricNumber::Float(0.5);
+    let family = exposition.families.get_mut("latency").unwrap();
+    if let PrometheusValue::Histogram(h) = &mut family.iter_samples_mut().next().unwrap().value {
+        h.native = Some(native.clone());
+    }
+
+    let parsed = parse_protobuf(&render_protobuf(&exposition)).unwrap();
+    match &parsed.families["latency"]
+        .iter_samples()
+        .next()
+        .unwrap()
+        .value
+    {
+        PrometheusValue::Histogram(h) => {
+            let native = h.native.as_ref().unwrap();
+            assert!(native.is_float());
+            assert_eq!(native.positive_counts[1], MetricNumber::Float(0.5));
+            assert_eq!(native.zero_count, MetricNumber::Float(1.));
+        }
+        value => panic!("Expected a histogram, got {:?}", value),
+    };
+
+    // Deltas that overflow a count are an error, not a panic
+    native.positive_spans = vec![BucketSpan::new(0, 2)];
+    native.positive_counts = vec![MetricNumber::Int(-1), MetricNumber::Int(i64::MAX)];
+    native.zero_count = MetricNumber::Int(0);
+    let family = exposition.families.get_mut("latency").unwrap();
+    if let PrometheusValue::Histogram(h) = &mut family.iter_samples_mut().next().unwrap().value {
+        h.native = Some(native.clone());
+    }
+
+    assert!(parse_protobuf(&render_protobuf(&exposition)).is_err());
+}
+
+#[test]
+fn test_partial_parse() {
+    use crate::prometheus::parse_prometheus_partial;
+    use crate::ParserOptions;
+
+    let exposition = "# TYPE foo counter
+foo -1
+# TYPE bar gauge
+bar 1
+";
+
+    let (parsed, errors) = parse_prometheus_partial(exposition, ParserOptions::new());
+    assert_eq!(parsed.families.keys().collect::<Vec<_>>(), ["bar"]);
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn test_counter_exemplars() {
+    use crate::prometheus::parse_prometheus;
+    use crate::PrometheusValue;
+
+    let exposition = "# TYPE requests_total counter
+requests_total 7 # {trace_id=\"abc\"} 1
+";
+    let parsed = parse_prometheus(exposition).unwrap();
+    match &parsed.families["requests_total"]
+        .iter_samples()
+        .next()
+        .unwrap()
+        .value
+    {
+        PrometheusValue::Counter(c) => {
+            assert_eq!(c.exemplar.as_ref().unwrap().labels["trace_id"], "abc")
+        }
+        v => panic!("Expected a counter, got {:?}", v),
+    };
+}
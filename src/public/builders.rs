@@ -0,0 +1,386 @@
+use crate::{
+    CounterValue, Exemplar, HistogramBucket, HistogramValue, LabelValues, MetricFamily,
+    MetricNumber, MetricsExposition, OpenMetricsType, OpenMetricsValue, ParseError, ParserOptions,
+    Quantile, Sample, SummaryValue, Timestamp, ValidateFamily,
+};
+
+/// Builds a HistogramValue, checking that the result is a valid OpenMetrics histogram
+/// ```
+/// use openmetrics_parser::HistogramValue;
+///
+/// let histogram = HistogramValue::builder()
+///     .bucket(0.1, 5)
+///     .bucket(f64::INFINITY, 9)
+///     .sum(1.2)
+///     .count(9)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(histogram.buckets.len(), 2);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct HistogramValueBuilder {
+    value: HistogramValue,
+    gauge_histogram: bool,
+}
+
+impl HistogramValueBuilder {
+    pub fn bucket<N: Into<MetricNumber>>(self, upper_bound: f64, count: N) -> Self {
+        self.bucket_with_exemplar(upper_bound, count, None)
+    }
+
+    pub fn bucket_with_exemplar<N: Into<MetricNumber>>(
+        mut self,
+        upper_bound: f64,
+        count: N,
+        exemplar: Option<Exemplar>,
+    ) -> Self {
+        self.value.buckets.push(HistogramBucket {
+            count: count.into(),
+            upper_bound,
+            raw_upper_bound: None,
+            exemplar,
+        });
+
+        self
+    }
+
+    pub fn sum<N: Into<MetricNumber>>(mut self, sum: N) -> Self {
+        self.value.sum = Some(sum.into());
+        self
+    }
+
+    pub fn count(mut self, count: u64) -> Self {
+        self.value.count = Some(count);
+        self
+    }
+
+    pub fn created(mut self, created: Timestamp) -> Self {
+        self.value.created = Some(created);
+        self
+    }
+
+    /// Validate against the GaugeHistogram rules instead, which allow a negative sum
+    pub fn gauge_histogram(mut self) -> Self {
+        self.gauge_histogram = true;
+        self
+    }
+
+    /// Sorts the buckets by their upper bound, and checks that the histogram is valid
+    /// (has a +Inf bucket, is cumulative, has no duplicate bounds etc)
+    pub fn build(mut self) -> Result<HistogramValue, ParseError> {
+        if let Some(bucket) = self.value.buckets.iter().find(|b| b.upper_bound.is_nan()) {
+            return Err(ParseError::InvalidMetric(format!(
+                "Histogram bucket bounds can't be NaN: {:?}",
+                bucket
+            )));
+        }
+
+        self.value
+            .buckets
+            .sort_by(|a, b| a.upper_bound.partial_cmp(&b.upper_bound).unwrap());
+
+        if let Some(pair) = self
+            .value
+            .buckets
+            .windows(2)
+            .find(|pair| pair[0].upper_bound == pair[1].upper_bound)
+        {
+            return Err(ParseError::DuplicateBucket(pair[0].upper_bound));
+        }
+
+        for bucket in self.value.buckets.iter() {
+            if bucket.count.as_i64().filter(|c| *c >= 0).is_none() {
+                return Err(ParseError::InvalidMetric(format!(
+                    "Histogram bucket counts must be non negative integers (got: {})",
+                    bucket.count
+                )));
+            }
+        }
+
+        self.value.check_invariants(self.gauge_histogram)?;
+
+        Ok(self.value)
+    }
+}
+
+/// Builds a SummaryValue, checking that the result is a valid OpenMetrics summary
+/// ```
+/// use openmetrics_parser::SummaryValue;
+///
+/// let summary = SummaryValue::builder()
+///     .quantile(0.5, 0.2)
+///     .quantile(0.99, 1.5)
+///     .sum(12.5)
+///     .count(40)
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(summary.quantiles.len(), 2);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct SummaryValueBuilder {
+    value: SummaryValue,
+}
+
+impl SummaryValueBuilder {
+    pub fn quantile<N: Into<MetricNumber>>(mut self, quantile: f64, value: N) -> Self {
+        self.value.quantiles.push(Quantile {
+            quantile,
+            raw_quantile: None,
+            value: value.into(),
+        });
+
+        self
+    }
+
+    pub fn sum<N: Into<MetricNumber>>(mut self, sum: N) -> Self {
+        self.value.sum = Some(sum.into());
+        self
+    }
+
+    pub fn count(mut self, count: u64) -> Self {
+        self.value.count = Some(count);
+        self
+    }
+
+    pub fn created(mut self, created: Timestamp) -> Self {
+        self.value.created = Some(created);
+        self
+    }
+
+    /// Checks that the summary is valid (quantiles between 0 and 1, no duplicate quantiles,
+    /// non negative sum etc)
+    pub fn build(self) -> Result<SummaryValue, ParseError> {
+        self.value.check_invariants()?;
+        Ok(self.value)
+    }
+}
+
+/// A sample being built by an `ExpositionBuilder`, with its labels as they were given
+#[derive(Debug, Clone)]
+struct SampleDraft {
+    labels: Vec<(String, String)>,
+    value: OpenMetricsValue,
+    timestamp: Option<Timestamp>,
+}
+
+#[derive(Debug, Clone)]
+struct FamilyDraft {
+    name: String,
+    family_type: OpenMetricsType,
+    help: String,
+    unit: String,
+    samples: Vec<SampleDraft>,
+}
+
+impl FamilyDraft {
+    fn build(self) -> Result<MetricFamily<OpenMetricsType, OpenMetricsValue>, ParseError> {
+        let label_names = |labels: &[(String, String)]| -> Vec<String> {
+            let mut names: Vec<_> = labels.iter().map(|(name, _)| name.clone()).collect();
+            names.sort();
+            names
+        };
+
+        let names = self
+            .samples
+            .first()
+            .map(|sample| label_names(&sample.labels))
+            .unwrap_or_default();
+
+        if let Some(pair) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(ParseError::InvalidMetric(format!(
+                "Found label `{}` twice in the same labelset",
+                pair[0]
+            )));
+        }
+
+        let mut family = MetricFamily::new(
+            self.name,
+            names.clone(),
+            self.family_type,
+            self.help,
+            self.unit,
+        );
+
+        for sample in self.samples {
+            if label_names(&sample.labels) != names {
+                return Err(ParseError::InvalidMetric(format!(
+                    "Samples in the family {} must all have the labels {:?}",
+                    family.family_name, names
+                )));
+            }
+
+            let values: LabelValues = names
+                .iter()
+                .map(|name| {
+                    let (_, value) = sample.labels.iter().find(|(n, _)| n == name).unwrap();
+                    value.clone()
+                })
+                .collect();
+
+            family.add_sample(Sample::new(values, sample.timestamp, sample.value))?;
+        }
+
+        family.validate(&ParserOptions::default())?;
+
+        Ok(family)
+    }
+}
+
+/// Builds an OpenMetrics exposition in code: each family is started with its type, and followed
+/// by its samples. The result is checked the way the parser checks what it parses (see
+/// `ValidateFamily`), so an invalid family, e.g. with a negative counter, is an error
+/// ```
+/// use openmetrics_parser::{ExpositionBuilder, HistogramValue, OpenMetricsValue};
+///
+/// let histogram = HistogramValue::builder().bucket(f64::INFINITY, 3).build().unwrap();
+/// let exposition = ExpositionBuilder::new()
+///     .counter("http_requests", "Requests served", "")
+///     .sample(&[("code", "200")], 12)
+///     .sample(&[("code", "500")], 1)
+///     .histogram("latency_seconds", "Request latency", "")
+///     .sample_value(&[], OpenMetricsValue::Histogram(histogram))
+///     .build()
+///     .unwrap();
+///
+/// assert!(exposition
+///     .to_string()
+///     .contains("http_requests_total{code=\"200\"} 12\n"));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct ExpositionBuilder {
+    families: Vec<FamilyDraft>,
+
+    // The first mistake made while building, reported by `build`
+    error: Option<String>,
+}
+
+impl ExpositionBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a family of the given type, that the samples after it go into
+    pub fn family(
+        mut self,
+        name: &str,
+        family_type: OpenMetricsType,
+        help: &str,
+        unit: &str,
+    ) -> Self {
+        if self.families.iter().any(|f| f.name == name) {
+            self.fail(format!("Found two metric families called {}", name));
+        }
+
+        self.families.push(FamilyDraft {
+            name: name.to_owned(),
+            family_type,
+            help: help.to_owned(),
+            unit: unit.to_owned(),
+            samples: Vec::new(),
+        });
+
+        self
+    }
+
+    pub fn counter(self, name: &str, help: &str, unit: &str) -> Self {
+        self.family(name, OpenMetricsType::Counter, help, unit)
+    }
+
+    pub fn gauge(self, name: &str, help: &str, unit: &str) -> Self {
+        self.family(name, OpenMetricsType::Gauge, help, unit)
+    }
+
+    pub fn histogram(self, name: &str, help: &str, unit: &str) -> Self {
+        self.family(name, OpenMetricsType::Histogram, help, unit)
+    }
+
+    pub fn summary(self, name: &str, help: &str, unit: &str) -> Self {
+        self.family(name, OpenMetricsType::Summary, help, unit)
+    }
+
+    pub fn unknown(self, name: &str, help: &str, unit: &str) -> Self {
+        self.family(name, OpenMetricsType::Unknown, help, unit)
+    }
+
+    /// Adds a sample to the current family, for the types whose value is a single number
+    /// (counters, gauges, statesets and unknowns)
+    pub fn sample<N: Into<MetricNumber>>(self, labels: &[(&str, &str)], value: N) -> Self {
+        let value = value.into();
+        let value = match self.families.last().map(|f| &f.family_type) {
+            Some(OpenMetricsType::Counter) => OpenMetricsValue::Counter(CounterValue {
+                value,
+                created: None,
+                exemplar: None,
+            }),
+            Some(OpenMetricsType::Gauge) => OpenMetricsValue::Gauge(value),
+            Some(OpenMetricsType::StateSet) => OpenMetricsValue::StateSet(value),
+            Some(OpenMetricsType::Unknown) => OpenMetricsValue::Unknown(value),
+            Some(family_type) => {
+                let error = format!(
+                    "A {:?} sample can't be a single number, use sample_value instead",
+                    family_type
+                );
+                return self.failed(error);
+            }
+            None => return self.failed("Samples must come after a family".to_owned()),
+        };
+
+        self.sample_value(labels, value)
+    }
+
+    /// Adds a sample with any value to the current family, e.g. a histogram
+    pub fn sample_value(mut self, labels: &[(&str, &str)], value: OpenMetricsValue) -> Self {
+        let labels = labels
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .collect();
+
+        match self.families.last_mut() {
+            Some(family) => family.samples.push(SampleDraft {
+                labels,
+                value,
+                timestamp: None,
+            }),
+            None => self.fail("Samples must come after a family".to_owned()),
+        }
+
+        self
+    }
+
+    /// Sets the timestamp (in seconds) of the last sample
+    pub fn at(mut self, timestamp: Timestamp) -> Self {
+        match self.families.last_mut().and_then(|f| f.samples.last_mut()) {
+            Some(sample) => sample.timestamp = Some(timestamp),
+            None => self.fail("Timestamps must come after a sample".to_owned()),
+        }
+
+        self
+    }
+
+    fn fail(&mut self, error: String) {
+        self.error.get_or_insert(error);
+    }
+
+    fn failed(mut self, error: String) -> Self {
+        self.fail(error);
+        self
+    }
+
+    pub fn build(self) -> Result<MetricsExposition<OpenMetricsType, OpenMetricsValue>, ParseError> {
+        if let Some(error) = self.error {
+            return Err(ParseError::InvalidMetric(error));
+        }
+
+        let mut exposition = MetricsExposition::new();
+        for draft in self.families {
+            let family = draft.build()?;
+            exposition
+                .families
+                .insert(family.family_name.clone(), family);
+        }
+
+        Ok(exposition)
+    }
+}
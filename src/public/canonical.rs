@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use crate::{
+    HistogramValue, LabelValues, MetricFamily, MetricNumber, MetricsExposition, OpenMetricsValue,
+    OrderedMap, PrometheusValue, RenderableMetricValue, Sample, SummaryValue,
+};
+
+/// A value that has a single normal form, regardless of how it was parsed or built
+pub trait CanonicalValue {
+    /// Rewrites the value into its normal form in place
+    fn canonicalize(&mut self);
+}
+
+impl CanonicalValue for MetricNumber {
+    /// Floats that hold an exactly representable integer become ints, so `1` and `1.0` are the same
+    fn canonicalize(&mut self) {
+        if let MetricNumber::Float(f) = *self {
+            if f.fract() == 0. && f.abs() < (1u64 << 53) as f64 {
+                *self = MetricNumber::Int(f as i64);
+            }
+        }
+    }
+}
+
+impl CanonicalValue for HistogramValue {
+    fn canonicalize(&mut self) {
+        if let Some(sum) = self.sum.as_mut() {
+            sum.canonicalize();
+        }
+
+        for bucket in self.buckets.iter_mut() {
+            bucket.count.canonicalize();
+            bucket.raw_upper_bound = None;
+        }
+
+        self.buckets
+            .sort_by(|a, b| a.upper_bound.total_cmp(&b.upper_bound));
+
+        if let Some(native) = self.native.as_mut() {
+            native.zero_count.canonicalize();
+            for count in native
+                .positive_counts
+                .iter_mut()
+                .chain(native.negative_counts.iter_mut())
+            {
+                count.canonicalize();
+            }
+        }
+    }
+}
+
+impl CanonicalValue for SummaryValue {
+    fn canonicalize(&mut self) {
+        if let Some(sum) = self.sum.as_mut() {
+            sum.canonicalize();
+        }
+
+        for quantile in self.quantiles.iter_mut() {
+            quantile.value.canonicalize();
+            quantile.raw_quantile = None;
+        }
+
+        self.quantiles
+            .sort_by(|a, b| a.quantile.total_cmp(&b.quantile));
+    }
+}
+
+impl CanonicalValue for OpenMetricsValue {
+    fn canonicalize(&mut self) {
+        match self {
+            OpenMetricsValue::Untyped(n)
+            | OpenMetricsValue::Unknown(n)
+            | OpenMetricsValue::Gauge(n)
+            | OpenMetricsValue::StateSet(n) => n.canonicalize(),
+            OpenMetricsValue::Counter(c) => c.value.canonicalize(),
+            OpenMetricsValue::Histogram(h) | OpenMetricsValue::GaugeHistogram(h) => {
+                h.canonicalize()
+            }
+            OpenMetricsValue::Summary(s) => s.canonicalize(),
+            OpenMetricsValue::Info => {}
+        }
+    }
+}
+
+impl CanonicalValue for PrometheusValue {
+    fn canonicalize(&mut self) {
+        match self {
+            PrometheusValue::Untyped(n)
+            | PrometheusValue::Unknown(n)
+            | PrometheusValue::Gauge(n) => n.canonicalize(),
+            PrometheusValue::Counter(c) => c.value.canonicalize(),
+            PrometheusValue::Histogram(h) => h.canonicalize(),
+            PrometheusValue::Summary(s) => s.canonicalize(),
+        }
+    }
+}
+
+impl<TypeSet, ValueType> MetricFamily<TypeSet, ValueType>
+where
+    TypeSet: Clone,
+    ValueType: CanonicalValue + RenderableMetricValue + Clone,
+{
+    /// Returns a copy of the family in its normal form: label names sorted, samples sorted by
+    /// their label values (then timestamp), and every value canonicalized (see `CanonicalValue`).
+    /// Samples that share a labelset (which can only happen if they were edited in place) are
+    /// collapsed into the latest one
+    pub fn canonicalize(&self) -> Self {
+        let mut label_names = self.get_label_names().to_vec();
+        label_names.sort();
+
+        let mut samples: Vec<Sample<ValueType>> = self
+            .iter_samples()
+            .map(|sample| {
+                let labels: HashMap<String, String> = sample
+                    .get_labelset()
+                    .map(|l| l.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+                    .unwrap_or_default();
+                let label_values: LabelValues = label_names
+                    .iter()
+                    .map(|name| labels.get(name).cloned().unwrap_or_default())
+                    .collect();
+
+                let mut value = sample.value.clone();
+                value.canonicalize();
+
+                Sample::new(label_values, sample.timestamp, value)
+                    .with_exemplar(sample.exemplar.clone())
+            })
+            .collect();
+
+        samples.sort_by(|a, b| {
+            a.get_label_values().cmp(b.get_label_values()).then(
+                a.timestamp
+                    .unwrap_or(f64::NEG_INFINITY)
+                    .total_cmp(&b.timestamp.unwrap_or(f64::NEG_INFINITY)),
+            )
+        });
+
+        let mut deduped: Vec<Sample<ValueType>> = Vec::with_capacity(samples.len());
+        for sample in samples {
+            match deduped.last_mut() {
+                Some(last) if last.get_label_values() == sample.get_label_values() => {
+                    *last = sample
+                }
+                _ => deduped.push(sample),
+            }
+        }
+
+        MetricFamily::new(
+            self.family_name.clone(),
+            label_names,
+            self.family_type.clone(),
+            self.help.clone(),
+            self.unit.clone(),
+        )
+        .with_samples(deduped)
+        .expect("canonical samples have unique labelsets")
+    }
+}
+
+impl<TypeSet, ValueType> MetricsExposition<TypeSet, ValueType>
+where
+    TypeSet: Clone,
+    ValueType: CanonicalValue + RenderableMetricValue + Clone,
+{
+    /// Returns a copy of the exposition with every family canonicalized (see
+    /// `MetricFamily::canonicalize`), and the families sorted by name. Two equivalent expositions
+    /// render identically once canonicalized, which makes the rendered output suitable for hashing
+    /// ```
+    /// use openmetrics_parser::prometheus::parse_prometheus;
+    ///
+    /// let a = parse_prometheus("# TYPE a gauge\na{y=\"2\",x=\"1\"} 1.0\na{x=\"0\",y=\"2\"} 2\n").unwrap();
+    /// let b = parse_prometheus("# TYPE a gauge\na{x=\"0\",y=\"2\"} 2\na{x=\"1\",y=\"2\"} 1\n").unwrap();
+    /// assert_eq!(a.canonicalize().to_string(), b.canonicalize().to_string());
+    /// ```
+    pub fn canonicalize(&self) -> Self {
+        let mut families: OrderedMap<_> = self
+            .families
+            .iter()
+            .map(|(name, family)| (name.clone(), family.canonicalize()))
+            .collect();
+        families.sort_keys();
+
+        MetricsExposition { families }
+    }
+}
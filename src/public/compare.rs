@@ -0,0 +1,328 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    Exemplar, HistogramValue, MetricFamily, MetricNumber, MetricsExposition, OpenMetricsValue,
+    PrometheusValue, RenderableMetricValue, Sample, SummaryValue, Timestamp,
+};
+
+/// Controls what `semantic_diff` considers to be a meaningful difference
+#[derive(Debug, Clone, Default)]
+pub struct CompareOptions {
+    /// Whether to ignore exemplars entirely, so that an exemplar being present on one side
+    /// and missing (or different) on the other isn't a difference
+    pub ignore_exemplars: bool,
+}
+
+impl CompareOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_ignore_exemplars(mut self, ignore_exemplars: bool) -> Self {
+        self.ignore_exemplars = ignore_exemplars;
+        self
+    }
+}
+
+/// A value that can be compared ignoring differences in representation that don't change
+/// its meaning (e.g. `1` vs `1.0`, the order of histogram buckets, or NaN vs NaN)
+pub trait SemanticValue {
+    /// Describes the first difference between the two values, or None if they're equivalent
+    fn semantic_diff(&self, other: &Self, options: &CompareOptions) -> Option<String>;
+}
+
+fn floats_eq(a: f64, b: f64) -> bool {
+    a == b || (a.is_nan() && b.is_nan())
+}
+
+fn diff_float(what: &str, a: f64, b: f64) -> Option<String> {
+    if floats_eq(a, b) {
+        return None;
+    }
+
+    Some(format!("{}: {} != {}", what, a, b))
+}
+
+fn diff_option<T, F>(what: &str, a: &Option<T>, b: &Option<T>, eq: F) -> Option<String>
+where
+    T: fmt::Debug,
+    F: Fn(&T, &T) -> bool,
+{
+    match (a, b) {
+        (None, None) => None,
+        (Some(a), Some(b)) if eq(a, b) => None,
+        _ => Some(format!("{}: {:?} != {:?}", what, a, b)),
+    }
+}
+
+fn diff_number(what: &str, a: &Option<MetricNumber>, b: &Option<MetricNumber>) -> Option<String> {
+    diff_option(what, a, b, |a, b| floats_eq(a.as_f64(), b.as_f64()))
+}
+
+fn diff_timestamp(what: &str, a: &Option<Timestamp>, b: &Option<Timestamp>) -> Option<String> {
+    diff_option(what, a, b, |a, b| floats_eq(*a, *b))
+}
+
+fn diff_exemplar(
+    a: &Option<Exemplar>,
+    b: &Option<Exemplar>,
+    options: &CompareOptions,
+) -> Option<String> {
+    if options.ignore_exemplars {
+        return None;
+    }
+
+    diff_option("exemplar", a, b, |a, b| {
+        a.labels == b.labels
+            && floats_eq(a.id, b.id)
+            && diff_timestamp("", &a.timestamp, &b.timestamp).is_none()
+    })
+}
+
+impl SemanticValue for HistogramValue {
+    fn semantic_diff(&self, other: &Self, options: &CompareOptions) -> Option<String> {
+        if let Some(diff) = diff_number("sum", &self.sum, &other.sum)
+            .or_else(|| diff_option("count", &self.count, &other.count, |a, b| a == b))
+            .or_else(|| diff_timestamp("created", &self.created, &other.created))
+            .or_else(|| diff_option("native buckets", &self.native, &other.native, |a, b| a == b))
+        {
+            return Some(diff);
+        }
+
+        if self.buckets.len() != other.buckets.len() {
+            return Some(format!(
+                "bucket count: {} != {}",
+                self.buckets.len(),
+                other.buckets.len()
+            ));
+        }
+
+        let mut ours: Vec<_> = self.buckets.iter().collect();
+        let mut theirs: Vec<_> = other.buckets.iter().collect();
+        ours.sort_by(|a, b| a.upper_bound.total_cmp(&b.upper_bound));
+        theirs.sort_by(|a, b| a.upper_bound.total_cmp(&b.upper_bound));
+
+        ours.iter().zip(theirs.iter()).find_map(|(a, b)| {
+            diff_float("bucket bound", a.upper_bound, b.upper_bound)
+                .or_else(|| {
+                    diff_float(
+                        &format!("bucket {}", a.upper_bound),
+                        a.count.as_f64(),
+                        b.count.as_f64(),
+                    )
+                })
+                .or_else(|| diff_exemplar(&a.exemplar, &b.exemplar, options))
+        })
+    }
+}
+
+impl SemanticValue for SummaryValue {
+    fn semantic_diff(&self, other: &Self, _: &CompareOptions) -> Option<String> {
+        if let Some(diff) = diff_number("sum", &self.sum, &other.sum)
+            .or_else(|| diff_option("count", &self.count, &other.count, |a, b| a == b))
+            .or_else(|| diff_timestamp("created", &self.created, &other.created))
+        {
+            return Some(diff);
+        }
+
+        if self.quantiles.len() != other.quantiles.len() {
+            return Some(format!(
+                "quantile count: {} != {}",
+                self.quantiles.len(),
+                other.quantiles.len()
+            ));
+        }
+
+        let mut ours: Vec<_> = self.quantiles.iter().collect();
+        let mut theirs: Vec<_> = other.quantiles.iter().collect();
+        ours.sort_by(|a, b| a.quantile.total_cmp(&b.quantile));
+        theirs.sort_by(|a, b| a.quantile.total_cmp(&b.quantile));
+
+        ours.iter().zip(theirs.iter()).find_map(|(a, b)| {
+            diff_float("quantile", a.quantile, b.quantile).or_else(|| {
+                diff_float(
+                    &format!("quantile {}", a.quantile),
+                    a.value.as_f64(),
+                    b.value.as_f64(),
+                )
+            })
+        })
+    }
+}
+
+impl SemanticValue for OpenMetricsValue {
+    fn semantic_diff(&self, other: &Self, options: &CompareOptions) -> Option<String> {
+        match (self, other) {
+            (OpenMetricsValue::Untyped(a), OpenMetricsValue::Untyped(b))
+            | (OpenMetricsValue::Unknown(a), OpenMetricsValue::Unknown(b))
+            | (OpenMetricsValue::Gauge(a), OpenMetricsValue::Gauge(b))
+            | (OpenMetricsValue::StateSet(a), OpenMetricsValue::StateSet(b)) => {
+                diff_float("value", a.as_f64(), b.as_f64())
+            }
+            (OpenMetricsValue::Counter(a), OpenMetricsValue::Counter(b)) => {
+                diff_float("value", a.value.as_f64(), b.value.as_f64())
+                    .or_else(|| diff_timestamp("created", &a.created, &b.created))
+                    .or_else(|| diff_exemplar(&a.exemplar, &b.exemplar, options))
+            }
+            (OpenMetricsValue::Histogram(a), OpenMetricsValue::Histogram(b))
+            | (OpenMetricsValue::GaugeHistogram(a), OpenMetricsValue::GaugeHistogram(b)) => {
+                a.semantic_diff(b, options)
+            }
+            (OpenMetricsValue::Summary(a), OpenMetricsValue::Summary(b)) => {
+                a.semantic_diff(b, options)
+            }
+            (OpenMetricsValue::Info, OpenMetricsValue::Info) => None,
+            (a, b) => Some(format!("value: {:?} != {:?}", a, b)),
+        }
+    }
+}
+
+impl SemanticValue for PrometheusValue {
+    fn semantic_diff(&self, other: &Self, options: &CompareOptions) -> Option<String> {
+        match (self, other) {
+            (PrometheusValue::Untyped(a), PrometheusValue::Untyped(b))
+            | (PrometheusValue::Unknown(a), PrometheusValue::Unknown(b))
+            | (PrometheusValue::Gauge(a), PrometheusValue::Gauge(b)) => {
+                diff_float("value", a.as_f64(), b.as_f64())
+            }
+            (PrometheusValue::Counter(a), PrometheusValue::Counter(b)) => {
+                diff_float("value", a.value.as_f64(), b.value.as_f64())
+                    .or_else(|| diff_exemplar(&a.exemplar, &b.exemplar, options))
+            }
+            (PrometheusValue::Histogram(a), PrometheusValue::Histogram(b)) => {
+                a.semantic_diff(b, options)
+            }
+            (PrometheusValue::Summary(a), PrometheusValue::Summary(b)) => {
+                a.semantic_diff(b, options)
+            }
+            (a, b) => Some(format!("value: {:?} != {:?}", a, b)),
+        }
+    }
+}
+
+fn labels_of<ValueType>(sample: &Sample<ValueType>) -> BTreeMap<String, String>
+where
+    ValueType: RenderableMetricValue + Clone,
+{
+    sample
+        .get_labelset()
+        .map(|labels| labels.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+        .unwrap_or_default()
+}
+
+fn family_diff<TypeSet, ValueType>(
+    a: &MetricFamily<TypeSet, ValueType>,
+    b: &MetricFamily<TypeSet, ValueType>,
+    options: &CompareOptions,
+) -> Option<String>
+where
+    TypeSet: Clone + PartialEq + fmt::Debug,
+    ValueType: SemanticValue + RenderableMetricValue + Clone,
+{
+    if a.family_type != b.family_type {
+        return Some(format!("type: {:?} != {:?}", a.family_type, b.family_type));
+    }
+
+    if a.help != b.help {
+        return Some(format!("help: {:?} != {:?}", a.help, b.help));
+    }
+
+    if a.unit != b.unit {
+        return Some(format!("unit: {:?} != {:?}", a.unit, b.unit));
+    }
+
+    let mut ours = a.get_label_names().to_vec();
+    let mut theirs = b.get_label_names().to_vec();
+    ours.sort();
+    theirs.sort();
+    if ours != theirs {
+        return Some(format!("label names: {:?} != {:?}", ours, theirs));
+    }
+
+    let theirs: Vec<_> = b.iter_samples().map(|s| (labels_of(s), s)).collect();
+    for sample in a.iter_samples() {
+        let labels = labels_of(sample);
+        let other = match theirs.iter().find(|(l, _)| l == &labels) {
+            Some((_, other)) => other,
+            None => return Some(format!("sample {:?} is missing from the right", labels)),
+        };
+
+        let diff = diff_timestamp("timestamp", &sample.timestamp, &other.timestamp)
+            .or_else(|| diff_exemplar(&sample.exemplar, &other.exemplar, options))
+            .or_else(|| sample.value.semantic_diff(&other.value, options));
+        if let Some(diff) = diff {
+            return Some(format!("sample {:?}: {}", labels, diff));
+        }
+    }
+
+    if a.samples_count() != b.samples_count() {
+        let ours: Vec<_> = a.iter_samples().map(labels_of).collect();
+        if let Some((labels, _)) = theirs.iter().find(|(l, _)| !ours.contains(l)) {
+            return Some(format!("sample {:?} is missing from the left", labels));
+        }
+    }
+
+    None
+}
+
+/// Describes the first meaningful difference between two expositions, or returns None if they're
+/// equivalent. Differences in the order of families, samples, labels, buckets and quantiles are
+/// ignored, as are differences in number representation (`1` vs `1.0`). Families are compared in
+/// name order, so the reported difference is deterministic
+pub fn semantic_diff<TypeSet, ValueType>(
+    a: &MetricsExposition<TypeSet, ValueType>,
+    b: &MetricsExposition<TypeSet, ValueType>,
+    options: &CompareOptions,
+) -> Option<String>
+where
+    TypeSet: Clone + PartialEq + fmt::Debug,
+    ValueType: SemanticValue + RenderableMetricValue + Clone,
+{
+    let mut names: Vec<&String> = a.families.keys().chain(b.families.keys()).collect();
+    names.sort();
+    names.dedup();
+
+    names
+        .into_iter()
+        .find_map(|name| match (a.families.get(name), b.families.get(name)) {
+            (Some(a), Some(b)) => {
+                family_diff(a, b, options).map(|diff| format!("family {}: {}", name, diff))
+            }
+            (Some(_), None) => Some(format!("family {} is missing from the right", name)),
+            (None, _) => Some(format!("family {} is missing from the left", name)),
+        })
+}
+
+/// Whether two expositions are equivalent, as defined by `semantic_diff` with the default options
+/// ```
+/// use openmetrics_parser::{prometheus::parse_prometheus, semantic_eq};
+///
+/// let a = parse_prometheus("# TYPE a gauge\na{x=\"1\",y=\"2\"} 1\n# TYPE b gauge\nb 2\n").unwrap();
+/// let b = parse_prometheus("# TYPE b gauge\nb 2.0\n# TYPE a gauge\na{y=\"2\",x=\"1\"} 1\n").unwrap();
+/// assert!(semantic_eq(&a, &b));
+/// ```
+pub fn semantic_eq<TypeSet, ValueType>(
+    a: &MetricsExposition<TypeSet, ValueType>,
+    b: &MetricsExposition<TypeSet, ValueType>,
+) -> bool
+where
+    TypeSet: Clone + PartialEq + fmt::Debug,
+    ValueType: SemanticValue + RenderableMetricValue + Clone,
+{
+    semantic_diff(a, b, &CompareOptions::default()).is_none()
+}
+
+/// Panics with the first meaningful difference if the two expositions aren't equivalent
+#[track_caller]
+pub fn assert_semantic_eq<TypeSet, ValueType>(
+    a: &MetricsExposition<TypeSet, ValueType>,
+    b: &MetricsExposition<TypeSet, ValueType>,
+    options: &CompareOptions,
+) where
+    TypeSet: Clone + PartialEq + fmt::Debug,
+    ValueType: SemanticValue + RenderableMetricValue + Clone,
+{
+    if let Some(diff) = semantic_diff(a, b, options) {
+        panic!("expositions differ: {}", diff);
+    }
+}
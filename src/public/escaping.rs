@@ -0,0 +1,336 @@
+use std::{borrow::Cow, convert::TryFrom, fmt};
+
+use crate::{
+    internal::{is_metric_name_char, is_metric_name_start, is_valid_metric_name},
+    LabelSet, MetricFamily, OpenMetricsType, ParseError, PrometheusType, TimestampUnit,
+};
+
+/// The text formats this crate can parse and render. They mostly agree, but differ in the details
+/// (e.g. which characters need escaping in HELP text)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpositionFormat {
+    OpenMetrics,
+    Prometheus,
+}
+
+impl ExpositionFormat {
+    /// The unit the format's sample timestamps are in
+    pub fn timestamp_unit(self) -> TimestampUnit {
+        match self {
+            ExpositionFormat::OpenMetrics => TimestampUnit::Seconds,
+            ExpositionFormat::Prometheus => TimestampUnit::Milliseconds,
+        }
+    }
+}
+
+/// Ties a family type set (e.g. `OpenMetricsType`) to the format it belongs to, so that
+/// rendering can follow the right escaping rules
+pub trait ExpositionTypeSet {
+    const FORMAT: ExpositionFormat;
+
+    /// StateSets carry their state in a label named after the family
+    fn is_stateset(&self) -> bool {
+        false
+    }
+}
+
+impl ExpositionTypeSet for OpenMetricsType {
+    const FORMAT: ExpositionFormat = ExpositionFormat::OpenMetrics;
+
+    fn is_stateset(&self) -> bool {
+        *self == OpenMetricsType::StateSet
+    }
+}
+
+impl ExpositionTypeSet for PrometheusType {
+    const FORMAT: ExpositionFormat = ExpositionFormat::Prometheus;
+}
+
+/// Escapes HELP text for the given format. Both formats escape backslashes and newlines,
+/// OpenMetrics additionally escapes double quotes
+/// ```
+/// use openmetrics_parser::{escape_help, ExpositionFormat};
+///
+/// assert_eq!(escape_help("a \"b\"\n", ExpositionFormat::Prometheus), "a \"b\"\\n");
+/// assert_eq!(escape_help("a \"b\"\n", ExpositionFormat::OpenMetrics), "a \\\"b\\\"\\n");
+/// ```
+pub fn escape_help(help: &str, format: ExpositionFormat) -> Cow<'_, str> {
+    match format {
+        ExpositionFormat::OpenMetrics => escape(help, &['\\', '\n', '"']),
+        ExpositionFormat::Prometheus => escape(help, &['\\', '\n']),
+    }
+}
+
+/// Reverses `escape_help`. Unknown escape sequences are kept as is, like the upstream parsers do
+pub fn unescape_help(help: &str, format: ExpositionFormat) -> Cow<'_, str> {
+    match format {
+        ExpositionFormat::OpenMetrics => unescape(help, &['\\', 'n', '"']),
+        ExpositionFormat::Prometheus => unescape(help, &['\\', 'n']),
+    }
+}
+
+/// Escapes a label value. Both formats escape backslashes, double quotes, and newlines in label values
+/// ```
+/// use openmetrics_parser::escape_label_value;
+///
+/// assert_eq!(escape_label_value("C:\\ \"x\""), "C:\\\\ \\\"x\\\"");
+/// ```
+pub fn escape_label_value(value: &str) -> Cow<'_, str> {
+    escape(value, &['\\', '\n', '"'])
+}
+
+/// Reverses `escape_label_value`
+pub fn unescape_label_value(value: &str) -> Cow<'_, str> {
+    unescape(value, &['\\', 'n', '"'])
+}
+
+/// How metric and label names that aren't valid in the legacy (`[a-zA-Z_:][a-zA-Z0-9_:]*`) name
+/// syntax are written for consumers that only understand it, as negotiated with the `escaping`
+/// Content-Type parameter (e.g. `text/plain; version=0.0.4; escaping=dots`)
+/// ```
+/// use openmetrics_parser::EscapingScheme;
+///
+/// assert_eq!(EscapingScheme::Underscores.escape_name("http.requests"), "http_requests");
+/// assert_eq!(EscapingScheme::Dots.escape_name("http.requests_total"), "http_dot_requests__total");
+/// assert_eq!(EscapingScheme::Values.escape_name("http.requests"), "U__http_2e_requests");
+/// assert_eq!(EscapingScheme::Values.unescape_name("U__http_2e_requests"), "http.requests");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapingScheme {
+    /// Names are written as they are, UTF-8 and all
+    AllowUtf8,
+
+    /// Every character that isn't allowed becomes an `_`. This can't be undone
+    Underscores,
+
+    /// `.` becomes `_dot_`, `_` becomes `__`, and every other character that isn't allowed becomes
+    /// `__`. Names that only had dots in them can be recovered
+    Dots,
+
+    /// Names that aren't valid are prefixed with `U__`, `_` becomes `__`, and every other character
+    /// that isn't allowed becomes its Unicode code point in hex between underscores (e.g. `_2e_`).
+    /// Every name can be recovered
+    Values,
+}
+
+impl EscapingScheme {
+    /// The value of the scheme's `escaping` Content-Type parameter
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EscapingScheme::AllowUtf8 => "allow-utf-8",
+            EscapingScheme::Underscores => "underscores",
+            EscapingScheme::Dots => "dots",
+            EscapingScheme::Values => "values",
+        }
+    }
+
+    /// The scheme given by the `escaping` parameter of a Content-Type (or `Accept` media range),
+    /// if it has one
+    /// ```
+    /// use openmetrics_parser::EscapingScheme;
+    ///
+    /// let content_type = "application/openmetrics-text; version=1.0.0; escaping=values";
+    /// assert_eq!(EscapingScheme::from_content_type(content_type), Some(EscapingScheme::Values));
+    /// assert_eq!(EscapingScheme::from_content_type("text/plain; version=0.0.4"), None);
+    /// ```
+    pub fn from_content_type(content_type: &str) -> Option<EscapingScheme> {
+        content_type
+            .split(';')
+            .skip(1)
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("escaping"))
+            .and_then(|(_, value)| EscapingScheme::try_from(value.trim().trim_matches('"')).ok())
+    }
+
+    /// Escapes a metric or label name, leaving it alone if it's already valid (unless the scheme is
+    /// `Dots`, which always doubles underscores)
+    pub fn escape_name<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        let valid = is_valid_metric_name(name);
+        match self {
+            EscapingScheme::AllowUtf8 => Cow::Borrowed(name),
+            EscapingScheme::Underscores | EscapingScheme::Values if valid || name.is_empty() => {
+                Cow::Borrowed(name)
+            }
+            EscapingScheme::Underscores => Cow::Owned(
+                name.chars()
+                    .enumerate()
+                    .map(|(i, c)| if is_legacy_char(c, i) { c } else { '_' })
+                    .collect(),
+            ),
+            EscapingScheme::Dots => {
+                if !name.contains(['_', '.']) && valid {
+                    return Cow::Borrowed(name);
+                }
+
+                let mut escaped = String::with_capacity(name.len() + 8);
+                for (i, c) in name.chars().enumerate() {
+                    match c {
+                        '_' => escaped.push_str("__"),
+                        '.' => escaped.push_str("_dot_"),
+                        c if is_legacy_char(c, i) => escaped.push(c),
+                        _ => escaped.push_str("__"),
+                    }
+                }
+
+                Cow::Owned(escaped)
+            }
+            EscapingScheme::Values => {
+                let mut escaped = String::with_capacity(name.len() + 16);
+                escaped.push_str("U__");
+                for (i, c) in name.chars().enumerate() {
+                    match c {
+                        '_' => escaped.push_str("__"),
+                        c if is_legacy_char(c, i) => escaped.push(c),
+                        c => escaped.push_str(&format!("_{:x}_", c as u32)),
+                    }
+                }
+
+                Cow::Owned(escaped)
+            }
+        }
+    }
+
+    /// Reverses `escape_name`, as far as the scheme allows. Names that weren't escaped (e.g. ones
+    /// without the `U__` prefix), or that are malformed, are left alone
+    pub fn unescape_name<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        match self {
+            EscapingScheme::AllowUtf8 | EscapingScheme::Underscores => Cow::Borrowed(name),
+            EscapingScheme::Dots => {
+                if !name.contains("__") && !name.contains("_dot_") {
+                    return Cow::Borrowed(name);
+                }
+
+                Cow::Owned(name.replace("_dot_", ".").replace("__", "_"))
+            }
+            EscapingScheme::Values => match name.strip_prefix("U__") {
+                Some(escaped) => unescape_values(escaped).map_or(Cow::Borrowed(name), Cow::Owned),
+                None => Cow::Borrowed(name),
+            },
+        }
+    }
+}
+
+impl TryFrom<&str> for EscapingScheme {
+    type Error = ParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "allow-utf-8" => Ok(EscapingScheme::AllowUtf8),
+            "underscores" => Ok(EscapingScheme::Underscores),
+            "dots" => Ok(EscapingScheme::Dots),
+            "values" => Ok(EscapingScheme::Values),
+            _ => Err(ParseError::UnknownEscapingScheme(value.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for EscapingScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether `c` is allowed at position `i` of a legacy name
+fn is_legacy_char(c: char, i: usize) -> bool {
+    let allowed = if i == 0 {
+        is_metric_name_start
+    } else {
+        is_metric_name_char
+    };
+    c.is_ascii() && allowed(c as u8)
+}
+
+/// Decodes the part of a `Values` escaped name after its `U__`, or None if it's malformed
+fn unescape_values(escaped: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        if c != '_' {
+            unescaped.push(c);
+            continue;
+        }
+
+        let mut code = String::new();
+        loop {
+            match chars.next()? {
+                '_' if code.is_empty() => {
+                    unescaped.push('_');
+                    break;
+                }
+                '_' => {
+                    unescaped.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                    break;
+                }
+                // No code point takes more than 6 hex digits
+                c if c.is_ascii_hexdigit() && code.len() < 6 => code.push(c),
+                _ => return None,
+            }
+        }
+    }
+
+    Some(unescaped)
+}
+
+/// The model always holds unescaped text (as it was before being written into the exposition).
+/// These give the escaped form, as it appears in rendered text
+impl<TypeSet, ValueType> MetricFamily<TypeSet, ValueType>
+where
+    TypeSet: ExpositionTypeSet,
+{
+    /// The HELP text of the family, escaped for the family's format
+    pub fn escaped_help(&self) -> Cow<'_, str> {
+        escape_help(&self.help, TypeSet::FORMAT)
+    }
+}
+
+impl LabelSet<'_> {
+    /// The value of a label, escaped as it would be in the exposition
+    pub fn get_escaped_label_value(&self, label_name: &str) -> Option<Cow<'_, str>> {
+        self.get_label_value(label_name).map(escape_label_value)
+    }
+}
+
+fn escape<'a>(s: &'a str, special: &[char]) -> Cow<'a, str> {
+    if !s.contains(special) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut escaped = String::with_capacity(s.len() + 8);
+    for c in s.chars() {
+        match c {
+            '\n' if special.contains(&c) => escaped.push_str("\\n"),
+            c if special.contains(&c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+
+    Cow::Owned(escaped)
+}
+
+fn unescape<'a>(s: &'a str, escapable: &[char]) -> Cow<'a, str> {
+    if !s.contains('\\') {
+        return Cow::Borrowed(s);
+    }
+
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.peek() {
+            Some(&next) if escapable.contains(&next) => {
+                chars.next();
+                unescaped.push(if next == 'n' { '\n' } else { next });
+            }
+            _ => unescaped.push('\\'),
+        }
+    }
+
+    Cow::Owned(unescaped)
+}
@@ -0,0 +1,293 @@
+use std::io;
+
+use crate::{
+    openmetrics::{encode_openmetrics, parse_openmetrics},
+    prometheus::{encode_prometheus, parse_prometheus},
+    EncodingPolicy, ExpositionFormat, MetricsExposition, OpenMetricsType, OpenMetricsValue,
+    ParseError, PrometheusType, PrometheusValue,
+};
+
+/// The Content-Type of OpenMetrics text
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// The Content-Type of Prometheus text (version 0.0.4)
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+impl ExpositionFormat {
+    /// The Content-Type to serve the format with
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExpositionFormat::OpenMetrics => OPENMETRICS_CONTENT_TYPE,
+            ExpositionFormat::Prometheus => PROMETHEUS_CONTENT_TYPE,
+        }
+    }
+}
+
+/// Picks the format to respond to a scrape with, from its `Accept` header. The acceptable format
+/// with the highest `q` wins (the first one listed on a tie), and Prometheus text is the fallback
+/// when nothing acceptable is listed, as every scraper understands it
+/// ```
+/// use openmetrics_parser::{negotiate, ExpositionFormat};
+///
+/// let accept = "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5,*/*;q=0.1";
+/// assert_eq!(negotiate(accept), ExpositionFormat::OpenMetrics);
+/// assert_eq!(negotiate("text/plain"), ExpositionFormat::Prometheus);
+/// assert_eq!(negotiate(""), ExpositionFormat::Prometheus);
+/// ```
+pub fn negotiate(accept_header: &str) -> ExpositionFormat {
+    let mut best: Option<(f64, ExpositionFormat)> = None;
+    for range in accept_header.split(',') {
+        let mut params = range.split(';');
+        let format = match media_type(params.next().unwrap_or_default()).as_str() {
+            "application/openmetrics-text" => ExpositionFormat::OpenMetrics,
+            "text/plain" | "text/*" | "*/*" => ExpositionFormat::Prometheus,
+            _ => continue,
+        };
+
+        let quality = params
+            .filter_map(|p| p.split_once('='))
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case("q"))
+            .and_then(|(_, v)| v.trim().parse::<f64>().ok())
+            .unwrap_or(1.);
+
+        if quality > 0. && best.map_or(true, |(q, _)| quality > q) {
+            best = Some((quality, format));
+        }
+    }
+
+    best.map(|(_, format)| format)
+        .unwrap_or(ExpositionFormat::Prometheus)
+}
+
+/// An exposition of either type, so that formats with different models can sit in the same
+/// `FormatRegistry`
+#[derive(Debug)]
+pub enum AnyExposition {
+    OpenMetrics(MetricsExposition<OpenMetricsType, OpenMetricsValue>),
+    Prometheus(MetricsExposition<PrometheusType, PrometheusValue>),
+}
+
+impl AnyExposition {
+    /// The text format whose model the exposition is in
+    pub fn format(&self) -> ExpositionFormat {
+        match self {
+            AnyExposition::OpenMetrics(_) => ExpositionFormat::OpenMetrics,
+            AnyExposition::Prometheus(_) => ExpositionFormat::Prometheus,
+        }
+    }
+
+    pub fn as_openmetrics(&self) -> Option<&MetricsExposition<OpenMetricsType, OpenMetricsValue>> {
+        match self {
+            AnyExposition::OpenMetrics(exposition) => Some(exposition),
+            AnyExposition::Prometheus(_) => None,
+        }
+    }
+
+    pub fn as_prometheus(&self) -> Option<&MetricsExposition<PrometheusType, PrometheusValue>> {
+        match self {
+            AnyExposition::Prometheus(exposition) => Some(exposition),
+            AnyExposition::OpenMetrics(_) => None,
+        }
+    }
+}
+
+impl From<MetricsExposition<OpenMetricsType, OpenMetricsValue>> for AnyExposition {
+    fn from(exposition: MetricsExposition<OpenMetricsType, OpenMetricsValue>) -> Self {
+        AnyExposition::OpenMetrics(exposition)
+    }
+}
+
+impl From<MetricsExposition<PrometheusType, PrometheusValue>> for AnyExposition {
+    fn from(exposition: MetricsExposition<PrometheusType, PrometheusValue>) -> Self {
+        AnyExposition::Prometheus(exposition)
+    }
+}
+
+/// A format that expositions can be read from. Implement this to add support for a new format
+/// (e.g. a proprietary agent's), and register it in a `FormatRegistry`
+pub trait ExpositionDecoder {
+    /// The media types (e.g. `text/plain`) this decoder understands, without any parameters
+    fn content_types(&self) -> &[&str];
+
+    fn decode(&self, input: &[u8]) -> Result<AnyExposition, ParseError>;
+}
+
+/// A format that expositions can be written out in
+pub trait ExpositionEncoder {
+    /// The full Content-Type of the output, including parameters
+    fn content_type(&self) -> &str;
+
+    /// Writes out the exposition. Expositions in a model the format can't hold are rejected with
+    /// an `io::ErrorKind::InvalidInput` error
+    fn encode(&self, exposition: &AnyExposition, writer: &mut dyn io::Write) -> io::Result<()>;
+}
+
+/// Strips the parameters (`; version=...`) off a Content-Type, and lowercases it
+fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+fn unsupported_model(format: &str, exposition: &AnyExposition) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "{} text can't be encoded from a {:?} exposition",
+            format,
+            exposition.format()
+        ),
+    )
+}
+
+/// The OpenMetrics text format
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenMetricsTextFormat;
+
+impl ExpositionDecoder for OpenMetricsTextFormat {
+    fn content_types(&self) -> &[&str] {
+        &["application/openmetrics-text"]
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<AnyExposition, ParseError> {
+        parse_openmetrics(&EncodingPolicy::Strict.decode(input)?).map(AnyExposition::from)
+    }
+}
+
+impl ExpositionEncoder for OpenMetricsTextFormat {
+    fn content_type(&self) -> &str {
+        OPENMETRICS_CONTENT_TYPE
+    }
+
+    fn encode(&self, exposition: &AnyExposition, writer: &mut dyn io::Write) -> io::Result<()> {
+        match exposition {
+            AnyExposition::OpenMetrics(exposition) => encode_openmetrics(writer, exposition),
+            other => Err(unsupported_model("OpenMetrics", other)),
+        }
+    }
+}
+
+/// The Prometheus text format (version 0.0.4)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrometheusTextFormat;
+
+impl ExpositionDecoder for PrometheusTextFormat {
+    fn content_types(&self) -> &[&str] {
+        &["text/plain"]
+    }
+
+    fn decode(&self, input: &[u8]) -> Result<AnyExposition, ParseError> {
+        parse_prometheus(&EncodingPolicy::Strict.decode(input)?).map(AnyExposition::from)
+    }
+}
+
+impl ExpositionEncoder for PrometheusTextFormat {
+    fn content_type(&self) -> &str {
+        PROMETHEUS_CONTENT_TYPE
+    }
+
+    fn encode(&self, exposition: &AnyExposition, writer: &mut dyn io::Write) -> io::Result<()> {
+        match exposition {
+            AnyExposition::Prometheus(exposition) => encode_prometheus(writer, exposition),
+            other => Err(unsupported_model("Prometheus", other)),
+        }
+    }
+}
+
+/// Dispatches decoding and encoding to the registered format for a Content-Type, so that
+/// code handling expositions doesn't need to know about every format up front. Formats of
+/// either model can be registered side by side
+/// ```
+/// use openmetrics_parser::{AnyExposition, FormatRegistry, OpenMetricsTextFormat, PrometheusTextFormat};
+///
+/// let registry = FormatRegistry::new()
+///     .with_decoder(OpenMetricsTextFormat)
+///     .with_decoder(PrometheusTextFormat)
+///     .with_encoder(OpenMetricsTextFormat)
+///     .with_encoder(PrometheusTextFormat);
+///
+/// let exposition = registry
+///     .decode("text/plain; version=0.0.4", b"up 1\n")
+///     .unwrap();
+/// assert!(matches!(exposition, AnyExposition::Prometheus(_)));
+///
+/// let mut output = Vec::new();
+/// registry.encode("text/plain", &exposition, &mut output).unwrap();
+/// assert_eq!(output, b"up 1\n");
+///
+/// let exposition = registry
+///     .decode("application/openmetrics-text", b"up 1\n# EOF\n")
+///     .unwrap();
+/// assert!(matches!(exposition, AnyExposition::OpenMetrics(_)));
+/// ```
+#[derive(Default)]
+pub struct FormatRegistry {
+    decoders: Vec<Box<dyn ExpositionDecoder>>,
+    encoders: Vec<Box<dyn ExpositionEncoder>>,
+}
+
+impl FormatRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a decoder. Decoders registered later take priority over earlier ones for the same Content-Type
+    pub fn with_decoder<D>(mut self, decoder: D) -> Self
+    where
+        D: ExpositionDecoder + 'static,
+    {
+        self.decoders.insert(0, Box::new(decoder));
+        self
+    }
+
+    /// Registers an encoder. Encoders registered later take priority over earlier ones for the same Content-Type
+    pub fn with_encoder<T>(mut self, encoder: T) -> Self
+    where
+        T: ExpositionEncoder + 'static,
+    {
+        self.encoders.insert(0, Box::new(encoder));
+        self
+    }
+
+    pub fn decoder_for(&self, content_type: &str) -> Option<&dyn ExpositionDecoder> {
+        let wanted = media_type(content_type);
+        self.decoders
+            .iter()
+            .find(|d| d.content_types().iter().any(|c| wanted == media_type(c)))
+            .map(|d| d.as_ref())
+    }
+
+    pub fn encoder_for(&self, content_type: &str) -> Option<&dyn ExpositionEncoder> {
+        let wanted = media_type(content_type);
+        self.encoders
+            .iter()
+            .find(|e| wanted == media_type(e.content_type()))
+            .map(|e| e.as_ref())
+    }
+
+    pub fn decode(&self, content_type: &str, input: &[u8]) -> Result<AnyExposition, ParseError> {
+        match self.decoder_for(content_type) {
+            Some(decoder) => decoder.decode(input),
+            None => Err(ParseError::UnknownContentType(content_type.to_owned())),
+        }
+    }
+
+    pub fn encode(
+        &self,
+        content_type: &str,
+        exposition: &AnyExposition,
+        writer: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        match self.encoder_for(content_type) {
+            Some(encoder) => encoder.encode(exposition, writer),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("No encoder registered for `{}`", content_type),
+            )),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{HistogramValue, MetricFamily, OpenMetricsType, OpenMetricsValue, ParseError};
+
+impl HistogramValue {
+    /// The number of observations in each bucket on its own, rather than cumulatively, as
+    /// `(upper_bound, count)` pairs ordered by bound. For a gauge histogram this is the current
+    /// occupancy of each bucket, e.g. how many items in a queue have waited for up to that long
+    pub fn bucket_occupancy(&self) -> Vec<(f64, f64)> {
+        let mut buckets: Vec<(f64, f64)> = self
+            .buckets
+            .iter()
+            .map(|b| (b.upper_bound, b.count.as_f64()))
+            .collect();
+        buckets.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut previous = 0.;
+        for (_, count) in buckets.iter_mut() {
+            let cumulative = *count;
+            *count -= previous;
+            previous = cumulative;
+        }
+
+        buckets
+    }
+}
+
+impl MetricFamily<OpenMetricsType, OpenMetricsValue> {
+    /// Converts a GaugeHistogram family into a Histogram family holding the same distribution,
+    /// for backends that don't support gauge histograms.
+    ///
+    /// Each sample becomes a snapshot of the distribution at the time it was taken: the buckets
+    /// are copied as-is, and `_gsum`/`_gcount` become `_sum`/`_count`. Unlike a real histogram,
+    /// the snapshot isn't monotonic, so it should be read as a series of gauges (e.g. with
+    /// `histogram_quantile` over the raw buckets), and never with `rate` or `increase`.
+    /// Histograms can't carry a sum when they have negative buckets, so in that case the sum and
+    /// count are dropped (the count is still available as the `+Inf` bucket)
+    pub fn gauge_histogram_snapshot(&self) -> Result<Self, ParseError> {
+        if self.family_type != OpenMetricsType::GaugeHistogram {
+            return Err(ParseError::InvalidMetric(format!(
+                "Only GaugeHistograms can be snapshotted (got {:?})",
+                self.family_type
+            )));
+        }
+
+        let samples = self.iter_samples().map(|sample| {
+            let mut snapshot = sample.clone();
+            if let OpenMetricsValue::GaugeHistogram(h) = &sample.value {
+                let mut histogram = h.clone();
+                if histogram.buckets.iter().any(|b| b.upper_bound < 0.) {
+                    histogram.sum = None;
+                    histogram.count = None;
+                }
+
+                snapshot.value = OpenMetricsValue::Histogram(histogram);
+            }
+
+            snapshot
+        });
+
+        MetricFamily::new(
+            self.family_name.clone(),
+            self.get_label_names().to_vec(),
+            OpenMetricsType::Histogram,
+            self.help.clone(),
+            self.unit.clone(),
+        )
+        .with_samples(samples)
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    mem,
+    ops::{Deref, DerefMut},
+};
+
+/// How many label values a sample holds without a heap allocation of its own
+const INLINE_LABELS: usize = 4;
+
+/// The label values of a sample, in the order of its family's label names. Samples rarely have
+/// more than a handful, so up to four are held inline in the sample, spilling over into a `Vec`
+/// past that. It derefs to a slice of the values
+/// ```
+/// use openmetrics_parser::LabelValues;
+///
+/// let mut values = LabelValues::from(vec!["a".to_owned(), "c".to_owned()]);
+/// values.insert(1, "b".to_owned());
+/// assert_eq!(values.as_slice(), ["a", "b", "c"]);
+/// assert!(values.is_inline());
+/// ```
+#[derive(Clone)]
+pub struct LabelValues {
+    repr: Repr,
+}
+
+#[derive(Clone)]
+enum Repr {
+    // Empty strings don't allocate, so the unused slots are free
+    Inline {
+        len: usize,
+        values: [String; INLINE_LABELS],
+    },
+    Heap(Vec<String>),
+}
+
+impl Default for LabelValues {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LabelValues {
+    pub fn new() -> Self {
+        LabelValues {
+            repr: Repr::Inline {
+                len: 0,
+                values: Default::default(),
+            },
+        }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        if capacity <= INLINE_LABELS {
+            Self::new()
+        } else {
+            LabelValues {
+                repr: Repr::Heap(Vec::with_capacity(capacity)),
+            }
+        }
+    }
+
+    /// Whether the values are held inline, rather than in an allocation of their own
+    pub fn is_inline(&self) -> bool {
+        matches!(self.repr, Repr::Inline { .. })
+    }
+
+    pub fn as_slice(&self) -> &[String] {
+        match &self.repr {
+            Repr::Inline { len, values } => &values[..*len],
+            Repr::Heap(values) => values,
+        }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [String] {
+        match &mut self.repr {
+            Repr::Inline { len, values } => &mut values[..*len],
+            Repr::Heap(values) => values,
+        }
+    }
+
+    /// The values on the heap, moving them there if they're inline and there's no room for
+    /// `additional` more
+    fn spill(&mut self, additional: usize) -> Option<&mut Vec<String>> {
+        if let Repr::Inline { len, values } = &mut self.repr {
+            if *len + additional <= INLINE_LABELS {
+                return None;
+            }
+
+            let mut spilled = Vec::with_capacity(*len + additional.max(INLINE_LABELS));
+            spilled.extend(values[..*len].iter_mut().map(mem::take));
+            self.repr = Repr::Heap(spilled);
+        }
+
+        match &mut self.repr {
+            Repr::Heap(values) => Some(values),
+            Repr::Inline { .. } => None,
+        }
+    }
+
+    pub fn push(&mut self, value: String) {
+        if let Some(values) = self.spill(1) {
+            return values.push(value);
+        }
+
+        if let Repr::Inline { len, values } = &mut self.repr {
+            values[*len] = value;
+            *len += 1;
+        }
+    }
+
+    /// Inserts the value at `index`, shifting the ones after it along. Panics if `index` is
+    /// past the end, as `Vec::insert` does
+    pub fn insert(&mut self, index: usize, value: String) {
+        if let Some(values) = self.spill(1) {
+            return values.insert(index, value);
+        }
+
+        if let Repr::Inline { len, values } = &mut self.repr {
+            assert!(index <= *len, "insertion index {} is out of bounds", index);
+            values[*len] = value;
+            values[index..=*len].rotate_right(1);
+            *len += 1;
+        }
+    }
+
+    /// Removes the value at `index`, shifting the ones after it back. Panics if `index` is out
+    /// of bounds, as `Vec::remove` does
+    pub fn remove(&mut self, index: usize) -> String {
+        match &mut self.repr {
+            Repr::Inline { len, values } => {
+                assert!(index < *len, "removal index {} is out of bounds", index);
+                values[index..*len].rotate_left(1);
+                *len -= 1;
+                mem::take(&mut values[*len])
+            }
+            Repr::Heap(values) => values.remove(index),
+        }
+    }
+
+    pub fn into_vec(self) -> Vec<String> {
+        match self.repr {
+            Repr::Inline { len, values } => values.into_iter().take(len).collect(),
+            Repr::Heap(values) => values,
+        }
+    }
+}
+
+impl Deref for LabelValues {
+    type Target = [String];
+
+    fn deref(&self) -> &[String] {
+        self.as_slice()
+    }
+}
+
+impl DerefMut for LabelValues {
+    fn deref_mut(&mut self) -> &mut [String] {
+        self.as_mut_slice()
+    }
+}
+
+impl fmt::Debug for LabelValues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl PartialEq for LabelValues {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl Eq for LabelValues {}
+
+impl PartialOrd for LabelValues {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LabelValues {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_slice().cmp(other.as_slice())
+    }
+}
+
+impl Hash for LabelValues {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_slice().hash(state)
+    }
+}
+
+impl From<Vec<String>> for LabelValues {
+    fn from(values: Vec<String>) -> Self {
+        if values.len() > INLINE_LABELS {
+            return LabelValues {
+                repr: Repr::Heap(values),
+            };
+        }
+
+        values.into_iter().collect()
+    }
+}
+
+impl From<LabelValues> for Vec<String> {
+    fn from(values: LabelValues) -> Self {
+        values.into_vec()
+    }
+}
+
+impl FromIterator<String> for LabelValues {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+        let mut values = LabelValues::with_capacity(iter.size_hint().0);
+        values.extend(iter);
+        values
+    }
+}
+
+impl Extend<String> for LabelValues {
+    fn extend<I: IntoIterator<Item = String>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl IntoIterator for LabelValues {
+    type Item = String;
+    type IntoIter = std::vec::IntoIter<String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_vec().into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a LabelValues {
+    type Item = &'a String;
+    type IntoIter = std::slice::Iter<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut LabelValues {
+    type Item = &'a mut String;
+    type IntoIter = std::slice::IterMut<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
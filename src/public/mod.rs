@@ -1,6 +1,40 @@
+mod builders;
+mod canonical;
+mod compare;
+mod escaping;
+mod format;
+mod gaugehistogram;
+mod labels;
 mod model;
+mod native;
+mod options;
+mod ordered;
+#[cfg(feature = "serde")]
+mod serialization;
+mod sizehint;
+mod table;
+#[cfg(test)]
 mod tests;
+mod timerange;
+mod timeseries;
+mod typed;
 mod types;
+mod validation;
 
+pub use builders::*;
+pub use canonical::*;
+pub use compare::*;
+pub use escaping::*;
+pub use format::*;
+pub use labels::*;
 pub use model::*;
+pub use native::*;
+pub use options::*;
+pub use ordered::*;
+pub use sizehint::*;
+pub use table::*;
+pub use timerange::*;
+pub use timeseries::*;
+pub use typed::*;
 pub use types::*;
+pub use validation::*;
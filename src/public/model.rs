@@ -1,15 +1,57 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::{self, Write},
     sync::Arc,
 };
 
 use auto_ops::impl_op_ex;
 
-use crate::internal::{render_label_values, RenderableMetricValue};
+use crate::internal::{parse_timestamp_nanos, render_label_values, RenderableMetricValue};
+use crate::{
+    escape_help, EscapingScheme, ExpositionFormat, ExpositionTypeSet, HistogramValueBuilder,
+    LabelValues, NativeHistogram, OrderedMap, ParserOptions, SummaryValueBuilder,
+};
 
+/// A sample or exemplar timestamp. Sample timestamps are in the unit of their format (see
+/// `ExpositionFormat::timestamp_unit`), exemplar ones are always in seconds
 pub type Timestamp = f64;
 
+/// The unit of a `Timestamp`: seconds in OpenMetrics, milliseconds in the Prometheus text format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampUnit {
+    Seconds,
+    Milliseconds,
+    Nanoseconds,
+}
+
+impl TimestampUnit {
+    /// Converts a timestamp in this unit into the given one
+    /// ```
+    /// use openmetrics_parser::TimestampUnit;
+    ///
+    /// assert_eq!(TimestampUnit::Seconds.convert(1.5, TimestampUnit::Milliseconds), 1500.);
+    /// assert_eq!(TimestampUnit::Milliseconds.convert(1500., TimestampUnit::Seconds), 1.5);
+    /// ```
+    pub fn convert(self, timestamp: Timestamp, to: TimestampUnit) -> Timestamp {
+        const SCALES: [f64; 4] = [1., 1e3, 1e6, 1e9];
+        let (from, to) = (self.digits(), to.digits());
+        if to >= from {
+            timestamp * SCALES[(to - from) as usize / 3]
+        } else {
+            timestamp / SCALES[(from - to) as usize / 3]
+        }
+    }
+
+    /// How many decimal digits of a second the unit is, e.g. 3 for milliseconds
+    pub(crate) fn digits(self) -> i32 {
+        match self {
+            TimestampUnit::Seconds => 0,
+            TimestampUnit::Milliseconds => 3,
+            TimestampUnit::Nanoseconds => 9,
+        }
+    }
+}
+
 /// An OpenMetrics Exemplar (that is also valid in Prometheus)
 /// https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md#exemplars
 /// Exemplars are references to data outside of the MetricSet. A common use case are IDs of program traces.
@@ -18,13 +60,18 @@ pub type Timestamp = f64;
 /// Other characters in the text rendering of an exemplar such as ",= are not included in this limit for implementation
 /// simplicity and for consistency between the text and proto formats.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Exemplar {
     pub labels: HashMap<String, String>,
     pub timestamp: Option<f64>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::public::serialization::float"))]
     pub id: f64,
 }
 
 impl Exemplar {
+    /// The most characters an exemplar's label names and values can add up to
+    pub const MAX_LABEL_LENGTH: usize = 128;
+
     pub fn new(labels: HashMap<String, String>, id: f64, timestamp: Option<f64>) -> Exemplar {
         Exemplar {
             labels,
@@ -32,13 +79,59 @@ impl Exemplar {
             timestamp,
         }
     }
+
+    /// The combined length, in characters, of the exemplar's label names and values
+    /// ```
+    /// use openmetrics_parser::Exemplar;
+    ///
+    /// let labels = [("trace_id".to_owned(), "ab\u{e9}".to_owned())].into_iter().collect();
+    /// assert_eq!(Exemplar::new(labels, 1., None).label_length(), 11);
+    /// ```
+    pub fn label_length(&self) -> usize {
+        self.labels
+            .iter()
+            .map(|(name, value)| name.chars().count() + value.chars().count())
+            .sum()
+    }
+
+    /// Checks that the exemplar's labels fit in `MAX_LABEL_LENGTH`, as strictly as `options` asks
+    pub(crate) fn check_label_length(
+        &self,
+        options: &ParserOptions,
+        warnings: &mut Vec<ParseError>,
+    ) -> Result<(), ParseError> {
+        let length = self.label_length();
+        if length <= Exemplar::MAX_LABEL_LENGTH {
+            return Ok(());
+        }
+
+        options.exemplar_label_length.enforce(
+            ParseError::InvalidMetric(format!(
+                "Exemplar labels can't be longer than {} characters (got: {})",
+                Exemplar::MAX_LABEL_LENGTH,
+                length
+            )),
+            warnings,
+        )
+    }
 }
 
 impl fmt::Display for Exemplar {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let names: Vec<&str> = self.labels.keys().map(|s| s.as_str()).collect();
-        let values: Vec<&str> = self.labels.keys().map(|s| s.as_str()).collect();
-        write!(f, "# {} {}", render_label_values(&names, &values), self.id)?;
+        // Sorted by name, as the labels are held in hash order
+        let mut labels: Vec<(&str, &str)> = self
+            .labels
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        labels.sort_unstable();
+        let (names, values): (Vec<&str>, Vec<&str>) = labels.into_iter().unzip();
+        write!(
+            f,
+            " # {} {}",
+            render_exemplar_labels(&names, &values),
+            format_float(self.id)
+        )?;
         if let Some(timestamp) = self.timestamp {
             write!(f, " {}", format_float(timestamp))?;
         }
@@ -51,7 +144,7 @@ impl fmt::Display for Exemplar {
 /// https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md#metricfamily
 /// A MetricFamily MAY have zero or more Metrics. A MetricFamily MUST have a name, HELP, TYPE, and UNIT metadata.
 /// Every Metric within a MetricFamily MUST have a unique LabelSet.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MetricFamily<TypeSet, ValueType> {
     pub family_name: String,
     label_names: Arc<Vec<String>>,
@@ -84,14 +177,12 @@ where
     }
 
     pub fn get_label_names(&self) -> &[String] {
-        return self.label_names.as_ref().as_slice();
+        self.label_names.as_ref().as_slice()
     }
 
-    pub fn clone_and_convert_type<T: RenderableMetricValue + Clone>(
-        &self,
-    ) -> MetricFamily<TypeSet, T>
+    pub fn clone_and_convert_type<T>(&self) -> MetricFamily<TypeSet, T>
     where
-        T: From<ValueType>,
+        T: RenderableMetricValue + Clone + From<ValueType>,
     {
         MetricFamily {
             family_name: self.family_name.clone(),
@@ -182,6 +273,23 @@ where
         self.metrics.iter()
     }
 
+    /// Iterates over the samples along with the family's label names, to get at their labels by name
+    /// ```
+    /// use openmetrics_parser::openmetrics::parse_openmetrics;
+    ///
+    /// let exposition = "# TYPE up gauge\nup{instance=\"a\",job=\"b\"} 1\n# EOF\n";
+    /// let parsed = parse_openmetrics(exposition).unwrap();
+    /// let sample = parsed.families["up"].iter_labeled_samples().next().unwrap();
+    /// assert_eq!(sample.get_label("instance"), Some("a"));
+    /// assert_eq!(sample.labels().collect::<Vec<_>>(), [("instance", "a"), ("job", "b")]);
+    /// ```
+    pub fn iter_labeled_samples(&self) -> impl Iterator<Item = LabeledSample<'_, ValueType>> {
+        self.metrics.iter().map(|sample| LabeledSample {
+            label_names: self.label_names.as_slice(),
+            sample,
+        })
+    }
+
     pub fn iter_samples_mut(&mut self) -> impl Iterator<Item = &mut Sample<ValueType>> {
         self.metrics.iter_mut()
     }
@@ -198,48 +306,85 @@ where
     }
 
     pub fn get_sample_matches(&self, sample: &Sample<ValueType>) -> Option<&Sample<ValueType>> {
-        return self
-            .metrics
+        self.metrics
             .iter()
-            .find(|&s| s.label_values == sample.label_values);
+            .find(|&s| s.label_values == sample.label_values)
     }
 
     pub fn get_sample_matches_mut(
         &mut self,
         sample: &Sample<ValueType>,
     ) -> Option<&mut Sample<ValueType>> {
-        return self
-            .metrics
+        self.metrics
             .iter_mut()
-            .find(|s| s.label_values == sample.label_values);
+            .find(|s| s.label_values == sample.label_values)
+    }
+
+    /// Finds the sample with exactly these labels, given in any order
+    /// ```
+    /// use openmetrics_parser::prometheus::parse_prometheus;
+    ///
+    /// let exposition = "http{code=\"200\",method=\"GET\"} 1\nhttp{code=\"500\",method=\"GET\"} 2\n";
+    /// let parsed = parse_prometheus(exposition).unwrap();
+    /// let family = &parsed.families["http"];
+    /// let sample = family.get_sample(&[("method", "GET"), ("code", "500")]).unwrap();
+    /// assert_eq!(sample.get_label_values(), ["500", "GET"]);
+    /// assert!(family.get_sample(&[("code", "500")]).is_none());
+    /// ```
+    pub fn get_sample(&self, labels: &[(&str, &str)]) -> Option<&Sample<ValueType>> {
+        let label_values = self.label_values_for(labels)?;
+        self.get_sample_by_label_values(&label_values)
+    }
+
+    pub fn get_sample_mut(&mut self, labels: &[(&str, &str)]) -> Option<&mut Sample<ValueType>> {
+        let label_values = self.label_values_for(labels)?;
+        self.get_sample_by_label_values_mut(&label_values)
+    }
+
+    /// Puts `labels` in the order of the family's label names, if they're exactly those labels
+    fn label_values_for(&self, labels: &[(&str, &str)]) -> Option<LabelValues> {
+        if labels.len() != self.label_names.len() {
+            return None;
+        }
+
+        self.label_names
+            .iter()
+            .map(|name| {
+                labels
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, value)| value.to_string())
+            })
+            .collect()
     }
 
     pub fn get_sample_by_label_values(
         &self,
         label_values: &[String],
     ) -> Option<&Sample<ValueType>> {
-        return self.metrics.iter().find(|s| s.label_values == label_values);
+        self.metrics
+            .iter()
+            .find(|s| s.label_values.as_slice() == label_values)
     }
 
     pub fn get_sample_by_label_values_mut(
         &mut self,
         label_values: &[String],
     ) -> Option<&mut Sample<ValueType>> {
-        return self
-            .metrics
+        self.metrics
             .iter_mut()
-            .find(|s| s.label_values == label_values);
+            .find(|s| s.label_values.as_slice() == label_values)
     }
 
     pub fn get_sample_by_labelset(&self, labelset: &LabelSet) -> Option<&Sample<ValueType>> {
-        return self.metrics.iter().find(|s| labelset.matches_sample(s));
+        self.metrics.iter().find(|s| labelset.matches_sample(s))
     }
 
     pub fn get_sample_by_labelset_mut(
         &mut self,
         labelset: &LabelSet,
     ) -> Option<&mut Sample<ValueType>> {
-        return self.metrics.iter_mut().find(|s| labelset.matches_sample(s));
+        self.metrics.iter_mut().find(|s| labelset.matches_sample(s))
     }
 
     pub fn set_label(&mut self, label_name: &str, label_value: &str) -> Result<(), ParseError> {
@@ -264,6 +409,87 @@ where
         Ok(())
     }
 
+    /// Renames a label on every sample in the family, keeping the label names sorted
+    pub fn rename_label(&mut self, from: &str, to: &str) -> Result<(), ParseError> {
+        if from == to {
+            return Ok(());
+        }
+
+        if self.label_names.iter().any(|n| n == to) {
+            return Err(ParseError::InvalidMetric(format!(
+                "Label `{}` already exists in metric family",
+                to
+            )));
+        }
+
+        let mut label_names = self.label_names.as_ref().clone();
+        match label_names.iter_mut().find(|n| *n == from) {
+            Some(name) => *name = to.to_owned(),
+            None => {
+                return Err(ParseError::InvalidMetric(format!(
+                    "No label `{}` in metric family",
+                    from
+                )))
+            }
+        }
+
+        self.replace_label_names(label_names);
+
+        Ok(())
+    }
+
+    /// Escapes the family's name and label names with `scheme`, e.g. for a consumer that only
+    /// understands legacy names
+    pub fn escape_names(&mut self, scheme: EscapingScheme) -> Result<(), ParseError> {
+        self.rename_with(|name| scheme.escape_name(name).into_owned())
+    }
+
+    /// Reverses `escape_names`, e.g. for an exposition that was served with `escaping=values`
+    pub fn unescape_names(&mut self, scheme: EscapingScheme) -> Result<(), ParseError> {
+        self.rename_with(|name| scheme.unescape_name(name).into_owned())
+    }
+
+    /// Renames the family and all of its labels at once
+    fn rename_with<F: Fn(&str) -> String>(&mut self, f: F) -> Result<(), ParseError> {
+        let label_names: Vec<String> = self.label_names.iter().map(|name| f(name)).collect();
+        let mut sorted: Vec<&String> = label_names.iter().collect();
+        sorted.sort();
+        if let Some(pair) = sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(ParseError::InvalidMetric(format!(
+                "Label `{}` already exists in metric family",
+                pair[0]
+            )));
+        }
+
+        self.family_name = f(&self.family_name);
+        self.replace_label_names(label_names);
+
+        Ok(())
+    }
+
+    /// Swaps in new names for the family's labels (given in the current order), keeping them sorted
+    fn replace_label_names(&mut self, label_names: Vec<String>) {
+        let mut order: Vec<usize> = (0..label_names.len()).collect();
+        order.sort_by(|&a, &b| label_names[a].cmp(&label_names[b]));
+
+        self.label_names = Arc::new(order.iter().map(|&i| label_names[i].clone()).collect());
+        for metric in self.metrics.iter_mut() {
+            metric.label_values = order
+                .iter()
+                .map(|&i| metric.label_values[i].clone())
+                .collect();
+            metric.set_label_names(self.label_names.clone());
+        }
+    }
+
+    /// Keeps only the samples for which `f` returns true
+    pub fn retain_samples<F>(&mut self, f: F)
+    where
+        F: FnMut(&Sample<ValueType>) -> bool,
+    {
+        self.metrics.retain(f);
+    }
+
     pub fn add_sample(&mut self, mut s: Sample<ValueType>) -> Result<(), ParseError> {
         if s.label_values.len() != self.label_names.len() {
             return Err(ParseError::InvalidMetric(format!(
@@ -287,50 +513,92 @@ where
     }
 }
 
+/// Renders the family as valid exposition text in its own format: its descriptors, then all
+/// of its samples. This can be used to render families one at a time, without building a whole
+/// `MetricsExposition` (an OpenMetrics exposition still needs a `# EOF` after the last one)
+/// ```
+/// use openmetrics_parser::{MetricFamily, MetricNumber, PrometheusType, PrometheusValue, Sample};
+///
+/// let family = MetricFamily::new(
+///     String::from("temperature"),
+///     vec![String::from("room")],
+///     PrometheusType::Gauge,
+///     String::from("The temperature"),
+///     String::new(),
+/// )
+/// .with_samples(vec![Sample::new(
+///     vec![String::from("kitchen")],
+///     None,
+///     PrometheusValue::Gauge(MetricNumber::Float(21.5)),
+/// )])
+/// .unwrap();
+///
+/// assert_eq!(
+///     family.to_string(),
+///     "# HELP temperature The temperature\n# TYPE temperature gauge\ntemperature{room=\"kitchen\"} 21.5\n"
+/// );
+/// ```
 impl<TypeSet, ValueType> fmt::Display for MetricFamily<TypeSet, ValueType>
 where
-    TypeSet: fmt::Display + Default + PartialEq,
+    TypeSet: fmt::Display + Default + PartialEq + ExpositionTypeSet,
     ValueType: RenderableMetricValue + Clone,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         if !self.help.is_empty() {
-            writeln!(f, "# HELP {} {}", self.family_name, self.help)?;
+            writeln!(
+                f,
+                "# HELP {} {}",
+                self.family_name,
+                escape_help(&self.help, TypeSet::FORMAT)
+            )?;
         }
 
         if self.family_type != <TypeSet>::default() {
             writeln!(f, "# TYPE {} {}", self.family_name, self.family_type)?;
         }
 
-        if !self.unit.is_empty() {
+        // The Prometheus format has no UNIT descriptor
+        if !self.unit.is_empty() && TypeSet::FORMAT == ExpositionFormat::OpenMetrics {
             writeln!(f, "# UNIT {} {}", self.family_name, self.unit)?;
         }
 
         let label_names: Vec<&str> = self.label_names.iter().map(|s| s.as_str()).collect();
 
         for metric in self.metrics.iter() {
-            metric.render(f, &self.family_name, &label_names)?;
+            metric.render(
+                f,
+                &self.family_name,
+                &label_names,
+                TypeSet::FORMAT.timestamp_unit(),
+            )?;
         }
 
         Ok(())
     }
 }
 
-/// Exposition is the top level object of the parser. It's a collection of metric families, indexed by name
+/// Exposition is the top level object of the parser. It's a collection of metric families, indexed by name,
+/// kept in the order they were parsed (or inserted) in
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(serialize = "MetricFamily<TypeSet, ValueType>: serde::Serialize"))
+)]
 pub struct MetricsExposition<TypeSet, ValueType> {
-    pub families: HashMap<String, MetricFamily<TypeSet, ValueType>>,
+    pub families: OrderedMap<MetricFamily<TypeSet, ValueType>>,
 }
 
 impl<TypeSet, ValueType> fmt::Display for MetricsExposition<TypeSet, ValueType>
 where
-    TypeSet: fmt::Display + Default + PartialEq,
+    TypeSet: fmt::Display + Default + PartialEq + ExpositionTypeSet,
     ValueType: RenderableMetricValue + Clone,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for (i, (_, family)) in self.families.iter().enumerate() {
+        for (i, family) in self.families.values().enumerate() {
             write!(f, "{}", family)?;
             if i != self.families.len() - 1 {
-                write!(f, "\n")?;
+                writeln!(f)?;
             }
         }
 
@@ -347,12 +615,248 @@ impl<TypeSet, ValueType> Default for MetricsExposition<TypeSet, ValueType> {
 impl<TypeSet, ValueType> MetricsExposition<TypeSet, ValueType> {
     pub fn new() -> MetricsExposition<TypeSet, ValueType> {
         MetricsExposition {
-            families: HashMap::new(),
+            families: OrderedMap::new(),
+        }
+    }
+
+    /// The number of families in the exposition
+    pub fn len(&self) -> usize {
+        self.families.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.families.is_empty()
+    }
+
+    /// Iterates over the families by name, in the order they were written
+    /// ```
+    /// use openmetrics_parser::prometheus::parse_prometheus;
+    ///
+    /// let parsed = parse_prometheus("# TYPE b gauge\nb 1\n# TYPE a gauge\na 2\n").unwrap();
+    /// let names: Vec<_> = parsed.iter().map(|(name, _)| name.as_str()).collect();
+    /// assert_eq!(names, ["b", "a"]);
+    /// ```
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &MetricFamily<TypeSet, ValueType>)> {
+        self.families.iter()
+    }
+
+    pub fn iter_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (&String, &mut MetricFamily<TypeSet, ValueType>)> {
+        self.families.iter_mut()
+    }
+}
+
+impl<TypeSet, ValueType> IntoIterator for MetricsExposition<TypeSet, ValueType> {
+    type Item = (String, MetricFamily<TypeSet, ValueType>);
+    type IntoIter = <OrderedMap<MetricFamily<TypeSet, ValueType>> as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.families.into_iter()
+    }
+}
+
+impl<'a, TypeSet, ValueType> IntoIterator for &'a MetricsExposition<TypeSet, ValueType> {
+    type Item = (&'a String, &'a MetricFamily<TypeSet, ValueType>);
+    type IntoIter = <&'a OrderedMap<MetricFamily<TypeSet, ValueType>> as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        (&self.families).into_iter()
+    }
+}
+
+impl<'a, TypeSet, ValueType> IntoIterator for &'a mut MetricsExposition<TypeSet, ValueType> {
+    type Item = (&'a String, &'a mut MetricFamily<TypeSet, ValueType>);
+    type IntoIter =
+        <&'a mut OrderedMap<MetricFamily<TypeSet, ValueType>> as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        (&mut self.families).into_iter()
+    }
+}
+
+impl<TypeSet, ValueType> MetricsExposition<TypeSet, ValueType>
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: RenderableMetricValue + Clone,
+{
+    /// Prepends `prefix` to the name of every family. Suffixed series (e.g. `_total`, `_bucket`)
+    /// are derived from the family name when rendering so follow along, and the state label of a
+    /// StateSet (which must share the family name) is renamed with it. On error, the exposition
+    /// is left as it was
+    pub fn prefix_families(&mut self, prefix: &str) -> Result<(), ParseError> {
+        let mut families = OrderedMap::with_capacity(self.families.len());
+        for (name, family) in self.families.iter() {
+            let prefixed = format!("{}{}", prefix, name);
+            let mut family = family.clone();
+            if family.family_type.is_stateset() {
+                family.rename_label(name, &prefixed)?;
+            }
+
+            family.family_name = prefixed.clone();
+            families.insert(prefixed, family);
+        }
+
+        self.families = families;
+
+        Ok(())
+    }
+
+    /// Escapes the name and label names of every family with `scheme` (see
+    /// `MetricFamily::escape_names`)
+    pub fn escape_names(&mut self, scheme: EscapingScheme) -> Result<(), ParseError> {
+        self.rename_families(|family| family.escape_names(scheme))
+    }
+
+    /// Reverses `escape_names`
+    pub fn unescape_names(&mut self, scheme: EscapingScheme) -> Result<(), ParseError> {
+        self.rename_families(|family| family.unescape_names(scheme))
+    }
+
+    /// Renames every family with `f`, only swapping the renamed families in if all of them succeed
+    fn rename_families<F>(&mut self, f: F) -> Result<(), ParseError>
+    where
+        F: Fn(&mut MetricFamily<TypeSet, ValueType>) -> Result<(), ParseError>,
+    {
+        let mut families = OrderedMap::with_capacity(self.families.len());
+        for family in self.families.values() {
+            let mut family = family.clone();
+            f(&mut family)?;
+            if families.contains_key(&family.family_name) {
+                return Err(ParseError::InvalidMetric(format!(
+                    "Found two metric families called {}",
+                    family.family_name
+                )));
+            }
+
+            families.insert(family.family_name.clone(), family);
+        }
+
+        self.families = families;
+
+        Ok(())
+    }
+}
+
+/// How `fill_timestamps` treats the existing timestamps of samples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FillPolicy {
+    /// Only samples without a timestamp are stamped
+    Missing,
+
+    /// Every sample is stamped, replacing any timestamp it already had
+    Overwrite,
+
+    /// Every timestamp is removed (the timestamp given is ignored)
+    Clear,
+}
+
+impl<TypeSet, ValueType> MetricFamily<TypeSet, ValueType>
+where
+    TypeSet: Clone,
+    ValueType: RenderableMetricValue + Clone,
+{
+    /// Stamps the samples of the family according to `policy`. Every policy leaves either all
+    /// or none of the samples with a timestamp, as a family requires
+    pub fn fill_timestamps(&mut self, timestamp: Timestamp, policy: FillPolicy) {
+        for sample in self.iter_samples_mut() {
+            match policy {
+                FillPolicy::Missing => {
+                    sample.timestamp.get_or_insert(timestamp);
+                }
+                FillPolicy::Overwrite => sample.timestamp = Some(timestamp),
+                FillPolicy::Clear => sample.timestamp = None,
+            }
         }
     }
 }
 
+impl<TypeSet, ValueType> MetricFamily<TypeSet, ValueType>
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: RenderableMetricValue + Clone,
+{
+    /// The timestamp of one of the family's samples in the given unit, whichever unit the
+    /// family's format keeps it in
+    /// ```
+    /// use openmetrics_parser::{prometheus::parse_prometheus, TimestampUnit};
+    ///
+    /// let exposition = parse_prometheus("foo 1 1500\n").unwrap();
+    /// let family = &exposition.families["foo"];
+    /// let sample = family.iter_samples().next().unwrap();
+    /// assert_eq!(sample.timestamp, Some(1500.));
+    /// assert_eq!(family.sample_timestamp(sample, TimestampUnit::Seconds), Some(1.5));
+    /// ```
+    pub fn sample_timestamp(
+        &self,
+        sample: &Sample<ValueType>,
+        unit: TimestampUnit,
+    ) -> Option<Timestamp> {
+        let from = TypeSet::FORMAT.timestamp_unit();
+        sample.timestamp.map(|t| from.convert(t, unit))
+    }
+
+    /// The timestamp of one of the family's samples in nanoseconds since the epoch (see
+    /// `Sample::timestamp_nanos`)
+    /// ```
+    /// use openmetrics_parser::openmetrics::parse_openmetrics;
+    ///
+    /// let exposition = "# TYPE foo gauge\nfoo 1 1700000000.123456789\n# EOF\n";
+    /// let exposition = parse_openmetrics(exposition).unwrap();
+    /// let family = &exposition.families["foo"];
+    /// let sample = family.iter_samples().next().unwrap();
+    /// assert_eq!(family.sample_timestamp_nanos(sample), Some(1_700_000_000_123_456_789));
+    /// ```
+    pub fn sample_timestamp_nanos(&self, sample: &Sample<ValueType>) -> Option<i64> {
+        sample.timestamp_nanos(TypeSet::FORMAT.timestamp_unit())
+    }
+}
+
+impl<TypeSet, ValueType> MetricsExposition<TypeSet, ValueType>
+where
+    TypeSet: Clone,
+    ValueType: RenderableMetricValue + Clone,
+{
+    /// Stamps the samples of every family according to `policy`, e.g. to assign the scrape
+    /// time to an exposition before sending it to a backend that requires explicit timestamps
+    /// ```
+    /// use openmetrics_parser::{prometheus::parse_prometheus, FillPolicy};
+    ///
+    /// let mut exposition = parse_prometheus("# TYPE up gauge\nup 1\n").unwrap();
+    /// exposition.fill_timestamps(1000., FillPolicy::Missing);
+    /// assert_eq!(exposition.to_string(), "# TYPE up gauge\nup 1 1000\n");
+    /// ```
+    pub fn fill_timestamps(&mut self, timestamp: Timestamp, policy: FillPolicy) {
+        for family in self.families.values_mut() {
+            family.fill_timestamps(timestamp, policy);
+        }
+    }
+}
+
+/// Adapts a rendering closure into something that can be `Display`ed
+struct RenderFn<F>(F);
+
+impl<F> fmt::Display for RenderFn<F>
+where
+    F: Fn(&mut fmt::Formatter<'_>) -> fmt::Result,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (self.0)(f)
+    }
+}
+
+/// Exemplars always have a labelset, even if it's empty
+fn render_exemplar_labels(label_names: &[&str], label_values: &[&str]) -> String {
+    if label_names.is_empty() {
+        return String::from("{}");
+    }
+
+    render_label_values(label_names, label_values)
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CounterValue {
     pub value: MetricNumber,
     pub created: Option<Timestamp>,
@@ -371,10 +875,68 @@ fn format_float(f: f64) -> String {
     }
 }
 
+/// Formats a float sample value so that it reads back as a float: integral values keep a `.0`
+/// (so `5.0` doesn't turn into the int `5`), and very large or small values use an exponent
+/// (e.g. `1e21`, `1.5e-7`). Rendering the result of parsing this gives back the same text
+fn format_float_value(f: f64) -> String {
+    if !f.is_finite() {
+        return format_float(f);
+    }
+
+    let magnitude = f.abs();
+    if magnitude != 0. && !(1e-4..1e16).contains(&magnitude) {
+        return format!("{:e}", f);
+    }
+
+    let formatted = format!("{}", f);
+    if formatted.contains('.') {
+        formatted
+    } else {
+        formatted + ".0"
+    }
+}
+
+/// The ` <timestamp>` suffix of a sample line, if it has one
+fn format_timestamp(timestamp: Option<&str>) -> String {
+    timestamp.map(|t| format!(" {}", t)).unwrap_or_default()
+}
+
+/// Formats a histogram bucket bound or summary quantile in its canonical form, which matches
+/// the output of the Go client libraries (`strconv.FormatFloat(f, 'g', -1, 64)`), e.g.
+/// `0.005`, `1`, `1e-05`, `1e+21` and `+Inf`
+pub fn format_bound(f: f64) -> String {
+    if !f.is_finite() {
+        return format_float(f);
+    }
+
+    let scientific = format!("{:e}", f);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    if !(-4..21).contains(&exponent) {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+    } else {
+        format_float(f)
+    }
+}
+
+/// Renders a bucket bound or quantile as it was parsed, if it still holds the same value
+fn render_bound(bound: f64, raw: Option<&str>) -> String {
+    match raw {
+        Some(raw) if raw.parse::<f64>().ok() == Some(bound) => raw.to_string(),
+        _ => format_bound(bound),
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HistogramBucket {
     pub count: MetricNumber,
+    #[cfg_attr(feature = "serde", serde(with = "crate::public::serialization::float"))]
     pub upper_bound: f64,
+    /// The `le` label exactly as it was parsed (e.g. `1.0` rather than `1`), so rendering doesn't
+    /// change the identity of the series. Ignored if it no longer matches `upper_bound`
+    pub raw_upper_bound: Option<String>,
     pub exemplar: Option<Exemplar>,
 }
 
@@ -383,11 +945,11 @@ impl RenderableMetricValue for HistogramBucket {
         &self,
         f: &mut fmt::Formatter<'_>,
         metric_name: &str,
-        _: Option<&Timestamp>,
+        timestamp: Option<&str>,
         label_names: &[&str],
         label_values: &[&str],
     ) -> fmt::Result {
-        let upper_bound_str = format_float(self.upper_bound);
+        let upper_bound_str = render_bound(self.upper_bound, self.raw_upper_bound.as_deref());
         let label_names = {
             let mut names = Vec::from(label_names);
             names.push("le");
@@ -402,10 +964,11 @@ impl RenderableMetricValue for HistogramBucket {
 
         write!(
             f,
-            "{}_bucket{} {}",
+            "{}_bucket{} {}{}",
             metric_name,
             render_label_values(&label_names, &label_values),
-            self.count
+            self.count,
+            format_timestamp(timestamp)
         )?;
 
         if let Some(ex) = self.exemplar.as_ref() {
@@ -419,11 +982,123 @@ impl RenderableMetricValue for HistogramBucket {
 }
 
 #[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct HistogramValue {
     pub sum: Option<MetricNumber>,
     pub count: Option<u64>,
     pub created: Option<Timestamp>,
     pub buckets: Vec<HistogramBucket>,
+
+    /// The exponential buckets of a native histogram, alongside (or instead of) the classic ones.
+    /// Only the protobuf formats carry these, so they're left out when rendering text
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub native: Option<NativeHistogram>,
+}
+
+impl HistogramValue {
+    pub fn builder() -> HistogramValueBuilder {
+        HistogramValueBuilder::default()
+    }
+
+    /// Records a single observation: every bucket whose upper bound is at least `value` is
+    /// incremented, along with the count and sum
+    pub fn observe_into_bucket(&mut self, value: f64) {
+        for bucket in self.buckets.iter_mut() {
+            if value <= bucket.upper_bound {
+                bucket.count += MetricNumber::Int(1);
+            }
+        }
+
+        self.count = Some(self.count.unwrap_or_default() + 1);
+        self.sum = Some(self.sum.unwrap_or(MetricNumber::Int(0)) + MetricNumber::Float(value));
+    }
+
+    /// Checks the rules that the OpenMetrics spec places on histogram values. GaugeHistograms
+    /// follow the same rules, except that they may have a negative sum
+    pub(crate) fn check_invariants(&self, gauge_histogram: bool) -> Result<(), ParseError> {
+        self.check_invariants_with_options(
+            gauge_histogram,
+            &ParserOptions::default(),
+            &mut Vec::new(),
+        )
+    }
+
+    /// Checks the same rules as `check_invariants`, but the cumulativity of the buckets and the
+    /// pairing of the sum and count are enforced as strictly as `options` asks, with any broken
+    /// rule that's only warned about pushed to `warnings`
+    pub(crate) fn check_invariants_with_options(
+        &self,
+        gauge_histogram: bool,
+        options: &ParserOptions,
+        warnings: &mut Vec<ParseError>,
+    ) -> Result<(), ParseError> {
+        let invalid = |reason: String| ParseError::InvalidHistogram {
+            reason,
+            family: String::new(),
+            labels: Vec::new(),
+        };
+
+        if self.buckets.is_empty() {
+            return Err(invalid(
+                "Histograms must have at least one bucket".to_owned(),
+            ));
+        }
+
+        if !self.buckets.iter().any(|b| b.upper_bound == f64::INFINITY) {
+            return Err(invalid(format!(
+                "Histograms must have a +INF bucket: {:?}",
+                self.buckets
+            )));
+        }
+
+        let has_negative_bucket = self.buckets.iter().any(|f| f.upper_bound < 0.);
+
+        match self.sum {
+            Some(_) if has_negative_bucket && !gauge_histogram => {
+                return Err(invalid(
+                    "Histograms cannot have a sum with a negative bucket".to_owned(),
+                ));
+            }
+            Some(sum) if !has_negative_bucket && sum.as_f64() < 0. => {
+                return Err(invalid(
+                    "Histograms cannot have a negative sum without a negative bucket".to_owned(),
+                ));
+            }
+            _ => {}
+        }
+
+        if self.sum.is_some() && self.count.is_none() {
+            options.sum_count_pairing.enforce(
+                invalid("Count must be present if sum is present".to_owned()),
+                warnings,
+            )?;
+        }
+
+        if self.sum.is_none() && self.count.is_some() {
+            options.sum_count_pairing.enforce(
+                invalid("Sum must be present if count is present".to_owned()),
+                warnings,
+            )?;
+        }
+
+        let mut last = f64::NEG_INFINITY;
+        for bucket in self.buckets.iter() {
+            if bucket.count.as_f64() < last {
+                return options.histogram_cumulativity.enforce(
+                    invalid("Histograms must be cumulative".to_owned()),
+                    warnings,
+                );
+            }
+
+            last = bucket.count.as_f64();
+        }
+
+        Ok(())
+    }
 }
 
 impl RenderableMetricValue for HistogramValue {
@@ -431,26 +1106,65 @@ impl RenderableMetricValue for HistogramValue {
         &self,
         f: &mut fmt::Formatter<'_>,
         metric_name: &str,
-        timestamp: Option<&Timestamp>,
+        timestamp: Option<&str>,
         label_names: &[&str],
         label_values: &[&str],
+    ) -> fmt::Result {
+        self.render_with_suffixes(
+            f,
+            metric_name,
+            timestamp,
+            label_names,
+            label_values,
+            ("_sum", "_count"),
+        )
+    }
+}
+
+impl HistogramValue {
+    /// Renders the histogram with the given sum and count suffixes, as GaugeHistograms use
+    /// `_gsum` and `_gcount` instead of `_sum` and `_count`
+    fn render_with_suffixes(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        metric_name: &str,
+        timestamp: Option<&str>,
+        label_names: &[&str],
+        label_values: &[&str],
+        (sum_suffix, count_suffix): (&str, &str),
     ) -> fmt::Result {
         for bucket in self.buckets.iter() {
             bucket.render(f, metric_name, timestamp, label_names, label_values)?;
         }
 
         let labels = render_label_values(label_names, label_values);
+        let timestamp = format_timestamp(timestamp);
 
         if let Some(s) = self.sum {
-            writeln!(f, "{}_sum{} {}", metric_name, labels, s)?;
+            writeln!(
+                f,
+                "{}{}{} {}{}",
+                metric_name, sum_suffix, labels, s, timestamp
+            )?;
         }
 
         if let Some(c) = self.count {
-            writeln!(f, "{}_count{} {}", metric_name, labels, c)?;
+            writeln!(
+                f,
+                "{}{}{} {}{}",
+                metric_name, count_suffix, labels, c, timestamp
+            )?;
         }
 
         if let Some(c) = self.created {
-            writeln!(f, "{}_created{} {}", metric_name, labels, c)?;
+            writeln!(
+                f,
+                "{}_created{} {}{}",
+                metric_name,
+                labels,
+                format_float(c),
+                timestamp
+            )?;
         }
 
         Ok(())
@@ -458,14 +1172,19 @@ impl RenderableMetricValue for HistogramValue {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct State {
     pub name: String,
     pub enabled: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Quantile {
+    #[cfg_attr(feature = "serde", serde(with = "crate::public::serialization::float"))]
     pub quantile: f64,
+    /// The `quantile` label exactly as it was parsed. Ignored if it no longer matches `quantile`
+    pub raw_quantile: Option<String>,
     pub value: MetricNumber,
 }
 
@@ -474,11 +1193,11 @@ impl RenderableMetricValue for Quantile {
         &self,
         f: &mut fmt::Formatter<'_>,
         metric_name: &str,
-        _: Option<&Timestamp>,
+        timestamp: Option<&str>,
         label_names: &[&str],
         label_values: &[&str],
     ) -> fmt::Result {
-        let quantile_str = format_float(self.quantile);
+        let quantile_str = render_bound(self.quantile, self.raw_quantile.as_deref());
         let label_names = {
             let mut names = Vec::from(label_names);
             names.push("quantile");
@@ -493,15 +1212,18 @@ impl RenderableMetricValue for Quantile {
 
         writeln!(
             f,
-            "{}{} {}",
+            "{}{} {}{}",
             metric_name,
             render_label_values(&label_names, &label_values),
-            self.value
+            self.value,
+            format_timestamp(timestamp)
         )
     }
 }
 
 #[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SummaryValue {
     pub sum: Option<MetricNumber>,
     pub count: Option<u64>,
@@ -509,12 +1231,73 @@ pub struct SummaryValue {
     pub quantiles: Vec<Quantile>,
 }
 
+impl SummaryValue {
+    pub fn builder() -> SummaryValueBuilder {
+        SummaryValueBuilder::default()
+    }
+
+    /// Sets the value of the given quantile, adding it if it doesn't exist yet
+    pub fn set_quantile(&mut self, quantile: f64, value: MetricNumber) {
+        match self.quantiles.iter_mut().find(|q| q.quantile == quantile) {
+            Some(existing) => existing.value = value,
+            None => self.quantiles.push(Quantile {
+                quantile,
+                raw_quantile: None,
+                value,
+            }),
+        }
+    }
+
+    /// Checks the rules that the OpenMetrics spec places on summary values
+    pub(crate) fn check_invariants(&self) -> Result<(), ParseError> {
+        let invalid = |reason: String| ParseError::InvalidSummary {
+            reason,
+            family: String::new(),
+            labels: Vec::new(),
+        };
+
+        if let Some(sum) = self.sum {
+            if sum.as_f64() < 0. || sum.as_f64().is_nan() {
+                return Err(invalid(format!(
+                    "Summary sums must be non negative (got: {})",
+                    sum
+                )));
+            }
+        }
+
+        for (i, quantile) in self.quantiles.iter().enumerate() {
+            if !(0. ..=1.).contains(&quantile.quantile) {
+                return Err(invalid(format!(
+                    "Summary bounds must be between 0 and 1 (got: {})",
+                    quantile.quantile
+                )));
+            }
+
+            if quantile.value.as_f64() < 0. {
+                return Err(invalid("Summary quantiles can't be negative".to_owned()));
+            }
+
+            if self.quantiles[..i]
+                .iter()
+                .any(|q| q.quantile == quantile.quantile)
+            {
+                return Err(invalid(format!(
+                    "Found quantile {} twice in the same summary",
+                    format_bound(quantile.quantile)
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl RenderableMetricValue for SummaryValue {
     fn render(
         &self,
         f: &mut fmt::Formatter<'_>,
         metric_name: &str,
-        timestamp: Option<&Timestamp>,
+        timestamp: Option<&str>,
         label_names: &[&str],
         label_values: &[&str],
     ) -> fmt::Result {
@@ -523,24 +1306,33 @@ impl RenderableMetricValue for SummaryValue {
         }
 
         let labels = render_label_values(label_names, label_values);
+        let timestamp = format_timestamp(timestamp);
 
         if let Some(s) = self.sum {
-            writeln!(f, "{}_sum{} {}", metric_name, labels, s)?;
+            writeln!(f, "{}_sum{} {}{}", metric_name, labels, s, timestamp)?;
         }
 
         if let Some(s) = self.count {
-            writeln!(f, "{}_count{} {}", metric_name, labels, s)?;
+            writeln!(f, "{}_count{} {}{}", metric_name, labels, s, timestamp)?;
         }
 
         if let Some(s) = self.created {
-            writeln!(f, "{}_created{} {}", metric_name, labels, s)?;
+            writeln!(
+                f,
+                "{}_created{} {}{}",
+                metric_name,
+                labels,
+                format_float(s),
+                timestamp
+            )?;
         }
 
         Ok(())
     }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OpenMetricsType {
     /// A Counter that only goes up
     /// Counters measure discrete events. Common examples are the number of HTTP requests received,
@@ -550,6 +1342,7 @@ pub enum OpenMetricsType {
     /// A MetricPoint in a Metric with the type Counter SHOULD have a Timestamp value called Created. This can help ingestors discern between new metrics and long-running ones it did not see before.
     /// A MetricPoint in a Metric's Counter's Total MAY reset to 0. If present, the corresponding Created time MUST also be set to the timestamp of the reset.
     /// A MetricPoint in a Metric's Counter's Total MAY have an exemplar.
+    #[cfg_attr(feature = "serde", serde(alias = "counter"))]
     Counter,
 
     /// A Gauge that can go up or down
@@ -561,6 +1354,7 @@ pub enum OpenMetricsType {
     /// they might still be gauges and not counters. The size of a log file would usually only increase,
     /// a resource might decrease, and the limit of a queue size may be constant.
     /// A gauge MAY be used to encode an enum where the enum has many states and changes over time, it is the most efficient but least user friendly.
+    #[cfg_attr(feature = "serde", serde(alias = "gauge"))]
     Gauge,
 
     /// A Histogram that has a number of buckets that count events, and a _sum and _count
@@ -579,6 +1373,7 @@ pub enum OpenMetricsType {
     /// metrics and long-running ones it did not see before.
     /// A Histogram's Metric's LabelSet MUST NOT have a "le" label name.
     /// Bucket values MAY have exemplars. Buckets are cumulative to allow monitoring systems to drop any non-+Inf bucket for performance/anti-denial-of-service reasons in a way that loses granularity but is still a valid Histogram.
+    #[cfg_attr(feature = "serde", serde(alias = "histogram"))]
     Histogram,
 
     /// GaugeHistograms measure current distributions. Common examples are how long items have been waiting in a queue, or size of the requests in a queue.
@@ -591,6 +1386,7 @@ pub enum OpenMetricsType {
     /// Bucket values can have exemplars.
     /// Each bucket covers the values less and or equal to it, and the value of the exemplar MUST be within this range. E
     /// Exemplars SHOULD be put into the bucket with the highest value. A bucket MUST NOT have more than one exemplar.
+    #[cfg_attr(feature = "serde", serde(alias = "gaugehistogram"))]
     GaugeHistogram,
 
     /// StateSets represent a series of related boolean values, also called a bitset. If ENUMs need to be encoded this MAY be done via StateSet.
@@ -598,6 +1394,7 @@ pub enum OpenMetricsType {
     /// A StateSet Metric's LabelSet MUST NOT have a label name which is the same as the name of its MetricFamily.
     /// If encoded as a StateSet, ENUMs MUST have exactly one Boolean which is true within a MetricPoint.
     /// This is suitable where the enum value changes over time, and the number of States isn't much more than a handful.
+    #[cfg_attr(feature = "serde", serde(alias = "stateset"))]
     StateSet,
 
     /// Summaries also measure distributions of discrete events and MAY be used when Histograms are too expensive and/or an average event size is sufficient.
@@ -614,6 +1411,7 @@ pub enum OpenMetricsType {
     /// If there are no events in the relevant timeframe, the value for a quantile MUST be NaN.
     /// A Quantile's Metric's LabelSet MUST NOT have "quantile" label name. Quantiles MUST be between 0 and 1 inclusive.
     /// Quantile values MUST NOT be negative. Quantile values SHOULD represent the recent values. Commonly this would be over the last 5-10 minutes.
+    #[cfg_attr(feature = "serde", serde(alias = "summary"))]
     Summary,
 
     /// Info metrics are used to expose textual information which SHOULD NOT change during process lifetime.
@@ -622,38 +1420,115 @@ pub enum OpenMetricsType {
     /// is the same as the name of a label of the LabelSet of its Metric.
     /// Info MAY be used to encode ENUMs whose values do not change over time, such as the type of a network interface.
     /// MetricFamilies of type Info MUST have an empty Unit string.
+    #[cfg_attr(feature = "serde", serde(alias = "info"))]
     Info,
 
     /// Unknown SHOULD NOT be used. Unknown MAY be used when it is impossible to determine the types of individual metrics from 3rd party systems.
     /// A point in a metric with the unknown type MUST have a single value.
+    #[default]
+    #[cfg_attr(feature = "serde", serde(alias = "unknown"))]
     Unknown,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OpenMetricsValue {
+    #[cfg_attr(feature = "serde", serde(alias = "untyped"))]
     Untyped(MetricNumber),
+    #[cfg_attr(feature = "serde", serde(alias = "unknown"))]
     Unknown(MetricNumber),
+    #[cfg_attr(feature = "serde", serde(alias = "gauge"))]
     Gauge(MetricNumber),
+    #[cfg_attr(feature = "serde", serde(alias = "counter"))]
     Counter(CounterValue),
+    #[cfg_attr(feature = "serde", serde(alias = "histogram"))]
     Histogram(HistogramValue),
+    #[cfg_attr(feature = "serde", serde(alias = "stateset"))]
     StateSet(MetricNumber),
+    #[cfg_attr(feature = "serde", serde(alias = "gaugehistogram"))]
     GaugeHistogram(HistogramValue),
+    #[cfg_attr(feature = "serde", serde(alias = "info"))]
     Info,
+    #[cfg_attr(feature = "serde", serde(alias = "summary"))]
     Summary(SummaryValue),
 }
 
+/// Accessors for when the type of a value is already known, each returning `None` for any other type
+/// ```
+/// use openmetrics_parser::openmetrics::parse_openmetrics;
+///
+/// let parsed = parse_openmetrics("# TYPE foo gauge\nfoo 1.5\n# EOF\n").unwrap();
+/// let value = &parsed.families["foo"].iter_samples().next().unwrap().value;
+/// assert_eq!(value.as_gauge().map(|n| n.as_f64()), Some(1.5));
+/// assert!(value.as_counter().is_none());
+/// ```
+impl OpenMetricsValue {
+    /// The value of an unknown (or untyped) metric
+    pub fn as_unknown(&self) -> Option<MetricNumber> {
+        match self {
+            OpenMetricsValue::Unknown(n) | OpenMetricsValue::Untyped(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_gauge(&self) -> Option<MetricNumber> {
+        match self {
+            OpenMetricsValue::Gauge(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_counter(&self) -> Option<&CounterValue> {
+        match self {
+            OpenMetricsValue::Counter(c) => Some(c),
+            _ => None,
+        }
+    }
+
+    pub fn as_histogram(&self) -> Option<&HistogramValue> {
+        match self {
+            OpenMetricsValue::Histogram(h) => Some(h),
+            _ => None,
+        }
+    }
+
+    pub fn as_gauge_histogram(&self) -> Option<&HistogramValue> {
+        match self {
+            OpenMetricsValue::GaugeHistogram(h) => Some(h),
+            _ => None,
+        }
+    }
+
+    /// The value of one state of a stateset
+    pub fn as_stateset(&self) -> Option<MetricNumber> {
+        match self {
+            OpenMetricsValue::StateSet(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_summary(&self) -> Option<&SummaryValue> {
+        match self {
+            OpenMetricsValue::Summary(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn is_info(&self) -> bool {
+        matches!(self, OpenMetricsValue::Info)
+    }
+}
+
 impl RenderableMetricValue for OpenMetricsValue {
     fn render(
         &self,
         f: &mut fmt::Formatter<'_>,
         metric_name: &str,
-        timestamp: Option<&Timestamp>,
+        timestamp: Option<&str>,
         label_names: &[&str],
         label_values: &[&str],
     ) -> fmt::Result {
-        let timestamp_str = timestamp
-            .map(|t| format!(" {}", format_float(*t)))
-            .unwrap_or_default();
+        let timestamp_str = format_timestamp(timestamp);
         match self {
             OpenMetricsValue::Unknown(n)
             | OpenMetricsValue::Untyped(n)
@@ -669,31 +1544,49 @@ impl RenderableMetricValue for OpenMetricsValue {
                 )
             }
             OpenMetricsValue::Counter(c) => {
+                let labels = render_label_values(label_names, label_values);
                 write!(
                     f,
-                    "{}{} {}{}",
-                    metric_name,
-                    render_label_values(label_names, label_values),
-                    c.value,
-                    timestamp_str
+                    "{}_total{} {}{}",
+                    metric_name, labels, c.value, timestamp_str
                 )?;
                 if let Some(ex) = c.exemplar.as_ref() {
                     write!(f, "{}", ex)?;
                 }
 
-                f.write_char('\n')
+                f.write_char('\n')?;
+
+                if let Some(created) = c.created {
+                    writeln!(
+                        f,
+                        "{}_created{} {}{}",
+                        metric_name,
+                        labels,
+                        format_float(created),
+                        timestamp_str
+                    )?;
+                }
+
+                Ok(())
             }
-            OpenMetricsValue::Histogram(h) | OpenMetricsValue::GaugeHistogram(h) => {
-                // TODO: This is actually wrong for GaugeHistograms (they should have _gsum and _gcount), but I'm too lazy to fix this at the moment
+            OpenMetricsValue::Histogram(h) => {
                 h.render(f, metric_name, timestamp, label_names, label_values)
             }
+            OpenMetricsValue::GaugeHistogram(h) => h.render_with_suffixes(
+                f,
+                metric_name,
+                timestamp,
+                label_names,
+                label_values,
+                ("_gsum", "_gcount"),
+            ),
             OpenMetricsValue::Summary(s) => {
                 s.render(f, metric_name, timestamp, label_names, label_values)
             }
             OpenMetricsValue::Info => {
                 writeln!(
                     f,
-                    "{}{} {} {}",
+                    "{}_info{} {}{}",
                     metric_name,
                     render_label_values(label_names, label_values),
                     MetricNumber::Int(1),
@@ -704,13 +1597,21 @@ impl RenderableMetricValue for OpenMetricsValue {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PrometheusType {
+    #[cfg_attr(feature = "serde", serde(alias = "counter"))]
     Counter,
+    #[cfg_attr(feature = "serde", serde(alias = "gauge"))]
     Gauge,
+    #[cfg_attr(feature = "serde", serde(alias = "histogram"))]
     Histogram,
+    #[cfg_attr(feature = "serde", serde(alias = "summary"))]
     Summary,
+    #[default]
+    #[cfg_attr(feature = "serde", serde(alias = "unknown"))]
     Unknown,
+    #[cfg_attr(feature = "serde", serde(alias = "untyped"))]
     Untyped,
 }
 
@@ -730,33 +1631,78 @@ impl fmt::Display for PrometheusType {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PrometheusCounterValue {
     pub value: MetricNumber,
     pub exemplar: Option<Exemplar>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PrometheusValue {
+    #[cfg_attr(feature = "serde", serde(alias = "untyped"))]
     Untyped(MetricNumber),
+    #[cfg_attr(feature = "serde", serde(alias = "unknown"))]
     Unknown(MetricNumber),
+    #[cfg_attr(feature = "serde", serde(alias = "gauge"))]
     Gauge(MetricNumber),
+    #[cfg_attr(feature = "serde", serde(alias = "counter"))]
     Counter(PrometheusCounterValue),
+    #[cfg_attr(feature = "serde", serde(alias = "histogram"))]
     Histogram(HistogramValue),
+    #[cfg_attr(feature = "serde", serde(alias = "summary"))]
     Summary(SummaryValue),
 }
 
+/// Accessors for when the type of a value is already known, each returning `None` for any other type
+impl PrometheusValue {
+    /// The value of an untyped (or unknown) metric
+    pub fn as_untyped(&self) -> Option<MetricNumber> {
+        match self {
+            PrometheusValue::Untyped(n) | PrometheusValue::Unknown(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_gauge(&self) -> Option<MetricNumber> {
+        match self {
+            PrometheusValue::Gauge(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_counter(&self) -> Option<&PrometheusCounterValue> {
+        match self {
+            PrometheusValue::Counter(c) => Some(c),
+            _ => None,
+        }
+    }
+
+    pub fn as_histogram(&self) -> Option<&HistogramValue> {
+        match self {
+            PrometheusValue::Histogram(h) => Some(h),
+            _ => None,
+        }
+    }
+
+    pub fn as_summary(&self) -> Option<&SummaryValue> {
+        match self {
+            PrometheusValue::Summary(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
 impl RenderableMetricValue for PrometheusValue {
     fn render(
         &self,
         f: &mut fmt::Formatter<'_>,
         metric_name: &str,
-        timestamp: Option<&Timestamp>,
+        timestamp: Option<&str>,
         label_names: &[&str],
         label_values: &[&str],
     ) -> fmt::Result {
-        let timestamp_str = timestamp
-            .map(|t| format!(" {}", format_float(*t)))
-            .unwrap_or_default();
+        let timestamp_str = format_timestamp(timestamp);
         match self {
             PrometheusValue::Unknown(n)
             | PrometheusValue::Untyped(n)
@@ -794,36 +1740,119 @@ impl RenderableMetricValue for PrometheusValue {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sample<ValueType> {
+    #[cfg_attr(feature = "serde", serde(skip))]
     label_names: Option<Arc<Vec<String>>>,
-    label_values: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    label_values: LabelValues,
     pub timestamp: Option<Timestamp>,
     pub value: ValueType,
+
+    // The timestamp in nanoseconds as it was parsed (or set), which a float can't always hold,
+    // along with the `timestamp` it was for. It's ignored once `timestamp` has changed
+    #[cfg_attr(feature = "serde", serde(skip))]
+    exact_timestamp: Option<(Timestamp, i64)>,
+
+    /// An exemplar attached to the sample itself, rather than to its value. Only populated
+    /// for gauges, when parsing with `ParserOptions::gauge_exemplars` (experimental)
+    pub exemplar: Option<Exemplar>,
 }
 
 impl<ValueType> Sample<ValueType>
 where
     ValueType: RenderableMetricValue + Clone,
 {
-    pub fn new(label_values: Vec<String>, timestamp: Option<Timestamp>, value: ValueType) -> Self {
+    pub fn new(
+        label_values: impl Into<LabelValues>,
+        timestamp: Option<Timestamp>,
+        value: ValueType,
+    ) -> Self {
         Self {
-            label_values,
+            label_values: label_values.into(),
             timestamp,
             value,
             label_names: None,
+            exact_timestamp: None,
+            exemplar: None,
         }
     }
 
+    pub fn with_exemplar(mut self, exemplar: Option<Exemplar>) -> Self {
+        self.exemplar = exemplar;
+        self
+    }
+
+    /// Sets the timestamp from nanoseconds since the epoch, for a family whose timestamps are in
+    /// `unit`. It's kept exactly, for `timestamp_nanos`
+    pub fn with_timestamp_nanos(mut self, nanos: i64, unit: TimestampUnit) -> Self {
+        let timestamp = TimestampUnit::Nanoseconds.convert(nanos as f64, unit);
+        self.timestamp = Some(timestamp);
+        self.exact_timestamp = Some((timestamp, nanos));
+        self
+    }
+
+    /// Records the exact value of the timestamp, as it was parsed
+    pub(crate) fn with_exact_timestamp(mut self, nanos: Option<i64>) -> Self {
+        self.exact_timestamp = self.timestamp.zip(nanos);
+        self
+    }
+
+    /// The timestamp in nanoseconds since the epoch, given the unit of the family's format (see
+    /// `MetricFamily::sample_timestamp_nanos`, which passes it). Parsed timestamps keep every
+    /// digit down to the nanosecond, even where `timestamp` is too coarse to hold them all
+    pub(crate) fn timestamp_nanos(&self, unit: TimestampUnit) -> Option<i64> {
+        let timestamp = self.timestamp?;
+        match self.exact_timestamp {
+            Some((parsed, nanos)) if parsed == timestamp => Some(nanos),
+            _ => {
+                let nanos = unit.convert(timestamp, TimestampUnit::Nanoseconds);
+                (nanos.abs() < i64::MAX as f64).then_some(nanos as i64)
+            }
+        }
+    }
+
+    pub fn get_label_values(&self) -> &[String] {
+        self.label_values.as_slice()
+    }
+
+    /// The timestamp as it's written out, in `unit`. Parsed timestamps with more digits than
+    /// `timestamp` can hold are written from the exact nanoseconds, so nothing is rounded off
+    fn render_timestamp(&self, unit: TimestampUnit) -> Option<String> {
+        let timestamp = self.timestamp?;
+        let formatted = format_float(timestamp);
+        let nanos = match self.exact_timestamp {
+            Some((parsed, nanos)) if parsed == timestamp => nanos,
+            _ => return Some(formatted),
+        };
+
+        if parse_timestamp_nanos(&formatted, unit) == Some(nanos) {
+            return Some(formatted);
+        }
+
+        let places = (TimestampUnit::Nanoseconds.digits() - unit.digits()) as usize;
+        let scale = 10u64.pow(places as u32);
+        let (sign, magnitude) = (if nanos < 0 { "-" } else { "" }, nanos.unsigned_abs());
+        let fraction = format!("{:0places$}", magnitude % scale, places = places);
+        let fraction = fraction.trim_end_matches('0');
+        Some(match fraction {
+            "" => format!("{}{}", sign, magnitude / scale),
+            fraction => format!("{}{}.{}", sign, magnitude / scale, fraction),
+        })
+    }
+
     fn clone_with_new_value<T>(&self, value: T) -> Sample<T>
     where
         T: RenderableMetricValue + Clone,
     {
-        return Sample {
+        Sample {
             label_names: self.label_names.clone(),
             label_values: self.label_values.clone(),
-            timestamp: self.timestamp.clone(),
+            timestamp: self.timestamp,
             value,
-        };
+            exact_timestamp: self.exact_timestamp,
+            exemplar: self.exemplar.clone(),
+        }
     }
 
     fn set_label_names(&mut self, label_names: Arc<Vec<String>>) {
@@ -836,11 +1865,8 @@ where
                 let mut label_values = self.label_values.clone();
                 label_values.remove(idx);
 
-                return Ok(Self::new(
-                    label_values,
-                    self.timestamp.clone(),
-                    self.value.clone(),
-                ));
+                return Ok(Self::new(label_values, self.timestamp, self.value.clone())
+                    .with_exemplar(self.exemplar.clone()));
             }
 
             return Err(ParseError::InvalidMetric(format!(
@@ -849,12 +1875,12 @@ where
             )));
         }
 
-        return Err(ParseError::InvalidMetric(format!(
-            "Metric isn't bound to a family, so doesn't have names"
-        )));
+        Err(ParseError::InvalidMetric(
+            "Metric isn't bound to a family, so doesn't have names".to_string(),
+        ))
     }
 
-    pub fn get_labelset(&self) -> Result<LabelSet, ParseError> {
+    pub fn get_labelset(&self) -> Result<LabelSet<'_>, ParseError> {
         if let Some(label_names) = &self.label_names {
             return LabelSet::new(label_names.clone(), self);
         }
@@ -870,33 +1896,72 @@ where
         f: &mut fmt::Formatter<'_>,
         metric_name: &str,
         label_names: &[&str],
+        unit: TimestampUnit,
     ) -> fmt::Result {
         let values: Vec<&str> = self.label_values.iter().map(|s| s.as_str()).collect();
-        self.value.render(
-            f,
-            metric_name,
-            self.timestamp.as_ref(),
-            label_names,
-            &values,
-        )
+        let timestamp = self.render_timestamp(unit);
+        let exemplar = match &self.exemplar {
+            Some(exemplar) => exemplar,
+            None => {
+                return self.value.render(
+                    f,
+                    metric_name,
+                    timestamp.as_deref(),
+                    label_names,
+                    &values,
+                )
+            }
+        };
+
+        // Sample level exemplars only exist on single line values (gauges), so the exemplar
+        // is spliced onto the end of that line
+        let line = RenderFn(|f: &mut fmt::Formatter<'_>| {
+            self.value
+                .render(f, metric_name, timestamp.as_deref(), label_names, &values)
+        })
+        .to_string();
+
+        writeln!(f, "{}{}", line.trim_end_matches('\n'), exemplar)
     }
 }
 
+/// A sample value, which keeps whether it was written as an int or a float. Rendering preserves
+/// the distinction (ints never gain a decimal point, and floats always have one or an exponent),
+/// so a parsed value renders the same way however many times it's parsed and rendered again
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub enum MetricNumber {
-    Float(f64),
+    Float(#[cfg_attr(feature = "serde", serde(with = "crate::public::serialization::float"))] f64),
     Int(i64),
 }
 
 impl fmt::Display for MetricNumber {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            MetricNumber::Float(n) => write!(f, "{}", format_float(*n)),
+            MetricNumber::Float(n) => f.write_str(&format_float_value(*n)),
             MetricNumber::Int(n) => write!(f, "{}", n),
         }
     }
 }
 
+impl From<i32> for MetricNumber {
+    fn from(n: i32) -> Self {
+        MetricNumber::Int(n.into())
+    }
+}
+
+impl From<i64> for MetricNumber {
+    fn from(n: i64) -> Self {
+        MetricNumber::Int(n)
+    }
+}
+
+impl From<f64> for MetricNumber {
+    fn from(n: f64) -> Self {
+        MetricNumber::Float(n)
+    }
+}
+
 impl MetricNumber {
     pub fn as_f64(&self) -> f64 {
         match self {
@@ -986,11 +2051,264 @@ impl_op_ex!(/= |a: &mut MetricNumber, b: &MetricNumber| {
     }
 });
 
+/// The kinds of metadata line a family can have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataKind {
+    Help,
+    Type,
+    Unit,
+}
+
+impl fmt::Display for MetadataKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MetadataKind::Help => "help",
+            MetadataKind::Type => "type",
+            MetadataKind::Unit => "unit",
+        })
+    }
+}
+
+/// The resource limits an exposition can be parsed under (see `ParseLimits`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Families,
+    SamplesPerFamily,
+    LabelsPerSample,
+    LabelValueLength,
+    LineLength,
+    TotalBytes,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Families => "families",
+            Limit::SamplesPerFamily => "samples per family",
+            Limit::LabelsPerSample => "labels per sample",
+            Limit::LabelValueLength => "bytes per label value",
+            Limit::LineLength => "bytes per line",
+            Limit::TotalBytes => "bytes in total",
+        })
+    }
+}
+
 #[derive(Debug)]
 pub enum ParseError {
     ParseError(String),
     DuplicateMetric,
+    DuplicateBucket(f64),
     InvalidMetric(String),
+
+    /// Input that doesn't follow the format's grammar, e.g. `expected labelname`. The underlying
+    /// (pest) error is kept as the error's `source`, for the rules it expected and a snippet of
+    /// the input
+    Syntax {
+        message: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// An exposition that couldn't be read from a reader (see
+    /// `openmetrics::parse_openmetrics_from_reader`), including one that isn't valid UTF-8
+    Io(std::io::Error),
+
+    /// A family with two `# HELP`, `# TYPE` or `# UNIT` lines
+    DuplicateMetadata {
+        family: String,
+        kind: MetadataKind,
+    },
+
+    /// A histogram (or gauge histogram) that breaks one of the rules on its buckets, sum, or
+    /// count. `family` is empty, and `labels` too, for histograms checked outside of a family
+    InvalidHistogram {
+        reason: String,
+        family: String,
+        labels: Vec<(String, String)>,
+    },
+
+    /// A counter whose total breaks one of the rules on it, e.g. a negative one. `family` and
+    /// `labels` are empty in the same way as for `InvalidHistogram`
+    InvalidCounter {
+        reason: String,
+        family: String,
+        labels: Vec<(String, String)>,
+    },
+
+    /// A summary that breaks one of the rules on its sum or quantiles
+    InvalidSummary {
+        reason: String,
+        family: String,
+        labels: Vec<(String, String)>,
+    },
+
+    /// A stateset state with a value other than 0 or 1
+    InvalidStateSet {
+        reason: String,
+        family: String,
+        labels: Vec<(String, String)>,
+    },
+
+    /// A metric with a different number of label values than its family has label names
+    LabelSetMismatch {
+        family: String,
+        label_names: Vec<String>,
+        label_values: Vec<String>,
+    },
+
+    /// Binary or JSON input that couldn't be decoded, e.g. a truncated protobuf message.
+    /// `format` says what was being decoded
+    Decode {
+        format: &'static str,
+        reason: String,
+    },
+
+    /// A content type that no decoder is registered for (see `FormatRegistry::decode`)
+    UnknownContentType(String),
+
+    /// An escaping scheme name that isn't one of `allow-utf-8`, `underscores`, `dots` or `values`
+    UnknownEscapingScheme(String),
+
+    /// Samples from the labelset `labels` that came back after the samples from `after`, which
+    /// OpenMetrics doesn't allow (see `ParserOptions::allow_interleaved_labelsets`)
+    InterwovenLabelSets {
+        family: String,
+        labels: Vec<String>,
+        after: Vec<String>,
+    },
+
+    /// An OpenMetrics exposition that ended without a `# EOF`, as truncated expositions do
+    MissingEof,
+
+    /// An exposition that went over one of the limits it was parsed under (see
+    /// `ParserOptions::limits`). Parsing stops at the first one, even when continuing on errors
+    LimitExceeded {
+        limit: Limit,
+        max: usize,
+    },
+
+    /// An error in an exposition read from a file, rendered as e.g.
+    /// `metrics.txt: line 12: Histograms must be cumulative`
+    File {
+        path: std::path::PathBuf,
+        error: Box<ParseError>,
+    },
+
+    /// An error found at a particular place in the input, rendered as e.g.
+    /// `line 12: Histograms must be cumulative`. Lines and columns count from 1
+    Located {
+        line: usize,
+        column: usize,
+        error: Box<ParseError>,
+    },
+}
+
+impl ParseError {
+    /// Attaches the place in the input the error was found at, unless it already has one
+    pub fn at(self, line: usize, column: usize) -> ParseError {
+        match self {
+            ParseError::Located { .. } => self,
+            error => ParseError::Located {
+                line,
+                column,
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// The line the error was found on, if it's known
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            ParseError::Located { line, .. } => Some(*line),
+            ParseError::File { error, .. } => error.line(),
+            _ => None,
+        }
+    }
+
+    /// The column the error was found at, if it's known
+    pub fn column(&self) -> Option<usize> {
+        match self {
+            ParseError::Located { column, .. } => Some(*column),
+            ParseError::File { error, .. } => error.column(),
+            _ => None,
+        }
+    }
+
+    /// The error itself, without its place in the input
+    /// ```
+    /// use openmetrics_parser::ParseError;
+    ///
+    /// let error = ParseError::DuplicateMetric.at(3, 1);
+    /// assert_eq!(error.to_string(), "line 3: Found two metrics with the same labelset");
+    /// assert!(matches!(error.kind(), ParseError::DuplicateMetric));
+    /// ```
+    pub fn kind(&self) -> &ParseError {
+        match self {
+            ParseError::Located { error, .. } | ParseError::File { error, .. } => error.kind(),
+            error => error,
+        }
+    }
+
+    /// Whether the error is one that parsing can't continue past, even when continuing on errors
+    pub(crate) fn is_fatal(&self) -> bool {
+        matches!(self.kind(), ParseError::LimitExceeded { .. })
+    }
+
+    /// Puts the error in the file the exposition was read from
+    pub(crate) fn in_file(self, path: &std::path::Path) -> ParseError {
+        ParseError::File {
+            path: path.to_owned(),
+            error: Box::new(self),
+        }
+    }
+
+    /// Puts an error about a metric's value that was found without any context (e.g. by
+    /// `check_counter_negativity`) in the given family and labelset
+    pub(crate) fn in_metric(mut self, family: &str, labels: &[(String, String)]) -> ParseError {
+        if let ParseError::InvalidHistogram {
+            family: unknown,
+            labels: unknown_labels,
+            ..
+        }
+        | ParseError::InvalidCounter {
+            family: unknown,
+            labels: unknown_labels,
+            ..
+        }
+        | ParseError::InvalidSummary {
+            family: unknown,
+            labels: unknown_labels,
+            ..
+        }
+        | ParseError::InvalidStateSet {
+            family: unknown,
+            labels: unknown_labels,
+            ..
+        } = &mut self
+        {
+            if unknown.is_empty() {
+                *unknown = family.to_owned();
+                *unknown_labels = labels.to_vec();
+            }
+        }
+
+        self
+    }
+
+    /// Moves the error to another line, e.g. to account for lines dropped before parsing
+    pub(crate) fn map_line<F: FnOnce(usize) -> usize>(self, f: F) -> ParseError {
+        match self {
+            ParseError::Located {
+                line,
+                column,
+                error,
+            } => ParseError::Located {
+                line: f(line),
+                column,
+                error,
+            },
+            error => error,
+        }
+    }
 }
 
 impl fmt::Display for ParseError {
@@ -998,11 +2316,159 @@ impl fmt::Display for ParseError {
         match self {
             ParseError::ParseError(e) => e.fmt(f),
             ParseError::DuplicateMetric => f.write_str("Found two metrics with the same labelset"),
+            ParseError::DuplicateBucket(bound) => write!(
+                f,
+                "Found two histogram buckets with the same upper bound ({})",
+                format_float(*bound)
+            ),
             ParseError::InvalidMetric(s) => f.write_str(s),
+            ParseError::Syntax { message, .. } => f.write_str(message),
+            ParseError::Io(e) => write!(f, "Couldn't read the exposition: {}", e),
+            ParseError::DuplicateMetadata { family, kind } => {
+                write!(f, "Got two {} lines in the metric family {}", kind, family)
+            }
+            ParseError::InvalidHistogram {
+                reason,
+                family,
+                labels,
+            }
+            | ParseError::InvalidCounter {
+                reason,
+                family,
+                labels,
+            }
+            | ParseError::InvalidSummary {
+                reason,
+                family,
+                labels,
+            }
+            | ParseError::InvalidStateSet {
+                reason,
+                family,
+                labels,
+            } => {
+                f.write_str(reason)?;
+                if !family.is_empty() {
+                    let (names, values): (Vec<_>, Vec<_>) = labels
+                        .iter()
+                        .map(|(name, value)| (name.as_str(), value.as_str()))
+                        .unzip();
+                    write!(f, " in {}{}", family, render_label_values(&names, &values))?;
+                }
+
+                Ok(())
+            }
+            ParseError::LabelSetMismatch {
+                family,
+                label_names,
+                label_values,
+            } => write!(
+                f,
+                "Metrics in {} have different label sets: expected values for {:?}, got {:?}",
+                family, label_names, label_values
+            ),
+            ParseError::Decode { format, reason } => write!(f, "Invalid {}: {}", format, reason),
+            ParseError::UnknownContentType(content_type) => {
+                write!(f, "No decoder registered for `{}`", content_type)
+            }
+            ParseError::UnknownEscapingScheme(scheme) => {
+                write!(f, "Invalid escaping scheme: {}", scheme)
+            }
+            ParseError::InterwovenLabelSets {
+                family,
+                labels,
+                after,
+            } => write!(
+                f,
+                "Interwoven labelsets in {}: Found {:?} after {:?}",
+                family, labels, after
+            ),
+            ParseError::MissingEof => f.write_str("Didn't find an EOF token"),
+            ParseError::LimitExceeded { limit, max } => {
+                write!(f, "Exceeded the limit of {} {}", max, limit)
+            }
+            ParseError::File { path, error } => write!(f, "{}: {}", path.display(), error),
+            ParseError::Located { line, error, .. } => write!(f, "line {}: {}", line, error),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Syntax { source, .. } => Some(source.as_ref()),
+            ParseError::Io(e) => Some(e),
+            ParseError::Located { error, .. } | ParseError::File { error, .. } => error.source(),
+            _ => None,
         }
     }
 }
 
+/// What a parse noticed about an exposition, besides the exposition itself
+#[derive(Debug, Default)]
+pub struct ParseReport {
+    /// Input that was accepted but broke a rule (see `Strictness::Warn`), or that was dropped or
+    /// ignored along the way (e.g. a second `# HELP`), in the order it was found
+    pub warnings: Vec<ParseError>,
+}
+
+impl ParseReport {
+    /// Whether the exposition was accepted without any warnings
+    pub fn is_clean(&self) -> bool {
+        self.warnings.is_empty()
+    }
+}
+
+/// A sample along with the label names of its family (see `MetricFamily::iter_labeled_samples`).
+/// It derefs to the sample, for its value and timestamp
+#[derive(Debug)]
+pub struct LabeledSample<'a, ValueType> {
+    label_names: &'a [String],
+    sample: &'a Sample<ValueType>,
+}
+
+impl<'a, ValueType> LabeledSample<'a, ValueType> {
+    pub fn sample(&self) -> &'a Sample<ValueType> {
+        self.sample
+    }
+
+    /// The sample's labels as `(name, value)` pairs, in the family's order
+    pub fn labels(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.label_names
+            .iter()
+            .zip(self.sample.label_values.iter())
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn get_label(&self, label_name: &str) -> Option<&'a str> {
+        self.labels()
+            .find(|(name, _)| *name == label_name)
+            .map(|(_, value)| value)
+    }
+
+    /// The sample's labels, keyed by name. `labels` can be collected into any other map
+    /// ```
+    /// use openmetrics_parser::prometheus::parse_prometheus;
+    ///
+    /// let parsed = parse_prometheus("up{job=\"b\",instance=\"a\"} 1\n").unwrap();
+    /// let sample = parsed.families["up"].iter_labeled_samples().next().unwrap();
+    /// let labels = sample.labels_map();
+    /// assert_eq!(labels["job"], "b");
+    /// assert_eq!(labels.keys().collect::<Vec<_>>(), [&"instance", &"job"]);
+    /// ```
+    pub fn labels_map(&self) -> BTreeMap<&'a str, &'a str> {
+        self.labels().collect()
+    }
+}
+
+impl<ValueType> std::ops::Deref for LabeledSample<'_, ValueType> {
+    type Target = Sample<ValueType>;
+
+    fn deref(&self) -> &Sample<ValueType> {
+        self.sample
+    }
+}
+
 pub struct LabelSet<'a> {
     label_names: Arc<Vec<String>>,
     label_values: &'a [String],
@@ -1036,7 +2502,7 @@ impl<'a> LabelSet<'a> {
     }
 
     pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
-        return self.label_names.iter().zip(self.label_values);
+        self.label_names.iter().zip(self.label_values)
     }
 
     pub fn iter_names(&self) -> impl Iterator<Item = &String> {
@@ -1048,10 +2514,9 @@ impl<'a> LabelSet<'a> {
     }
 
     pub fn get_label_value(&self, label_name: &str) -> Option<&str> {
-        return self
-            .label_names
+        self.label_names
             .iter()
             .position(|s| s == label_name)
-            .map(|i| self.label_values[i].as_str());
+            .map(|i| self.label_values[i].as_str())
     }
 }
@@ -0,0 +1,101 @@
+use crate::MetricNumber;
+
+/// A run of consecutive buckets in a native histogram. `offset` is the gap from the end of the
+/// previous span (or from index 0, for the first span) to the first bucket of this one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BucketSpan {
+    pub offset: i32,
+    pub length: u32,
+}
+
+impl BucketSpan {
+    pub fn new(offset: i32, length: u32) -> Self {
+        Self { offset, length }
+    }
+}
+
+/// The exponential buckets of a native (sparse) histogram, as exposed in the Prometheus protobuf
+/// format and sent over remote write
+/// https://prometheus.io/docs/specs/native_histograms/
+///
+/// Bucket `i` holds the observations in `(base^(i-1), base^i]`, where `base` is
+/// `2^(2^-schema)`; negative buckets mirror the positive ones. Unlike classic buckets, the counts
+/// here aren't cumulative: each is the number of observations in that bucket alone
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NativeHistogram {
+    /// The resolution of the buckets, from -4 (each bucket 65536 times wider than the last) to 8
+    pub schema: i32,
+
+    /// Observations with an absolute value up to this are counted in the zero bucket
+    pub zero_threshold: f64,
+    pub zero_count: MetricNumber,
+    pub positive_spans: Vec<BucketSpan>,
+
+    /// The count of each bucket covered by `positive_spans`, in order
+    pub positive_counts: Vec<MetricNumber>,
+    pub negative_spans: Vec<BucketSpan>,
+    pub negative_counts: Vec<MetricNumber>,
+}
+
+impl Default for NativeHistogram {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl NativeHistogram {
+    pub fn new(schema: i32) -> Self {
+        Self {
+            schema,
+            zero_threshold: 0.,
+            zero_count: MetricNumber::Int(0),
+            positive_spans: Vec::new(),
+            positive_counts: Vec::new(),
+            negative_spans: Vec::new(),
+            negative_counts: Vec::new(),
+        }
+    }
+
+    /// Whether any of the counts are floats, as in histograms that have been scaled or aggregated
+    pub fn is_float(&self) -> bool {
+        std::iter::once(&self.zero_count)
+            .chain(self.positive_counts.iter())
+            .chain(self.negative_counts.iter())
+            .any(|count| matches!(count, MetricNumber::Float(_)))
+    }
+
+    /// The `(lower, upper]` bounds of the positive bucket with the given index
+    /// ```
+    /// use openmetrics_parser::NativeHistogram;
+    ///
+    /// assert_eq!(NativeHistogram::new(0).bucket_bounds(3), (4., 8.));
+    /// assert_eq!(NativeHistogram::new(1).bucket_bounds(2), (2f64.sqrt(), 2.));
+    /// ```
+    pub fn bucket_bounds(&self, index: i32) -> (f64, f64) {
+        let bound = |index: i32| 2f64.powf(index as f64 * 2f64.powi(-self.schema));
+        (bound(index - 1), bound(index))
+    }
+
+    /// The index and count of every positive bucket, in order
+    pub fn positive_buckets(&self) -> Vec<(i32, MetricNumber)> {
+        expand(&self.positive_spans, &self.positive_counts)
+    }
+
+    /// The index and count of every negative bucket, in order. Negative bucket `i` holds the
+    /// observations in `[-base^i, -base^(i-1))`
+    pub fn negative_buckets(&self) -> Vec<(i32, MetricNumber)> {
+        expand(&self.negative_spans, &self.negative_counts)
+    }
+}
+
+fn expand(spans: &[BucketSpan], counts: &[MetricNumber]) -> Vec<(i32, MetricNumber)> {
+    let indices = spans.iter().scan(0, |next, span| {
+        let start = *next + span.offset;
+        *next = start + span.length as i32;
+        Some(start..*next)
+    });
+
+    indices.flatten().zip(counts.iter().copied()).collect()
+}
@@ -0,0 +1,830 @@
+use std::{borrow::Cow, fmt, io, sync::Arc};
+
+use crate::{
+    unescape_label_value, CanonicalValue, EscapingScheme, ExemplarValue, FillPolicy, Limit,
+    MetricFamily, MetricsExposition, OpenMetricsValue, ParseError, PrometheusValue,
+    RenderableMetricValue, Timestamp,
+};
+
+/// How a rule that exporters are known to break is enforced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    /// Fail the parse
+    #[default]
+    Error,
+
+    /// Accept the input, but record the broken rule as a warning (see `ParseReport`)
+    Warn,
+
+    /// Accept the input silently
+    Ignore,
+}
+
+impl Strictness {
+    /// Returns `error` if the rule is enforced, and otherwise accepts the input, pushing `error`
+    /// to `warnings` if it should be warned about
+    pub(crate) fn enforce(
+        self,
+        error: ParseError,
+        warnings: &mut Vec<ParseError>,
+    ) -> Result<(), ParseError> {
+        match self {
+            Strictness::Error => Err(error),
+            Strictness::Warn => {
+                warnings.push(error);
+                Ok(())
+            }
+            Strictness::Ignore => Ok(()),
+        }
+    }
+}
+
+/// Limits on the size of an exposition, for parsing untrusted input. Going over any of them stops
+/// the parse with a `ParseError::LimitExceeded`. There are no limits by default
+/// ```
+/// use openmetrics_parser::{openmetrics::parse_openmetrics_with_options, ParseLimits, ParserOptions};
+///
+/// let limits = ParseLimits::new().with_max_labels_per_sample(1);
+/// let options = ParserOptions::new().with_limits(limits);
+/// let exposition = "# TYPE foo gauge\nfoo{a=\"1\",b=\"2\"} 1\n# EOF\n";
+/// let error = parse_openmetrics_with_options(exposition, options).unwrap_err();
+/// assert_eq!(error.to_string(), "line 2: Exceeded the limit of 1 labels per sample");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ParseLimits {
+    pub max_families: Option<usize>,
+    pub max_samples_per_family: Option<usize>,
+    pub max_labels_per_sample: Option<usize>,
+
+    /// In bytes, once unescaped
+    pub max_label_value_length: Option<usize>,
+
+    /// In bytes, not counting the line ending
+    pub max_line_length: Option<usize>,
+    pub max_total_bytes: Option<usize>,
+}
+
+impl ParseLimits {
+    pub fn new() -> ParseLimits {
+        ParseLimits::default()
+    }
+
+    pub fn with_max_families(mut self, max: usize) -> Self {
+        self.max_families = Some(max);
+        self
+    }
+
+    pub fn with_max_samples_per_family(mut self, max: usize) -> Self {
+        self.max_samples_per_family = Some(max);
+        self
+    }
+
+    pub fn with_max_labels_per_sample(mut self, max: usize) -> Self {
+        self.max_labels_per_sample = Some(max);
+        self
+    }
+
+    pub fn with_max_label_value_length(mut self, max: usize) -> Self {
+        self.max_label_value_length = Some(max);
+        self
+    }
+
+    pub fn with_max_line_length(mut self, max: usize) -> Self {
+        self.max_line_length = Some(max);
+        self
+    }
+
+    pub fn with_max_total_bytes(mut self, max: usize) -> Self {
+        self.max_total_bytes = Some(max);
+        self
+    }
+
+    /// Checks `count` against the limit, e.g. the number of families parsed so far
+    pub(crate) fn check(limit: Limit, max: Option<usize>, count: usize) -> Result<(), ParseError> {
+        match max {
+            Some(max) if count > max => Err(ParseError::LimitExceeded { limit, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks the length of the lines of `input`, a chunk of the exposition that starts after
+    /// `lines` lines and `bytes` bytes of it, before any of it is parsed
+    pub(crate) fn check_input(
+        &self,
+        input: &str,
+        lines: usize,
+        bytes: usize,
+    ) -> Result<(), ParseError> {
+        ParseLimits::check(
+            Limit::TotalBytes,
+            self.max_total_bytes,
+            bytes.saturating_add(input.len()),
+        )?;
+
+        if let Some(max) = self.max_line_length {
+            for (number, line) in input.lines().enumerate() {
+                ParseLimits::check(Limit::LineLength, Some(max), line.len())
+                    .map_err(|error| error.at(lines + number + 1, max + 1))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the labels of a sample
+    pub(crate) fn check_labels(&self, label_values: &[String]) -> Result<(), ParseError> {
+        ParseLimits::check(
+            Limit::LabelsPerSample,
+            self.max_labels_per_sample,
+            label_values.len(),
+        )?;
+
+        for value in label_values {
+            ParseLimits::check(
+                Limit::LabelValueLength,
+                self.max_label_value_length,
+                value.len(),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A test on family names (see `FamilyFilter::with_predicate`)
+pub type FamilyPredicate = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Which families of an exposition to keep, by name. Families that are filtered out are skipped
+/// as soon as their lines are split up, without their samples being parsed or checked at all.
+/// Names are matched against globs, where `*` matches any run of characters and `?` any one
+/// character, and an optional predicate. Everything is kept by default
+/// ```
+/// use openmetrics_parser::{openmetrics::parse_openmetrics_with_options, FamilyFilter, ParserOptions};
+///
+/// let filter = FamilyFilter::new().allow("container_*").deny("*_created");
+/// let options = ParserOptions::new().with_family_filter(filter);
+/// let exposition = "# TYPE container_cpu gauge\ncontainer_cpu 1\n# TYPE kubelet_up gauge\nkubelet_up 1\n# EOF\n";
+/// let parsed = parse_openmetrics_with_options(exposition, options).unwrap();
+/// assert_eq!(parsed.families.keys().collect::<Vec<_>>(), vec!["container_cpu"]);
+/// ```
+#[derive(Clone, Default)]
+pub struct FamilyFilter {
+    /// Keep only the families that match one of these, if there are any
+    pub allow: Vec<String>,
+
+    /// Drop the families that match any of these, even if they're allowed
+    pub deny: Vec<String>,
+
+    /// Keep only the families it returns true for
+    pub predicate: Option<FamilyPredicate>,
+}
+
+impl fmt::Debug for FamilyFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FamilyFilter")
+            .field("allow", &self.allow)
+            .field("deny", &self.deny)
+            .field("predicate", &self.predicate.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+impl FamilyFilter {
+    pub fn new() -> FamilyFilter {
+        FamilyFilter::default()
+    }
+
+    pub fn allow<S: Into<String>>(mut self, glob: S) -> Self {
+        self.allow.push(glob.into());
+        self
+    }
+
+    pub fn deny<S: Into<String>>(mut self, glob: S) -> Self {
+        self.deny.push(glob.into());
+        self
+    }
+
+    pub fn with_predicate<F: Fn(&str) -> bool + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.predicate = Some(Arc::new(f));
+        self
+    }
+
+    /// Whether the family with the given name is kept
+    pub fn matches(&self, name: &str) -> bool {
+        (self.allow.is_empty() || self.allow.iter().any(|glob| glob_matches(glob, name)))
+            && !self.deny.iter().any(|glob| glob_matches(glob, name))
+            && self
+                .predicate
+                .as_ref()
+                .map_or(true, |predicate| predicate(name))
+    }
+}
+
+/// Matches `text` against a glob of `*`s and `?`s, backtracking to the last `*` on a mismatch
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let (glob, text): (Vec<char>, Vec<char>) = (glob.chars().collect(), text.chars().collect());
+    let (mut g, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                g += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_g, star_t)) => {
+                    g = star_g + 1;
+                    t = star_t + 1;
+                    star = Some((star_g, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// Decides, sample line by sample line, which samples to keep, from the sample's name as written
+/// (e.g. `foo_bucket`) and its (unescaped) labels. Samples are tested before anything of them is
+/// copied, so dropped ones cost next to nothing. The lines of a histogram or summary labelset
+/// should be kept or dropped together, or what's left of it may not be valid
+/// ```
+/// use openmetrics_parser::{openmetrics::parse_openmetrics_with_options, ParserOptions, SampleFilter};
+///
+/// let filter = SampleFilter::new(|_, labels| !labels.iter().any(|(name, _)| *name == "pod"));
+/// let options = ParserOptions::new().with_sample_filter(filter);
+/// let exposition = "# TYPE up gauge\nup 1\nup{pod=\"a\"} 1\n# EOF\n";
+/// let parsed = parse_openmetrics_with_options(exposition, options).unwrap();
+/// assert_eq!(parsed.families["up"].iter_samples().count(), 1);
+/// ```
+#[derive(Clone)]
+pub struct SampleFilter(Arc<SampleTest>);
+
+type SampleTest = dyn Fn(&str, &[(&str, &str)]) -> bool + Send + Sync;
+
+impl fmt::Debug for SampleFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SampleFilter(..)")
+    }
+}
+
+impl SampleFilter {
+    pub fn new<F: Fn(&str, &[(&str, &str)]) -> bool + Send + Sync + 'static>(f: F) -> Self {
+        SampleFilter(Arc::new(f))
+    }
+
+    /// Whether the sample with the given name and labels is kept
+    pub fn matches(&self, name: &str, labels: &[(&str, &str)]) -> bool {
+        (self.0)(name, labels)
+    }
+
+    /// Whether the sample is kept, given its labels as they were written (i.e. still escaped)
+    pub(crate) fn matches_escaped<'a, I>(&self, name: &str, labels: I) -> bool
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let unescaped: Vec<_> = labels
+            .into_iter()
+            .map(|(name, value)| (name, unescape_label_value(value)))
+            .collect();
+        let labels: Vec<_> = unescaped
+            .iter()
+            .map(|(name, value)| (*name, value.as_ref()))
+            .collect();
+        self.matches(name, &labels)
+    }
+}
+
+/// How bytes that aren't valid UTF-8 are decoded (see `parse_openmetrics_bytes`). Broken
+/// exporters occasionally put them in label values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncodingPolicy {
+    /// Reject the exposition
+    #[default]
+    Strict,
+
+    /// Replace each invalid sequence with U+FFFD, as `String::from_utf8_lossy` does
+    Lossy,
+
+    /// Decode each invalid byte as Latin-1 (ISO 8859-1), which is what the exporter most likely
+    /// meant. Valid UTF-8 is still decoded as such
+    Latin1,
+}
+
+impl EncodingPolicy {
+    /// Decodes an exposition, borrowing it if it's valid UTF-8
+    /// ```
+    /// use openmetrics_parser::EncodingPolicy;
+    ///
+    /// let bytes = b"foo{a=\"caf\xe9\"} 1\n";
+    /// assert!(EncodingPolicy::Strict.decode(bytes).is_err());
+    /// assert_eq!(EncodingPolicy::Lossy.decode(bytes).unwrap(), "foo{a=\"caf\u{fffd}\"} 1\n");
+    /// assert_eq!(EncodingPolicy::Latin1.decode(bytes).unwrap(), "foo{a=\"caf\u{e9}\"} 1\n");
+    /// ```
+    pub fn decode(self, bytes: &[u8]) -> Result<Cow<'_, str>, ParseError> {
+        match self {
+            EncodingPolicy::Strict => std::str::from_utf8(bytes)
+                .map(Cow::Borrowed)
+                .map_err(|e| ParseError::Io(io::Error::new(io::ErrorKind::InvalidData, e))),
+            EncodingPolicy::Lossy => Ok(String::from_utf8_lossy(bytes)),
+            EncodingPolicy::Latin1 => {
+                if let Ok(text) = std::str::from_utf8(bytes) {
+                    return Ok(Cow::Borrowed(text));
+                }
+
+                let mut text = String::with_capacity(bytes.len() + bytes.len() / 8);
+                let mut rest = bytes;
+                loop {
+                    match std::str::from_utf8(rest) {
+                        Ok(valid) => {
+                            text.push_str(valid);
+                            break;
+                        }
+                        Err(e) => {
+                            let (valid, invalid) = rest.split_at(e.valid_up_to());
+                            text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                            // Without an error length the invalid sequence runs to the end
+                            let len = e.error_len().unwrap_or(invalid.len());
+                            text.extend(invalid[..len].iter().map(|&b| char::from(b)));
+                            rest = &invalid[len..];
+                        }
+                    }
+                }
+
+                Ok(Cow::Owned(text))
+            }
+        }
+    }
+}
+
+/// Options that tweak how strictly an exposition is parsed.
+/// The defaults follow the specifications to the letter
+#[derive(Debug, Clone, Default)]
+pub struct ParserOptions {
+    /// Merge histogram buckets that share an upper bound (e.g. `le="1"` and `le="1.0"`) instead of
+    /// returning a `ParseError::DuplicateBucket`. The merged bucket keeps the larger of the two counts
+    pub merge_duplicate_buckets: bool,
+
+    /// Experimental: accept exemplars on gauge samples. They are stored on `Sample::exemplar` and
+    /// rendered back out. This isn't allowed by OpenMetrics 1.0, so is off by default
+    pub gauge_exemplars: bool,
+
+    /// Rewrite `le` and `quantile` label values into the canonical form the Go client libraries
+    /// use (e.g. `1.0` becomes `1`). Histograms and summaries otherwise keep (and render) their
+    /// bounds as they were written; this rewrites them, along with those labels on untyped/unknown
+    /// families, so the same series from different client libraries ends up with identical labelsets
+    pub normalize_bounds: bool,
+
+    /// Accept `\r\n` line endings (including after `# EOF`), normalizing them to `\n` before parsing
+    pub allow_crlf: bool,
+
+    /// Strip trailing spaces and tabs from every line, and drop blank lines (e.g. between families)
+    pub trim_whitespace: bool,
+
+    /// Accept OpenMetrics lines with runs of spaces or tabs between their tokens (e.g.
+    /// `foo{a="b"}\t1  123`), as the Prometheus text format does, collapsing them into single
+    /// spaces. Label values and help text are left as they are
+    pub collapse_whitespace: bool,
+
+    /// Accept OpenMetrics expositions that end without a `# EOF`, which many exporters leave out.
+    /// A missing `# EOF` is still reported as a warning (see `ParseReport`), and the input must still
+    /// end at the end of a line, so expositions that were cut off mid-line are rejected
+    pub allow_missing_eof: bool,
+
+    /// Accept blank lines (and trailing whitespace) after an OpenMetrics `# EOF`
+    pub allow_blank_after_eof: bool,
+
+    /// How anything else after an OpenMetrics `# EOF` (e.g. padding appended by a proxy) is
+    /// handled. If it's accepted, it's ignored without being parsed
+    pub text_after_eof: Strictness,
+
+    /// Accept `NaN` as a counter total. Some exporters report NaN for counters they couldn't read
+    pub allow_nan_counters: bool,
+
+    /// Accept OpenMetrics samples from a labelset that has already been finished with, e.g. a
+    /// histogram's `_count` after the buckets of another labelset. They're folded into the
+    /// labelset's existing value, so families grouped by suffix rather than by labelset (all the
+    /// `_bucket`s, then all the `_count`s...) are rendered back out one labelset at a time
+    pub allow_interleaved_labelsets: bool,
+
+    /// Accept `# HELP`, `# TYPE` and `# UNIT` lines that come after the samples of their
+    /// (OpenMetrics) family, as long as no other family has started in between
+    pub allow_late_metadata: bool,
+
+    /// Keep the first `# HELP` of a family that has two, warning about the second one
+    pub allow_duplicate_help: bool,
+
+    /// Accept a `# HELP`, `# TYPE` or `# UNIT` line repeated with the same value in a family (e.g.
+    /// when several processes write to the same textfile), warning about the repeat. A repeat with
+    /// a different value is still an error
+    pub allow_duplicate_metadata: bool,
+
+    /// Accept (and drop) exemplars on gauge histogram `_gcount` samples, which some exporters
+    /// emit. OpenMetrics only allows them on `_bucket` samples
+    pub allow_gcount_exemplars: bool,
+
+    /// Drop exemplars on samples that can't have them (e.g. a histogram's `_count`), with a
+    /// warning, rather than rejecting the exposition
+    pub drop_invalid_exemplars: bool,
+
+    /// Skip OpenMetrics comment lines that aren't `# HELP`, `# TYPE`, `# UNIT` or `# EOF` (e.g.
+    /// `# generated by exporter v1.2`), with a warning. Prometheus comments are always skipped
+    pub ignore_unknown_metadata: bool,
+
+    /// How histogram (and gauge histogram) buckets whose counts go down are handled
+    pub histogram_cumulativity: Strictness,
+
+    /// How negative counter totals are handled
+    pub counter_negativity: Strictness,
+
+    /// How stateset values other than 0 or 1 are handled
+    pub stateset_values: Strictness,
+
+    /// How histograms with a `_sum` but no `_count`, or a `_count` but no `_sum`, are handled
+    pub sum_count_pairing: Strictness,
+
+    /// How exemplars whose label names and values add up to more than
+    /// `Exemplar::MAX_LABEL_LENGTH` characters are handled. Accepted exemplars are kept whole
+    pub exemplar_label_length: Strictness,
+
+    /// Split OpenMetrics lines up with a hand-written lexer rather than the pest grammar. It
+    /// accepts the same input (bar lines broken by a lone `\r`) several times faster, but its
+    /// syntax errors are terser
+    pub fast_lexer: bool,
+
+    /// Limits on the size of the exposition, for untrusted input
+    pub limits: ParseLimits,
+
+    /// Which families to keep. The rest are skipped without being parsed
+    pub family_filter: FamilyFilter,
+
+    /// Which samples to keep, if not all of them
+    pub sample_filter: Option<SampleFilter>,
+
+    /// How the names in the exposition were escaped (see `EscapingScheme::from_content_type`), to
+    /// unescape them as they're parsed. Filters still see the names as they were written
+    pub name_escaping: Option<EscapingScheme>,
+}
+
+impl ParserOptions {
+    pub fn new() -> ParserOptions {
+        ParserOptions::default()
+    }
+
+    /// Options that accept the most common ways real exporters stray from the specifications:
+    /// `\r\n` line endings, extra whitespace, duplicate buckets, a missing `# EOF` or blank lines
+    /// after it, NaN counters, interleaved labelsets, metadata after samples, duplicate metadata lines,
+    /// misplaced exemplars and unknown comment lines. Broken value rules (e.g. non cumulative
+    /// histograms) are only warned about. Nothing is rewritten that was valid to begin with
+    /// ```
+    /// use openmetrics_parser::{openmetrics::parse_openmetrics_with_options, ParserOptions};
+    ///
+    /// let exposition = "foo_total NaN\n# TYPE foo counter\n";
+    /// let parsed = parse_openmetrics_with_options(exposition, ParserOptions::lenient()).unwrap();
+    /// assert!(parsed.families["foo"].iter_samples().count() == 1);
+    /// ```
+    pub fn lenient() -> ParserOptions {
+        ParserOptions {
+            merge_duplicate_buckets: true,
+            allow_crlf: true,
+            trim_whitespace: true,
+            collapse_whitespace: true,
+            allow_missing_eof: true,
+            allow_blank_after_eof: true,
+            allow_nan_counters: true,
+            allow_interleaved_labelsets: true,
+            allow_late_metadata: true,
+            allow_duplicate_help: true,
+            allow_duplicate_metadata: true,
+            allow_gcount_exemplars: true,
+            drop_invalid_exemplars: true,
+            ignore_unknown_metadata: true,
+            histogram_cumulativity: Strictness::Warn,
+            counter_negativity: Strictness::Warn,
+            stateset_values: Strictness::Warn,
+            sum_count_pairing: Strictness::Warn,
+            exemplar_label_length: Strictness::Warn,
+            ..ParserOptions::default()
+        }
+    }
+
+    pub fn with_merge_duplicate_buckets(mut self, merge: bool) -> Self {
+        self.merge_duplicate_buckets = merge;
+        self
+    }
+
+    pub fn with_gauge_exemplars(mut self, allow: bool) -> Self {
+        self.gauge_exemplars = allow;
+        self
+    }
+
+    pub fn with_normalize_bounds(mut self, normalize: bool) -> Self {
+        self.normalize_bounds = normalize;
+        self
+    }
+
+    pub fn with_allow_crlf(mut self, allow: bool) -> Self {
+        self.allow_crlf = allow;
+        self
+    }
+
+    pub fn with_trim_whitespace(mut self, trim: bool) -> Self {
+        self.trim_whitespace = trim;
+        self
+    }
+
+    pub fn with_collapse_whitespace(mut self, collapse: bool) -> Self {
+        self.collapse_whitespace = collapse;
+        self
+    }
+
+    pub fn with_allow_missing_eof(mut self, allow: bool) -> Self {
+        self.allow_missing_eof = allow;
+        self
+    }
+
+    pub fn with_allow_blank_after_eof(mut self, allow: bool) -> Self {
+        self.allow_blank_after_eof = allow;
+        self
+    }
+
+    pub fn with_text_after_eof(mut self, strictness: Strictness) -> Self {
+        self.text_after_eof = strictness;
+        self
+    }
+
+    pub fn with_allow_nan_counters(mut self, allow: bool) -> Self {
+        self.allow_nan_counters = allow;
+        self
+    }
+
+    pub fn with_allow_interleaved_labelsets(mut self, allow: bool) -> Self {
+        self.allow_interleaved_labelsets = allow;
+        self
+    }
+
+    pub fn with_allow_late_metadata(mut self, allow: bool) -> Self {
+        self.allow_late_metadata = allow;
+        self
+    }
+
+    pub fn with_allow_duplicate_help(mut self, allow: bool) -> Self {
+        self.allow_duplicate_help = allow;
+        self
+    }
+
+    pub fn with_allow_duplicate_metadata(mut self, allow: bool) -> Self {
+        self.allow_duplicate_metadata = allow;
+        self
+    }
+
+    pub fn with_allow_gcount_exemplars(mut self, allow: bool) -> Self {
+        self.allow_gcount_exemplars = allow;
+        self
+    }
+
+    pub fn with_drop_invalid_exemplars(mut self, drop: bool) -> Self {
+        self.drop_invalid_exemplars = drop;
+        self
+    }
+
+    pub fn with_ignore_unknown_metadata(mut self, ignore: bool) -> Self {
+        self.ignore_unknown_metadata = ignore;
+        self
+    }
+
+    pub fn with_histogram_cumulativity(mut self, strictness: Strictness) -> Self {
+        self.histogram_cumulativity = strictness;
+        self
+    }
+
+    pub fn with_counter_negativity(mut self, strictness: Strictness) -> Self {
+        self.counter_negativity = strictness;
+        self
+    }
+
+    pub fn with_stateset_values(mut self, strictness: Strictness) -> Self {
+        self.stateset_values = strictness;
+        self
+    }
+
+    pub fn with_sum_count_pairing(mut self, strictness: Strictness) -> Self {
+        self.sum_count_pairing = strictness;
+        self
+    }
+
+    pub fn with_exemplar_label_length(mut self, strictness: Strictness) -> Self {
+        self.exemplar_label_length = strictness;
+        self
+    }
+
+    pub fn with_fast_lexer(mut self, fast: bool) -> Self {
+        self.fast_lexer = fast;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: ParseLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_family_filter(mut self, filter: FamilyFilter) -> Self {
+        self.family_filter = filter;
+        self
+    }
+
+    pub fn with_sample_filter(mut self, filter: SampleFilter) -> Self {
+        self.sample_filter = Some(filter);
+        self
+    }
+
+    pub fn with_name_escaping(mut self, scheme: EscapingScheme) -> Self {
+        self.name_escaping = Some(scheme);
+        self
+    }
+}
+
+/// Options that tweak how an exposition is rendered. The defaults render the model as-is.
+/// Leaving things out only affects the output, the exposition itself isn't changed
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    /// Render every family in its canonical form (see `MetricFamily::canonicalize`), with samples
+    /// sorted by labelset and labels sorted by name, and families in name order, so that two
+    /// equivalent expositions render to identical bytes. Otherwise families are rendered in the
+    /// exposition's order
+    pub canonical: bool,
+
+    /// Render exemplars, both on values (counters and histogram buckets) and on samples.
+    /// Some older scrapers fail on exemplars, so they can be left out
+    pub exemplars: bool,
+
+    /// Render sample timestamps
+    pub timestamps: bool,
+
+    /// Render the `_created` series of counters, histograms and summaries. They're rendered from
+    /// the `created` field of each value, and triple the number of series for some backends, so
+    /// they can be left out
+    pub created: bool,
+
+    /// Render the `# EOF` that ends an OpenMetrics exposition. Leaving it out lets the families be
+    /// spliced into another exposition (see `openmetrics::splice_openmetrics`). Prometheus
+    /// expositions have no end marker, so aren't affected
+    pub eof: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            canonical: false,
+            exemplars: true,
+            timestamps: true,
+            created: true,
+            eof: true,
+        }
+    }
+}
+
+impl RenderOptions {
+    pub fn new() -> RenderOptions {
+        RenderOptions::default()
+    }
+
+    pub fn with_canonical(mut self, canonical: bool) -> Self {
+        self.canonical = canonical;
+        self
+    }
+
+    pub fn with_exemplars(mut self, exemplars: bool) -> Self {
+        self.exemplars = exemplars;
+        self
+    }
+
+    pub fn with_timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    pub fn with_created(mut self, created: bool) -> Self {
+        self.created = created;
+        self
+    }
+
+    pub fn with_eof(mut self, eof: bool) -> Self {
+        self.eof = eof;
+        self
+    }
+
+    /// Whether families have to be rewritten before they're rendered
+    fn rewrites(&self) -> bool {
+        self.canonical || !self.exemplars || !self.timestamps || !self.created
+    }
+
+    /// Calls `f` with each family of the exposition, in order, as it should be rendered
+    pub(crate) fn for_each_family<TypeSet, ValueType, E, F>(
+        &self,
+        exposition: &MetricsExposition<TypeSet, ValueType>,
+        mut f: F,
+    ) -> Result<(), E>
+    where
+        TypeSet: Clone,
+        ValueType: CanonicalValue + ExemplarValue + CreatedValue + RenderableMetricValue + Clone,
+        F: FnMut(&MetricFamily<TypeSet, ValueType>) -> Result<(), E>,
+    {
+        for family in families_in_order(exposition, self) {
+            self.with_rendered_family(family, &mut f)?;
+        }
+
+        Ok(())
+    }
+
+    /// Calls `f` with the family as it should be rendered. Only the family being rendered is
+    /// copied (and only if the options rewrite it), never the whole exposition
+    pub(crate) fn with_rendered_family<TypeSet, ValueType, R, F>(
+        &self,
+        family: &MetricFamily<TypeSet, ValueType>,
+        f: F,
+    ) -> R
+    where
+        TypeSet: Clone,
+        ValueType: CanonicalValue + ExemplarValue + CreatedValue + RenderableMetricValue + Clone,
+        F: FnOnce(&MetricFamily<TypeSet, ValueType>) -> R,
+    {
+        if !self.rewrites() {
+            return f(family);
+        }
+
+        let mut family = if self.canonical {
+            family.canonicalize()
+        } else {
+            family.clone_and_convert_type::<ValueType>()
+        };
+
+        if !self.timestamps {
+            family.fill_timestamps(0., FillPolicy::Clear);
+        }
+
+        for sample in family.iter_samples_mut() {
+            if !self.exemplars {
+                sample.exemplar = None;
+                for exemplar in sample.value.exemplars_mut() {
+                    *exemplar = None;
+                }
+            }
+
+            if !self.created {
+                if let Some(created) = sample.value.created_mut() {
+                    *created = None;
+                }
+            }
+        }
+
+        f(&family)
+    }
+}
+
+/// The families of the exposition, in the order they're rendered in: name order for canonical
+/// output, and the exposition's own order otherwise
+pub(crate) fn families_in_order<'a, TypeSet, ValueType>(
+    exposition: &'a MetricsExposition<TypeSet, ValueType>,
+    options: &RenderOptions,
+) -> Vec<&'a MetricFamily<TypeSet, ValueType>> {
+    let mut families: Vec<_> = exposition.families.values().collect();
+    if options.canonical {
+        families.sort_by(|a, b| a.family_name.cmp(&b.family_name));
+    }
+
+    families
+}
+
+/// A value that can carry a `_created` timestamp
+pub trait CreatedValue {
+    fn created_mut(&mut self) -> Option<&mut Option<Timestamp>>;
+}
+
+impl CreatedValue for OpenMetricsValue {
+    fn created_mut(&mut self) -> Option<&mut Option<Timestamp>> {
+        match self {
+            OpenMetricsValue::Counter(c) => Some(&mut c.created),
+            OpenMetricsValue::Histogram(h) | OpenMetricsValue::GaugeHistogram(h) => {
+                Some(&mut h.created)
+            }
+            OpenMetricsValue::Summary(s) => Some(&mut s.created),
+            _ => None,
+        }
+    }
+}
+
+impl CreatedValue for PrometheusValue {
+    fn created_mut(&mut self) -> Option<&mut Option<Timestamp>> {
+        match self {
+            PrometheusValue::Histogram(h) => Some(&mut h.created),
+            PrometheusValue::Summary(s) => Some(&mut s.created),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::{collections::HashMap, fmt, ops::Index};
+
+/// A map from names to values that remembers the order its entries were inserted in. It's how
+/// an exposition holds its families, so that they're rendered back out in the order the exporter
+/// wrote them. Lookups are by name, as with a `HashMap`
+#[derive(Clone)]
+pub struct OrderedMap<V> {
+    entries: Vec<(String, V)>,
+    indices: HashMap<String, usize>,
+}
+
+impl<V> Default for OrderedMap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for OrderedMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<V: PartialEq> PartialEq for OrderedMap<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl<V> OrderedMap<V> {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        OrderedMap {
+            entries: Vec::with_capacity(capacity),
+            indices: HashMap::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.indices.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&V> {
+        self.indices.get(name).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut V> {
+        self.indices.get(name).map(|&i| &mut self.entries[i].1)
+    }
+
+    /// Inserts the value at the end, returning the old value if the name was already taken.
+    /// Replacing a value keeps its original position
+    pub fn insert(&mut self, name: String, value: V) -> Option<V> {
+        match self.indices.get(&name) {
+            Some(&i) => Some(std::mem::replace(&mut self.entries[i].1, value)),
+            None => {
+                self.indices.insert(name.clone(), self.entries.len());
+                self.entries.push((name, value));
+                None
+            }
+        }
+    }
+
+    /// Removes the value, keeping the order of the rest
+    pub fn remove(&mut self, name: &str) -> Option<V> {
+        let index = self.indices.remove(name)?;
+        let (_, value) = self.entries.remove(index);
+        self.reindex_from(index);
+        Some(value)
+    }
+
+    /// Keeps only the entries that `f` returns true for, keeping their order
+    pub fn retain<F: FnMut(&String, &mut V) -> bool>(&mut self, mut f: F) {
+        let indices = &mut self.indices;
+        let mut kept = 0;
+        self.entries.retain_mut(|(name, value)| {
+            if !f(name, value) {
+                indices.remove(name.as_str());
+                return false;
+            }
+
+            if let Some(index) = indices.get_mut(name.as_str()) {
+                *index = kept;
+            }
+
+            kept += 1;
+            true
+        });
+    }
+
+    /// Sorts the entries by name
+    pub fn sort_keys(&mut self) {
+        self.entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.reindex_from(0);
+    }
+
+    /// Removes every entry, returning them in order
+    pub fn drain(&mut self) -> std::vec::Drain<'_, (String, V)> {
+        self.indices.clear();
+        self.entries.drain(..)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.entries.iter().map(|(name, _)| name)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.entries.iter_mut().map(|(_, value)| value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.entries.iter().map(|(name, value)| (name, value))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut V)> {
+        self.entries.iter_mut().map(|(name, value)| (&*name, value))
+    }
+
+    /// Points the indices of the entries from `start` on back at where they are now, after
+    /// they've moved. Their names are already in `indices`, so nothing is cloned
+    fn reindex_from(&mut self, start: usize) {
+        for (i, (name, _)) in self.entries.iter().enumerate().skip(start) {
+            if let Some(index) = self.indices.get_mut(name.as_str()) {
+                *index = i;
+            }
+        }
+    }
+}
+
+impl<V> Index<&str> for OrderedMap<V> {
+    type Output = V;
+
+    fn index(&self, name: &str) -> &V {
+        self.get(name).expect("no entry found for name")
+    }
+}
+
+impl<V> Index<&String> for OrderedMap<V> {
+    type Output = V;
+
+    fn index(&self, name: &String) -> &V {
+        &self[name.as_str()]
+    }
+}
+
+impl<V> FromIterator<(String, V)> for OrderedMap<V> {
+    fn from_iter<I: IntoIterator<Item = (String, V)>>(iter: I) -> Self {
+        let mut map = OrderedMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<V> Extend<(String, V)> for OrderedMap<V> {
+    fn extend<I: IntoIterator<Item = (String, V)>>(&mut self, iter: I) {
+        for (name, value) in iter {
+            self.insert(name, value);
+        }
+    }
+}
+
+impl<V> IntoIterator for OrderedMap<V> {
+    type Item = (String, V);
+    type IntoIter = std::vec::IntoIter<(String, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+type Entry<'a, V> = fn(&'a (String, V)) -> (&'a String, &'a V);
+type EntryMut<'a, V> = fn(&'a mut (String, V)) -> (&'a String, &'a mut V);
+
+impl<'a, V> IntoIterator for &'a OrderedMap<V> {
+    type Item = (&'a String, &'a V);
+    type IntoIter = std::iter::Map<std::slice::Iter<'a, (String, V)>, Entry<'a, V>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter().map(|(name, value)| (name, value))
+    }
+}
+
+impl<'a, V> IntoIterator for &'a mut OrderedMap<V> {
+    type Item = (&'a String, &'a mut V);
+    type IntoIter = std::iter::Map<std::slice::IterMut<'a, (String, V)>, EntryMut<'a, V>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter_mut().map(|(name, value)| (&*name, value))
+    }
+}
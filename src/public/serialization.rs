@@ -0,0 +1,230 @@
+//! Serde support for the types that can't simply derive it: families share their label names
+//! with their samples, and ordered maps have to keep their order.
+//!
+//! Deserializing is lenient enough to write fixtures by hand (in JSON, YAML or anything else serde
+//! reads): family names default to their key in the exposition, types and values can be written
+//! as they are in `# TYPE` lines (`counter` as well as `Counter`), numbers can be plain numbers
+//! rather than `{"Int": 1}`, and anything optional or empty can be left out
+
+use std::{fmt, marker::PhantomData};
+
+use serde::{
+    de::{self, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    LabelValues, MetricFamily, MetricNumber, MetricsExposition, OrderedMap, RenderableMetricValue,
+    Sample,
+};
+
+#[derive(Serialize)]
+struct SerializeFamily<'a, TypeSet, ValueType> {
+    family_name: &'a str,
+    label_names: &'a [String],
+    family_type: &'a TypeSet,
+    help: &'a str,
+    unit: &'a str,
+    samples: Vec<&'a Sample<ValueType>>,
+}
+
+#[derive(Deserialize)]
+struct DeserializeFamily<TypeSet, ValueType> {
+    #[serde(default)]
+    family_name: String,
+    #[serde(default)]
+    label_names: Vec<String>,
+    family_type: TypeSet,
+    #[serde(default)]
+    help: String,
+    #[serde(default)]
+    unit: String,
+    // A plain `default` would require ValueType: Default
+    #[serde(default = "Vec::new")]
+    samples: Vec<Sample<ValueType>>,
+}
+
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "MetricFamily<TypeSet, ValueType>: Deserialize<'de>"))]
+struct DeserializeExposition<TypeSet, ValueType> {
+    families: OrderedMap<MetricFamily<TypeSet, ValueType>>,
+}
+
+impl<TypeSet, ValueType> Serialize for MetricFamily<TypeSet, ValueType>
+where
+    TypeSet: Serialize + Clone,
+    ValueType: Serialize + RenderableMetricValue + Clone,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializeFamily {
+            family_name: &self.family_name,
+            label_names: self.get_label_names(),
+            family_type: &self.family_type,
+            help: &self.help,
+            unit: &self.unit,
+            samples: self.iter_samples().collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Families are rebuilt through `MetricFamily::with_samples`, so a deserialized family is held to
+/// the same rules as one built by hand (e.g. no duplicate labelsets)
+impl<'de, TypeSet, ValueType> Deserialize<'de> for MetricFamily<TypeSet, ValueType>
+where
+    TypeSet: Deserialize<'de> + Clone,
+    ValueType: Deserialize<'de> + RenderableMetricValue + Clone,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let family = DeserializeFamily::<TypeSet, ValueType>::deserialize(deserializer)?;
+        MetricFamily::new(
+            family.family_name,
+            family.label_names,
+            family.family_type,
+            family.help,
+            family.unit,
+        )
+        .with_samples(family.samples)
+        .map_err(|e| de::Error::custom(format!("{:?}", e)))
+    }
+}
+
+/// Families without a name are named after their key, and families whose name doesn't match
+/// their key are rejected, as rendering would otherwise lose track of which is which
+impl<'de, TypeSet, ValueType> Deserialize<'de> for MetricsExposition<TypeSet, ValueType>
+where
+    MetricFamily<TypeSet, ValueType>: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut exposition = DeserializeExposition::deserialize(deserializer)?;
+        for (name, family) in exposition.families.iter_mut() {
+            if family.family_name.is_empty() {
+                family.family_name = name.clone();
+            } else if family.family_name != *name {
+                return Err(de::Error::custom(format!(
+                    "Family `{}` is named `{}`",
+                    name, family.family_name
+                )));
+            }
+        }
+
+        Ok(MetricsExposition {
+            families: exposition.families,
+        })
+    }
+}
+
+impl<V: Serialize> Serialize for OrderedMap<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (name, value) in self.iter() {
+            map.serialize_entry(name, value)?;
+        }
+
+        map.end()
+    }
+}
+
+struct OrderedMapVisitor<V>(PhantomData<V>);
+
+impl<'de, V: Deserialize<'de>> Visitor<'de> for OrderedMapVisitor<V> {
+    type Value = OrderedMap<V>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Self::Value, A::Error> {
+        let mut map = OrderedMap::with_capacity(access.size_hint().unwrap_or(0));
+        while let Some((name, value)) = access.next_entry()? {
+            map.insert(name, value);
+        }
+
+        Ok(map)
+    }
+}
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for OrderedMap<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(OrderedMapVisitor(PhantomData))
+    }
+}
+
+#[derive(Deserialize)]
+enum TaggedNumber {
+    #[serde(alias = "float")]
+    Float(#[serde(with = "float")] f64),
+    #[serde(alias = "int")]
+    Int(i64),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyNumber {
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Tagged(TaggedNumber),
+}
+
+/// Numbers are written tagged (`{"Int": 1}`), but plain numbers are read too, as ints if they're
+/// integers and floats otherwise, along with `NaN`, `+Inf` and `-Inf` strings
+impl Serialize for LabelValues {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.as_slice().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for LabelValues {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<String>::deserialize(deserializer).map(LabelValues::from)
+    }
+}
+
+impl<'de> Deserialize<'de> for MetricNumber {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match AnyNumber::deserialize(deserializer)? {
+            AnyNumber::Int(i) | AnyNumber::Tagged(TaggedNumber::Int(i)) => Ok(MetricNumber::Int(i)),
+            AnyNumber::Float(f) | AnyNumber::Tagged(TaggedNumber::Float(f)) => {
+                Ok(MetricNumber::Float(f))
+            }
+            AnyNumber::Text(text) => text
+                .parse()
+                .map(MetricNumber::Float)
+                .map_err(|_| de::Error::custom(format!("Invalid number: {}", text))),
+        }
+    }
+}
+
+/// JSON has no NaN or infinities, so non finite floats (e.g. the `+Inf` bucket bound) are
+/// written as they are in the text formats (`+Inf`, `-Inf`, `NaN`) instead
+pub(crate) mod float {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use crate::format_bound;
+
+    pub fn serialize<S: Serializer>(f: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        if f.is_finite() {
+            serializer.serialize_f64(*f)
+        } else {
+            serializer.serialize_str(&format_bound(*f))
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Float {
+        Number(f64),
+        Text(String),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+        match Float::deserialize(deserializer)? {
+            Float::Number(f) => Ok(f),
+            Float::Text(text) => text
+                .parse()
+                .map_err(|_| de::Error::custom(format!("Invalid float: {}", text))),
+        }
+    }
+}
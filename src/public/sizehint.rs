@@ -0,0 +1,88 @@
+use crate::{
+    HistogramValue, MetricsExposition, OpenMetricsValue, PrometheusValue, RenderableMetricValue,
+    SummaryValue,
+};
+
+/// Room for a rendered value, timestamp, and any extra label (e.g. `le`) on a single line
+const LINE_OVERHEAD: usize = 48;
+
+/// Room for the `# TYPE` etc keywords and the type name of a family
+const DESCRIPTOR_OVERHEAD: usize = 32;
+
+/// A value that knows how many lines it renders to
+pub trait RenderedLines {
+    fn rendered_lines(&self) -> usize;
+}
+
+impl RenderedLines for HistogramValue {
+    fn rendered_lines(&self) -> usize {
+        self.buckets.len()
+            + self.sum.is_some() as usize
+            + self.count.is_some() as usize
+            + self.created.is_some() as usize
+    }
+}
+
+impl RenderedLines for SummaryValue {
+    fn rendered_lines(&self) -> usize {
+        self.quantiles.len()
+            + self.sum.is_some() as usize
+            + self.count.is_some() as usize
+            + self.created.is_some() as usize
+    }
+}
+
+impl RenderedLines for OpenMetricsValue {
+    fn rendered_lines(&self) -> usize {
+        match self {
+            OpenMetricsValue::Counter(c) => 1 + c.created.is_some() as usize,
+            OpenMetricsValue::Histogram(h) | OpenMetricsValue::GaugeHistogram(h) => {
+                h.rendered_lines()
+            }
+            OpenMetricsValue::Summary(s) => s.rendered_lines(),
+            _ => 1,
+        }
+    }
+}
+
+impl RenderedLines for PrometheusValue {
+    fn rendered_lines(&self) -> usize {
+        match self {
+            PrometheusValue::Histogram(h) => h.rendered_lines(),
+            PrometheusValue::Summary(s) => s.rendered_lines(),
+            _ => 1,
+        }
+    }
+}
+
+impl<TypeSet, ValueType> MetricsExposition<TypeSet, ValueType>
+where
+    TypeSet: Clone,
+    ValueType: RenderedLines + RenderableMetricValue + Clone,
+{
+    /// Estimates how many bytes the exposition renders to, without rendering it. This is only
+    /// meant for preallocating output buffers, so it errs on the side of overestimating, and
+    /// doesn't account for exemplars
+    pub fn rendered_size_hint(&self) -> usize {
+        let mut size = "# EOF\n".len();
+        for family in self.families.values() {
+            let name_len = family.family_name.len();
+            size += 3 * name_len + family.help.len() + family.unit.len() + DESCRIPTOR_OVERHEAD;
+
+            let label_names_len: usize = family.get_label_names().iter().map(|n| n.len()).sum();
+            for sample in family.iter_samples() {
+                let labels_len = label_names_len
+                    + sample
+                        .get_label_values()
+                        .iter()
+                        .map(|v| v.len() + 4)
+                        .sum::<usize>()
+                    + 2;
+
+                size += sample.value.rendered_lines() * (name_len + labels_len + LINE_OVERHEAD);
+            }
+        }
+
+        size
+    }
+}
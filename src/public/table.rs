@@ -0,0 +1,147 @@
+use std::fmt::{self, Write};
+
+use crate::{MetricsExposition, RenderableMetricValue, SeriesValue};
+
+/// Controls how `to_table_string_with_options` lays out its table
+#[derive(Debug, Clone)]
+pub struct TableOptions {
+    /// Cells longer than this many characters are truncated (with a trailing `…`)
+    pub max_column_width: Option<usize>,
+
+    /// Rows beyond this many are left out, and replaced with a count of how many were left out
+    pub max_rows: Option<usize>,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        Self {
+            max_column_width: Some(60),
+            max_rows: None,
+        }
+    }
+}
+
+impl TableOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_column_width(mut self, width: Option<usize>) -> Self {
+        self.max_column_width = width;
+        self
+    }
+
+    pub fn with_max_rows(mut self, rows: Option<usize>) -> Self {
+        self.max_rows = rows;
+        self
+    }
+}
+
+const HEADERS: [&str; 5] = ["FAMILY", "TYPE", "LABELS", "VALUE", "TIMESTAMP"];
+
+fn truncate(cell: String, max_width: Option<usize>) -> String {
+    match max_width {
+        Some(width) if cell.chars().count() > width => {
+            let mut truncated: String = cell.chars().take(width.saturating_sub(1)).collect();
+            truncated.push('…');
+            truncated
+        }
+        _ => cell,
+    }
+}
+
+impl<TypeSet, ValueType> MetricsExposition<TypeSet, ValueType>
+where
+    TypeSet: Clone + fmt::Display,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    /// Renders the exposition as an aligned table for debugging, with the default `TableOptions`.
+    /// This isn't a wire format, and shouldn't be parsed
+    pub fn to_table_string(&self) -> String {
+        self.to_table_string_with_options(&TableOptions::default())
+    }
+
+    /// Renders the exposition as an aligned table, with one row per series (so e.g. each bucket
+    /// of a histogram gets its own row). Families are listed in the exposition's order
+    /// ```
+    /// use openmetrics_parser::{prometheus::parse_prometheus, TableOptions};
+    ///
+    /// let exposition = parse_prometheus("# TYPE up gauge\nup{job=\"a\"} 1 1000\n").unwrap();
+    /// assert_eq!(
+    ///     exposition.to_table_string_with_options(&TableOptions::new()),
+    ///     "FAMILY  TYPE   LABELS     VALUE  TIMESTAMP\nup      gauge  {job=\"a\"}  1      1000\n"
+    /// );
+    /// ```
+    pub fn to_table_string_with_options(&self, options: &TableOptions) -> String {
+        let mut rows: Vec<[String; 5]> = Vec::new();
+        for family in self.families.values() {
+            for sample in family.iter_samples() {
+                let labels: Vec<(String, String)> = sample
+                    .get_labelset()
+                    .map(|l| l.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+                    .unwrap_or_default();
+
+                for point in sample.value.series_points(&family.family_name) {
+                    let mut point_labels = labels.clone();
+                    point_labels.extend(
+                        point
+                            .extra_labels
+                            .iter()
+                            .map(|(k, v)| (k.to_string(), v.clone())),
+                    );
+
+                    let labels_str = format!(
+                        "{{{}}}",
+                        point_labels
+                            .iter()
+                            .map(|(k, v)| format!("{}={:?}", k, v))
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    );
+
+                    rows.push([
+                        point.name.clone(),
+                        family.family_type.to_string(),
+                        labels_str,
+                        point.value.to_string(),
+                        sample.timestamp.map(|t| t.to_string()).unwrap_or_default(),
+                    ]);
+                }
+            }
+        }
+
+        let total = rows.len();
+        if let Some(max_rows) = options.max_rows {
+            rows.truncate(max_rows);
+        }
+
+        let rows: Vec<[String; 5]> = std::iter::once(HEADERS.map(String::from))
+            .chain(rows)
+            .map(|row| row.map(|cell| truncate(cell, options.max_column_width)))
+            .collect();
+
+        let mut widths = [0; 5];
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut table = String::new();
+        for row in rows.iter() {
+            let mut line = String::new();
+            for (i, cell) in row.iter().enumerate() {
+                let _ = write!(line, "{:width$}  ", cell, width = widths[i]);
+            }
+
+            table.push_str(line.trim_end());
+            table.push('\n');
+        }
+
+        if total > rows.len() - 1 {
+            let _ = writeln!(table, "... ({} more rows)", total - (rows.len() - 1));
+        }
+
+        table
+    }
+}
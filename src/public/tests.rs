@@ -233,3 +233,1113 @@ fn test_metric_number_operations() {
         assert_eq!(a, MetricNumber::Float(1.0 / 3.0));
     }
 }
+
+#[test]
+fn test_format_bound() {
+    use crate::format_bound;
+
+    assert_eq!(format_bound(1.0), "1");
+    assert_eq!(format_bound(0.005), "0.005");
+    assert_eq!(format_bound(2.5e-5), "2.5e-05");
+    assert_eq!(format_bound(1e21), "1e+21");
+    assert_eq!(format_bound(123456.0), "123456");
+    assert_eq!(format_bound(f64::INFINITY), "+Inf");
+}
+
+#[test]
+fn test_value_builders() {
+    use crate::{HistogramValue, MetricNumber, ParseError, SummaryValue};
+
+    let mut histogram = HistogramValue::builder()
+        .bucket(f64::INFINITY, 9)
+        .bucket(0.1, 5)
+        .sum(1.2)
+        .count(9)
+        .build()
+        .unwrap();
+
+    assert_eq!(histogram.buckets[0].upper_bound, 0.1);
+
+    histogram.observe_into_bucket(0.5);
+    assert_eq!(histogram.buckets[0].count, MetricNumber::Int(5));
+    assert_eq!(histogram.buckets[1].count, MetricNumber::Int(10));
+    assert_eq!(histogram.count, Some(10));
+    assert_eq!(histogram.sum, Some(MetricNumber::Float(1.7)));
+
+    assert!(HistogramValue::builder().bucket(0.1, 5).build().is_err());
+    assert!(HistogramValue::builder()
+        .bucket(0.1, 10)
+        .bucket(f64::INFINITY, 9)
+        .build()
+        .is_err());
+    assert!(matches!(
+        HistogramValue::builder()
+            .bucket(1., 1)
+            .bucket(1., 1)
+            .bucket(f64::INFINITY, 1)
+            .build(),
+        Err(ParseError::DuplicateBucket(_))
+    ));
+
+    let mut summary = SummaryValue::builder()
+        .quantile(0.5, 1)
+        .sum(3)
+        .count(2)
+        .build()
+        .unwrap();
+    summary.set_quantile(0.5, MetricNumber::Int(2));
+    summary.set_quantile(0.9, MetricNumber::Int(3));
+    assert_eq!(summary.quantiles.len(), 2);
+    assert_eq!(summary.quantiles[0].value, MetricNumber::Int(2));
+
+    assert!(SummaryValue::builder().quantile(1.5, 1).build().is_err());
+}
+
+#[test]
+fn test_format_registry() {
+    use crate::{
+        AnyExposition, ExpositionDecoder, FormatRegistry, OpenMetricsTextFormat, ParseError,
+        PrometheusTextFormat,
+    };
+
+    /// A made up format of `name,value` lines
+    struct CsvDecoder;
+
+    impl ExpositionDecoder for CsvDecoder {
+        fn content_types(&self) -> &[&str] {
+            &["text/csv"]
+        }
+
+        fn decode(&self, input: &[u8]) -> Result<AnyExposition, ParseError> {
+            let mut text = String::from_utf8_lossy(input).replace(',', " ");
+            text.push_str("# EOF\n");
+            OpenMetricsTextFormat.decode(text.as_bytes())
+        }
+    }
+
+    let registry = FormatRegistry::new()
+        .with_decoder(OpenMetricsTextFormat)
+        .with_decoder(CsvDecoder)
+        .with_encoder(OpenMetricsTextFormat);
+
+    let exposition = registry
+        .decode("Text/CSV; charset=utf-8", b"up,1\n")
+        .unwrap();
+    assert!(exposition
+        .as_openmetrics()
+        .unwrap()
+        .families
+        .contains_key("up"));
+
+    let mut output = Vec::new();
+    registry
+        .encode("application/openmetrics-text", &exposition, &mut output)
+        .unwrap();
+    assert_eq!(output, b"up 1\n# EOF\n");
+
+    assert!(registry.decode("text/plain", b"up 1\n").is_err());
+
+    // Both models can be registered at once, and each encoder turns away the other's expositions
+    let registry = registry
+        .with_decoder(PrometheusTextFormat)
+        .with_encoder(PrometheusTextFormat);
+    let exposition = registry.decode("text/plain", b"up 1\n").unwrap();
+    assert!(exposition.as_prometheus().is_some());
+    let error = registry
+        .encode("application/openmetrics-text", &exposition, &mut Vec::new())
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+    // Invalid UTF-8 is reported as `EncodingPolicy::Strict` reports it
+    assert!(matches!(
+        registry.decode("text/plain", b"up{a=\"\xff\"} 1\n"),
+        Err(ParseError::Io(e)) if e.kind() == std::io::ErrorKind::InvalidData
+    ));
+}
+
+#[test]
+fn test_post_hoc_validation() {
+    use crate::openmetrics::parse_openmetrics;
+    use crate::{
+        CounterValue, HistogramValue, MetricFamily, MetricsExposition, OpenMetricsType,
+        OpenMetricsValue, ParserOptions, Sample, ValidateFamily,
+    };
+
+    let profile = ParserOptions::default();
+
+    let parsed = parse_openmetrics(
+        "# TYPE a histogram\na_bucket{le=\"+Inf\"} 1\na_sum 1\na_count 1\n# TYPE s stateset\ns{s=\"x\"} 1\n# EOF\n",
+    )
+    .unwrap();
+    assert!(parsed.validate(&profile).is_ok());
+
+    let histogram = HistogramValue {
+        buckets: vec![],
+        ..Default::default()
+    };
+    let family = MetricFamily::new(
+        String::from("latency"),
+        vec![],
+        OpenMetricsType::Histogram,
+        String::new(),
+        String::new(),
+    )
+    .with_samples(vec![Sample::new(
+        vec![],
+        None,
+        OpenMetricsValue::Histogram(histogram),
+    )])
+    .unwrap();
+    assert!(family.validate(&profile).is_err());
+
+    let family = MetricFamily::new(
+        String::from("temp"),
+        vec![],
+        OpenMetricsType::Counter,
+        String::new(),
+        String::new(),
+    )
+    .with_samples(vec![Sample::new(
+        vec![],
+        None,
+        OpenMetricsValue::Gauge(1.into()),
+    )])
+    .unwrap();
+    assert!(family.validate(&profile).is_err());
+
+    let mut exposition = MetricsExposition::new();
+    exposition.families.insert(String::from("other"), family);
+    assert!(exposition.validate(&profile).is_err());
+
+    // NaN and negative totals are told apart, and checked the same way the parser checks them
+    let counter = |value: f64| {
+        MetricFamily::new(
+            String::from("requests"),
+            vec![],
+            OpenMetricsType::Counter,
+            String::new(),
+            String::new(),
+        )
+        .with_samples(vec![Sample::new(
+            vec![],
+            None,
+            OpenMetricsValue::Counter(CounterValue {
+                value: value.into(),
+                created: None,
+                exemplar: None,
+            }),
+        )])
+        .unwrap()
+    };
+    let error = counter(f64::NAN)
+        .validate(&profile)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("NaN") && !error.contains("non negative"),
+        "{}",
+        error
+    );
+    assert!(counter(f64::NAN)
+        .validate(&profile.clone().with_allow_nan_counters(true))
+        .is_ok());
+    let error = counter(-1.).validate(&profile).unwrap_err().to_string();
+    assert!(error.contains("non negative"), "{}", error);
+
+    let parsed = parse_prometheus("# TYPE foo gauge\nfoo{a=\"b\"} 1\n").unwrap();
+    assert!(parsed.validate(&profile).is_ok());
+}
+
+#[test]
+fn test_semantic_eq() {
+    use crate::{assert_semantic_eq, semantic_diff, semantic_eq, CompareOptions};
+
+    let a = parse_prometheus(
+        "# TYPE foo histogram\nfoo_bucket{a=\"1\",le=\"1\"} 1 # {trace=\"x\"} 0.5\nfoo_bucket{a=\"1\",le=\"+Inf\"} 2\nfoo_sum{a=\"1\"} 3\nfoo_count{a=\"1\"} 2\n# TYPE bar gauge\nbar{x=\"1\",y=\"2\"} 1\nbar{x=\"2\",y=\"2\"} 2\n",
+    )
+    .unwrap();
+    let b = parse_prometheus(
+        "# TYPE bar gauge\nbar{y=\"2\",x=\"2\"} 2.0\nbar{y=\"2\",x=\"1\"} 1\n# TYPE foo histogram\nfoo_bucket{a=\"1\",le=\"1\"} 1\nfoo_bucket{a=\"1\",le=\"+Inf\"} 2\nfoo_sum{a=\"1\"} 3\nfoo_count{a=\"1\"} 2\n",
+    )
+    .unwrap();
+
+    assert!(!semantic_eq(&a, &b));
+    assert!(semantic_diff(&a, &b, &CompareOptions::new())
+        .unwrap()
+        .contains("exemplar"));
+    assert_semantic_eq(&a, &b, &CompareOptions::new().with_ignore_exemplars(true));
+
+    let c = parse_prometheus("# TYPE bar gauge\nbar{x=\"1\",y=\"2\"} 1\nbar{x=\"2\",y=\"2\"} 3\n")
+        .unwrap();
+    assert_eq!(
+        semantic_diff(&c, &b, &CompareOptions::new().with_ignore_exemplars(true)).unwrap(),
+        "family bar: sample {\"x\": \"2\", \"y\": \"2\"}: value: 3 != 2"
+    );
+}
+
+#[test]
+fn test_canonicalize() {
+    use crate::{MetricFamily, MetricNumber, PrometheusType, PrometheusValue, Sample};
+
+    let family = MetricFamily::new(
+        String::from("foo"),
+        vec![String::from("b"), String::from("a")],
+        PrometheusType::Gauge,
+        String::new(),
+        String::new(),
+    )
+    .with_samples(vec![
+        Sample::new(
+            vec![String::from("2"), String::from("x")],
+            None,
+            PrometheusValue::Gauge(MetricNumber::Float(3.0)),
+        ),
+        Sample::new(
+            vec![String::from("1"), String::from("x")],
+            None,
+            PrometheusValue::Gauge(MetricNumber::Float(0.5)),
+        ),
+    ])
+    .unwrap()
+    .canonicalize();
+
+    assert_eq!(family.get_label_names(), &["a", "b"]);
+    assert_eq!(
+        family.to_string(),
+        "# TYPE foo gauge\nfoo{a=\"x\",b=\"1\"} 0.5\nfoo{a=\"x\",b=\"2\"} 3\n"
+    );
+    assert_eq!(
+        family.iter_samples().nth(1).unwrap().value,
+        PrometheusValue::Gauge(MetricNumber::Int(3))
+    );
+}
+
+#[test]
+fn test_fill_timestamps() {
+    use crate::{FillPolicy, ParserOptions};
+
+    let mut exposition =
+        parse_prometheus("# TYPE foo gauge\nfoo{a=\"1\"} 1 5\nfoo{a=\"2\"} 2 7\n").unwrap();
+
+    exposition.fill_timestamps(10., FillPolicy::Missing);
+    let timestamps: Vec<_> = exposition.families["foo"]
+        .iter_samples()
+        .map(|s| s.timestamp)
+        .collect();
+    assert_eq!(timestamps, vec![Some(5.), Some(7.)]);
+
+    exposition.fill_timestamps(10., FillPolicy::Overwrite);
+    assert!(exposition.families["foo"]
+        .iter_samples()
+        .all(|s| s.timestamp == Some(10.)));
+
+    exposition.fill_timestamps(10., FillPolicy::Clear);
+    assert!(exposition.families["foo"]
+        .iter_samples()
+        .all(|s| s.timestamp.is_none()));
+    assert!(exposition.validate(&ParserOptions::default()).is_ok());
+}
+
+#[test]
+fn test_table_string() {
+    use crate::TableOptions;
+
+    let exposition = parse_prometheus(
+        "# TYPE foo histogram\nfoo_bucket{path=\"/a/very/long/path\",le=\"1\"} 1\nfoo_bucket{path=\"/a/very/long/path\",le=\"+Inf\"} 2\nfoo_sum{path=\"/a/very/long/path\"} 3\nfoo_count{path=\"/a/very/long/path\"} 2\n",
+    )
+    .unwrap();
+
+    let table = exposition.to_table_string_with_options(
+        &TableOptions::new()
+            .with_max_column_width(Some(16))
+            .with_max_rows(Some(2)),
+    );
+
+    assert_eq!(
+        table,
+        "FAMILY      TYPE       LABELS            VALUE  TIMESTAMP\n\
+         foo_bucket  histogram  {path=\"/a/very/…  1\n\
+         foo_bucket  histogram  {path=\"/a/very/…  2\n\
+         ... (2 more rows)\n"
+    );
+}
+
+#[test]
+fn test_filter_by_time() {
+    use crate::PrometheusValue;
+
+    let mut exposition = parse_prometheus(
+        "# TYPE foo histogram\nfoo_bucket{a=\"1\",le=\"+Inf\"} 1 100 # {trace=\"x\"} 1 50\nfoo_bucket{a=\"2\",le=\"+Inf\"} 2 200 # {trace=\"y\"} 1 150\n# TYPE bar gauge\nbar 1 10\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        exposition.families["foo"]
+            .samples_between(100., 200.)
+            .count(),
+        1
+    );
+
+    exposition.filter_by_time(100.0..=200.0);
+    assert!(!exposition.families.contains_key("bar"));
+
+    let exemplars: Vec<_> = exposition.families["foo"]
+        .iter_samples()
+        .map(|s| match &s.value {
+            PrometheusValue::Histogram(h) => h.buckets[0]
+                .exemplar
+                .as_ref()
+                .map(|e| e.labels["trace"].clone()),
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(exemplars, vec![None, Some(String::from("y"))]);
+}
+
+#[test]
+fn test_family_display() {
+    use crate::openmetrics::parse_openmetrics;
+
+    let exposition = parse_openmetrics(
+        "# TYPE latency histogram\nlatency_bucket{le=\"1\"} 1 5\nlatency_bucket{le=\"+Inf\"} 2 5\nlatency_sum 1.5 5\nlatency_count 2 5\n# TYPE temp gauge\n# UNIT temp celsius\ntemp 21.5\n# EOF\n",
+    )
+    .unwrap();
+
+    // Each family renders on its own as valid text, including the timestamps of every line
+    let latency = exposition.families["latency"].to_string();
+    assert!(latency.contains("latency_sum 1.5 5\n"));
+    assert!(parse_openmetrics(&format!("{}# EOF\n", latency)).is_ok());
+
+    assert!(exposition.families["temp"]
+        .to_string()
+        .contains("# UNIT temp celsius\n"));
+
+    // Prometheus has no UNIT descriptor, so it's left out there
+    let mut prometheus = parse_prometheus("# TYPE temp gauge\ntemp 21.5\n").unwrap();
+    let temp = prometheus.families.get_mut("temp").unwrap();
+    temp.unit = String::from("celsius");
+    assert_eq!(temp.to_string(), "# TYPE temp gauge\ntemp 21.5\n");
+}
+
+#[test]
+fn test_escaping_tricky_values() {
+    use crate::{
+        escape_help, escape_label_value, openmetrics::parse_openmetrics, unescape_help,
+        unescape_label_value, ExpositionFormat,
+    };
+
+    let values = [
+        "",
+        "plain",
+        "\\",
+        "trailing \\",
+        "\\n is not a newline",
+        "a\nb",
+        "\"quoted\"",
+        "\\\"",
+        "ünïcødé ✓",
+    ];
+
+    for value in values {
+        assert_eq!(unescape_label_value(&escape_label_value(value)), value);
+        for format in [ExpositionFormat::OpenMetrics, ExpositionFormat::Prometheus] {
+            assert_eq!(unescape_help(&escape_help(value, format), format), value);
+        }
+    }
+
+    // Through a full parse -> render -> parse cycle
+    let exposition = "# TYPE foo gauge\n# HELP foo \\\\n \\\"q\\\" \\\\\nfoo{a=\"\\\\n\",b=\"x\\ny\",c=\"\\\"\\\\\"} 1\n# EOF\n";
+    let parsed = parse_openmetrics(exposition).unwrap();
+    let family = &parsed.families["foo"];
+    assert_eq!(family.help, "\\n \"q\" \\");
+    assert_eq!(family.escaped_help(), "\\\\n \\\"q\\\" \\\\");
+
+    let sample = family.iter_samples().next().unwrap();
+    let labels = sample.get_labelset().unwrap();
+    assert_eq!(labels.get_label_value("a"), Some("\\n"));
+    assert_eq!(labels.get_label_value("b"), Some("x\ny"));
+    assert_eq!(labels.get_label_value("c"), Some("\"\\"));
+    assert_eq!(labels.get_escaped_label_value("c").unwrap(), "\\\"\\\\");
+
+    let reparsed = parse_openmetrics(&parsed.to_openmetrics_string()).unwrap();
+    assert_eq!(
+        reparsed.to_openmetrics_string(),
+        parsed.to_openmetrics_string()
+    );
+    assert_eq!(reparsed.families["foo"].help, family.help);
+}
+
+#[test]
+fn test_negotiate() {
+    use crate::{negotiate, ExpositionFormat, OPENMETRICS_CONTENT_TYPE};
+
+    assert_eq!(
+        negotiate("text/plain;q=0.9, application/openmetrics-text;version=1.0.0;q=0.8"),
+        ExpositionFormat::Prometheus
+    );
+    assert_eq!(
+        negotiate("application/json, application/openmetrics-text;q=0.2"),
+        ExpositionFormat::OpenMetrics
+    );
+    assert_eq!(
+        negotiate("application/openmetrics-text;q=0, application/json"),
+        ExpositionFormat::Prometheus
+    );
+    assert_eq!(
+        negotiate("Application/OpenMetrics-Text").content_type(),
+        OPENMETRICS_CONTENT_TYPE
+    );
+}
+
+#[test]
+fn test_family_order_preserved() {
+    use crate::openmetrics::parse_openmetrics;
+    use crate::RenderOptions;
+
+    let exposition =
+        "# TYPE zeta gauge\nzeta 1\n# TYPE alpha gauge\nalpha 2\n# TYPE mid gauge\nmid 3\n# EOF\n";
+    let mut parsed = parse_openmetrics(exposition).unwrap();
+    assert_eq!(
+        parsed.families.keys().collect::<Vec<_>>(),
+        vec!["zeta", "alpha", "mid"]
+    );
+    assert_eq!(parsed.to_openmetrics_string(), exposition);
+
+    // Canonical output sorts the families by name instead
+    assert_eq!(
+        crate::openmetrics::render_openmetrics_with_options(
+            &parsed,
+            &RenderOptions::new().with_canonical(true)
+        ),
+        "# TYPE alpha gauge\nalpha 2\n# TYPE mid gauge\nmid 3\n# TYPE zeta gauge\nzeta 1\n# EOF\n"
+    );
+
+    // Removing a family keeps the order of the rest
+    parsed.families.remove("alpha");
+    assert_eq!(
+        parsed.families.keys().collect::<Vec<_>>(),
+        vec!["zeta", "mid"]
+    );
+    assert_eq!(parsed.families["mid"].family_name, "mid");
+
+    // As does retaining some of them, with lookups still finding the ones that moved
+    let mut map: crate::OrderedMap<usize> = (0..10).map(|i| (format!("f{}", i), i)).collect();
+    map.remove("f2");
+    map.retain(|_, v| *v % 3 != 0);
+    assert_eq!(map.values().copied().collect::<Vec<_>>(), [1, 4, 5, 7, 8]);
+    assert!(map
+        .keys()
+        .all(|name| map[name] == name[1..].parse::<usize>().unwrap()));
+    assert!(!map.contains_key("f3") && !map.contains_key("f2"));
+    map.insert(String::from("f0"), 0);
+    map.sort_keys();
+    assert_eq!(
+        map.keys().collect::<Vec<_>>(),
+        ["f0", "f1", "f4", "f5", "f7", "f8"]
+    );
+    assert_eq!(map["f8"], 8);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_round_trip() {
+    use crate::openmetrics::parse_openmetrics;
+    use crate::{MetricsExposition, OpenMetricsType, OpenMetricsValue};
+
+    let exposition = "# HELP requests Requests served\n# TYPE requests counter\nrequests_total{path=\"/\"} 5 10\nrequests_created{path=\"/\"} 1 10\n# TYPE latency histogram\nlatency_bucket{le=\"0.5\"} 1 # {trace=\"b\"} 0.2\nlatency_bucket{le=\"+Inf\"} 2\nlatency_sum 1.5\nlatency_count 2\n# TYPE alpha gauge\nalpha 1.0\n# EOF\n";
+    let parsed = parse_openmetrics(exposition).unwrap();
+
+    let json = serde_json::to_string(&parsed).unwrap();
+    let deserialized: MetricsExposition<OpenMetricsType, OpenMetricsValue> =
+        serde_json::from_str(&json).unwrap();
+    assert_eq!(deserialized.to_openmetrics_string(), exposition);
+
+    // Families are rebuilt with the usual checks, e.g. samples need a value for every label
+    let mismatched = json.replace("\"label_names\":[\"path\"]", "\"label_names\":[]");
+    assert!(
+        serde_json::from_str::<MetricsExposition<OpenMetricsType, OpenMetricsValue>>(&mismatched)
+            .is_err()
+    );
+}
+#[cfg(feature = "serde")]
+#[test]
+fn test_deserialize_fixture() {
+    use crate::{MetricsExposition, OpenMetricsType, OpenMetricsValue};
+
+    let fixture = r#"{"families": {
+        "requests": {
+            "family_type": "counter",
+            "help": "Requests served",
+            "label_names": ["path"],
+            "samples": [
+                {"label_values": ["/"], "value": {"counter": {"value": 5}}},
+                {"label_values": ["/login"], "value": {"counter": {"value": 1.5}}}
+            ]
+        },
+        "latency": {
+            "family_type": "histogram",
+            "samples": [{"value": {"histogram": {
+                "sum": 2.5,
+                "count": 3,
+                "buckets": [
+                    {"upper_bound": 1, "count": 2},
+                    {"upper_bound": "+Inf", "count": 3}
+                ]
+            }}}]
+        },
+        "temp": {"family_type": "Gauge", "samples": [{"value": {"Gauge": {"Float": 21.0}}, "timestamp": 10}]}
+    }}"#;
+
+    let exposition: MetricsExposition<OpenMetricsType, OpenMetricsValue> =
+        serde_json::from_str(fixture).unwrap();
+    assert_eq!(
+        exposition.to_openmetrics_string(),
+        "# HELP requests Requests served\n# TYPE requests counter\nrequests_total{path=\"/\"} 5\nrequests_total{path=\"/login\"} 1.5\n# TYPE latency histogram\nlatency_bucket{le=\"1\"} 2\nlatency_bucket{le=\"+Inf\"} 3\nlatency_sum 2.5\nlatency_count 3\n# TYPE temp gauge\ntemp 21.0 10\n# EOF\n"
+    );
+
+    // A family can't be named differently from its key
+    let renamed = r#"{"families": {"a": {"family_name": "b", "family_type": "gauge"}}}"#;
+    assert!(
+        serde_json::from_str::<MetricsExposition<OpenMetricsType, OpenMetricsValue>>(renamed)
+            .is_err()
+    );
+}
+
+#[test]
+fn test_number_parsing() {
+    use crate::internal::{parse_f64, parse_i64};
+
+    let numbers = [
+        "0",
+        "-0",
+        "+0",
+        "1",
+        "-1",
+        "+1",
+        "42",
+        "9007199254740992",
+        "9007199254740993",
+        "999999999999999999",
+        "9223372036854775807",
+        "-9223372036854775808",
+        "9223372036854775808",
+        "1.",
+        ".5",
+        "-.5",
+        ".",
+        "-",
+        "+",
+        "",
+        "1.5",
+        "0.1",
+        "0.3",
+        "123.456",
+        "-0.0",
+        "1e3",
+        "1E3",
+        "1e-3",
+        "1e+3",
+        "1e22",
+        "1e23",
+        "1e-22",
+        "1e-23",
+        "1.5e300",
+        "1e400",
+        "1e-400",
+        "1e",
+        "1e+",
+        "1ee3",
+        "1.2.3",
+        "1_000",
+        " 1",
+        "1 ",
+        "0x10",
+        "NaN",
+        "nan",
+        "Inf",
+        "+Inf",
+        "-inf",
+        "infinity",
+        "12345678901234567890",
+        "0.000000000000000000000000001",
+        "179769313486231570000000000000000000000e270",
+    ];
+
+    for number in numbers {
+        let expected = number.parse::<f64>().ok();
+        match (parse_f64(number), expected) {
+            (Some(a), Some(b)) if a.is_nan() => assert!(b.is_nan(), "{}", number),
+            (a, b) => assert_eq!(a.map(f64::to_bits), b.map(f64::to_bits), "{}", number),
+        }
+
+        assert_eq!(parse_i64(number), number.parse::<i64>().ok(), "{}", number);
+    }
+}
+
+#[test]
+fn test_name_escaping() {
+    use crate::openmetrics::parse_openmetrics_with_options;
+    use crate::{EscapingScheme, ParserOptions};
+    use std::convert::TryFrom;
+
+    let names = [
+        "http.requests_total",
+        "Ünïcödé",
+        "9lives",
+        "a:b_c",
+        "_",
+        "",
+        "x y",
+    ];
+    for name in names {
+        for scheme in [EscapingScheme::Dots, EscapingScheme::Values] {
+            let escaped = scheme.escape_name(name);
+            if name.contains('.') || name.contains('_') || scheme == EscapingScheme::Values {
+                assert_eq!(scheme.unescape_name(&escaped), name, "{} {}", scheme, name);
+            }
+        }
+    }
+
+    assert_eq!(
+        EscapingScheme::Underscores.escape_name("9lives.x"),
+        "_lives_x"
+    );
+    assert_eq!(EscapingScheme::Dots.escape_name("Ü"), "__");
+    assert_eq!(EscapingScheme::Values.escape_name("Ü"), "U___dc_");
+    assert_eq!(
+        EscapingScheme::Values.escape_name("valid_name"),
+        "valid_name"
+    );
+    assert_eq!(
+        EscapingScheme::Values.unescape_name("U__bad_zz_"),
+        "U__bad_zz_"
+    );
+    assert_eq!(
+        EscapingScheme::Values.unescape_name("U__open_2e"),
+        "U__open_2e"
+    );
+    assert_eq!(EscapingScheme::AllowUtf8.escape_name("a.b"), "a.b");
+    for scheme in ["allow-utf-8", "underscores", "dots", "values"] {
+        assert_eq!(EscapingScheme::try_from(scheme).unwrap().as_str(), scheme);
+    }
+    assert!(EscapingScheme::try_from("hex").is_err());
+
+    // Names are unescaped as they're parsed, per the Content-Type they were served with
+    let content_type = "application/openmetrics-text; version=1.0.0; escaping=values";
+    let scheme = EscapingScheme::from_content_type(content_type).unwrap();
+    let exposition = "# TYPE U__http_2e_requests counter\nU__http_2e_requests_total{U__route_2e_name=\"/\"} 1\n# EOF\n";
+    let options = ParserOptions::new().with_name_escaping(scheme);
+    let mut parsed = parse_openmetrics_with_options(exposition, options).unwrap();
+    let family = &parsed.families["http.requests"];
+    assert_eq!(family.get_label_names(), &["route.name"]);
+
+    // And escaped again on the way out to a legacy consumer
+    parsed.escape_names(EscapingScheme::Underscores).unwrap();
+    assert_eq!(
+        parsed.to_string(),
+        "# TYPE http_requests counter\nhttp_requests_total{route_name=\"/\"} 1\n"
+    );
+
+    let mut clash = parse_prometheus("a__b{x__y=\"1\",x_y=\"2\"} 1\n").unwrap();
+    let before = clash.to_string();
+    let error = clash.unescape_names(EscapingScheme::Dots).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Label `x_y` already exists in metric family"
+    );
+    assert_eq!(clash.to_string(), before);
+
+    // As does two families escaping to the same name
+    let exposition = "# TYPE a gauge\na 1\n# TYPE U__a gauge\nU__a 2\n";
+    let mut clash = parse_prometheus(exposition).unwrap();
+    let before = clash.to_string();
+    assert!(clash.unescape_names(EscapingScheme::Values).is_err());
+    assert_eq!(clash.families.len(), 2);
+    assert_eq!(clash.to_string(), before);
+}
+
+#[test]
+fn test_timestamp_units() {
+    use crate::convert::exposition::{openmetrics_to_prometheus, prometheus_to_openmetrics};
+    use crate::openmetrics::parse_openmetrics;
+    use crate::TimestampUnit;
+
+    let prometheus = parse_prometheus("foo 1 1500\n").unwrap();
+    let openmetrics = parse_openmetrics("# TYPE foo unknown\nfoo 1 1.5\n# EOF\n").unwrap();
+
+    let timestamp = |family: &crate::PrometheusMetricFamily, unit| {
+        family.sample_timestamp(family.iter_samples().next().unwrap(), unit)
+    };
+    let om_timestamp = |family: &crate::OpenMetricsMetricFamily, unit| {
+        family.sample_timestamp(family.iter_samples().next().unwrap(), unit)
+    };
+
+    // The same instant, whichever format it was written in
+    for unit in [TimestampUnit::Seconds, TimestampUnit::Milliseconds] {
+        let expected = TimestampUnit::Seconds.convert(1.5, unit);
+        assert_eq!(timestamp(&prometheus.families["foo"], unit), Some(expected));
+        assert_eq!(
+            om_timestamp(&openmetrics.families["foo"], unit),
+            Some(expected)
+        );
+
+        let converted = prometheus_to_openmetrics(&prometheus).unwrap();
+        assert_eq!(
+            om_timestamp(&converted.families["foo"], unit),
+            Some(expected)
+        );
+        let converted = openmetrics_to_prometheus(&openmetrics).unwrap();
+        assert_eq!(timestamp(&converted.families["foo"], unit), Some(expected));
+    }
+}
+
+#[test]
+fn test_labeled_samples() {
+    use crate::PrometheusValue;
+
+    let exposition = "foo{instance=\"a\",job=\"x\"} 1\nfoo{instance=\"b\",job=\"y\"} 2\n";
+    let parsed = parse_prometheus(exposition).unwrap();
+    let family = &parsed.families["foo"];
+
+    let instances: Vec<_> = family
+        .iter_labeled_samples()
+        .map(|sample| (sample.get_label("instance").unwrap(), sample.value.clone()))
+        .collect();
+    assert_eq!(
+        instances,
+        [
+            ("a", PrometheusValue::Unknown(1.into())),
+            ("b", PrometheusValue::Unknown(2.into()))
+        ]
+    );
+
+    let sample = family.iter_labeled_samples().nth(1).unwrap();
+    assert_eq!(
+        sample.labels().collect::<Vec<_>>(),
+        [("instance", "b"), ("job", "y")]
+    );
+    assert_eq!(sample.get_label("missing"), None);
+    assert_eq!(sample.sample().get_label_values(), ["b", "y"]);
+}
+
+#[test]
+fn test_labels_map() {
+    use std::collections::{BTreeMap, HashMap};
+
+    let parsed = parse_prometheus("foo{b=\"2\",a=\"1\"} 1\nfoo{a=\"3\",b=\"4\"} 2\n").unwrap();
+    let maps: Vec<_> = parsed.families["foo"]
+        .iter_labeled_samples()
+        .map(|sample| sample.labels_map())
+        .collect();
+    assert_eq!(maps[0], BTreeMap::from([("a", "1"), ("b", "2")]));
+    assert_eq!(maps[1], BTreeMap::from([("a", "3"), ("b", "4")]));
+
+    let sample = parsed.families["foo"]
+        .iter_labeled_samples()
+        .next()
+        .unwrap();
+    let hashed: HashMap<_, _> = sample.labels().collect();
+    assert_eq!(hashed["b"], "2");
+}
+
+#[test]
+fn test_get_sample() {
+    use crate::PrometheusValue;
+
+    let exposition = "http{code=\"200\",method=\"GET\"} 1\nhttp{code=\"200\",method=\"POST\"} 2\n";
+    let mut parsed = parse_prometheus(exposition).unwrap();
+    let family = parsed.families.get_mut("http").unwrap();
+
+    let sample = family
+        .get_sample(&[("method", "POST"), ("code", "200")])
+        .unwrap();
+    assert_eq!(sample.value, PrometheusValue::Unknown(2.into()));
+
+    // Every label has to be given, and no others
+    assert!(family.get_sample(&[("code", "200")]).is_none());
+    assert!(family
+        .get_sample(&[("code", "200"), ("code", "200")])
+        .is_none());
+    assert!(family
+        .get_sample(&[("code", "200"), ("method", "GET"), ("path", "/")])
+        .is_none());
+    assert!(family
+        .get_sample(&[("code", "404"), ("method", "GET")])
+        .is_none());
+
+    family
+        .get_sample_mut(&[("code", "200"), ("method", "GET")])
+        .unwrap()
+        .value = PrometheusValue::Unknown(3.into());
+    assert!(family
+        .to_string()
+        .contains("http{code=\"200\",method=\"GET\"} 3\n"));
+}
+
+#[test]
+fn test_exposition_iterators() {
+    let mut parsed =
+        parse_prometheus("# TYPE b gauge\nb 1\n# TYPE a gauge\na 2\n# TYPE c gauge\nc 3\n")
+            .unwrap();
+    assert_eq!(parsed.len(), 3);
+    assert!(!parsed.is_empty());
+    assert!(
+        crate::MetricsExposition::<crate::PrometheusType, crate::PrometheusValue>::new().is_empty()
+    );
+
+    let mut names = Vec::new();
+    for (name, family) in &parsed {
+        assert_eq!(name, &family.family_name);
+        names.push(name.clone());
+    }
+    assert_eq!(names, ["b", "a", "c"]);
+
+    for (_, family) in &mut parsed {
+        family.help = String::from("help");
+    }
+    assert!(parsed.iter().all(|(_, family)| family.help == "help"));
+
+    parsed
+        .iter_mut()
+        .for_each(|(_, family)| family.unit.clear());
+    let owned: Vec<_> = parsed
+        .into_iter()
+        .filter(|(name, _)| name != "a")
+        .map(|(_, family)| family.samples_count())
+        .collect();
+    assert_eq!(owned, [1, 1]);
+}
+
+#[test]
+fn test_flat_samples() {
+    use crate::openmetrics::parse_openmetrics;
+    use crate::OpenMetricsType;
+
+    let exposition = "# TYPE requests counter
+requests_total{code=\"200\"} 5 # {trace_id=\"abc\"} 1 1.5
+# TYPE latency histogram
+latency_bucket{code=\"200\",le=\"1\"} 1 2
+latency_bucket{code=\"200\",le=\"+Inf\"} 2 2
+latency_sum{code=\"200\"} 1.5 2
+latency_count{code=\"200\"} 2 2
+# EOF
+";
+    let parsed = parse_openmetrics(exposition).unwrap();
+    let samples: Vec<_> = parsed.iter_samples().collect();
+    assert_eq!(samples.len(), 5);
+
+    let counter = &samples[0];
+    assert_eq!(counter.family_name, "requests");
+    assert_eq!(counter.family_type, &OpenMetricsType::Counter);
+    assert_eq!(counter.suffix, "_total");
+    assert_eq!(counter.labels, [("code", "200".to_owned())]);
+    assert_eq!(counter.value, 5.);
+    assert_eq!(counter.timestamp, None);
+    assert_eq!(counter.exemplar.as_ref().unwrap().labels["trace_id"], "abc");
+
+    let bucket = &samples[1];
+    assert_eq!(bucket.name(), "latency_bucket");
+    assert_eq!(
+        bucket.labels,
+        [("code", "200".to_owned()), ("le", "1".to_owned())]
+    );
+    assert_eq!(bucket.timestamp, Some(2.));
+
+    let names: Vec<_> = samples[2..].iter().map(|s| s.name()).collect();
+    assert_eq!(names, ["latency_bucket", "latency_sum", "latency_count"]);
+}
+
+#[test]
+fn test_typed_value_accessors() {
+    use crate::openmetrics::parse_openmetrics;
+
+    let exposition = "# TYPE c counter
+c_total 3
+# TYPE h histogram
+h_bucket{le=\"+Inf\"} 1
+# TYPE s stateset
+s{s=\"a\"} 1
+# TYPE i info
+i_info{version=\"1\"} 1
+# EOF
+";
+    let parsed = parse_openmetrics(exposition).unwrap();
+    let value = |name: &str| {
+        parsed.families[name]
+            .iter_samples()
+            .next()
+            .unwrap()
+            .value
+            .clone()
+    };
+
+    assert_eq!(value("c").as_counter().unwrap().value.as_f64(), 3.);
+    assert!(value("c").as_gauge().is_none());
+    assert_eq!(value("h").as_histogram().unwrap().buckets.len(), 1);
+    assert!(value("h").as_gauge_histogram().is_none());
+    assert_eq!(value("s").as_stateset().map(|n| n.as_f64()), Some(1.));
+    assert!(value("i").is_info());
+    assert!(value("i").as_summary().is_none());
+
+    let parsed = parse_prometheus("# TYPE g gauge\ng 2\n# TYPE u untyped\nu 1\n").unwrap();
+    let value = |name: &str| {
+        parsed.families[name]
+            .iter_samples()
+            .next()
+            .unwrap()
+            .value
+            .clone()
+    };
+    assert_eq!(value("g").as_gauge().map(|n| n.as_f64()), Some(2.));
+    assert_eq!(value("u").as_untyped().map(|n| n.as_f64()), Some(1.));
+    assert!(value("u").as_counter().is_none());
+    assert!(value("g").as_histogram().is_none());
+}
+
+#[test]
+fn test_typed_family_getters() {
+    use crate::openmetrics::parse_openmetrics;
+
+    let exposition = "# TYPE requests counter
+requests_total{code=\"200\"} 3
+requests_total{code=\"500\"} 1
+# TYPE latency histogram
+latency_bucket{le=\"+Inf\"} 2
+# EOF
+";
+    let parsed = parse_openmetrics(exposition).unwrap();
+
+    let requests = parsed.get_counter("requests").unwrap().unwrap();
+    let totals: Vec<_> = requests
+        .iter()
+        .map(|(sample, counter)| (sample.get_label("code").unwrap(), counter.value.as_f64()))
+        .collect();
+    assert_eq!(totals, [("200", 3.), ("500", 1.)]);
+    assert_eq!(requests.family_name, "requests");
+    assert!(requests.get(&[("code", "404")]).is_none());
+
+    let latency = parsed.get_histogram("latency").unwrap().unwrap();
+    assert_eq!(latency.get(&[]).unwrap().buckets.len(), 1);
+
+    let error = parsed.get_gauge("latency").err().unwrap();
+    assert_eq!(
+        error.to_string(),
+        "Expected latency to be a gauge, but it's a histogram"
+    );
+    assert!(parsed.get_summary("missing").unwrap().is_none());
+
+    let parsed = parse_prometheus("# TYPE temp gauge\ntemp 21.5\n").unwrap();
+    let temp = parsed.get_gauge("temp").unwrap().unwrap();
+    assert_eq!(temp.get(&[]).map(|n| n.as_f64()), Some(21.5));
+    assert!(parsed.get_counter("temp").is_err());
+}
+
+#[test]
+fn test_exposition_builder() {
+    use crate::openmetrics::parse_openmetrics;
+    use crate::{ExpositionBuilder, OpenMetricsValue, SummaryValue};
+
+    let summary = SummaryValue::builder()
+        .quantile(0.5, 0.2)
+        .sum(3.5)
+        .count(10)
+        .build()
+        .unwrap();
+    let built = ExpositionBuilder::new()
+        .counter("http_requests", "Requests served", "")
+        .sample(&[("method", "GET"), ("code", "200")], 12)
+        .sample(&[("code", "500"), ("method", "GET")], 1)
+        .gauge("temperature", "", "celsius")
+        .sample(&[], 21.5)
+        .at(1000.)
+        .summary("rpc", "", "")
+        .sample_value(&[], OpenMetricsValue::Summary(summary))
+        .build()
+        .unwrap();
+
+    let rendered = built.to_openmetrics_string();
+    assert_eq!(
+        rendered,
+        "# HELP http_requests Requests served
+# TYPE http_requests counter
+http_requests_total{code=\"200\",method=\"GET\"} 12
+http_requests_total{code=\"500\",method=\"GET\"} 1
+# TYPE temperature gauge
+# UNIT temperature celsius
+temperature 21.5 1000
+# TYPE rpc summary
+rpc{quantile=\"0.5\"} 0.2
+rpc_sum 3.5
+rpc_count 10
+# EOF
+"
+    );
+    assert_eq!(
+        parse_openmetrics(&rendered)
+            .unwrap()
+            .to_openmetrics_string(),
+        rendered
+    );
+
+    // Mistakes are reported when building
+    assert!(ExpositionBuilder::new().sample(&[], 1).build().is_err());
+    assert!(ExpositionBuilder::new()
+        .counter("a", "", "")
+        .sample(&[], -1)
+        .build()
+        .is_err());
+    assert!(ExpositionBuilder::new()
+        .counter("a", "", "")
+        .sample(&[("x", "1")], 1)
+        .sample(&[("y", "1")], 1)
+        .build()
+        .is_err());
+    assert!(ExpositionBuilder::new()
+        .gauge("a", "", "")
+        .sample(&[], 1)
+        .sample(&[], 2)
+        .build()
+        .is_err());
+    assert!(ExpositionBuilder::new()
+        .gauge("a", "", "")
+        .gauge("a", "", "")
+        .build()
+        .is_err());
+    assert!(ExpositionBuilder::new()
+        .gauge("a", "", "")
+        .sample(&[("x", "1"), ("x", "2")], 1)
+        .build()
+        .is_err());
+    assert!(ExpositionBuilder::new()
+        .histogram("a", "", "")
+        .sample(&[], 1)
+        .build()
+        .is_err());
+}
+
+#[test]
+fn test_inline_label_values() {
+    use crate::LabelValues;
+
+    let mut values: LabelValues = ["b", "d"].iter().map(|v| v.to_string()).collect();
+    values.insert(0, "a".to_owned());
+    values.insert(2, "c".to_owned());
+    assert_eq!(values.as_slice(), ["a", "b", "c", "d"]);
+    assert!(values.is_inline());
+
+    // A fifth value spills onto the heap, keeping the order
+    values.push("e".to_owned());
+    assert!(!values.is_inline());
+    assert_eq!(values.remove(1), "b");
+    assert_eq!(values.as_slice(), ["a", "c", "d", "e"]);
+
+    let mut inline = LabelValues::from(vec!["x".to_owned(), "y".to_owned()]);
+    assert_eq!(inline.remove(0), "x");
+    assert_eq!(inline, LabelValues::from(vec!["y".to_owned()]));
+    assert_eq!(Vec::from(inline), ["y"]);
+
+    // Parsed samples go through the same storage
+    let parsed = parse_prometheus("foo{a=\"1\",b=\"2\",c=\"3\",d=\"4\",e=\"5\"} 1\n").unwrap();
+    let sample = parsed.families["foo"].iter_samples().next().unwrap();
+    assert_eq!(sample.get_label_values(), ["1", "2", "3", "4", "5"]);
+}
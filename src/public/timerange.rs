@@ -0,0 +1,116 @@
+use std::ops::RangeBounds;
+
+use crate::{
+    Exemplar, HistogramValue, MetricFamily, MetricsExposition, OpenMetricsValue, PrometheusValue,
+    RenderableMetricValue, Sample, Timestamp,
+};
+
+/// A value that can carry exemplars
+pub trait ExemplarValue {
+    /// Every exemplar slot in the value (e.g. one per histogram bucket), whether filled or not
+    fn exemplars_mut(&mut self) -> Vec<&mut Option<Exemplar>>;
+}
+
+impl ExemplarValue for HistogramValue {
+    fn exemplars_mut(&mut self) -> Vec<&mut Option<Exemplar>> {
+        self.buckets.iter_mut().map(|b| &mut b.exemplar).collect()
+    }
+}
+
+impl ExemplarValue for OpenMetricsValue {
+    fn exemplars_mut(&mut self) -> Vec<&mut Option<Exemplar>> {
+        match self {
+            OpenMetricsValue::Counter(c) => vec![&mut c.exemplar],
+            OpenMetricsValue::Histogram(h) | OpenMetricsValue::GaugeHistogram(h) => {
+                h.exemplars_mut()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl ExemplarValue for PrometheusValue {
+    fn exemplars_mut(&mut self) -> Vec<&mut Option<Exemplar>> {
+        match self {
+            PrometheusValue::Counter(c) => vec![&mut c.exemplar],
+            PrometheusValue::Histogram(h) => h.exemplars_mut(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Exemplars without a timestamp can't be placed in time, so are always kept
+fn drop_exemplar_outside<R>(exemplar: &mut Option<Exemplar>, range: &R)
+where
+    R: RangeBounds<Timestamp>,
+{
+    if let Some(timestamp) = exemplar.as_ref().and_then(|e| e.timestamp) {
+        if !range.contains(&timestamp) {
+            *exemplar = None;
+        }
+    }
+}
+
+impl<TypeSet, ValueType> MetricFamily<TypeSet, ValueType>
+where
+    TypeSet: Clone,
+    ValueType: RenderableMetricValue + Clone,
+{
+    /// The samples with a timestamp in `[start, end)`. Samples without a timestamp are never included
+    pub fn samples_between(
+        &self,
+        start: Timestamp,
+        end: Timestamp,
+    ) -> impl Iterator<Item = &Sample<ValueType>> {
+        self.iter_samples()
+            .filter(move |s| matches!(s.timestamp, Some(t) if (start..end).contains(&t)))
+    }
+}
+
+impl<TypeSet, ValueType> MetricFamily<TypeSet, ValueType>
+where
+    TypeSet: Clone,
+    ValueType: ExemplarValue + RenderableMetricValue + Clone,
+{
+    /// Drops the samples with a timestamp outside of `range` (or without a timestamp at all),
+    /// along with any exemplars of the remaining samples that are timestamped outside of it
+    pub fn filter_by_time<R>(&mut self, range: R)
+    where
+        R: RangeBounds<Timestamp>,
+    {
+        self.retain_samples(|s| matches!(s.timestamp, Some(t) if range.contains(&t)));
+        for sample in self.iter_samples_mut() {
+            drop_exemplar_outside(&mut sample.exemplar, &range);
+            for exemplar in sample.value.exemplars_mut() {
+                drop_exemplar_outside(exemplar, &range);
+            }
+        }
+    }
+}
+
+impl<TypeSet, ValueType> MetricsExposition<TypeSet, ValueType>
+where
+    TypeSet: Clone,
+    ValueType: ExemplarValue + RenderableMetricValue + Clone,
+{
+    /// Filters every family to the samples (and exemplars) within `range` (see
+    /// `MetricFamily::filter_by_time`). Families that are left with no samples are removed
+    /// ```
+    /// use openmetrics_parser::prometheus::parse_prometheus;
+    ///
+    /// let mut exposition =
+    ///     parse_prometheus("# TYPE up gauge\nup{job=\"a\"} 1 1000\nup{job=\"b\"} 1 2000\n").unwrap();
+    /// exposition.filter_by_time(1500.0..);
+    /// assert_eq!(exposition.to_string(), "# TYPE up gauge\nup{job=\"b\"} 1 2000\n");
+    /// ```
+    pub fn filter_by_time<R>(&mut self, range: R)
+    where
+        R: RangeBounds<Timestamp>,
+    {
+        for family in self.families.values_mut() {
+            family.filter_by_time((range.start_bound(), range.end_bound()));
+        }
+
+        self.families.retain(|_, f| f.samples_count() > 0);
+    }
+}
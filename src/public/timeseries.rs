@@ -0,0 +1,407 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    format_bound, Exemplar, ExpositionTypeSet, HistogramValue, LabelValues, MetricFamily,
+    MetricNumber, MetricsExposition, OpenMetricsValue, ParseError, PrometheusType, PrometheusValue,
+    Sample, SummaryValue, Timestamp, TimestampUnit,
+};
+
+/// The label that holds the metric name in a TimeSeries, as in Prometheus itself
+pub const METRIC_NAME_LABEL: &str = "__name__";
+
+/// A TimeSeries is a single series (a metric name and labelset), along with any number of points.
+/// This is the model Prometheus uses internally (and in its HTTP API / remote write), where
+/// the metric name is just another label (`__name__`), and histograms and summaries are split
+/// up into their `_bucket`, `_sum`, `_count` etc series
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TimeSeries {
+    pub labels: BTreeMap<String, String>,
+
+    /// (timestamp, value) pairs, with the timestamp in milliseconds since the epoch
+    pub samples: Vec<(Timestamp, f64)>,
+}
+
+impl TimeSeries {
+    pub fn new(name: &str) -> Self {
+        let mut labels = BTreeMap::new();
+        labels.insert(METRIC_NAME_LABEL.to_owned(), name.to_owned());
+        Self {
+            labels,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, name: &str, value: &str) -> Self {
+        self.labels.insert(name.to_owned(), value.to_owned());
+        self
+    }
+
+    pub fn with_sample(mut self, timestamp: Timestamp, value: f64) -> Self {
+        self.samples.push((timestamp, value));
+        self
+    }
+
+    /// The metric name of the series, if it has one
+    pub fn name(&self) -> Option<&str> {
+        self.labels.get(METRIC_NAME_LABEL).map(|s| s.as_str())
+    }
+
+    /// The labels of the series, excluding the metric name
+    pub fn iter_labels(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.labels
+            .iter()
+            .filter(|(k, _)| k.as_str() != METRIC_NAME_LABEL)
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Builds an (untyped) Prometheus exposition out of a set of series, using the latest point
+    /// of each one. Series with the same name end up in the same family, and so must share label names
+    pub fn to_prometheus_exposition(
+        series: &[TimeSeries],
+    ) -> Result<MetricsExposition<PrometheusType, PrometheusValue>, ParseError> {
+        let mut grouped: HashMap<&str, Vec<&TimeSeries>> = HashMap::new();
+        for s in series.iter() {
+            let name = s.name().ok_or_else(|| {
+                ParseError::InvalidMetric(format!("TimeSeries has no metric name: {:?}", s.labels))
+            })?;
+            grouped.entry(name).or_default().push(s);
+        }
+
+        let mut exposition = MetricsExposition::new();
+        for (name, series) in grouped {
+            let label_names: Vec<String> =
+                series[0].iter_labels().map(|(k, _)| k.to_owned()).collect();
+            let mut family = MetricFamily::new(
+                name.to_owned(),
+                label_names,
+                PrometheusType::Untyped,
+                String::new(),
+                String::new(),
+            );
+
+            for s in series {
+                let (timestamp, value) = match s.samples.iter().max_by(|a, b| a.0.total_cmp(&b.0)) {
+                    Some(point) => *point,
+                    None => continue,
+                };
+
+                if !s
+                    .iter_labels()
+                    .map(|(k, _)| k)
+                    .eq(family.get_label_names().iter())
+                {
+                    return Err(ParseError::InvalidMetric(format!(
+                        "All the series of `{}` must have the same label names",
+                        name
+                    )));
+                }
+
+                let label_values: LabelValues =
+                    s.iter_labels().map(|(_, v)| v.to_owned()).collect();
+                family.add_sample(Sample::new(
+                    label_values,
+                    Some(timestamp),
+                    PrometheusValue::Untyped(MetricNumber::Float(value)),
+                ))?;
+            }
+
+            exposition.families.insert(name.to_owned(), family);
+        }
+
+        Ok(exposition)
+    }
+}
+
+/// A single point of a metric value, as Prometheus would store it
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesPoint {
+    /// The metric name, including any suffix (e.g. `_bucket`)
+    pub name: String,
+
+    /// Labels on top of the sample's own, e.g. `le`
+    pub extra_labels: Vec<(&'static str, String)>,
+    pub value: f64,
+
+    /// Whether the value only ever goes up (until a reset), like counters and histogram buckets
+    pub monotonic: bool,
+
+    /// The exemplar attached to this point, if any (e.g. on a counter total, or a histogram bucket)
+    pub exemplar: Option<Exemplar>,
+}
+
+/// One series of a sample, flattened out of its family (see `MetricsExposition::iter_samples`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlatSample<'a, TypeSet> {
+    pub family_name: &'a str,
+    pub family_type: &'a TypeSet,
+
+    /// What the series adds to the family name (e.g. `_bucket`), if anything
+    pub suffix: String,
+
+    /// The sample's labels in the family's order, followed by any of the series' own (e.g. `le`)
+    pub labels: Vec<(&'a str, String)>,
+    pub value: f64,
+
+    /// As written, in the unit of the exposition's format
+    pub timestamp: Option<Timestamp>,
+    pub exemplar: Option<Exemplar>,
+}
+
+impl<TypeSet> FlatSample<'_, TypeSet> {
+    /// The name of the series, i.e. the family name followed by the suffix
+    pub fn name(&self) -> String {
+        format!("{}{}", self.family_name, self.suffix)
+    }
+}
+
+/// Splits a metric value up into the individual series that Prometheus would store it as
+/// (e.g. a histogram becomes its `_bucket`, `_sum` and `_count` series)
+pub trait SeriesValue {
+    fn series_points(&self, metric_name: &str) -> Vec<SeriesPoint>;
+
+    /// The histogram behind the value, if it has native buckets. Prometheus stores these as a
+    /// single series of histogram samples, under the family name
+    fn native_histogram(&self) -> Option<&HistogramValue> {
+        None
+    }
+}
+
+fn point(metric_name: &str, suffix: &str, value: f64, monotonic: bool) -> SeriesPoint {
+    SeriesPoint {
+        name: format!("{}{}", metric_name, suffix),
+        extra_labels: Vec::new(),
+        value,
+        monotonic,
+        exemplar: None,
+    }
+}
+
+fn histogram_points(
+    histogram: &HistogramValue,
+    metric_name: &str,
+    gauge_histogram: bool,
+) -> Vec<SeriesPoint> {
+    let monotonic = !gauge_histogram;
+    let mut points: Vec<SeriesPoint> = histogram
+        .buckets
+        .iter()
+        .map(|b| SeriesPoint {
+            name: format!("{}_bucket", metric_name),
+            extra_labels: vec![("le", format_bound(b.upper_bound))],
+            value: b.count.as_f64(),
+            monotonic,
+            exemplar: b.exemplar.clone(),
+        })
+        .collect();
+
+    let (sum_suffix, count_suffix) = if gauge_histogram {
+        ("_gsum", "_gcount")
+    } else {
+        ("_sum", "_count")
+    };
+
+    if let Some(sum) = histogram.sum {
+        points.push(point(metric_name, sum_suffix, sum.as_f64(), monotonic));
+    }
+
+    if let Some(count) = histogram.count {
+        points.push(point(metric_name, count_suffix, count as f64, monotonic));
+    }
+
+    points
+}
+
+fn summary_points(summary: &SummaryValue, metric_name: &str) -> Vec<SeriesPoint> {
+    let mut points: Vec<SeriesPoint> = summary
+        .quantiles
+        .iter()
+        .map(|q| SeriesPoint {
+            name: metric_name.to_owned(),
+            extra_labels: vec![("quantile", format_bound(q.quantile))],
+            value: q.value.as_f64(),
+            monotonic: false,
+            exemplar: None,
+        })
+        .collect();
+
+    if let Some(sum) = summary.sum {
+        points.push(point(metric_name, "_sum", sum.as_f64(), true));
+    }
+
+    if let Some(count) = summary.count {
+        points.push(point(metric_name, "_count", count as f64, true));
+    }
+
+    points
+}
+
+impl SeriesValue for PrometheusValue {
+    fn series_points(&self, metric_name: &str) -> Vec<SeriesPoint> {
+        match self {
+            PrometheusValue::Untyped(n)
+            | PrometheusValue::Unknown(n)
+            | PrometheusValue::Gauge(n) => vec![point(metric_name, "", n.as_f64(), false)],
+            PrometheusValue::Counter(c) => vec![SeriesPoint {
+                exemplar: c.exemplar.clone(),
+                ..point(metric_name, "", c.value.as_f64(), true)
+            }],
+            PrometheusValue::Histogram(h) => histogram_points(h, metric_name, false),
+            PrometheusValue::Summary(s) => summary_points(s, metric_name),
+        }
+    }
+
+    fn native_histogram(&self) -> Option<&HistogramValue> {
+        match self {
+            PrometheusValue::Histogram(h) if h.native.is_some() => Some(h),
+            _ => None,
+        }
+    }
+}
+
+impl SeriesValue for OpenMetricsValue {
+    fn series_points(&self, metric_name: &str) -> Vec<SeriesPoint> {
+        match self {
+            OpenMetricsValue::Untyped(n)
+            | OpenMetricsValue::Unknown(n)
+            | OpenMetricsValue::Gauge(n)
+            | OpenMetricsValue::StateSet(n) => vec![point(metric_name, "", n.as_f64(), false)],
+            OpenMetricsValue::Counter(c) => vec![SeriesPoint {
+                exemplar: c.exemplar.clone(),
+                ..point(metric_name, "_total", c.value.as_f64(), true)
+            }],
+            OpenMetricsValue::Histogram(h) => histogram_points(h, metric_name, false),
+            OpenMetricsValue::GaugeHistogram(h) => histogram_points(h, metric_name, true),
+            OpenMetricsValue::Info => vec![point(metric_name, "_info", 1., false)],
+            OpenMetricsValue::Summary(s) => summary_points(s, metric_name),
+        }
+    }
+
+    fn native_histogram(&self) -> Option<&HistogramValue> {
+        match self {
+            OpenMetricsValue::Histogram(h) | OpenMetricsValue::GaugeHistogram(h)
+                if h.native.is_some() =>
+            {
+                Some(h)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<TypeSet, ValueType> MetricsExposition<TypeSet, ValueType>
+where
+    TypeSet: Clone + ExpositionTypeSet,
+    ValueType: SeriesValue + crate::RenderableMetricValue + Clone,
+{
+    /// The timestamp of the sample in milliseconds, or `default_timestamp` if it doesn't have one
+    pub(crate) fn timestamp_millis(
+        sample: &Sample<ValueType>,
+        default_timestamp: Timestamp,
+    ) -> Timestamp {
+        let unit = TypeSet::FORMAT.timestamp_unit();
+        sample.timestamp.map_or(default_timestamp, |t| {
+            unit.convert(t, TimestampUnit::Milliseconds)
+        })
+    }
+
+    fn sample_points(
+        family: &MetricFamily<TypeSet, ValueType>,
+        sample: &Sample<ValueType>,
+    ) -> Vec<SeriesPoint> {
+        let mut points = sample.value.series_points(&family.family_name);
+        if let [point] = points.as_mut_slice() {
+            // Sample level exemplars (see `ParserOptions::gauge_exemplars`) belong to the only point
+            if point.exemplar.is_none() {
+                point.exemplar = sample.exemplar.clone();
+            }
+        }
+
+        points
+    }
+
+    /// Iterates over every series of every sample in the exposition, e.g. a histogram's
+    /// `_bucket`s, `_sum` and `_count` one by one
+    /// ```
+    /// use openmetrics_parser::openmetrics::parse_openmetrics;
+    ///
+    /// let exposition = "# TYPE foo histogram\nfoo_bucket{le=\"+Inf\"} 2\nfoo_count 2\nfoo_sum 3\n# EOF\n";
+    /// let parsed = parse_openmetrics(exposition).unwrap();
+    /// let series: Vec<_> = parsed.iter_samples().map(|s| (s.name(), s.value)).collect();
+    /// assert_eq!(series, [("foo_bucket".to_owned(), 2.), ("foo_sum".to_owned(), 3.), ("foo_count".to_owned(), 2.)]);
+    /// ```
+    pub fn iter_samples(&self) -> impl Iterator<Item = FlatSample<'_, TypeSet>> {
+        self.families.values().flat_map(|family| {
+            family.iter_labeled_samples().flat_map(move |sample| {
+                let labels: Vec<_> = sample
+                    .labels()
+                    .map(|(name, value)| (name, value.to_owned()))
+                    .collect();
+
+                Self::sample_points(family, sample.sample())
+                    .into_iter()
+                    .map(move |point| {
+                        let mut labels = labels.clone();
+                        labels.extend(point.extra_labels);
+                        FlatSample {
+                            family_name: &family.family_name,
+                            family_type: &family.family_type,
+                            suffix: point.name[family.family_name.len()..].to_owned(),
+                            labels,
+                            value: point.value,
+                            timestamp: sample.timestamp,
+                            exemplar: point.exemplar,
+                        }
+                    })
+            })
+        })
+    }
+
+    /// Calls `f` with every point in the exposition, along with its full labelset (including
+    /// `__name__`) and its timestamp in milliseconds (`default_timestamp` if it doesn't have one)
+    pub fn for_each_point<F>(&self, default_timestamp: Timestamp, mut f: F)
+    where
+        F: FnMut(
+            &MetricFamily<TypeSet, ValueType>,
+            BTreeMap<String, String>,
+            Timestamp,
+            &SeriesPoint,
+        ),
+    {
+        for family in self.families.values() {
+            for sample in family.iter_samples() {
+                let timestamp = Self::timestamp_millis(sample, default_timestamp);
+
+                let mut labels = BTreeMap::new();
+                if let Ok(labelset) = sample.get_labelset() {
+                    for (k, v) in labelset.iter() {
+                        labels.insert(k.clone(), v.clone());
+                    }
+                }
+
+                for point in Self::sample_points(family, sample) {
+                    let mut labels = labels.clone();
+                    labels.insert(METRIC_NAME_LABEL.to_owned(), point.name.clone());
+                    for (k, v) in point.extra_labels.iter() {
+                        labels.insert((*k).to_owned(), v.clone());
+                    }
+
+                    f(family, labels, timestamp, &point);
+                }
+            }
+        }
+    }
+
+    /// Flattens the exposition into the series Prometheus would store after scraping it.
+    /// Samples without a timestamp are given `default_timestamp` (in milliseconds)
+    pub fn to_timeseries(&self, default_timestamp: Timestamp) -> Vec<TimeSeries> {
+        let mut series = Vec::new();
+        self.for_each_point(default_timestamp, |_, labels, timestamp, point| {
+            series.push(TimeSeries {
+                labels,
+                samples: vec![(timestamp, point.value)],
+            });
+        });
+
+        series
+    }
+}
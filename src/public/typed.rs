@@ -0,0 +1,170 @@
+use std::{fmt, ops::Deref};
+
+use crate::{
+    CounterValue, HistogramValue, LabeledSample, MetricFamily, MetricNumber, MetricsExposition,
+    OpenMetricsType, OpenMetricsValue, ParseError, PrometheusCounterValue, PrometheusType,
+    PrometheusValue, SummaryValue,
+};
+
+/// A family that's been checked to be of a given type (see e.g. `MetricsExposition::get_counter`),
+/// whose samples' values can be read as that type without matching on them. It derefs to the family
+pub struct TypedFamily<'a, TypeSet, ValueType, T> {
+    family: &'a MetricFamily<TypeSet, ValueType>,
+    extract: fn(&'a ValueType) -> Option<T>,
+}
+
+impl<'a, TypeSet, ValueType, T> TypedFamily<'a, TypeSet, ValueType, T>
+where
+    TypeSet: Clone,
+    ValueType: crate::RenderableMetricValue + Clone,
+{
+    pub fn family(&self) -> &'a MetricFamily<TypeSet, ValueType> {
+        self.family
+    }
+
+    /// The samples of the family, along with their values
+    pub fn iter(&self) -> impl Iterator<Item = (LabeledSample<'a, ValueType>, T)> + '_ {
+        self.family
+            .iter_labeled_samples()
+            .filter_map(|sample| (self.extract)(&sample.sample().value).map(|v| (sample, v)))
+    }
+
+    /// The value of the sample with exactly these labels (see `MetricFamily::get_sample`)
+    pub fn get(&self, labels: &[(&str, &str)]) -> Option<T> {
+        let sample = self.family.get_sample(labels)?;
+        (self.extract)(&sample.value)
+    }
+}
+
+impl<'a, TypeSet, ValueType, T> Deref for TypedFamily<'a, TypeSet, ValueType, T> {
+    type Target = MetricFamily<TypeSet, ValueType>;
+
+    fn deref(&self) -> &MetricFamily<TypeSet, ValueType> {
+        self.family
+    }
+}
+
+impl<TypeSet, ValueType> MetricsExposition<TypeSet, ValueType>
+where
+    TypeSet: Clone + PartialEq + fmt::Display,
+    ValueType: crate::RenderableMetricValue + Clone,
+{
+    /// The family called `name` if there is one, checking that it's of the `expected` type
+    fn get_typed<'a, T>(
+        &'a self,
+        name: &str,
+        expected: TypeSet,
+        extract: fn(&'a ValueType) -> Option<T>,
+    ) -> Result<Option<TypedFamily<'a, TypeSet, ValueType, T>>, ParseError> {
+        let family = match self.families.get(name) {
+            Some(family) => family,
+            None => return Ok(None),
+        };
+
+        if family.family_type != expected {
+            return Err(ParseError::InvalidMetric(format!(
+                "Expected {} to be a {}, but it's a {}",
+                name, expected, family.family_type
+            )));
+        }
+
+        Ok(Some(TypedFamily { family, extract }))
+    }
+}
+
+type OpenMetricsFamily<'a, T> = TypedFamily<'a, OpenMetricsType, OpenMetricsValue, T>;
+
+/// Getters for families of a known type. Each returns `None` if there's no family called `name`,
+/// and an error if there is, but it's of another type
+/// ```
+/// use openmetrics_parser::openmetrics::parse_openmetrics;
+///
+/// let exposition = "# TYPE requests counter\nrequests_total{code=\"200\"} 3\n# EOF\n";
+/// let parsed = parse_openmetrics(exposition).unwrap();
+///
+/// let requests = parsed.get_counter("requests").unwrap().unwrap();
+/// assert_eq!(requests.get(&[("code", "200")]).unwrap().value.as_f64(), 3.);
+/// assert!(parsed.get_gauge("requests").is_err());
+/// assert!(parsed.get_gauge("missing").unwrap().is_none());
+/// ```
+impl MetricsExposition<OpenMetricsType, OpenMetricsValue> {
+    pub fn get_counter(
+        &self,
+        name: &str,
+    ) -> Result<Option<OpenMetricsFamily<'_, &CounterValue>>, ParseError> {
+        self.get_typed(name, OpenMetricsType::Counter, OpenMetricsValue::as_counter)
+    }
+
+    pub fn get_gauge(
+        &self,
+        name: &str,
+    ) -> Result<Option<OpenMetricsFamily<'_, MetricNumber>>, ParseError> {
+        self.get_typed(name, OpenMetricsType::Gauge, OpenMetricsValue::as_gauge)
+    }
+
+    pub fn get_histogram(
+        &self,
+        name: &str,
+    ) -> Result<Option<OpenMetricsFamily<'_, &HistogramValue>>, ParseError> {
+        self.get_typed(
+            name,
+            OpenMetricsType::Histogram,
+            OpenMetricsValue::as_histogram,
+        )
+    }
+
+    pub fn get_gauge_histogram(
+        &self,
+        name: &str,
+    ) -> Result<Option<OpenMetricsFamily<'_, &HistogramValue>>, ParseError> {
+        self.get_typed(
+            name,
+            OpenMetricsType::GaugeHistogram,
+            OpenMetricsValue::as_gauge_histogram,
+        )
+    }
+
+    pub fn get_summary(
+        &self,
+        name: &str,
+    ) -> Result<Option<OpenMetricsFamily<'_, &SummaryValue>>, ParseError> {
+        self.get_typed(name, OpenMetricsType::Summary, OpenMetricsValue::as_summary)
+    }
+}
+
+type PrometheusFamily<'a, T> = TypedFamily<'a, PrometheusType, PrometheusValue, T>;
+
+/// Getters for families of a known type (see the OpenMetrics ones)
+impl MetricsExposition<PrometheusType, PrometheusValue> {
+    pub fn get_counter(
+        &self,
+        name: &str,
+    ) -> Result<Option<PrometheusFamily<'_, &PrometheusCounterValue>>, ParseError> {
+        self.get_typed(name, PrometheusType::Counter, PrometheusValue::as_counter)
+    }
+
+    pub fn get_gauge(
+        &self,
+        name: &str,
+    ) -> Result<Option<PrometheusFamily<'_, MetricNumber>>, ParseError> {
+        self.get_typed(name, PrometheusType::Gauge, PrometheusValue::as_gauge)
+    }
+
+    pub fn get_histogram(
+        &self,
+        name: &str,
+    ) -> Result<Option<PrometheusFamily<'_, &HistogramValue>>, ParseError> {
+        self.get_typed(
+            name,
+            PrometheusType::Histogram,
+            PrometheusValue::as_histogram,
+        )
+    }
+
+    pub fn get_summary(
+        &self,
+        name: &str,
+    ) -> Result<Option<PrometheusFamily<'_, &SummaryValue>>, ParseError> {
+        self.get_typed(name, PrometheusType::Summary, PrometheusValue::as_summary)
+    }
+}
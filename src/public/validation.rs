@@ -0,0 +1,239 @@
+use crate::{
+    internal::{
+        check_counter_nan, check_counter_negativity, check_stateset_value, is_valid_label_name,
+        is_valid_metric_name, MetricsType,
+    },
+    MetricFamily, MetricsExposition, OpenMetricsType, OpenMetricsValue, ParseError, ParserOptions,
+    PrometheusType, PrometheusValue, RenderableMetricValue, Sample,
+};
+
+/// Checks a family against the rules the parser applies to it. This lets expositions that were
+/// built by hand (or by a converter) be checked before they're rendered
+pub trait ValidateFamily {
+    /// Validates against the rules the parser would apply when parsing with `profile`, so e.g. gauge
+    /// exemplars are only accepted if `profile.gauge_exemplars` is set
+    fn validate(&self, profile: &ParserOptions) -> Result<(), ParseError>;
+}
+
+/// The rules that don't depend on the format: names, timestamps, and units
+fn validate_common<TypeSet, ValueType>(
+    family: &MetricFamily<TypeSet, ValueType>,
+) -> Result<(), ParseError>
+where
+    TypeSet: MetricsType + Clone + Default + std::fmt::Debug,
+    ValueType: RenderableMetricValue + Clone,
+{
+    if !is_valid_metric_name(&family.family_name) {
+        return Err(ParseError::InvalidMetric(format!(
+            "Invalid metric name: {}",
+            family.family_name
+        )));
+    }
+
+    if let Some(name) = family
+        .get_label_names()
+        .iter()
+        .find(|n| !is_valid_label_name(n))
+    {
+        return Err(ParseError::InvalidMetric(format!(
+            "Invalid label name: {}",
+            name
+        )));
+    }
+
+    let mut samples = family.iter_samples();
+    if let Some(first) = samples.next() {
+        if samples.any(|s| s.timestamp.is_some() != first.timestamp.is_some()) {
+            return Err(ParseError::InvalidMetric(
+                "Missing timestamp in family (one metric had a timestamp, another didn't)"
+                    .to_string(),
+            ));
+        }
+    }
+
+    if !family.unit.is_empty() && !family.family_type.can_have_units() {
+        return Err(ParseError::InvalidMetric(format!(
+            "{:?} metrics can't have units",
+            family.family_type
+        )));
+    }
+
+    for ignored in family
+        .family_type
+        .get_ignored_labels(&format!("{}_bucket", family.family_name))
+    {
+        if family.get_label_names().iter().any(|n| n == ignored) {
+            return Err(ParseError::InvalidMetric(format!(
+                "`{}` is a reserved label for {:?} metrics",
+                ignored, family.family_type
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_summary_labels<TypeSet, ValueType>(
+    family: &MetricFamily<TypeSet, ValueType>,
+) -> Result<(), ParseError>
+where
+    TypeSet: Clone,
+    ValueType: RenderableMetricValue + Clone,
+{
+    if family.get_label_names().iter().any(|n| n == "quantile") {
+        return Err(ParseError::InvalidMetric(
+            "`quantile` is a reserved label for Summary metrics".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn validate_counter_value(value: f64, profile: &ParserOptions) -> Result<(), ParseError> {
+    check_counter_nan(value, profile.allow_nan_counters)?;
+    check_counter_negativity(value, profile, &mut Vec::new())
+}
+
+fn validate_sample_exemplar<ValueType>(
+    sample: &Sample<ValueType>,
+    gauge: bool,
+    profile: &ParserOptions,
+) -> Result<(), ParseError> {
+    if sample.exemplar.is_some() && !(gauge && profile.gauge_exemplars) {
+        return Err(ParseError::InvalidMetric(
+            "Only gauges can have a sample level exemplar, and only with `gauge_exemplars` set"
+                .to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Puts an error about a sample's value (e.g. a negative counter) in its family and labelset
+fn in_sample<TypeSet, ValueType>(
+    family: &MetricFamily<TypeSet, ValueType>,
+    sample: &Sample<ValueType>,
+    error: ParseError,
+) -> ParseError
+where
+    TypeSet: Clone,
+    ValueType: RenderableMetricValue + Clone,
+{
+    let labels: Vec<_> = family
+        .get_label_names()
+        .iter()
+        .cloned()
+        .zip(sample.get_label_values().iter().cloned())
+        .collect();
+    error.in_metric(&family.family_name, &labels)
+}
+
+fn mismatched_type<T: std::fmt::Debug, V: std::fmt::Debug>(
+    family_type: &T,
+    value: &V,
+) -> ParseError {
+    ParseError::InvalidMetric(format!(
+        "A {:?} family can't hold the value {:?}",
+        family_type, value
+    ))
+}
+
+impl ValidateFamily for MetricFamily<OpenMetricsType, OpenMetricsValue> {
+    fn validate(&self, profile: &ParserOptions) -> Result<(), ParseError> {
+        validate_common(self)?;
+
+        if self.family_type == OpenMetricsType::Summary {
+            validate_summary_labels(self)?;
+        }
+
+        if self.family_type == OpenMetricsType::StateSet
+            && !self.get_label_names().contains(&self.family_name)
+        {
+            return Err(ParseError::InvalidMetric(
+                "Stateset must have a label with the same name as its MetricFamily".to_string(),
+            ));
+        }
+
+        for sample in self.iter_samples() {
+            validate_sample_exemplar(sample, self.family_type == OpenMetricsType::Gauge, profile)?;
+
+            let checked = match (&self.family_type, &sample.value) {
+                (OpenMetricsType::Counter, OpenMetricsValue::Counter(c)) => {
+                    validate_counter_value(c.value.as_f64(), profile)
+                }
+                (OpenMetricsType::Histogram, OpenMetricsValue::Histogram(h)) => {
+                    h.check_invariants_with_options(false, profile, &mut Vec::new())
+                }
+                (OpenMetricsType::GaugeHistogram, OpenMetricsValue::GaugeHistogram(h)) => {
+                    h.check_invariants_with_options(true, profile, &mut Vec::new())
+                }
+                (OpenMetricsType::Summary, OpenMetricsValue::Summary(s)) => s.check_invariants(),
+                (OpenMetricsType::StateSet, OpenMetricsValue::StateSet(n)) => {
+                    check_stateset_value(n.as_f64(), profile, &mut Vec::new())
+                }
+                (OpenMetricsType::Gauge, OpenMetricsValue::Gauge(_))
+                | (OpenMetricsType::Info, OpenMetricsValue::Info)
+                | (OpenMetricsType::Unknown, OpenMetricsValue::Unknown(_))
+                | (OpenMetricsType::Unknown, OpenMetricsValue::Untyped(_)) => Ok(()),
+                (family_type, value) => Err(mismatched_type(family_type, value)),
+            };
+            checked.map_err(|error| in_sample(self, sample, error))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ValidateFamily for MetricFamily<PrometheusType, PrometheusValue> {
+    fn validate(&self, profile: &ParserOptions) -> Result<(), ParseError> {
+        validate_common(self)?;
+
+        if self.family_type == PrometheusType::Summary {
+            validate_summary_labels(self)?;
+        }
+
+        for sample in self.iter_samples() {
+            validate_sample_exemplar(sample, self.family_type == PrometheusType::Gauge, profile)?;
+
+            let checked = match (&self.family_type, &sample.value) {
+                (PrometheusType::Counter, PrometheusValue::Counter(c)) => {
+                    validate_counter_value(c.value.as_f64(), profile)
+                }
+                (PrometheusType::Histogram, PrometheusValue::Histogram(h)) => {
+                    h.check_invariants_with_options(false, profile, &mut Vec::new())
+                }
+                (PrometheusType::Summary, PrometheusValue::Summary(s)) => s.check_invariants(),
+                (PrometheusType::Gauge, PrometheusValue::Gauge(_))
+                | (PrometheusType::Untyped, PrometheusValue::Untyped(_))
+                | (PrometheusType::Unknown, PrometheusValue::Unknown(_))
+                | (PrometheusType::Unknown, PrometheusValue::Untyped(_)) => Ok(()),
+                (family_type, value) => Err(mismatched_type(family_type, value)),
+            };
+            checked.map_err(|error| in_sample(self, sample, error))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<TypeSet, ValueType> MetricsExposition<TypeSet, ValueType>
+where
+    MetricFamily<TypeSet, ValueType>: ValidateFamily,
+{
+    /// Validates every family in the exposition (see `ValidateFamily::validate`), and checks that
+    /// each one is stored under its own name
+    pub fn validate(&self, profile: &ParserOptions) -> Result<(), ParseError> {
+        for (name, family) in self.families.iter() {
+            if name != &family.family_name {
+                return Err(ParseError::InvalidMetric(format!(
+                    "Family {} is stored under the name {}",
+                    family.family_name, name
+                )));
+            }
+
+            family.validate(profile)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+//! Bindings for running the parsers in the browser (or any other JS host) from a
+//! `wasm32-unknown-unknown` build, e.g. to validate and preview expositions client side.
+//!
+//! Build with the `wasm` feature as a `cdylib`
+//! (`cargo rustc --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib`),
+//! and load the module with `js/openmetrics_parser.js`, which turns each parse into a plain JS
+//! object of families and samples (see `exposition_to_json` for its shape).
+//!
+//! This is a partial stand-in for wasm-bindgen bindings: the exports only pass bytes across, and
+//! there's no wasm-bindgen layer returning JS objects directly, as this crate doesn't depend on
+//! it. Crates that do use wasm-bindgen can hand `exposition_to_json` to `serde_wasm_bindgen` (or
+//! `JSON.parse`) instead
+
+use std::{
+    ffi::{c_char, CString},
+    fmt::Display,
+};
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    format_bound, openmetrics::parse_openmetrics, prometheus::parse_prometheus, ExpositionTypeSet,
+    MetricsExposition, ParseError, RenderableMetricValue, SeriesValue, METRIC_NAME_LABEL,
+};
+
+/// JSON has no NaN or infinities, so those values are strings (`NaN`, `+Inf` and `-Inf`)
+fn number(value: f64) -> Value {
+    if value.is_finite() {
+        json!(value)
+    } else {
+        json!(format_bound(value))
+    }
+}
+
+/// Turns the exposition into the object handed to JS: a `families` array, where each family has
+/// a `name`, `type`, `help`, `unit` and its `samples`. A sample is a single point, the same way
+/// `MetricsExposition::for_each_point` splits them up (so a histogram has a sample per bucket,
+/// with an `le` label), with a series `name`, `labels` object, `value` and `timestamp` (in
+/// milliseconds, or null if it didn't have one)
+/// ```
+/// use openmetrics_parser::prometheus::parse_prometheus;
+/// use openmetrics_parser::wasm::exposition_to_json;
+///
+/// let exposition = parse_prometheus("# TYPE up gauge\nup{job=\"node\"} 1\n").unwrap();
+/// assert_eq!(
+///     exposition_to_json(&exposition).to_string(),
+///     r#"{"families":[{"help":"","name":"up","samples":[{"labels":{"job":"node"},"name":"up","timestamp":null,"value":1.0}],"type":"gauge","unit":""}]}"#
+/// );
+/// ```
+pub fn exposition_to_json<TypeSet, ValueType>(
+    exposition: &MetricsExposition<TypeSet, ValueType>,
+) -> Value
+where
+    TypeSet: Clone + ExpositionTypeSet + Display,
+    ValueType: SeriesValue + RenderableMetricValue + Clone,
+{
+    let mut families: Vec<Value> = Vec::new();
+    let mut current_family: Option<String> = None;
+    exposition.for_each_point(f64::NAN, |family, mut labels, timestamp, point| {
+        if current_family.as_deref() != Some(family.family_name.as_str()) {
+            current_family = Some(family.family_name.clone());
+            families.push(json!({
+                "name": family.family_name,
+                "type": family.family_type.to_string(),
+                "help": family.help,
+                "unit": family.unit,
+                "samples": [],
+            }));
+        }
+
+        labels.remove(METRIC_NAME_LABEL);
+        let labels: Map<String, Value> = labels.into_iter().map(|(k, v)| (k, json!(v))).collect();
+        let sample = json!({
+            "name": point.name,
+            "labels": labels,
+            "value": number(point.value),
+            "timestamp": if timestamp.is_nan() { Value::Null } else { json!(timestamp) },
+        });
+
+        if let Some(samples) = families
+            .last_mut()
+            .and_then(|f| f.get_mut("samples"))
+            .and_then(Value::as_array_mut)
+        {
+            samples.push(sample);
+        }
+    });
+
+    json!({ "families": families })
+}
+
+/// Allocates `len` bytes in the module's memory for the host to write its input into
+#[no_mangle]
+pub extern "C" fn om_json_alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+/// Frees memory from `om_json_alloc`
+///
+/// # Safety
+/// `ptr` and `len` must be the pointer returned by `om_json_alloc` and the length it was given
+#[no_mangle]
+pub unsafe extern "C" fn om_json_dealloc(ptr: *mut u8, len: usize) {
+    if !ptr.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+    }
+}
+
+unsafe fn parse_with<F>(ptr: *const u8, len: usize, parse: F) -> *mut c_char
+where
+    F: FnOnce(&str) -> Result<Value, ParseError>,
+{
+    let result = if ptr.is_null() {
+        Err("Input is null".to_owned())
+    } else {
+        std::str::from_utf8(std::slice::from_raw_parts(ptr, len))
+            .map_err(|e| format!("Input isn't valid UTF-8: {}", e))
+            .and_then(|text| parse(text).map_err(|e| e.to_string()))
+    };
+
+    let result = result.unwrap_or_else(|error| json!({ "error": error }));
+
+    // Serialized JSON never holds a raw NUL, as it would be escaped
+    CString::new(result.to_string()).unwrap().into_raw()
+}
+
+/// Parses `len` bytes of OpenMetrics text at `ptr`, returning a NUL terminated JSON string of
+/// either the exposition (see `exposition_to_json`) or `{"error": "<message>"}`, which must be
+/// freed with `om_json_free`
+///
+/// # Safety
+/// `ptr` must point to at least `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn om_json_parse_openmetrics(ptr: *const u8, len: usize) -> *mut c_char {
+    parse_with(ptr, len, |text| {
+        parse_openmetrics(text).map(|e| exposition_to_json(&e))
+    })
+}
+
+/// Parses Prometheus text, in the same way as `om_json_parse_openmetrics`
+///
+/// # Safety
+/// `ptr` must point to at least `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn om_json_parse_prometheus(ptr: *const u8, len: usize) -> *mut c_char {
+    parse_with(ptr, len, |text| {
+        parse_prometheus(text).map(|e| exposition_to_json(&e))
+    })
+}
+
+/// Frees a string returned by one of the parse functions
+///
+/// # Safety
+/// `s` must be null or returned by one of the parse functions, and not yet freed
+#[no_mangle]
+pub unsafe extern "C" fn om_json_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests;
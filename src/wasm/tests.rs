@@ -0,0 +1,41 @@
+use std::ffi::CStr;
+
+use serde_json::Value;
+
+use super::*;
+
+unsafe fn parse(f: unsafe extern "C" fn(*const u8, usize) -> *mut c_char, text: &str) -> Value {
+    let input = om_json_alloc(text.len());
+    std::ptr::copy_nonoverlapping(text.as_ptr(), input, text.len());
+    let output = f(input, text.len());
+    om_json_dealloc(input, text.len());
+
+    let json = serde_json::from_str(CStr::from_ptr(output).to_str().unwrap()).unwrap();
+    om_json_free(output);
+    json
+}
+
+#[test]
+fn test_wasm_json() {
+    let text = "# TYPE latency histogram\n# HELP latency How long requests took\nlatency_bucket{le=\"1\"} 2 1\nlatency_bucket{le=\"+Inf\"} 3 1\nlatency_sum 1.5 1\nlatency_count 3 1\n# TYPE temp gauge\ntemp NaN\n# EOF\n";
+    let json = unsafe { parse(om_json_parse_openmetrics, text) };
+
+    let families = json["families"].as_array().unwrap();
+    assert_eq!(families.len(), 2);
+    assert_eq!(families[0]["type"], "histogram");
+    assert_eq!(families[0]["help"], "How long requests took");
+
+    let bucket = &families[0]["samples"][1];
+    assert_eq!(bucket["name"], "latency_bucket");
+    assert_eq!(bucket["labels"]["le"], "+Inf");
+    assert_eq!(bucket["value"], 3.);
+    assert_eq!(bucket["timestamp"], 1000.);
+
+    let temp = &families[1]["samples"][0];
+    assert_eq!(temp["value"], "NaN");
+    assert_eq!(temp["timestamp"], Value::Null);
+
+    let json = unsafe { parse(om_json_parse_prometheus, "up{job=\"a\" 1\n") };
+    assert!(json["error"].as_str().is_some());
+    assert!(json.get("families").is_none());
+}